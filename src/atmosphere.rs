@@ -0,0 +1,777 @@
+use cgmath::prelude::*;
+use cgmath::Vector3;
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::prelude::*;
+use std::num::NonZeroU32;
+
+use crate::{camera, geodesy, texture};
+
+/// Texels of the transmittance table, along the view zenith angle and the
+/// height.
+const TRANSMITTANCE_SIZE: [usize; 2] = [256, 64];
+/// Texels of the scattering table, along the sun zenith angle, the view
+/// zenith angle and the height.
+const SCATTERING_SIZE: [usize; 3] = [32, 128, 32];
+/// Steps when integrating along a ray.
+const TRANSMITTANCE_STEPS: usize = 128;
+const SCATTERING_STEPS: usize = 32;
+
+/// How much the WGS84 ellipsoid is stretched along z to make it a sphere of
+/// the equatorial radius, which the tables assume. Keep in step with
+/// atmosphere.wgsl.
+const ELLIPSOID_SQUASH: f64 = 1.0 / (1.0 - geodesy::WGS84_F);
+
+/// An Earth-like atmosphere of Rayleigh and Mie scattering and ozone
+/// absorption, in meters. The defaults are those of Bruneton's 2017
+/// reference implementation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AtmosphereParams {
+    pub bottom_radius: f64,
+    pub top_radius: f64,
+    /// Per meter at the ground, for red, green and blue.
+    pub rayleigh_scattering: Vector3<f64>,
+    pub rayleigh_scale_height: f64,
+    /// Per meter at the ground, the same for every wavelength.
+    pub mie_scattering: f64,
+    pub mie_extinction: f64,
+    pub mie_scale_height: f64,
+    /// Asymmetry of the Mie phase function, towards forward scattering.
+    pub mie_g: f64,
+    /// Per meter where ozone is densest, 25 km up. It thins out linearly to
+    /// nothing 15 km above and below.
+    pub ozone_absorption: Vector3<f64>,
+}
+
+impl Default for AtmosphereParams {
+    fn default() -> Self {
+        Self {
+            bottom_radius: geodesy::WGS84_A,
+            top_radius: geodesy::WGS84_A + 60_000.0,
+            rayleigh_scattering: Vector3::new(5.802e-6, 13.558e-6, 33.1e-6),
+            rayleigh_scale_height: 8000.0,
+            mie_scattering: 3.996e-6,
+            mie_extinction: 4.44e-6,
+            mie_scale_height: 1200.0,
+            mie_g: 0.8,
+            ozone_absorption: Vector3::new(0.650e-6, 1.881e-6, 0.085e-6),
+        }
+    }
+}
+
+impl AtmosphereParams {
+    fn rayleigh_density(&self, height: f64) -> f64 {
+        (-height / self.rayleigh_scale_height).exp()
+    }
+
+    fn mie_density(&self, height: f64) -> f64 {
+        (-height / self.mie_scale_height).exp()
+    }
+
+    fn ozone_density(&self, height: f64) -> f64 {
+        (1.0 - (height - 25_000.0).abs() / 15_000.0).max(0.0)
+    }
+
+    /// Light lost per meter at `height`, to scattering and absorption.
+    pub fn extinction(&self, height: f64) -> Vector3<f64> {
+        self.rayleigh_scattering * self.rayleigh_density(height)
+            + Vector3::from_value(self.mie_extinction * self.mie_density(height))
+            + self.ozone_absorption * self.ozone_density(height)
+    }
+
+    /// The distance from radius `r` along a ray with cosine `mu` to the
+    /// zenith to where it leaves the atmosphere.
+    fn distance_to_top(&self, r: f64, mu: f64) -> f64 {
+        let discriminant = r * r * (mu * mu - 1.0) + self.top_radius * self.top_radius;
+        (-r * mu + discriminant.max(0.0).sqrt()).max(0.0)
+    }
+
+    fn distance_to_bottom(&self, r: f64, mu: f64) -> f64 {
+        let discriminant = r * r * (mu * mu - 1.0) + self.bottom_radius * self.bottom_radius;
+        (-r * mu - discriminant.max(0.0).sqrt()).max(0.0)
+    }
+
+    fn hits_ground(&self, r: f64, mu: f64) -> bool {
+        mu < 0.0 && r * r * (mu * mu - 1.0) + self.bottom_radius * self.bottom_radius >= 0.0
+    }
+
+    /// The fraction of light passing from radius `r` out of the top of the
+    /// atmosphere along a ray with cosine `mu` to the zenith, or none if the
+    /// ground is in the way.
+    pub fn transmittance(&self, r: f64, mu: f64) -> Vector3<f64> {
+        if self.hits_ground(r, mu) {
+            return Vector3::zero();
+        }
+        let step = self.distance_to_top(r, mu) / TRANSMITTANCE_STEPS as f64;
+        let depth = (0..TRANSMITTANCE_STEPS).fold(Vector3::zero(), |depth, i| {
+            let distance = (i as f64 + 0.5) * step;
+            let height =
+                (distance * distance + 2.0 * r * mu * distance + r * r).sqrt() - self.bottom_radius;
+            depth + self.extinction(height) * step
+        });
+        depth.map(|depth| (-depth).exp())
+    }
+
+    /// The height texture coordinate, with the texels spent mostly near the
+    /// ground.
+    fn r_to_unit(&self, r: f64) -> f64 {
+        let horizon = self.horizon_distance(self.top_radius);
+        self.horizon_distance(r) / horizon
+    }
+
+    fn unit_to_r(&self, x: f64) -> f64 {
+        let rho = x * self.horizon_distance(self.top_radius);
+        (rho * rho + self.bottom_radius * self.bottom_radius).sqrt()
+    }
+
+    /// The distance from radius `r` to the horizon.
+    fn horizon_distance(&self, r: f64) -> f64 {
+        (r * r - self.bottom_radius * self.bottom_radius)
+            .max(0.0)
+            .sqrt()
+    }
+
+    /// Where a ray that misses the ground lies between the zenith, at 0, and
+    /// the horizon, at 1, by its distance to the top of the atmosphere.
+    fn sky_mu_to_unit(&self, r: f64, mu: f64) -> f64 {
+        let min = self.top_radius - r;
+        let max = self.horizon_distance(r) + self.horizon_distance(self.top_radius);
+        (self.distance_to_top(r, mu) - min) / (max - min)
+    }
+
+    fn unit_to_sky_mu(&self, r: f64, x: f64) -> f64 {
+        let rho = self.horizon_distance(r);
+        let horizon = self.horizon_distance(self.top_radius);
+        let min = self.top_radius - r;
+        let distance = min + x * (rho + horizon - min);
+        if distance == 0.0 {
+            return 1.0;
+        }
+        ((horizon * horizon - rho * rho - distance * distance) / (2.0 * r * distance))
+            .clamp(-1.0, 1.0)
+    }
+
+    /// As `unit_to_sky_mu`, between straight down, at 0, and the horizon,
+    /// at 1, for rays that hit the ground.
+    fn unit_to_ground_mu(&self, r: f64, x: f64) -> f64 {
+        let rho = self.horizon_distance(r);
+        let min = r - self.bottom_radius;
+        let distance = min + x * (rho - min);
+        if distance == 0.0 {
+            return -1.0;
+        }
+        (-(rho * rho + distance * distance) / (2.0 * r * distance)).clamp(-1.0, 1.0)
+    }
+}
+
+/// The sun zenith cosine at a texture coordinate, by Bruneton's 2008
+/// mapping, which gives up on the sun more than about 11 degrees below the
+/// horizon. atmosphere.wgsl maps the other way.
+fn unit_to_mu_s(x: f64) -> f64 {
+    (-((1.0 - x * (1.0 - (-3.6f64).exp())).ln() + 0.6) / 3.0).clamp(-1.0, 1.0)
+}
+
+/// Maps [0, 1] onto the centers of the first and last of `size` texels, so
+/// both ends are sampled exactly.
+fn unit_to_texel(x: f64, size: usize) -> f64 {
+    0.5 / size as f64 + x * (1.0 - 1.0 / size as f64)
+}
+
+fn texel_to_unit(u: f64, size: usize) -> f64 {
+    (u - 0.5 / size as f64) / (1.0 - 1.0 / size as f64)
+}
+
+/// Transmittance to the top of the atmosphere by height and view zenith
+/// angle.
+pub struct TransmittanceTable {
+    pub params: AtmosphereParams,
+    pub size: [usize; 2],
+    /// Rows of increasing height, with alpha unused.
+    pub texels: Vec<[f32; 4]>,
+}
+
+impl TransmittanceTable {
+    pub fn new(params: AtmosphereParams, size: [usize; 2]) -> Self {
+        let [width, height] = size;
+        let texels = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let r = params.unit_to_r(texel_to_unit((y as f64 + 0.5) / height as f64, height));
+                let mu =
+                    params.unit_to_sky_mu(r, texel_to_unit((x as f64 + 0.5) / width as f64, width));
+                let transmittance = params.transmittance(r, mu).cast::<f32>().unwrap();
+                transmittance.extend(1.0).into()
+            })
+            .collect();
+        Self {
+            params,
+            size,
+            texels,
+        }
+    }
+
+    /// Bilinearly filtered, as the GPU samples it.
+    pub fn sample(&self, r: f64, mu: f64) -> Vector3<f64> {
+        if self.params.hits_ground(r, mu) {
+            return Vector3::zero();
+        }
+        let r = r.clamp(self.params.bottom_radius, self.params.top_radius);
+        let [width, height] = self.size;
+        let u = unit_to_texel(self.params.sky_mu_to_unit(r, mu), width);
+        let v = unit_to_texel(self.params.r_to_unit(r), height);
+
+        let (x, fx) = texel_position(u, width);
+        let (y, fy) = texel_position(v, height);
+        let texel = |x: usize, y: usize| -> Vector3<f64> {
+            let [r, g, b, _] = self.texels[y * width + x];
+            Vector3::new(r, g, b).cast().unwrap()
+        };
+        let top = texel(x, y).lerp(texel((x + 1).min(width - 1), y), fx);
+        let bottom_row = (y + 1).min(height - 1);
+        let bottom = texel(x, bottom_row).lerp(texel((x + 1).min(width - 1), bottom_row), fx);
+        top.lerp(bottom, fy)
+    }
+}
+
+/// The texel left of a texture coordinate and how far past its center the
+/// coordinate lies.
+fn texel_position(u: f64, size: usize) -> (usize, f64) {
+    let position = (u * size as f64 - 0.5).clamp(0.0, size as f64 - 1.0);
+    (position.floor() as usize, position.fract())
+}
+
+/// Light scattered once towards the viewer by height, view zenith angle and
+/// sun zenith angle, after Bruneton and Neyret's precomputed scattering.
+///
+/// The table leaves out the sun's azimuth relative to the view, as Elek
+/// does, taking it as a right angle, and the phase functions, which are
+/// applied when sampling. Rayleigh scattering is kept in rgb and only the
+/// red of Mie scattering in alpha, the other channels being inferred from
+/// the Rayleigh ones.
+pub struct ScatteringTable {
+    pub size: [usize; 3],
+    /// Slices of increasing height, each in rows of increasing view zenith
+    /// cosine: ground rays in the first half and sky rays in the second.
+    pub texels: Vec<[f32; 4]>,
+}
+
+impl ScatteringTable {
+    pub fn new(transmittance: &TransmittanceTable, size: [usize; 3]) -> Self {
+        let params = &transmittance.params;
+        let [width, height, depth] = size;
+        let half = height / 2;
+        // One slice of heights at a time
+        let slice = |z: usize| {
+            let r = params.unit_to_r(texel_to_unit((z as f64 + 0.5) / depth as f64, depth));
+            let mut texels = Vec::with_capacity(width * height);
+            for y in 0..height {
+                let v = (y as f64 + 0.5) / height as f64;
+                let (mu, ground) = if y < half {
+                    let x = texel_to_unit(1.0 - 2.0 * v, half);
+                    (params.unit_to_ground_mu(r, x), true)
+                } else {
+                    let x = texel_to_unit(2.0 * v - 1.0, half);
+                    (params.unit_to_sky_mu(r, x), false)
+                };
+                for x in 0..width {
+                    let mu_s = unit_to_mu_s(texel_to_unit((x as f64 + 0.5) / width as f64, width));
+                    texels.push(single_scattering(transmittance, r, mu, mu_s, ground));
+                }
+            }
+            texels
+        };
+        let slices = {
+            cfg_if::cfg_if! {
+                if #[cfg(target_arch = "wasm32")] {
+                    (0..depth).map(slice).collect::<Vec<_>>()
+                } else {
+                    (0..depth).into_par_iter().map(slice).collect::<Vec<_>>()
+                }
+            }
+        };
+        let texels = slices.concat();
+        Self { size, texels }
+    }
+}
+
+fn single_scattering(
+    transmittance: &TransmittanceTable,
+    r: f64,
+    mu: f64,
+    mu_s: f64,
+    ground: bool,
+) -> [f32; 4] {
+    let params = &transmittance.params;
+    let origin = Vector3::new(0.0, r, 0.0);
+    let view = Vector3::new((1.0 - mu * mu).max(0.0).sqrt(), mu, 0.0);
+    // Square to the view, so the cosine between the two is mu * mu_s
+    let sun = Vector3::new(0.0, mu_s, (1.0 - mu_s * mu_s).max(0.0).sqrt());
+    let length = if ground {
+        params.distance_to_bottom(r, mu)
+    } else {
+        params.distance_to_top(r, mu)
+    };
+    let step = length / SCATTERING_STEPS as f64;
+
+    let mut depth = Vector3::zero();
+    let mut rayleigh = Vector3::zero();
+    let mut mie = Vector3::zero();
+    for i in 0..SCATTERING_STEPS {
+        let point = origin + view * ((i as f64 + 0.5) * step);
+        let r_point = point.magnitude();
+        let height = r_point - params.bottom_radius;
+        let extinction = params.extinction(height);
+        // Halfway through this step's own extinction
+        let to_point = (depth + extinction * (step / 2.0)).map(|depth: f64| (-depth).exp());
+        depth += extinction * step;
+        let light =
+            to_point.mul_element_wise(transmittance.sample(r_point, point.dot(sun) / r_point));
+        rayleigh += light * (params.rayleigh_density(height) * step);
+        mie += light * (params.mie_density(height) * step);
+    }
+    let rayleigh = rayleigh.mul_element_wise(params.rayleigh_scattering);
+    let mie = mie * params.mie_scattering;
+    [
+        rayleigh.x as f32,
+        rayleigh.y as f32,
+        rayleigh.z as f32,
+        mie.x as f32,
+    ]
+}
+
+/// The nearest half precision float, for filterable float textures.
+fn f16_bits(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if value.is_nan() {
+        return sign | 0x7e00;
+    }
+    if exponent >= 31 {
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal, with the implicit leading one made explicit
+        let shift = (14 - exponent) as u32;
+        if shift > 24 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        return sign | ((mantissa + (1 << (shift - 1))) >> shift) as u16;
+    }
+    // Rounding may carry into the exponent, up to infinity
+    let half = ((exponent as u32) << 10 | mantissa >> 13) + ((mantissa >> 12) & 1);
+    sign | half as u16
+}
+
+fn f16_texels(texels: &[[f32; 4]]) -> Vec<u16> {
+    texels
+        .iter()
+        .flatten()
+        .map(|&value| f16_bits(value))
+        .collect()
+}
+
+/// Stretches an ECEF position or direction so the WGS84 ellipsoid becomes
+/// a sphere of the equatorial radius.
+pub fn squash(v: Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(v.x, v.y, v.z * ELLIPSOID_SQUASH)
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct AtmosphereUniform {
+    /// The view ray through the center of the screen, and how far the ones
+    /// through its right and top edges lean from it, in ECEF.
+    view_forward: [f32; 4],
+    view_right: [f32; 4],
+    view_up: [f32; 4],
+    /// Squashed ECEF meters.
+    camera_position: [f32; 4],
+    /// Squashed and unit length, with the sun's intensity in w.
+    sun_direction: [f32; 4],
+    rayleigh_scattering: [f32; 4],
+    /// Bottom radius, top radius, Mie g and Mie scattering.
+    planet: [f32; 4],
+}
+
+/// The sky and the haze between the camera and what it sees. The tables
+/// are computed on the CPU once; the sky is drawn behind everything and
+/// shaders that include `shader_library` fade what they draw into it.
+pub struct Atmosphere {
+    pub params: AtmosphereParams,
+    /// Scales the scattered sunlight before tone mapping.
+    pub sun_intensity: f32,
+    buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    sky_pipeline: wgpu::RenderPipeline,
+}
+
+impl Atmosphere {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        params: AtmosphereParams,
+    ) -> Self {
+        let transmittance = TransmittanceTable::new(params, TRANSMITTANCE_SIZE);
+        let scattering = ScatteringTable::new(&transmittance, SCATTERING_SIZE);
+        let [width, height] = transmittance.size;
+        let transmittance_texture = create_table_texture(
+            device,
+            queue,
+            "Transmittance Table",
+            wgpu::TextureDimension::D2,
+            [width, height, 1],
+            &transmittance.texels,
+        );
+        let scattering_texture = create_table_texture(
+            device,
+            queue,
+            "Scattering Table",
+            wgpu::TextureDimension::D3,
+            scattering.size,
+            &scattering.texels,
+        );
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Atmosphere Buffer"),
+            size: std::mem::size_of::<AtmosphereUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let table_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                table_entry(1, wgpu::TextureViewDimension::D2),
+                table_entry(2, wgpu::TextureViewDimension::D3),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("atmosphere_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(
+                        &transmittance_texture.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(
+                        &scattering_texture.create_view(&Default::default()),
+                    ),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("atmosphere_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Sky Shader"),
+            source: wgpu::ShaderSource::Wgsl((shader_library(0) + include_str!("sky.wgsl")).into()),
+        });
+        let sky_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Sky Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::REPLACE),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Drawn first and behind everything, leaving depth alone
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            params,
+            sun_intensity: 20.0,
+            buffer,
+            bind_group_layout,
+            bind_group,
+            sky_pipeline,
+        }
+    }
+
+    /// Follows the camera and the sun, given as an ECEF direction.
+    pub fn update(
+        &self,
+        queue: &wgpu::Queue,
+        camera: &camera::GlobeCamera,
+        projection: &camera::Projection,
+        sun_direction: Vector3<f64>,
+    ) {
+        let (forward, up) = camera.orientation();
+        let tan_half_fovy = (projection.fovy().0 as f64 / 2.0).tan();
+        let right = forward.cross(up) * tan_half_fovy * projection.aspect() as f64;
+        let up = up * tan_half_fovy;
+        let sun_direction = squash(sun_direction).normalize().cast::<f32>().unwrap();
+        let camera_position = squash(camera.position().to_vec()).cast::<f32>().unwrap();
+        let params = &self.params;
+        let uniform = AtmosphereUniform {
+            view_forward: forward.cast::<f32>().unwrap().extend(0.0).into(),
+            view_right: right.cast::<f32>().unwrap().extend(0.0).into(),
+            view_up: up.cast::<f32>().unwrap().extend(0.0).into(),
+            camera_position: camera_position.extend(1.0).into(),
+            sun_direction: sun_direction.extend(self.sun_intensity).into(),
+            rayleigh_scattering: params
+                .rayleigh_scattering
+                .cast::<f32>()
+                .unwrap()
+                .extend(0.0)
+                .into(),
+            planet: [
+                params.bottom_radius as f32,
+                params.top_radius as f32,
+                params.mie_g as f32,
+                params.mie_scattering as f32,
+            ],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+fn create_table_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    label: &str,
+    dimension: wgpu::TextureDimension,
+    [width, height, depth]: [usize; 3],
+    texels: &[[f32; 4]],
+) -> wgpu::Texture {
+    let size = wgpu::Extent3d {
+        width: width as u32,
+        height: height as u32,
+        depth_or_array_layers: depth as u32,
+    };
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension,
+        format: wgpu::TextureFormat::Rgba16Float,
+        usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+    });
+    queue.write_texture(
+        wgpu::ImageCopyTexture {
+            aspect: wgpu::TextureAspect::All,
+            texture: &texture,
+            mip_level: 0,
+            origin: wgpu::Origin3d::ZERO,
+        },
+        bytemuck::cast_slice(&f16_texels(texels)),
+        wgpu::ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(8 * width as u32),
+            rows_per_image: NonZeroU32::new(height as u32),
+        },
+        size,
+    );
+    texture
+}
+
+/// WGSL declaring the atmosphere's bindings at `group` and functions to
+/// sample it, for a shader to be appended to.
+pub fn shader_library(group: u32) -> String {
+    include_str!("atmosphere.wgsl").replace("ATMOSPHERE_GROUP", &group.to_string())
+}
+
+pub trait DrawSky<'a> {
+    fn draw_sky(&mut self, atmosphere: &'a Atmosphere);
+}
+
+impl<'a, 'b> DrawSky<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_sky(&mut self, atmosphere: &'b Atmosphere) {
+        self.set_pipeline(&atmosphere.sky_pipeline);
+        self.set_bind_group(0, &atmosphere.bind_group, &[]);
+        // One triangle covering the screen
+        self.draw(0..3, 0..1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ground_mu_to_unit(params: &AtmosphereParams, r: f64, mu: f64) -> f64 {
+        let min = r - params.bottom_radius;
+        let max = params.horizon_distance(r);
+        (params.distance_to_bottom(r, mu) - min) / (max - min)
+    }
+
+    fn mu_s_to_unit(mu_s: f64) -> f64 {
+        ((1.0 - (-3.0 * mu_s - 0.6).exp()) / (1.0 - (-3.6f64).exp())).clamp(0.0, 1.0)
+    }
+
+    #[test]
+    fn half_floats_round_to_nearest() {
+        assert_eq!(f16_bits(0.0), 0x0000);
+        assert_eq!(f16_bits(1.0), 0x3c00);
+        assert_eq!(f16_bits(-2.0), 0xc000);
+        assert_eq!(f16_bits(0.333_333_34), 0x3555);
+        assert_eq!(f16_bits(65504.0), 0x7bff);
+        assert_eq!(f16_bits(1e6), 0x7c00);
+        // The smallest subnormal, and one too small for it
+        assert_eq!(f16_bits(5.96e-8), 0x0001);
+        assert_eq!(f16_bits(1e-9), 0x0000);
+    }
+
+    #[test]
+    fn table_coordinates_round_trip() {
+        let params = AtmosphereParams::default();
+        for &height in &[0.0, 150.0, 8000.0, 59_000.0] {
+            let r = params.bottom_radius + height;
+            assert!((params.unit_to_r(params.r_to_unit(r)) - r).abs() < 1e-6);
+            for &mu in &[1.0, 0.5, 0.01, -0.01] {
+                if !params.hits_ground(r, mu) {
+                    let x = params.sky_mu_to_unit(r, mu);
+                    assert!((params.unit_to_sky_mu(r, x) - mu).abs() < 1e-6);
+                }
+            }
+            if height > 0.0 {
+                let x = ground_mu_to_unit(&params, r, -0.5);
+                assert!((params.unit_to_ground_mu(r, x) + 0.5).abs() < 1e-6);
+            }
+        }
+        for &mu_s in &[-0.15, 0.0, 0.3, 1.0] {
+            assert!((unit_to_mu_s(mu_s_to_unit(mu_s)) - mu_s).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn transmittance_thins_with_height_and_reddens_the_horizon() {
+        let params = AtmosphereParams::default();
+        let ground = params.bottom_radius;
+        let zenith = params.transmittance(ground, 1.0);
+        let depth = params.rayleigh_scattering * params.rayleigh_scale_height
+            + Vector3::from_value(params.mie_extinction * params.mie_scale_height)
+            + params.ozone_absorption * 15_000.0;
+        for i in 0..3 {
+            assert!((zenith[i] - (-depth[i]).exp()).abs() < 1e-3);
+        }
+        // Blue is scattered away most, all the more so at the horizon
+        let horizon = params.transmittance(ground + 10.0, 0.0);
+        assert!(horizon.z < horizon.x && horizon.x < zenith.x);
+        assert!(params.transmittance(ground + 10_000.0, 1.0).z > zenith.z);
+        assert_eq!(params.transmittance(ground + 10.0, -0.5), Vector3::zero());
+
+        let table = TransmittanceTable::new(params, [64, 16]);
+        for &(height, mu) in &[(0.0, 1.0), (1000.0, 0.3), (20_000.0, 0.05), (40_000.0, 0.7)] {
+            let r = ground + height;
+            let error = table.sample(r, mu) - params.transmittance(r, mu);
+            assert!(
+                error.magnitude() < 0.01,
+                "{:?} at {} m, {}",
+                error,
+                height,
+                mu
+            );
+        }
+    }
+
+    #[test]
+    fn the_sky_is_blue_by_day_and_dark_at_night() {
+        let params = AtmosphereParams::default();
+        let transmittance = TransmittanceTable::new(params, [64, 16]);
+        let ground = params.bottom_radius + 10.0;
+        let [r, g, b, mie] = single_scattering(&transmittance, ground, 1.0, 1.0, false);
+        assert!(b > g && g > r && r > 0.0);
+        assert!(mie > 0.0);
+        // Looking down from a plane, light comes from the air below
+        let below = single_scattering(&transmittance, ground + 10_000.0, -1.0, 1.0, true);
+        assert!(below[2] > 0.0);
+
+        let night = single_scattering(&transmittance, ground, 1.0, -0.5, false);
+        assert_eq!(night, [0.0; 4]);
+
+        let table = ScatteringTable::new(&transmittance, [4, 8, 4]);
+        assert_eq!(table.texels.len(), 4 * 8 * 4);
+        assert!(table
+            .texels
+            .iter()
+            .flatten()
+            .all(|value| value.is_finite() && *value >= 0.0));
+    }
+}
@@ -0,0 +1,223 @@
+// Atmospheric scattering, sampled from the tables in atmosphere.rs. Shaders
+// append themselves to this, with ATMOSPHERE_GROUP replaced by their group.
+
+struct Atmosphere {
+    // The view ray through the center of the screen, and how far the ones
+    // through its right and top edges lean from it, in ECEF
+    view_forward: vec4<f32>;
+    view_right: vec4<f32>;
+    view_up: vec4<f32>;
+    // Squashed ECEF meters
+    camera_position: vec4<f32>;
+    // Squashed, with the sun's intensity in w
+    sun_direction: vec4<f32>;
+    rayleigh_scattering: vec4<f32>;
+    // Bottom radius, top radius, Mie g and Mie scattering
+    planet: vec4<f32>;
+};
+[[group(ATMOSPHERE_GROUP), binding(0)]]
+var<uniform> atmosphere: Atmosphere;
+[[group(ATMOSPHERE_GROUP), binding(1)]]
+var transmittance_table: texture_2d<f32>;
+[[group(ATMOSPHERE_GROUP), binding(2)]]
+var scattering_table: texture_3d<f32>;
+[[group(ATMOSPHERE_GROUP), binding(3)]]
+var atmosphere_sampler: sampler;
+
+// Keep in step with atmosphere.rs
+let ELLIPSOID_SQUASH: f32 = 1.0033640898;
+let ATMOSPHERE_PI: f32 = 3.14159265;
+
+// Stretches an ECEF vector so the ellipsoid becomes a sphere
+fn squash(v: vec3<f32>) -> vec3<f32> {
+    return vec3<f32>(v.xy, v.z * ELLIPSOID_SQUASH);
+}
+
+fn unit_to_texel(x: f32, size: f32) -> f32 {
+    return 0.5 / size + x * (1.0 - 1.0 / size);
+}
+
+fn horizon_distance(r: f32) -> f32 {
+    let bottom = atmosphere.planet.x;
+    return sqrt(max(r * r - bottom * bottom, 0.0));
+}
+
+fn distance_to_top(r: f32, mu: f32) -> f32 {
+    let top = atmosphere.planet.y;
+    return max(-r * mu + sqrt(max(r * r * (mu * mu - 1.0) + top * top, 0.0)), 0.0);
+}
+
+fn ray_hits_ground(r: f32, mu: f32) -> bool {
+    let bottom = atmosphere.planet.x;
+    return mu < 0.0 && r * r * (mu * mu - 1.0) + bottom * bottom >= 0.0;
+}
+
+// Transmittance from radius r out of the atmosphere, along a ray that misses
+// the ground
+fn transmittance_to_top(r: f32, mu: f32) -> vec3<f32> {
+    let top = atmosphere.planet.y;
+    let r = clamp(r, atmosphere.planet.x, top);
+    let rho = horizon_distance(r);
+    let horizon = horizon_distance(top);
+    let d_min = top - r;
+    let d_max = rho + horizon;
+    let size = vec2<f32>(textureDimensions(transmittance_table));
+    let uv = vec2<f32>(
+        unit_to_texel((distance_to_top(r, mu) - d_min) / (d_max - d_min), size.x),
+        unit_to_texel(rho / horizon, size.y),
+    );
+    return textureSampleLevel(transmittance_table, atmosphere_sampler, uv, 0.0).rgb;
+}
+
+// Sunlight reaching a point, or none once the sun has set there
+fn sun_transmittance(r: f32, mu_s: f32) -> vec3<f32> {
+    if (ray_hits_ground(r, mu_s)) {
+        return vec3<f32>(0.0);
+    }
+    return transmittance_to_top(r, mu_s);
+}
+
+fn sample_scattering(r: f32, mu: f32, mu_s: f32, hits_ground: bool) -> vec4<f32> {
+    let bottom = atmosphere.planet.x;
+    let top = atmosphere.planet.y;
+    let r = clamp(r, bottom, top);
+    let rho = horizon_distance(r);
+    let horizon = horizon_distance(top);
+    let size = vec3<f32>(textureDimensions(scattering_table));
+    let r_mu = r * mu;
+    let discriminant = r_mu * r_mu - r * r + bottom * bottom;
+    var u_mu: f32;
+    if (hits_ground) {
+        let d = -r_mu - sqrt(max(discriminant, 0.0));
+        let d_min = r - bottom;
+        let d_max = rho;
+        var x = 0.0;
+        if (d_max > d_min) {
+            x = (d - d_min) / (d_max - d_min);
+        }
+        u_mu = 0.5 - 0.5 * unit_to_texel(x, size.y / 2.0);
+    } else {
+        let d = -r_mu + sqrt(max(discriminant + horizon * horizon, 0.0));
+        let d_min = top - r;
+        let d_max = rho + horizon;
+        u_mu = 0.5 + 0.5 * unit_to_texel((d - d_min) / (d_max - d_min), size.y / 2.0);
+    }
+    let x_mu_s = clamp((1.0 - exp(-3.0 * mu_s - 0.6)) / (1.0 - exp(-3.6)), 0.0, 1.0);
+    let uvw = vec3<f32>(
+        unit_to_texel(x_mu_s, size.x),
+        u_mu,
+        unit_to_texel(rho / horizon, size.z),
+    );
+    return textureSampleLevel(scattering_table, atmosphere_sampler, uvw, 0.0);
+}
+
+fn rayleigh_phase(nu: f32) -> f32 {
+    return 3.0 / (16.0 * ATMOSPHERE_PI) * (1.0 + nu * nu);
+}
+
+fn mie_phase(nu: f32) -> f32 {
+    let g = atmosphere.planet.z;
+    let k = 3.0 / (8.0 * ATMOSPHERE_PI) * (1.0 - g * g) / (2.0 + g * g);
+    return k * (1.0 + nu * nu) / pow(1.0 + g * g - 2.0 * g * nu, 1.5);
+}
+
+// Radiance from a table entry, with the Mie green and blue inferred from the
+// Rayleigh ones, per unit of sunlight
+fn scattered_radiance(scattering: vec4<f32>, nu: f32) -> vec3<f32> {
+    let beta = atmosphere.rayleigh_scattering.rgb;
+    let mie = scattering.rgb * scattering.a / max(scattering.r, 1e-12) * (beta.r / beta);
+    return scattering.rgb * rayleigh_phase(nu) + mie * mie_phase(nu);
+}
+
+// Brings radiance into display range, the same way for the sky and for the
+// haze in front of the ground so the two meet at the horizon
+fn expose(radiance: vec3<f32>) -> vec3<f32> {
+    return 1.0 - exp(-radiance * atmosphere.sun_direction.w);
+}
+
+// How far along the ray from `camera` it enters the atmosphere, or a
+// negative distance if it never does
+fn distance_into_atmosphere(camera: vec3<f32>, view: vec3<f32>) -> f32 {
+    let top = atmosphere.planet.y;
+    let r = length(camera);
+    if (r <= top) {
+        return 0.0;
+    }
+    let r_mu = dot(camera, view);
+    let discriminant = r_mu * r_mu - r * r + top * top;
+    if (discriminant < 0.0 || r_mu > 0.0) {
+        return -1.0;
+    }
+    return -r_mu - sqrt(discriminant);
+}
+
+// The sky seen from `camera` looking along `view`, both squashed, exposed
+fn sky_color(camera: vec3<f32>, view: vec3<f32>) -> vec3<f32> {
+    let entry = distance_into_atmosphere(camera, view);
+    if (entry < 0.0) {
+        return vec3<f32>(0.0);
+    }
+    let origin = camera + view * entry;
+    let sun = atmosphere.sun_direction.xyz;
+    let r = length(origin);
+    let mu = dot(origin, view) / r;
+    let mu_s = dot(origin, sun) / r;
+    let nu = dot(view, sun);
+    let hits_ground = ray_hits_ground(r, mu);
+    let scattering = sample_scattering(r, mu, mu_s, hits_ground);
+    var radiance = scattered_radiance(scattering, nu);
+    // The sun's disk, about half a degree across
+    if (!hits_ground && nu > 0.99999) {
+        radiance = radiance + transmittance_to_top(r, mu) * 20.0;
+    }
+    return expose(radiance);
+}
+
+struct AerialPerspective {
+    transmittance: vec3<f32>;
+    // Exposed
+    in_scattering: vec3<f32>;
+};
+
+// The haze between `camera` and `point`, both squashed
+fn aerial_perspective(camera: vec3<f32>, point: vec3<f32>) -> AerialPerspective {
+    var out: AerialPerspective;
+    out.transmittance = vec3<f32>(1.0);
+    out.in_scattering = vec3<f32>(0.0);
+
+    let view = normalize(point - camera);
+    let entry = distance_into_atmosphere(camera, view);
+    let origin = camera + view * max(entry, 0.0);
+    let d = dot(point - origin, view);
+    if (entry < 0.0 || d <= 0.0) {
+        return out;
+    }
+    let sun = atmosphere.sun_direction.xyz;
+    let r = length(origin);
+    let mu = dot(origin, view) / r;
+    let mu_s = dot(origin, sun) / r;
+    let nu = dot(view, sun);
+    let hits_ground = ray_hits_ground(r, mu);
+
+    let r_point = max(sqrt(d * d + 2.0 * r * mu * d + r * r), atmosphere.planet.x);
+    let mu_point = (r * mu + d) / r_point;
+    let mu_s_point = (r * mu_s + d * nu) / r_point;
+
+    // Both ends looked up facing away from the ground
+    if (hits_ground) {
+        out.transmittance = min(
+            transmittance_to_top(r_point, -mu_point) / max(transmittance_to_top(r, -mu), vec3<f32>(1e-6)),
+            vec3<f32>(1.0),
+        );
+    } else {
+        out.transmittance = min(
+            transmittance_to_top(r, mu) / max(transmittance_to_top(r_point, mu_point), vec3<f32>(1e-6)),
+            vec3<f32>(1.0),
+        );
+    }
+    let scattering = sample_scattering(r, mu, mu_s, hits_ground)
+        - vec4<f32>(out.transmittance, out.transmittance.r)
+            * sample_scattering(r_point, mu_point, mu_s_point, hits_ground);
+    out.in_scattering = expose(scattered_radiance(max(scattering, vec4<f32>(0.0)), nu));
+    return out;
+}
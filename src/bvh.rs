@@ -0,0 +1,359 @@
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use crate::picking::{Aabb, Ray};
+
+/// Triangles per leaf, past which a node is split.
+const LEAF_SIZE: usize = 4;
+
+/// A node covers `count` triangles from `first` in the triangle order if it
+/// is a leaf, or has its two children at `first` and `first + 1` otherwise.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    bounds: Aabb,
+    first: u32,
+    count: u32,
+}
+
+impl Node {
+    fn is_leaf(&self) -> bool {
+        self.count > 0
+    }
+}
+
+/// Where a ray meets a mesh.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TriangleHit {
+    /// Index of the triangle, i.e. of its first index divided by 3.
+    pub triangle: usize,
+    /// Along the ray, in the units of its direction.
+    pub distance: f64,
+}
+
+/// The positions and triangles of a mesh kept on the CPU, with a bounding
+/// volume hierarchy over the triangles for ray, sphere and box queries.
+///
+/// The hierarchy splits the triangles at the median of their centers along
+/// the longest axis until at most a handful are left in each leaf.
+#[derive(Debug)]
+pub struct TriangleMesh {
+    pub positions: Vec<Vector3<f64>>,
+    pub indices: Vec<u32>,
+    nodes: Vec<Node>,
+    /// Triangle indices in the order the leaves refer to them.
+    order: Vec<u32>,
+}
+
+impl TriangleMesh {
+    pub fn new(positions: Vec<Vector3<f64>>, indices: Vec<u32>) -> Self {
+        let mut mesh = Self {
+            positions,
+            indices,
+            nodes: Vec::new(),
+            order: Vec::new(),
+        };
+        let count = mesh.indices.len() / 3;
+        if count == 0 {
+            return mesh;
+        }
+        let centers = (0..count)
+            .map(|triangle| {
+                let [a, b, c] = mesh.triangle(triangle);
+                (a + b + c) / 3.0
+            })
+            .collect::<Vec<_>>();
+        mesh.order = (0..count as u32).collect();
+        mesh.nodes.push(Node {
+            bounds: mesh.bounds_of(&mesh.order),
+            first: 0,
+            count: count as u32,
+        });
+        mesh.split(0, &centers);
+        mesh
+    }
+
+    fn split(&mut self, node: usize, centers: &[Vector3<f64>]) {
+        let Node { first, count, .. } = self.nodes[node];
+        if (count as usize) <= LEAF_SIZE {
+            return;
+        }
+        let range = first as usize..(first + count) as usize;
+        let center_bounds = Aabb::from_points(
+            self.order[range.clone()]
+                .iter()
+                .map(|&triangle| centers[triangle as usize]),
+        );
+        let extent = center_bounds.max - center_bounds.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        let middle = count as usize / 2;
+        self.order[range.clone()].select_nth_unstable_by(middle, |&a, &b| {
+            centers[a as usize][axis].total_cmp(&centers[b as usize][axis])
+        });
+
+        let children = self.nodes.len() as u32;
+        for (start, end) in [
+            (range.start, range.start + middle),
+            (range.start + middle, range.end),
+        ] {
+            self.nodes.push(Node {
+                bounds: self.bounds_of(&self.order[start..end]),
+                first: start as u32,
+                count: (end - start) as u32,
+            });
+        }
+        self.nodes[node].first = children;
+        self.nodes[node].count = 0;
+        self.split(children as usize, centers);
+        self.split(children as usize + 1, centers);
+    }
+
+    fn bounds_of(&self, triangles: &[u32]) -> Aabb {
+        Aabb::from_points(
+            triangles
+                .iter()
+                .flat_map(|&triangle| self.triangle(triangle as usize)),
+        )
+    }
+
+    /// The corners of a triangle.
+    pub fn triangle(&self, triangle: usize) -> [Vector3<f64>; 3] {
+        [0, 1, 2].map(|corner| self.positions[self.indices[triangle * 3 + corner] as usize])
+    }
+
+    /// Visits the leaves whose bounds `overlaps` accepts, stopping early if
+    /// `visit` returns false.
+    fn traverse(&self, overlaps: impl Fn(&Aabb) -> bool, mut visit: impl FnMut(usize) -> bool) {
+        let mut stack = Vec::new();
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+        while let Some(node) = stack.pop() {
+            let node: &Node = &self.nodes[node];
+            if !overlaps(&node.bounds) {
+                continue;
+            }
+            if node.is_leaf() {
+                let triangles =
+                    &self.order[node.first as usize..(node.first + node.count) as usize];
+                for &triangle in triangles {
+                    if !visit(triangle as usize) {
+                        return;
+                    }
+                }
+            } else {
+                stack.push(node.first as usize);
+                stack.push(node.first as usize + 1);
+            }
+        }
+    }
+
+    /// The nearest triangle the ray hits, seen from either side.
+    pub fn intersect_ray(&self, ray: &Ray) -> Option<TriangleHit> {
+        let mut nearest: Option<TriangleHit> = None;
+        let nearest_distance = std::cell::Cell::new(f64::INFINITY);
+        self.traverse(
+            |bounds| {
+                ray.intersect_box(bounds)
+                    .is_some_and(|distance| distance <= nearest_distance.get())
+            },
+            |triangle| {
+                let [a, b, c] = self.triangle(triangle);
+                if let Some(distance) = ray.intersect_triangle(a, b, c) {
+                    if distance < nearest_distance.get() {
+                        nearest_distance.set(distance);
+                        nearest = Some(TriangleHit { triangle, distance });
+                    }
+                }
+                true
+            },
+        );
+        nearest
+    }
+
+    /// The triangles reaching into a sphere.
+    // Nothing collides with models yet
+    #[allow(dead_code)]
+    pub fn triangles_in_sphere(&self, center: Vector3<f64>, radius: f64) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.traverse(
+            |bounds| {
+                (closest_point_on_box(bounds, center) - center).magnitude2() <= radius * radius
+            },
+            |triangle| {
+                let [a, b, c] = self.triangle(triangle);
+                if (closest_point_on_triangle(center, a, b, c) - center).magnitude2()
+                    <= radius * radius
+                {
+                    found.push(triangle);
+                }
+                true
+            },
+        );
+        found
+    }
+
+    /// The triangles reaching into a box.
+    // For measuring volumes, which the viewer has no tool for yet
+    #[allow(dead_code)]
+    pub fn triangles_in_box(&self, bounds: &Aabb) -> Vec<usize> {
+        let mut found = Vec::new();
+        self.traverse(
+            |node| overlap(node, bounds),
+            |triangle| {
+                if triangle_overlaps_box(self.triangle(triangle), bounds) {
+                    found.push(triangle);
+                }
+                true
+            },
+        );
+        found
+    }
+}
+
+fn overlap(a: &Aabb, b: &Aabb) -> bool {
+    (0..3).all(|axis| a.min[axis] <= b.max[axis] && b.min[axis] <= a.max[axis])
+}
+
+fn closest_point_on_box(bounds: &Aabb, point: Vector3<f64>) -> Vector3<f64> {
+    Vector3::new(
+        point.x.clamp(bounds.min.x, bounds.max.x),
+        point.y.clamp(bounds.min.y, bounds.max.y),
+        point.z.clamp(bounds.min.z, bounds.max.z),
+    )
+}
+
+/// From Ericson, Real-Time Collision Detection, 5.1.5: finds the Voronoi
+/// region of the triangle the point projects into.
+fn closest_point_on_triangle(
+    p: Vector3<f64>,
+    a: Vector3<f64>,
+    b: Vector3<f64>,
+    c: Vector3<f64>,
+) -> Vector3<f64> {
+    let (ab, ac, ap) = (b - a, c - a, p - a);
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = p - b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = p - c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denominator = 1.0 / (va + vb + vc);
+    a + ab * (vb * denominator) + ac * (vc * denominator)
+}
+
+/// Separating axis test between a triangle and a box: the box axes, the
+/// triangle normal and the cross products of their edges.
+fn triangle_overlaps_box(triangle: [Vector3<f64>; 3], bounds: &Aabb) -> bool {
+    let center = (bounds.min + bounds.max) / 2.0;
+    let half = (bounds.max - bounds.min) / 2.0;
+    let [a, b, c] = triangle.map(|corner| corner - center);
+    let edges = [b - a, c - b, a - c];
+    let separates = |axis: Vector3<f64>| {
+        let projections = [a.dot(axis), b.dot(axis), c.dot(axis)];
+        let min = projections.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = projections
+            .iter()
+            .copied()
+            .fold(f64::NEG_INFINITY, f64::max);
+        let radius = half.x * axis.x.abs() + half.y * axis.y.abs() + half.z * axis.z.abs();
+        min > radius || max < -radius
+    };
+
+    let box_axes = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()];
+    if box_axes.iter().any(|&axis| separates(axis)) {
+        return false;
+    }
+    if separates(edges[0].cross(edges[1])) {
+        return false;
+    }
+    !box_axes
+        .iter()
+        .flat_map(|&axis| edges.map(|edge| axis.cross(edge)))
+        .any(separates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Point3;
+
+    /// A flat grid of `n` by `n` quads in the xy plane, two triangles each.
+    fn grid(n: u32) -> TriangleMesh {
+        let positions = (0..=n)
+            .flat_map(|y| (0..=n).map(move |x| Vector3::new(x as f64, y as f64, 0.0)))
+            .collect();
+        let mut indices = Vec::new();
+        for y in 0..n {
+            for x in 0..n {
+                let corner = y * (n + 1) + x;
+                indices.extend_from_slice(&[corner, corner + 1, corner + n + 2]);
+                indices.extend_from_slice(&[corner, corner + n + 2, corner + n + 1]);
+            }
+        }
+        TriangleMesh::new(positions, indices)
+    }
+
+    #[test]
+    fn rays_find_the_nearest_triangle() {
+        let mesh = grid(16);
+        assert!(mesh.nodes.len() > 1);
+        assert_eq!(mesh.nodes[0].bounds.min, Vector3::new(0.0, 0.0, 0.0));
+        assert_eq!(mesh.nodes[0].bounds.max, Vector3::new(16.0, 16.0, 0.0));
+
+        let ray = Ray::new(Point3::new(3.25, 7.75, 5.0), Vector3::new(0.0, 0.0, -1.0));
+        let hit = mesh.intersect_ray(&ray).unwrap();
+        assert_eq!(hit.distance, 5.0);
+        // The upper left triangle of the quad at (3, 7)
+        assert_eq!(hit.triangle, (7 * 16 + 3) * 2 + 1);
+
+        let slanted = Ray::new(Point3::new(-1.0, -1.0, 1.0), Vector3::new(1.0, 1.0, -1.0));
+        assert!((mesh.intersect_ray(&slanted).unwrap().distance - 3f64.sqrt()).abs() < 1e-9);
+        let away = Ray::new(Point3::new(3.0, 3.0, 1.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(mesh.intersect_ray(&away), None);
+    }
+
+    #[test]
+    fn spheres_and_boxes_find_the_triangles_they_touch() {
+        let mesh = grid(8);
+        let mut touched = mesh.triangles_in_sphere(Vector3::new(4.0, 4.0, 0.5), 0.6);
+        touched.sort_unstable();
+        // The six triangles meeting at the vertex below
+        assert_eq!(touched.len(), 6);
+        assert!(mesh
+            .triangles_in_sphere(Vector3::new(4.0, 4.0, 2.0), 1.0)
+            .is_empty());
+
+        let inside = Aabb::from_points([Vector3::new(0.1, 0.6, -1.0), Vector3::new(0.4, 0.9, 1.0)]);
+        assert_eq!(mesh.triangles_in_box(&inside), vec![1]);
+        let above = Aabb::from_points([Vector3::new(0.0, 0.0, 0.5), Vector3::new(8.0, 8.0, 1.0)]);
+        assert!(mesh.triangles_in_box(&above).is_empty());
+        let everything = mesh.nodes[0].bounds;
+        assert_eq!(mesh.triangles_in_box(&everything).len(), 8 * 8 * 2);
+    }
+}
@@ -1,9 +1,12 @@
 use cgmath::*;
 use std::f32::consts::FRAC_PI_2;
+use std::f64::consts::{FRAC_PI_2 as FRAC_PI_2_F64, PI, TAU};
 use std::time::Duration;
 use winit::dpi::PhysicalPosition;
 use winit::event::*;
 
+use crate::geodesy;
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
@@ -12,17 +15,25 @@ pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
     0.0, 0.0, 0.5, 1.0,
 );
 
+// Only the fly camera's pitch limit, kept with it
+#[allow(dead_code)]
 const SAFE_FRAC_PI_2: f32 = FRAC_PI_2 - 0.0001;
+const SAFE_FRAC_PI_2_F64: f64 = FRAC_PI_2_F64 - 0.0001;
 
+/// A flat first person camera, from before the globe. The viewer orbits
+/// the globe with [`GlobeCamera`] now, but scenes without one can still
+/// use it.
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct Camera {
-    pub position: Point3<f32>,
+    pub position: Point3<f64>,
     yaw: Rad<f32>,
     pitch: Rad<f32>,
 }
 
+#[allow(dead_code)]
 impl Camera {
-    pub fn new<V: Into<Point3<f32>>, Y: Into<Rad<f32>>, P: Into<Rad<f32>>>(
+    pub fn new<V: Into<Point3<f64>>, Y: Into<Rad<f32>>, P: Into<Rad<f32>>>(
         position: V,
         yaw: Y,
         pitch: P,
@@ -34,12 +45,15 @@ impl Camera {
         }
     }
 
+    /// The view matrix of a camera sitting at the origin. World positions are
+    /// rebased against `position` in double precision before they reach the
+    /// GPU, so the translation is left out here.
     pub fn calc_matrix(&self) -> Matrix4<f32> {
         let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
         let (sin_yaw, cos_yaw) = self.yaw.0.sin_cos();
 
         Matrix4::look_to_rh(
-            self.position,
+            Point3::origin(),
             Vector3::new(cos_pitch * cos_yaw, sin_pitch, cos_pitch * sin_yaw).normalize(),
             Vector3::unit_y(),
         )
@@ -51,6 +65,7 @@ pub struct Projection {
     fovy: Rad<f32>,
     znear: f32,
     zfar: f32,
+    reverse_z: bool,
 }
 
 impl Projection {
@@ -60,6 +75,20 @@ impl Projection {
             fovy: fovy.into(),
             znear,
             zfar,
+            reverse_z: false,
+        }
+    }
+
+    /// A projection mapping `znear` to depth 1 and infinity to depth 0.
+    /// Floating point depth is densest near 0, so reversing the range spreads
+    /// the precision evenly enough to cover meters to thousands of kilometers.
+    pub fn new_reverse_z<F: Into<Rad<f32>>>(width: u32, height: u32, fovy: F, znear: f32) -> Self {
+        Self {
+            aspect: width as f32 / height as f32,
+            fovy: fovy.into(),
+            znear,
+            zfar: f32::INFINITY,
+            reverse_z: true,
         }
     }
 
@@ -67,11 +96,65 @@ impl Projection {
         self.aspect = width as f32 / height as f32;
     }
 
+    pub fn fovy(&self) -> Rad<f32> {
+        self.fovy
+    }
+
+    /// Width over height.
+    pub fn aspect(&self) -> f32 {
+        self.aspect
+    }
+
     pub fn calc_matrix(&self) -> Matrix4<f32> {
-        OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        if self.reverse_z {
+            let f = 1.0 / (self.fovy.0 / 2.0).tan();
+            #[rustfmt::skip]
+            let matrix = Matrix4::new(
+                f / self.aspect, 0.0, 0.0, 0.0,
+                0.0, f, 0.0, 0.0,
+                0.0, 0.0, 0.0, -1.0,
+                0.0, 0.0, self.znear, 0.0,
+            );
+            matrix
+        } else {
+            OPENGL_TO_WGPU_MATRIX * perspective(self.fovy, self.aspect, self.znear, self.zfar)
+        }
+    }
+
+    /// The depth test matching this projection: nearer fragments have a
+    /// larger depth with reverse-Z.
+    pub fn depth_compare(&self) -> wgpu::CompareFunction {
+        if self.reverse_z {
+            wgpu::CompareFunction::Greater
+        } else {
+            wgpu::CompareFunction::Less
+        }
+    }
+
+    /// The comparison for sampling the depth texture, which unlike the
+    /// depth test also passes at equal depths.
+    pub fn sampler_compare(&self) -> wgpu::CompareFunction {
+        if self.reverse_z {
+            wgpu::CompareFunction::GreaterEqual
+        } else {
+            wgpu::CompareFunction::LessEqual
+        }
+    }
+
+    /// The depth buffer clear value, i.e. the depth of the far plane.
+    pub fn depth_clear_value(&self) -> f32 {
+        if self.reverse_z {
+            0.0
+        } else {
+            1.0
+        }
     }
 }
 
+/// Flies a [`Camera`] with WASD, space and shift, looking around with the
+/// mouse. Left in for flat scenes, as the globe viewer drives
+/// [`GlobeCameraController`] instead.
+#[allow(dead_code)]
 #[derive(Debug)]
 pub struct CameraController {
     amount_left: f32,
@@ -87,6 +170,7 @@ pub struct CameraController {
     sensitivity: f32,
 }
 
+#[allow(dead_code)]
 impl CameraController {
     pub fn new(speed: f32, sensitivity: f32) -> Self {
         Self {
@@ -159,8 +243,8 @@ impl CameraController {
         let (yaw_sin, yaw_cos) = camera.yaw.0.sin_cos();
         let forward = Vector3::new(yaw_cos, 0.0, yaw_sin).normalize();
         let right = Vector3::new(-yaw_sin, 0.0, yaw_cos).normalize();
-        camera.position += forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
-        camera.position += right * (self.amount_right - self.amount_left) * self.speed * dt;
+        let mut offset = forward * (self.amount_forward - self.amount_backward) * self.speed * dt;
+        offset += right * (self.amount_right - self.amount_left) * self.speed * dt;
 
         // Move in/out (aka. "zoom")
         // Note: this isn't an actual zoom. The camera's position
@@ -169,12 +253,13 @@ impl CameraController {
         let (pitch_sin, pitch_cos) = camera.pitch.0.sin_cos();
         let scrollward =
             Vector3::new(pitch_cos * yaw_cos, pitch_sin, pitch_cos * yaw_sin).normalize();
-        camera.position += scrollward * self.scroll * self.speed * self.sensitivity * dt;
+        offset += scrollward * self.scroll * self.speed * self.sensitivity * dt;
         self.scroll = 0.0;
 
         // Move up/down. Since we don't use roll, we can just
         // modify the y coordinate directly.
-        camera.position.y += (self.amount_up - self.amount_down) * self.speed * dt;
+        offset.y += (self.amount_up - self.amount_down) * self.speed * dt;
+        camera.position += offset.cast().unwrap();
 
         // Rotate
         camera.yaw += Rad(self.rotate_horizontal) * self.sensitivity * dt;
@@ -194,3 +279,330 @@ impl CameraController {
         }
     }
 }
+
+/// A camera orbiting the WGS84 ellipsoid. The position is geodetic and the
+/// orientation is relative to the local east/north/up frame under the camera:
+/// a heading of 0 looks north, a pitch of -90 degrees looks straight down.
+#[derive(Debug)]
+pub struct GlobeCamera {
+    pub latitude: Rad<f64>,
+    pub longitude: Rad<f64>,
+    /// Height above the ellipsoid in meters.
+    pub altitude: f64,
+    pub heading: Rad<f64>,
+    pub pitch: Rad<f64>,
+    pub roll: Rad<f64>,
+}
+
+impl GlobeCamera {
+    pub fn new<LA: Into<Rad<f64>>, LO: Into<Rad<f64>>>(
+        latitude: LA,
+        longitude: LO,
+        altitude: f64,
+    ) -> Self {
+        Self {
+            latitude: latitude.into(),
+            longitude: longitude.into(),
+            altitude,
+            heading: Rad(0.0),
+            pitch: Rad(-FRAC_PI_2_F64),
+            roll: Rad(0.0),
+        }
+    }
+
+    /// Earth-centered, earth-fixed position of the camera.
+    pub fn position(&self) -> Point3<f64> {
+        Point3::from_vec(geodesy::geodetic_to_ecef(
+            self.latitude.0,
+            self.longitude.0,
+            self.altitude,
+        ))
+    }
+
+    /// Returns the camera's forward and up vectors in ECEF.
+    pub fn orientation(&self) -> (Vector3<f64>, Vector3<f64>) {
+        let (east, north, up) = geodesy::enu_axes(self.latitude.0, self.longitude.0);
+
+        let (sin_heading, cos_heading) = self.heading.0.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.0.sin_cos();
+        let (sin_roll, cos_roll) = self.roll.0.sin_cos();
+
+        let level = east * sin_heading + north * cos_heading;
+        let right = east * cos_heading - north * sin_heading;
+        let forward = level * cos_pitch + up * sin_pitch;
+        let camera_up = right.cross(forward);
+
+        (forward, camera_up * cos_roll + right * sin_roll)
+    }
+
+    /// The camera-relative view matrix, see [`Camera::calc_matrix`].
+    pub fn calc_matrix(&self) -> Matrix4<f32> {
+        let (forward, up) = self.orientation();
+        Matrix4::look_to_rh(Point3::origin(), forward, up)
+            .cast()
+            .unwrap()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum GlobeDrag {
+    Rotate,
+    Tilt,
+}
+
+#[derive(Debug)]
+pub struct GlobeCameraController {
+    drag: Option<GlobeDrag>,
+    rotate_horizontal: f64,
+    rotate_vertical: f64,
+    tilt_horizontal: f64,
+    tilt_vertical: f64,
+    amount_tilt_up: f64,
+    amount_tilt_down: f64,
+    amount_turn_left: f64,
+    amount_turn_right: f64,
+    scroll: f64,
+    reset_north: bool,
+    sensitivity: f64,
+    zoom_speed: f64,
+    pub min_altitude: f64,
+    pub max_altitude: f64,
+}
+
+impl GlobeCameraController {
+    /// `sensitivity` is the fraction of the camera altitude the globe moves
+    /// per dragged pixel, `zoom_speed` the relative altitude change per
+    /// scrolled line.
+    pub fn new(sensitivity: f64, zoom_speed: f64) -> Self {
+        Self {
+            drag: None,
+            rotate_horizontal: 0.0,
+            rotate_vertical: 0.0,
+            tilt_horizontal: 0.0,
+            tilt_vertical: 0.0,
+            amount_tilt_up: 0.0,
+            amount_tilt_down: 0.0,
+            amount_turn_left: 0.0,
+            amount_turn_right: 0.0,
+            scroll: 0.0,
+            reset_north: false,
+            sensitivity,
+            zoom_speed,
+            min_altitude: 10.0,
+            max_altitude: 5.0e7,
+        }
+    }
+
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        let amount = if state == ElementState::Pressed {
+            1.0
+        } else {
+            0.0
+        };
+        match key {
+            VirtualKeyCode::W | VirtualKeyCode::Up => {
+                self.amount_tilt_up = amount;
+                true
+            }
+            VirtualKeyCode::S | VirtualKeyCode::Down => {
+                self.amount_tilt_down = amount;
+                true
+            }
+            VirtualKeyCode::A | VirtualKeyCode::Left => {
+                self.amount_turn_left = amount;
+                true
+            }
+            VirtualKeyCode::D | VirtualKeyCode::Right => {
+                self.amount_turn_right = amount;
+                true
+            }
+            VirtualKeyCode::N => {
+                if state == ElementState::Pressed {
+                    self.reset_north = true;
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// The left button drags the globe, the right and middle buttons tilt
+    /// and turn the camera. Returns whether a drag is in progress.
+    pub fn process_mouse_button(&mut self, button: MouseButton, state: ElementState) -> bool {
+        let drag = match button {
+            MouseButton::Left => GlobeDrag::Rotate,
+            MouseButton::Right | MouseButton::Middle => GlobeDrag::Tilt,
+            MouseButton::Other(_) => return self.drag.is_some(),
+        };
+        if state == ElementState::Pressed {
+            self.drag = Some(drag);
+        } else if self.drag == Some(drag) {
+            self.drag = None;
+        }
+        self.drag.is_some()
+    }
+
+    pub fn process_mouse(&mut self, mouse_dx: f64, mouse_dy: f64) {
+        match self.drag {
+            Some(GlobeDrag::Rotate) => {
+                self.rotate_horizontal += mouse_dx;
+                self.rotate_vertical += mouse_dy;
+            }
+            Some(GlobeDrag::Tilt) => {
+                self.tilt_horizontal += mouse_dx;
+                self.tilt_vertical += mouse_dy;
+            }
+            None => {}
+        }
+    }
+
+    pub fn process_scroll(&mut self, delta: &MouseScrollDelta) {
+        self.scroll += match delta {
+            MouseScrollDelta::LineDelta(_, scroll) => *scroll as f64,
+            // Same assumption as the fly camera: a line is about 100 pixels
+            MouseScrollDelta::PixelDelta(PhysicalPosition { y: scroll, .. }) => *scroll / 100.0,
+        };
+    }
+
+    pub fn update_camera(&mut self, camera: &mut GlobeCamera, dt: Duration) {
+        let dt = dt.as_secs_f64();
+
+        // Drag the globe. Dragging down pulls the ground towards the viewer,
+        // so the camera moves along its heading; the distance covered per
+        // pixel grows with altitude so the ground roughly follows the cursor.
+        let meters_per_pixel = camera.altitude * self.sensitivity;
+        let forward = self.rotate_vertical * meters_per_pixel;
+        let right = -self.rotate_horizontal * meters_per_pixel;
+        let (sin_heading, cos_heading) = camera.heading.0.sin_cos();
+        let north = forward * cos_heading - right * sin_heading;
+        let east = forward * sin_heading + right * cos_heading;
+        camera.latitude.0 = (camera.latitude.0 + north / geodesy::WGS84_A)
+            .clamp(-SAFE_FRAC_PI_2_F64, SAFE_FRAC_PI_2_F64);
+        camera.longitude.0 += east / (geodesy::WGS84_A * camera.latitude.0.cos());
+        camera.longitude.0 = (camera.longitude.0 + PI).rem_euclid(TAU) - PI;
+        self.rotate_horizontal = 0.0;
+        self.rotate_vertical = 0.0;
+
+        // Zoom. Scaling the altitude rather than moving a fixed distance
+        // makes the camera slow down as it approaches the surface.
+        camera.altitude = (camera.altitude * (-self.scroll * self.zoom_speed).exp())
+            .clamp(self.min_altitude, self.max_altitude);
+        self.scroll = 0.0;
+
+        // Tilt and turn
+        const TILT_RADIANS_PER_PIXEL: f64 = 0.005;
+        const TILT_RADIANS_PER_SECOND: f64 = 1.0;
+        camera.heading.0 += self.tilt_horizontal * TILT_RADIANS_PER_PIXEL
+            + (self.amount_turn_right - self.amount_turn_left) * TILT_RADIANS_PER_SECOND * dt;
+        camera.pitch.0 -= self.tilt_vertical * TILT_RADIANS_PER_PIXEL
+            - (self.amount_tilt_up - self.amount_tilt_down) * TILT_RADIANS_PER_SECOND * dt;
+        self.tilt_horizontal = 0.0;
+        self.tilt_vertical = 0.0;
+
+        if self.reset_north {
+            camera.heading = Rad(0.0);
+            camera.roll = Rad(0.0);
+            self.reset_north = false;
+        }
+
+        camera.heading.0 = camera.heading.0.rem_euclid(TAU);
+        // Looking above the horizon only shows empty space.
+        camera.pitch.0 = camera.pitch.0.clamp(-FRAC_PI_2_F64, 0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: Vector3<f64>, b: Vector3<f64>) {
+        assert!((a - b).magnitude() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn reverse_z_puts_the_near_plane_at_one() {
+        let projection = Projection::new_reverse_z(800, 600, Deg(45.0), 1.0);
+        let depth = |distance: f32| {
+            let clip = projection.calc_matrix() * Vector4::new(0.0, 0.0, -distance, 1.0);
+            clip.z / clip.w
+        };
+        assert_eq!(depth(1.0), 1.0);
+        assert!(depth(1.0e7) > 0.0 && depth(1.0e7) < 1.0e-6);
+        // Nearer is deeper, so it must win the depth test
+        assert!(depth(10.0) > depth(20.0));
+        assert_eq!(projection.depth_compare(), wgpu::CompareFunction::Greater);
+        assert_eq!(projection.depth_clear_value(), 0.0);
+
+        // The forward range runs the other way, with the far plane at one
+        let forward = Projection::new(800, 600, Deg(45.0), 1.0, 1000.0);
+        let depth = |distance: f32| {
+            let clip = forward.calc_matrix() * Vector4::new(0.0, 0.0, -distance, 1.0);
+            clip.z / clip.w
+        };
+        assert!(depth(1.0).abs() < 1e-6);
+        assert!((depth(1000.0) - 1.0).abs() < 1e-6);
+        assert!(depth(10.0) < depth(20.0));
+        assert_eq!(forward.depth_compare(), wgpu::CompareFunction::Less);
+        assert_eq!(forward.sampler_compare(), wgpu::CompareFunction::LessEqual);
+        assert_eq!(forward.depth_clear_value(), 1.0);
+    }
+
+    #[test]
+    fn orientation_follows_the_local_frame() {
+        // Looking straight down over 0°, 0° with north at the top
+        let mut camera = GlobeCamera::new(Deg(0.0), Deg(0.0), 1000.0);
+        let (forward, up) = camera.orientation();
+        assert_near(forward, -Vector3::unit_x());
+        assert_near(up, Vector3::unit_z());
+        assert!((camera.position().x - geodesy::WGS84_A - 1000.0).abs() < 1e-6);
+
+        // Level and heading east, with the sky above
+        camera.heading = Deg(90.0).into();
+        camera.pitch = Rad(0.0);
+        let (forward, up) = camera.orientation();
+        assert_near(forward, Vector3::unit_y());
+        assert_near(up, Vector3::unit_x());
+
+        // Over the north pole, up is +Z
+        let camera = GlobeCamera::new(Deg(90.0), Deg(0.0), 0.0);
+        assert_near(camera.orientation().0, -Vector3::unit_z());
+    }
+
+    #[test]
+    fn controller_clamps_and_resets_north() {
+        let mut camera = GlobeCamera::new(Deg(45.0), Deg(7.0), 1.0e6);
+        let mut controller = GlobeCameraController::new(0.001, 0.5);
+        let dt = Duration::from_millis(16);
+
+        // Zooming far in or out stops at the altitude limits
+        controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, 100.0));
+        controller.update_camera(&mut camera, dt);
+        assert_eq!(camera.altitude, controller.min_altitude);
+        controller.process_scroll(&MouseScrollDelta::LineDelta(0.0, -100.0));
+        controller.update_camera(&mut camera, dt);
+        assert_eq!(camera.altitude, controller.max_altitude);
+
+        // Tilting stops at the horizon and straight down
+        controller.process_mouse_button(MouseButton::Right, ElementState::Pressed);
+        controller.process_mouse(0.0, -10_000.0);
+        controller.update_camera(&mut camera, dt);
+        assert_eq!(camera.pitch, Rad(0.0));
+        controller.process_mouse(0.0, 10_000.0);
+        controller.update_camera(&mut camera, dt);
+        assert_eq!(camera.pitch, Rad(-FRAC_PI_2_F64));
+
+        // Turning wraps the heading, and N brings north back up
+        controller.process_mouse(-100.0, 0.0);
+        controller.update_camera(&mut camera, dt);
+        assert!((camera.heading.0 - (TAU - 0.5)).abs() < 1e-9);
+        camera.roll = Rad(0.2);
+        assert!(controller.process_keyboard(VirtualKeyCode::N, ElementState::Pressed));
+        controller.update_camera(&mut camera, dt);
+        assert_eq!(camera.heading, Rad(0.0));
+        assert_eq!(camera.roll, Rad(0.0));
+        // Only once per press
+        camera.heading = Rad(1.0);
+        controller.update_camera(&mut camera, dt);
+        assert_eq!(camera.heading, Rad(1.0));
+    }
+}
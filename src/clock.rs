@@ -0,0 +1,293 @@
+use std::fmt;
+use std::time::Duration;
+use winit::event::{ElementState, VirtualKeyCode};
+
+/// Seconds in a day, leap seconds aside.
+const SECONDS_PER_DAY: f64 = 86_400.0;
+/// The Julian date of the Unix epoch.
+const UNIX_EPOCH_JULIAN_DATE: f64 = 2_440_587.5;
+/// How much faster or slower each press of `]` or `[` runs the clock.
+const MULTIPLIER_STEP: f64 = 10.0;
+
+/// An instant in UTC, as seconds since 1970-01-01T00:00:00Z. Leap seconds
+/// are ignored, as in Unix time.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub struct UtcTime {
+    pub unix_seconds: f64,
+}
+
+impl UtcTime {
+    pub fn from_unix_seconds(unix_seconds: f64) -> Self {
+        Self { unix_seconds }
+    }
+
+    /// The time on the system clock.
+    pub fn now() -> Self {
+        let since_epoch = instant::SystemTime::now()
+            .duration_since(instant::SystemTime::UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .unwrap_or(0.0);
+        Self::from_unix_seconds(since_epoch)
+    }
+
+    /// A date in the proleptic Gregorian calendar, months and days counting
+    /// from 1.
+    pub fn from_calendar(
+        year: i64,
+        month: u32,
+        day: u32,
+        hour: u32,
+        minute: u32,
+        second: f64,
+    ) -> Self {
+        let days = days_from_civil(year, month, day);
+        Self::from_unix_seconds(
+            days as f64 * SECONDS_PER_DAY + hour as f64 * 3600.0 + minute as f64 * 60.0 + second,
+        )
+    }
+
+    /// Parses an ISO 8601 date and time in UTC, like `2024-06-20T20:51:00Z`.
+    /// The time may be left out, and so may the seconds, their fraction and
+    /// the `Z`.
+    pub fn parse(text: &str) -> anyhow::Result<Self> {
+        let invalid = || anyhow::anyhow!("Not an ISO 8601 UTC time: {:?}", text);
+        let text = text.trim();
+        let text = text.strip_suffix('Z').unwrap_or(text);
+        let (date, time) = match text.split_once(['T', ' ']) {
+            Some((date, time)) => (date, time),
+            None => (text, "00:00"),
+        };
+
+        // Years may be negative, so split the month and day off the end
+        let mut date_parts = date.rsplitn(3, '-');
+        let day = date_parts.next().ok_or_else(invalid)?.parse::<u32>()?;
+        let month = date_parts.next().ok_or_else(invalid)?.parse::<u32>()?;
+        let year = date_parts.next().ok_or_else(invalid)?.parse::<i64>()?;
+
+        let mut time_parts = time.split(':');
+        let hour = time_parts.next().ok_or_else(invalid)?.parse::<u32>()?;
+        let minute = time_parts.next().ok_or_else(invalid)?.parse::<u32>()?;
+        let second = match time_parts.next() {
+            Some(second) => second.parse::<f64>()?,
+            None => 0.0,
+        };
+        if time_parts.next().is_some()
+            || !(1..=12).contains(&month)
+            || !(1..=31).contains(&day)
+            || hour > 23
+            || minute > 59
+            || !(0.0..61.0).contains(&second)
+        {
+            return Err(invalid());
+        }
+        Ok(Self::from_calendar(year, month, day, hour, minute, second))
+    }
+
+    pub fn julian_date(&self) -> f64 {
+        self.unix_seconds / SECONDS_PER_DAY + UNIX_EPOCH_JULIAN_DATE
+    }
+
+    /// Days since the J2000.0 epoch, 2000-01-01T12:00:00 in terrestrial
+    /// time, here taken as UTC.
+    pub fn days_since_j2000(&self) -> f64 {
+        self.julian_date() - 2_451_545.0
+    }
+
+    /// The calendar date and time of day.
+    pub fn to_calendar(self) -> (i64, u32, u32, u32, u32, f64) {
+        let days = (self.unix_seconds / SECONDS_PER_DAY).floor();
+        let seconds = self.unix_seconds - days * SECONDS_PER_DAY;
+        let (year, month, day) = civil_from_days(days as i64);
+        let hour = (seconds / 3600.0).floor();
+        let minute = ((seconds - hour * 3600.0) / 60.0).floor();
+        let second = seconds - hour * 3600.0 - minute * 60.0;
+        (year, month, day, hour as u32, minute as u32, second)
+    }
+}
+
+impl fmt::Display for UtcTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (year, month, day, hour, minute, second) = self.to_calendar();
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second.floor() as u32
+        )
+    }
+}
+
+/// Days from 1970-01-01 to a date, after Howard Hinnant's algorithm.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The inverse of `days_from_civil`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// The simulated time, which drives the sun. It runs at `multiplier`
+/// simulated seconds per real second, backwards if negative, unless paused.
+///
+/// Space pauses and resumes it, `]` and `[` speed it up and slow it down
+/// tenfold, `-` turns it around and backspace returns it to the present at
+/// normal speed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Clock {
+    pub time: UtcTime,
+    pub multiplier: f64,
+    pub paused: bool,
+}
+
+impl Clock {
+    pub fn new(time: UtcTime) -> Self {
+        Self {
+            time,
+            multiplier: 1.0,
+            paused: false,
+        }
+    }
+
+    pub fn play(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn set_time(&mut self, time: UtcTime) {
+        self.time = time;
+    }
+
+    pub fn set_multiplier(&mut self, multiplier: f64) {
+        self.multiplier = multiplier;
+    }
+
+    /// Moves the clock on by `dt` of real time.
+    pub fn tick(&mut self, dt: Duration) {
+        if !self.paused {
+            self.time.unix_seconds += dt.as_secs_f64() * self.multiplier;
+        }
+    }
+
+    /// Returns whether the key controls the clock.
+    pub fn process_keyboard(&mut self, key: VirtualKeyCode, state: ElementState) -> bool {
+        let handled = matches!(
+            key,
+            VirtualKeyCode::Space
+                | VirtualKeyCode::RBracket
+                | VirtualKeyCode::LBracket
+                | VirtualKeyCode::Minus
+                | VirtualKeyCode::Back
+        );
+        if !handled || state != ElementState::Pressed {
+            return handled;
+        }
+        match key {
+            VirtualKeyCode::Space if self.paused => self.play(),
+            VirtualKeyCode::Space => self.pause(),
+            VirtualKeyCode::RBracket => self.multiplier *= MULTIPLIER_STEP,
+            VirtualKeyCode::LBracket => self.multiplier /= MULTIPLIER_STEP,
+            VirtualKeyCode::Minus => self.multiplier = -self.multiplier,
+            _ => {
+                self.set_time(UtcTime::now());
+                self.set_multiplier(1.0);
+            }
+        }
+        log::info!("{}", self);
+        true
+    }
+}
+
+impl fmt::Display for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}x", self.time, self.multiplier)?;
+        if self.paused {
+            write!(f, ", paused")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dates_convert_both_ways() {
+        let time = UtcTime::parse("2024-06-20T20:51:30Z").unwrap();
+        assert_eq!(time.unix_seconds, 1_718_916_690.0);
+        assert_eq!(time.to_string(), "2024-06-20T20:51:30Z");
+        assert_eq!(
+            UtcTime::parse("2000-01-01T12:00").unwrap().julian_date(),
+            2_451_545.0
+        );
+        assert_eq!(UtcTime::parse("1970-01-01").unwrap().unix_seconds, 0.0);
+        assert_eq!(
+            UtcTime::parse("2024-02-29 06:30:15.5")
+                .unwrap()
+                .to_calendar(),
+            (2024, 2, 29, 6, 30, 15.5)
+        );
+        assert_eq!(
+            UtcTime::from_unix_seconds(-1.0).to_string(),
+            "1969-12-31T23:59:59Z"
+        );
+        assert!(UtcTime::parse("2024-13-01").is_err());
+        assert!(UtcTime::parse("yesterday").is_err());
+
+        // Every day across a few centuries round-trips
+        for days in (-100_000..100_000).step_by(97) {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn the_clock_runs_at_its_multiplier() {
+        let start = UtcTime::from_unix_seconds(1000.0);
+        let mut clock = Clock::new(start);
+        clock.tick(Duration::from_secs(2));
+        assert_eq!(clock.time.unix_seconds, 1002.0);
+
+        assert!(clock.process_keyboard(VirtualKeyCode::RBracket, ElementState::Pressed));
+        assert!(clock.process_keyboard(VirtualKeyCode::RBracket, ElementState::Released));
+        assert!(clock.process_keyboard(VirtualKeyCode::Minus, ElementState::Pressed));
+        clock.tick(Duration::from_secs(1));
+        assert_eq!(clock.time.unix_seconds, 992.0);
+
+        clock.pause();
+        clock.tick(Duration::from_secs(1));
+        assert_eq!(clock.time.unix_seconds, 992.0);
+        assert!(clock.process_keyboard(VirtualKeyCode::Space, ElementState::Pressed));
+        assert!(!clock.paused);
+        assert!(!clock.process_keyboard(VirtualKeyCode::W, ElementState::Pressed));
+    }
+}
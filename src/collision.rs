@@ -0,0 +1,120 @@
+use cgmath::{Matrix4, Vector3};
+
+/// Grid cells are this many pixels across.
+const CELL_SIZE: f32 = 64.0;
+
+/// A rectangle on screen in pixels: left, top, right and bottom, y down.
+pub type ScreenRect = [f32; 4];
+
+fn overlaps(a: &ScreenRect, b: &ScreenRect) -> bool {
+    a[0] < b[2] && b[0] < a[2] && a[1] < b[3] && b[1] < a[3]
+}
+
+/// Where a camera-relative position lands on a screen of `size` pixels, or
+/// `None` behind the camera.
+pub fn project(
+    view_proj: Matrix4<f32>,
+    position: Vector3<f32>,
+    size: [f32; 2],
+) -> Option<[f32; 2]> {
+    let clip = view_proj * position.extend(1.0);
+    if clip.w <= 0.0 {
+        return None;
+    }
+    Some([
+        (clip.x / clip.w + 1.0) / 2.0 * size[0],
+        (1.0 - clip.y / clip.w) / 2.0 * size[1],
+    ])
+}
+
+/// The rectangles taken on screen so far in a frame, for labels and markers
+/// to avoid each other. Rectangles are filed under the grid cells they cover,
+/// so only nearby ones are compared.
+pub struct CollisionGrid {
+    size: [f32; 2],
+    columns: usize,
+    rows: usize,
+    cells: Vec<Vec<u32>>,
+    rects: Vec<ScreenRect>,
+}
+
+impl CollisionGrid {
+    pub fn new(width: u32, height: u32) -> Self {
+        let columns = (width as f32 / CELL_SIZE).ceil().max(1.0) as usize;
+        let rows = (height as f32 / CELL_SIZE).ceil().max(1.0) as usize;
+        Self {
+            size: [width as f32, height as f32],
+            columns,
+            rows,
+            cells: vec![Vec::new(); columns * rows],
+            rects: Vec::new(),
+        }
+    }
+
+    /// The screen size in pixels.
+    pub fn size(&self) -> [f32; 2] {
+        self.size
+    }
+
+    /// The cells `rect` covers, or none when it's off screen.
+    fn cells(&self, rect: &ScreenRect) -> impl Iterator<Item = usize> {
+        let on_screen =
+            rect[2] >= 0.0 && rect[3] >= 0.0 && rect[0] < self.size[0] && rect[1] < self.size[1];
+        let cell =
+            |pixels: f32, count: usize| ((pixels / CELL_SIZE).max(0.0) as usize).min(count - 1);
+        let (left, right) = (cell(rect[0], self.columns), cell(rect[2], self.columns));
+        let (top, bottom) = (cell(rect[1], self.rows), cell(rect[3], self.rows));
+        let columns = self.columns;
+        (top..=bottom)
+            .flat_map(move |row| (left..=right).map(move |column| row * columns + column))
+            .filter(move |_| on_screen)
+    }
+
+    /// Whether `rect` overlaps none of the rectangles taken.
+    pub fn is_free(&self, rect: &ScreenRect) -> bool {
+        self.cells(rect).all(|cell| {
+            self.cells[cell]
+                .iter()
+                .all(|&i| !overlaps(rect, &self.rects[i as usize]))
+        })
+    }
+
+    /// Takes `rect` whether or not it overlaps others.
+    pub fn insert(&mut self, rect: ScreenRect) {
+        let i = self.rects.len() as u32;
+        for cell in self.cells(&rect).collect::<Vec<_>>() {
+            self.cells[cell].push(i);
+        }
+        self.rects.push(rect);
+    }
+
+    /// Takes `rect` if it's free, returning whether it was.
+    pub fn insert_if_free(&mut self, rect: ScreenRect) -> bool {
+        let free = self.is_free(&rect);
+        if free {
+            self.insert(rect);
+        }
+        free
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rectangles_collide_across_cells() {
+        let mut grid = CollisionGrid::new(300, 200);
+        assert!(grid.insert_if_free([50.0, 50.0, 150.0, 70.0]));
+        // Overlapping in a cell the first one spans into
+        assert!(!grid.insert_if_free([140.0, 60.0, 200.0, 80.0]));
+        // Touching edges don't overlap
+        assert!(grid.insert_if_free([150.0, 50.0, 200.0, 70.0]));
+        // Nothing collides off screen, and rectangles past the edge are
+        // filed under the edge cells
+        assert!(grid.insert_if_free([-100.0, -100.0, -10.0, -10.0]));
+        assert!(grid.insert_if_free([-100.0, -100.0, -10.0, -10.0]));
+        assert!(grid.insert_if_free([280.0, 180.0, 400.0, 400.0]));
+        assert!(!grid.is_free(&[290.0, 190.0, 500.0, 500.0]));
+    }
+}
@@ -0,0 +1,745 @@
+use anyhow::{anyhow, bail};
+use cgmath::prelude::*;
+use cgmath::{Point3, Quaternion, Rad, Vector3};
+use serde_json::Value;
+use std::ops::Range;
+
+use crate::clock::UtcTime;
+use crate::geodesy::{self, Geodetic};
+use crate::model;
+use crate::polyline::{self, DrawPolylines, LineStyle, Polyline, Polylines};
+
+/// How far apart in seconds paths are sampled between the samples of the
+/// trajectory, as CZML's `path.resolution`.
+const DEFAULT_PATH_RESOLUTION: f64 = 60.0;
+
+/// How positions are interpolated between samples, as CZML's
+/// `interpolationAlgorithm`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    Linear,
+    /// A polynomial through the `degree + 1` samples around the time.
+    Lagrange,
+    /// A cubic between the two samples around the time, matching their
+    /// velocities, which are estimated from the neighbouring samples when
+    /// not given.
+    Hermite,
+}
+
+/// Positions in ECEF meters at times in Unix seconds, sorted by time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampledPosition {
+    pub times: Vec<f64>,
+    pub positions: Vec<Vector3<f64>>,
+    /// Meters per second at each sample, for Hermite interpolation.
+    pub velocities: Option<Vec<Vector3<f64>>>,
+    pub interpolation: Interpolation,
+    pub degree: usize,
+}
+
+impl SampledPosition {
+    /// A position that never changes.
+    pub fn constant(position: Vector3<f64>) -> Self {
+        Self {
+            times: vec![f64::NEG_INFINITY],
+            positions: vec![position],
+            velocities: None,
+            interpolation: Interpolation::Linear,
+            degree: 1,
+        }
+    }
+
+    /// The first and last sample times.
+    pub fn interval(&self) -> (f64, f64) {
+        (self.times[0], *self.times.last().unwrap())
+    }
+
+    /// The position at `time`, or `None` outside the samples.
+    pub fn at(&self, time: f64) -> Option<Vector3<f64>> {
+        if self.times.len() == 1 {
+            return Some(self.positions[0]);
+        }
+        let (start, end) = self.interval();
+        if !(start..=end).contains(&time) {
+            return None;
+        }
+        // The sample at or before the time, short of the last one
+        let i = self
+            .times
+            .partition_point(|&t| t <= time)
+            .clamp(1, self.times.len() - 1)
+            - 1;
+        Some(match self.interpolation {
+            Interpolation::Linear => {
+                let s = (time - self.times[i]) / (self.times[i + 1] - self.times[i]);
+                self.positions[i].lerp(self.positions[i + 1], s)
+            }
+            Interpolation::Lagrange => self.lagrange(i, time),
+            Interpolation::Hermite => self.hermite(i, time),
+        })
+    }
+
+    fn lagrange(&self, i: usize, time: f64) -> Vector3<f64> {
+        let count = (self.degree + 1).clamp(2, self.times.len());
+        let first = (i + 1)
+            .saturating_sub(count / 2)
+            .min(self.times.len() - count);
+        let window = first..first + count;
+        window
+            .clone()
+            .map(|j| {
+                let weight: f64 = window
+                    .clone()
+                    .filter(|&k| k != j)
+                    .map(|k| (time - self.times[k]) / (self.times[j] - self.times[k]))
+                    .product();
+                self.positions[j] * weight
+            })
+            .sum()
+    }
+
+    fn hermite(&self, i: usize, time: f64) -> Vector3<f64> {
+        let h = self.times[i + 1] - self.times[i];
+        let s = (time - self.times[i]) / h;
+        let (s2, s3) = (s * s, s * s * s);
+        let (p0, p1) = (self.positions[i], self.positions[i + 1]);
+        let (v0, v1) = (self.velocity(i), self.velocity(i + 1));
+        p0 * (2.0 * s3 - 3.0 * s2 + 1.0)
+            + v0 * (h * (s3 - 2.0 * s2 + s))
+            + p1 * (-2.0 * s3 + 3.0 * s2)
+            + v1 * (h * (s3 - s2))
+    }
+
+    /// The given velocity at sample `i`, or one from its neighbours.
+    fn velocity(&self, i: usize) -> Vector3<f64> {
+        if let Some(velocities) = &self.velocities {
+            return velocities[i];
+        }
+        let before = i.saturating_sub(1);
+        let after = (i + 1).min(self.times.len() - 1);
+        (self.positions[after] - self.positions[before]) / (self.times[after] - self.times[before])
+    }
+}
+
+/// Rotations from model axes to ECEF at times in Unix seconds, sorted by
+/// time. They're interpolated along the shortest arc between samples.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SampledOrientation {
+    pub times: Vec<f64>,
+    pub rotations: Vec<Quaternion<f64>>,
+}
+
+impl SampledOrientation {
+    /// The rotation at `time`, held at the first and last samples beyond
+    /// them.
+    pub fn at(&self, time: f64) -> Quaternion<f64> {
+        let n = self.times.len();
+        let i = self.times.partition_point(|&t| t <= time);
+        if i == 0 {
+            return self.rotations[0];
+        }
+        if i == n {
+            return self.rotations[n - 1];
+        }
+        let s = (time - self.times[i - 1]) / (self.times[i] - self.times[i - 1]);
+        let (q0, mut q1) = (self.rotations[i - 1], self.rotations[i]);
+        if q0.dot(q1) < 0.0 {
+            q1 = -q1;
+        }
+        q0.slerp(q1, s)
+    }
+}
+
+/// How an entity's path is drawn, over the time around the current one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PathStyle {
+    /// Seconds of trajectory behind the entity.
+    pub trail_time: f64,
+    /// Seconds of trajectory ahead of it.
+    pub lead_time: f64,
+    /// Seconds between path points between the samples.
+    pub resolution: f64,
+    pub line: LineStyle,
+}
+
+/// Something moving along sampled positions, like a vehicle or a ship.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entity {
+    pub id: String,
+    pub name: Option<String>,
+    pub position: SampledPosition,
+    /// Without one, entities face the way they're moving.
+    pub orientation: Option<SampledOrientation>,
+    pub path: Option<PathStyle>,
+    /// Uniform scale from model units to meters.
+    pub scale: f32,
+    /// The model is scaled up to at least this many pixels across.
+    pub minimum_pixel_size: f32,
+}
+
+impl Entity {
+    pub fn position_at(&self, time: UtcTime) -> Option<Vector3<f64>> {
+        self.position.at(time.unix_seconds)
+    }
+
+    /// The model's rotation to ECEF at `time`, for Y-up models facing -Z as
+    /// in [`model::Instance::from_geodetic`] when it has to face the way it's
+    /// moving.
+    pub fn rotation_at(&self, time: UtcTime) -> Quaternion<f64> {
+        if let Some(orientation) = &self.orientation {
+            return orientation.at(time.unix_seconds);
+        }
+        let t = time.unix_seconds;
+        let (start, end) = self.position.interval();
+        // A second's travel around the time, kept inside the samples
+        let (before, after) = ((t - 0.5).max(start), (t + 0.5).min(end));
+        let position = self.position.at(t).unwrap_or_else(Vector3::zero);
+        let geodetic = Geodetic::from_ecef(position);
+        let velocity = match (self.position.at(before), self.position.at(after)) {
+            (Some(a), Some(b)) if after > before => b - a,
+            _ => Vector3::zero(),
+        };
+        let (east, north, up) = geodesy::enu_axes(geodetic.latitude, geodetic.longitude);
+        let (v_east, v_north, v_up) = (velocity.dot(east), velocity.dot(north), velocity.dot(up));
+        let heading = Rad(v_east.atan2(v_north));
+        let pitch = Rad(v_up.atan2(v_east.hypot(v_north)));
+        let instance = model::Instance::from_geodetic(&geodetic, heading, pitch, Rad(0.0), 1.0);
+        instance.rotation.cast().unwrap()
+    }
+
+    /// The times of the points the path runs through, sorted: every sample,
+    /// with points `resolution` apart from the first between them.
+    pub fn path_times(&self) -> Vec<f64> {
+        let resolution = match self.path {
+            Some(style) => style.resolution.max(1e-3),
+            None => return Vec::new(),
+        };
+        let mut times = self.position.times.clone();
+        let (first, last) = self.position.interval();
+        if first.is_finite() && last > first {
+            let steps = ((last - first) / resolution).ceil() as usize;
+            times.extend((0..=steps).map(|i| (first + i as f64 * resolution).min(last)));
+        }
+        times.sort_by(f64::total_cmp);
+        times.dedup();
+        times
+    }
+
+    /// The start of the trail and the end of the lead around `time`, kept
+    /// inside the samples.
+    fn path_span(&self, time: UtcTime) -> Option<(f64, f64)> {
+        let style = self.path?;
+        let (first, last) = self.position.interval();
+        let start = (time.unix_seconds - style.trail_time).max(first);
+        let end = (time.unix_seconds + style.lead_time).min(last);
+        (start < end).then_some((start, end))
+    }
+
+    /// Which of the `times` from [`Entity::path_times`] lie strictly inside
+    /// the path around `time`. It only changes as the ends of the path pass
+    /// one of them.
+    pub fn path_window(&self, times: &[f64], time: UtcTime) -> Option<Range<usize>> {
+        let (start, end) = self.path_span(time)?;
+        Some(times.partition_point(|&t| t <= start)..times.partition_point(|&t| t < end))
+    }
+
+    /// The path around `time` through the positions at the times in its
+    /// window, from exactly the start of the trail to the end of the lead.
+    pub fn path_through(
+        &self,
+        interior: &[Vector3<f64>],
+        time: UtcTime,
+    ) -> Option<Vec<Vector3<f64>>> {
+        let (start, end) = self.path_span(time)?;
+        let mut points = vec![self.position.at(start)?];
+        points.extend_from_slice(interior);
+        points.push(self.position.at(end)?);
+        Some(points)
+    }
+}
+
+/// Reads the entities of a CZML document: an array of packets, the first of
+/// which may describe the document itself. Packets need an `id` and a
+/// `position`, given as `cartographicDegrees`, `cartographicRadians`,
+/// `cartesian` or `cartesianVelocity`, either constant or as samples with
+/// times in seconds from an `epoch` or as ISO 8601 strings. They may also
+/// have a `name`, an `orientation` as `unitQuaternion`s, a `path` and a
+/// `model` with a `scale` and `minimumPixelSize`. Other properties, and
+/// interval lists, are ignored.
+pub fn parse_czml(text: &str) -> anyhow::Result<Vec<Entity>> {
+    let document: Value = serde_json::from_str(text)?;
+    let packets = document
+        .as_array()
+        .ok_or_else(|| anyhow!("a CZML document that isn't an array of packets"))?;
+    packets
+        .iter()
+        .filter(|packet| packet["id"] != "document" && packet.get("position").is_some())
+        .map(|packet| {
+            let id = packet["id"]
+                .as_str()
+                .ok_or_else(|| anyhow!("a packet without an id"))?;
+            parse_entity(id, packet).map_err(|e| e.context(format!("in packet {}", id)))
+        })
+        .collect()
+}
+
+fn parse_entity(id: &str, packet: &Value) -> anyhow::Result<Entity> {
+    let model = &packet["model"];
+    Ok(Entity {
+        id: id.to_string(),
+        name: packet["name"].as_str().map(str::to_string),
+        position: parse_position(&packet["position"])?,
+        orientation: match packet.get("orientation") {
+            Some(orientation) => Some(parse_orientation(orientation)?),
+            None => None,
+        },
+        path: match packet.get("path") {
+            Some(path) if path["show"] != false => Some(parse_path(path)?),
+            _ => None,
+        },
+        scale: model["scale"].as_f64().unwrap_or(1.0) as f32,
+        minimum_pixel_size: model["minimumPixelSize"].as_f64().unwrap_or(0.0) as f32,
+    })
+}
+
+/// Splits a property's values into samples of `width` numbers, with their
+/// times in Unix seconds, or a single sample without one when it's constant.
+fn parse_samples(
+    property: &Value,
+    key: &str,
+    width: usize,
+) -> anyhow::Result<Vec<(f64, Vec<f64>)>> {
+    let values = property[key]
+        .as_array()
+        .ok_or_else(|| anyhow!("{} isn't an array", key))?;
+    if values.len() == width {
+        let value = values.iter().map(number).collect::<anyhow::Result<_>>()?;
+        return Ok(vec![(f64::NEG_INFINITY, value)]);
+    }
+    if values.is_empty() || values.len() % (width + 1) != 0 {
+        bail!(
+            "{} has {} values, not samples of {}",
+            key,
+            values.len(),
+            width
+        );
+    }
+    let epoch = match property["epoch"].as_str() {
+        Some(epoch) => Some(UtcTime::parse(epoch)?),
+        None => None,
+    };
+    let mut samples = values
+        .chunks(width + 1)
+        .map(|sample| {
+            let time = match &sample[0] {
+                Value::String(time) => UtcTime::parse(time)?.unix_seconds,
+                time => {
+                    let epoch = epoch.ok_or_else(|| anyhow!("sample times without an epoch"))?;
+                    epoch.unix_seconds + number(time)?
+                }
+            };
+            let value = sample[1..]
+                .iter()
+                .map(number)
+                .collect::<anyhow::Result<_>>()?;
+            Ok((time, value))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+    samples.dedup_by(|a, b| a.0 == b.0);
+    Ok(samples)
+}
+
+fn number(value: &Value) -> anyhow::Result<f64> {
+    value
+        .as_f64()
+        .ok_or_else(|| anyhow!("{} isn't a number", value))
+}
+
+fn parse_position(position: &Value) -> anyhow::Result<SampledPosition> {
+    let mut velocities = None;
+    let samples: Vec<(f64, Vector3<f64>)> = if position.get("cartographicDegrees").is_some() {
+        parse_samples(position, "cartographicDegrees", 3)?
+            .into_iter()
+            .map(|(t, v)| (t, Geodetic::from_degrees(v[1], v[0], v[2]).to_ecef()))
+            .collect()
+    } else if position.get("cartographicRadians").is_some() {
+        parse_samples(position, "cartographicRadians", 3)?
+            .into_iter()
+            .map(|(t, v)| (t, geodesy::geodetic_to_ecef(v[1], v[0], v[2])))
+            .collect()
+    } else if position.get("cartesianVelocity").is_some() {
+        let samples = parse_samples(position, "cartesianVelocity", 6)?;
+        velocities = Some(
+            samples
+                .iter()
+                .map(|(_, v)| Vector3::new(v[3], v[4], v[5]))
+                .collect(),
+        );
+        samples
+            .into_iter()
+            .map(|(t, v)| (t, Vector3::new(v[0], v[1], v[2])))
+            .collect()
+    } else if position.get("cartesian").is_some() {
+        parse_samples(position, "cartesian", 3)?
+            .into_iter()
+            .map(|(t, v)| (t, Vector3::new(v[0], v[1], v[2])))
+            .collect()
+    } else {
+        bail!("a position without cartographic or cartesian values");
+    };
+
+    if let [(time, position)] = samples[..] {
+        if time == f64::NEG_INFINITY {
+            return Ok(SampledPosition::constant(position));
+        }
+    }
+    let interpolation = match position["interpolationAlgorithm"].as_str() {
+        None | Some("LINEAR") => Interpolation::Linear,
+        Some("LAGRANGE") => Interpolation::Lagrange,
+        Some("HERMITE") => Interpolation::Hermite,
+        Some(other) => bail!("unknown interpolation algorithm {}", other),
+    };
+    let (times, positions) = samples.into_iter().unzip();
+    Ok(SampledPosition {
+        times,
+        positions,
+        velocities,
+        interpolation,
+        degree: position["interpolationDegree"].as_u64().unwrap_or(1) as usize,
+    })
+}
+
+fn parse_orientation(orientation: &Value) -> anyhow::Result<SampledOrientation> {
+    let (times, rotations) = parse_samples(orientation, "unitQuaternion", 4)?
+        .into_iter()
+        .map(|(t, q)| (t, Quaternion::new(q[3], q[0], q[1], q[2]).normalize()))
+        .unzip();
+    Ok(SampledOrientation { times, rotations })
+}
+
+fn parse_path(path: &Value) -> anyhow::Result<PathStyle> {
+    let mut line = LineStyle::default();
+    if let Some(width) = path["width"].as_f64() {
+        line.width = width as f32;
+    }
+    let color = &path["material"]["solidColor"]["color"];
+    if let Some(rgba) = color.get("rgba") {
+        let rgba = rgba
+            .as_array()
+            .filter(|rgba| rgba.len() == 4)
+            .ok_or_else(|| anyhow!("a color that isn't four components"))?;
+        for (channel, value) in line.color.iter_mut().zip(rgba) {
+            *channel = (number(value)? / 255.0) as f32;
+        }
+    } else if let Some(rgbaf) = color.get("rgbaf") {
+        let rgbaf = rgbaf
+            .as_array()
+            .filter(|rgbaf| rgbaf.len() == 4)
+            .ok_or_else(|| anyhow!("a color that isn't four components"))?;
+        for (channel, value) in line.color.iter_mut().zip(rgbaf) {
+            *channel = number(value)? as f32;
+        }
+    }
+    Ok(PathStyle {
+        trail_time: path["trailTime"].as_f64().unwrap_or(f64::INFINITY),
+        lead_time: path["leadTime"].as_f64().unwrap_or(0.0),
+        resolution: path["resolution"]
+            .as_f64()
+            .unwrap_or(DEFAULT_PATH_RESOLUTION),
+        line,
+    })
+}
+
+/// The drawn path of an entity.
+struct EntityPath {
+    entity: usize,
+    times: Vec<f64>,
+    /// The range of `times` inside the path, and their positions.
+    window: Option<Range<usize>>,
+    interior: Vec<Vector3<f64>>,
+    lines: Polylines,
+}
+
+/// Entities driven by the simulation clock, drawn as instances of one model
+/// with their paths. Entities are left out while the clock is outside their
+/// samples.
+pub struct EntityLayer {
+    pub entities: Vec<Entity>,
+    /// The radius of the model in model units, for `minimum_pixel_size`.
+    model_radius: f32,
+    instance_count: u32,
+    instance_buffer: wgpu::Buffer,
+    paths: Vec<EntityPath>,
+    line_renderer: polyline::PolylineRenderer,
+}
+
+impl EntityLayer {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        projection: &crate::camera::Projection,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        entities: Vec<Entity>,
+        model_radius: f32,
+    ) -> Self {
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Entity Instance Buffer"),
+            size: (entities.len().max(1) * std::mem::size_of::<model::InstanceRaw>())
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let paths = entities
+            .iter()
+            .enumerate()
+            .filter(|(_, entity)| entity.path.is_some())
+            .map(|(i, entity)| EntityPath {
+                entity: i,
+                times: entity.path_times(),
+                window: None,
+                interior: Vec::new(),
+                lines: Polylines::new(device, []),
+            })
+            .collect();
+        Self {
+            entities,
+            model_radius,
+            instance_count: 0,
+            instance_buffer,
+            paths,
+            line_renderer: polyline::PolylineRenderer::new(
+                device,
+                config,
+                projection,
+                camera_bind_group_layout,
+            ),
+        }
+    }
+
+    pub fn instance_buffer(&self) -> &wgpu::Buffer {
+        &self.instance_buffer
+    }
+
+    /// How many instances the last update placed.
+    pub fn instance_count(&self) -> u32 {
+        self.instance_count
+    }
+
+    /// Moves the entities to `time` and rebases them against the camera.
+    /// `pixel_angle` is the angle a pixel spans at the center of the screen.
+    /// Paths only interpolate their inner points again when their window
+    /// gains or loses one, while their ends follow the clock.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        time: UtcTime,
+        camera_position: Point3<f64>,
+        pixel_angle: f64,
+    ) {
+        let instances: Vec<_> = self
+            .entities
+            .iter()
+            .filter_map(|entity| {
+                let position = entity.position_at(time)?;
+                let distance = (position - camera_position.to_vec()).magnitude();
+                let minimum_scale = entity.minimum_pixel_size as f64 * pixel_angle * distance
+                    / (2.0 * self.model_radius as f64);
+                Some(
+                    model::Instance {
+                        position,
+                        rotation: entity.rotation_at(time).cast().unwrap(),
+                        scale: entity.scale.max(minimum_scale as f32),
+                    }
+                    .to_raw(camera_position),
+                )
+            })
+            .collect();
+        self.instance_count = instances.len() as u32;
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&instances));
+
+        for path in &mut self.paths {
+            let entity = &self.entities[path.entity];
+            let window = entity.path_window(&path.times, time);
+            if window != path.window {
+                path.interior = window
+                    .clone()
+                    .map(|window| {
+                        path.times[window]
+                            .iter()
+                            .filter_map(|&t| entity.position.at(t))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                path.window = window;
+            }
+            let line = entity
+                .path_through(&path.interior, time)
+                .zip(entity.path)
+                .map(|(points, style)| Polyline {
+                    points,
+                    closed: false,
+                    style: style.line,
+                });
+            path.lines.write(device, queue, line);
+            path.lines.update(queue, camera_position);
+        }
+    }
+
+    pub fn resize(&self, queue: &wgpu::Queue, width: u32, height: u32, fovy: Rad<f32>) {
+        self.line_renderer.resize(queue, width, height, fovy);
+    }
+}
+
+pub trait DrawEntityPaths<'a> {
+    fn draw_entity_paths(&mut self, layer: &'a EntityLayer, camera_bind_group: &'a wgpu::BindGroup);
+}
+
+impl<'a, 'b> DrawEntityPaths<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_entity_paths(
+        &mut self,
+        layer: &'b EntityLayer,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for path in &layer.paths {
+            self.draw_polylines(&layer.line_renderer, &path.lines, camera_bind_group);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sampled(
+        interpolation: Interpolation,
+        degree: usize,
+        f: impl Fn(f64) -> f64,
+    ) -> SampledPosition {
+        let times: Vec<f64> = (0..8).map(|i| i as f64 * 10.0).collect();
+        SampledPosition {
+            positions: times
+                .iter()
+                .map(|&t| Vector3::new(f(t), 2.0 * t, 0.0))
+                .collect(),
+            times,
+            velocities: None,
+            interpolation,
+            degree,
+        }
+    }
+
+    #[test]
+    fn interpolation_follows_the_samples() {
+        let cubic = |t: f64| 0.001 * t * t * t - 0.05 * t * t + t;
+        let linear = sampled(Interpolation::Linear, 1, cubic);
+        assert_eq!(
+            linear.at(15.0).unwrap().x,
+            (cubic(10.0) + cubic(20.0)) / 2.0
+        );
+        assert_eq!(linear.at(-1.0), None);
+        assert_eq!(linear.at(71.0), None);
+        assert_eq!(linear.at(70.0).unwrap().x, cubic(70.0));
+
+        // A cubic through enough samples is exact, near either end too
+        let lagrange = sampled(Interpolation::Lagrange, 3, cubic);
+        for t in [1.0, 33.0, 68.5] {
+            assert!((lagrange.at(t).unwrap().x - cubic(t)).abs() < 1e-9);
+        }
+
+        // Straight lines stay straight with estimated velocities, and curves
+        // come out exact with the true ones
+        let hermite = sampled(Interpolation::Hermite, 1, |t| 3.0 * t);
+        assert!((hermite.at(44.0).unwrap() - Vector3::new(132.0, 88.0, 0.0)).magnitude() < 1e-9);
+        let mut hermite = sampled(Interpolation::Hermite, 1, cubic);
+        let slope = |t: f64| 0.003 * t * t - 0.1 * t + 1.0;
+        hermite.velocities = Some(
+            hermite
+                .times
+                .iter()
+                .map(|&t| Vector3::new(slope(t), 2.0, 0.0))
+                .collect(),
+        );
+        assert!((hermite.at(44.0).unwrap().x - cubic(44.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn czml_packets_become_entities() {
+        let czml = r#"[
+            {"id": "document", "version": "1.0"},
+            {
+                "id": "ship",
+                "name": "Ship",
+                "position": {
+                    "epoch": "2024-01-01T00:00:00Z",
+                    "interpolationAlgorithm": "LAGRANGE",
+                    "interpolationDegree": 2,
+                    "cartographicDegrees": [
+                        120, 0.02, 0, 0,
+                        0, 0, 0, 0,
+                        60, 0.01, 0, 0
+                    ]
+                },
+                "path": {
+                    "width": 3,
+                    "trailTime": 30,
+                    "material": {"solidColor": {"color": {"rgba": [255, 0, 0, 255]}}}
+                },
+                "model": {"scale": 20, "minimumPixelSize": 32}
+            },
+            {"id": "buoy", "position": {"cartesian": [6378137, 0, 0]}},
+            {"id": "label only"}
+        ]"#;
+        let entities = parse_czml(czml).unwrap();
+        assert_eq!(entities.len(), 2);
+        let ship = &entities[0];
+        assert_eq!(ship.name.as_deref(), Some("Ship"));
+        assert_eq!(ship.position.interpolation, Interpolation::Lagrange);
+        assert_eq!(ship.scale, 20.0);
+
+        // Samples are sorted, and the ship sails east along the equator
+        let start = UtcTime::parse("2024-01-01").unwrap();
+        let at = |seconds| UtcTime::from_unix_seconds(start.unix_seconds + seconds);
+        assert!(ship.position_at(at(-1.0)).is_none());
+        let halfway = Geodetic::from_ecef(ship.position_at(at(90.0)).unwrap());
+        assert!((halfway.longitude.to_degrees() - 0.015).abs() < 1e-9);
+        // Facing east, a Y-up model's -Z points east
+        let forward = ship.rotation_at(at(90.0)) * -Vector3::unit_z();
+        let (east, _, _) = geodesy::enu_axes(halfway.latitude, halfway.longitude);
+        assert!((forward - east).magnitude() < 1e-6);
+
+        // The path runs through the samples and points a minute apart
+        // inside it, from the start of its trail to the ship
+        let times = ship.path_times();
+        assert_eq!(times, [0.0, 60.0, 120.0].map(|t| at(t).unix_seconds));
+        assert_eq!(ship.path_window(&times, at(90.0)), Some(2..2));
+        assert_eq!(ship.path_window(&times, at(120.0)), Some(2..2));
+        let window = ship.path_window(&times, at(80.0)).unwrap();
+        assert_eq!(window, 1..2);
+        let interior = times[window]
+            .iter()
+            .map(|&t| ship.position.at(t).unwrap())
+            .collect::<Vec<_>>();
+        let path = ship.path_through(&interior, at(80.0)).unwrap();
+        assert_eq!(path.len(), 3);
+        assert_eq!(path[0], ship.position_at(at(50.0)).unwrap());
+        assert_eq!(path[2], ship.position_at(at(80.0)).unwrap());
+        assert_eq!(ship.path.unwrap().line.color, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(ship.path.unwrap().line.width, 3.0);
+
+        // Constant positions hold at any time
+        let buoy = &entities[1];
+        assert_eq!(
+            buoy.position_at(at(1e9)),
+            Some(Vector3::new(6378137.0, 0.0, 0.0))
+        );
+        assert!(buoy.path_window(&buoy.path_times(), at(0.0)).is_none());
+
+        assert!(parse_czml(r#"[{"id": "x", "position": {"cartesian": [1, 2]}}]"#).is_err());
+        assert!(parse_czml(r#"{"id": "x"}"#).is_err());
+    }
+}
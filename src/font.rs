@@ -0,0 +1,318 @@
+use std::collections::HashMap;
+
+use ab_glyph::{Font, FontVec, GlyphId, PxScale, ScaleFont};
+use anyhow::anyhow;
+
+/// Glyphs are rasterized at this many pixels per em, and scaled from there.
+pub const RASTER_SIZE: f32 = 48.0;
+/// How far from the outline distances are kept, in atlas pixels. This also
+/// limits how wide outlines can be, to this many pixels at `RASTER_SIZE`.
+pub const SPREAD: f32 = 6.0;
+const ATLAS_WIDTH: u32 = 1024;
+
+/// Printable ASCII and Latin-1, which the atlas always has.
+fn default_characters() -> impl Iterator<Item = char> {
+    (' '..='~').chain('\u{a0}'..='\u{ff}')
+}
+
+/// Where a glyph's distance field is in the atlas.
+#[derive(Debug, Clone, Copy)]
+struct Glyph {
+    id: GlyphId,
+    /// The field's top left from the pen position, y down, in atlas pixels.
+    offset: [f32; 2],
+    size: [f32; 2],
+    /// The field's bounds in texture coordinates, or `None` for blanks.
+    uv: Option<[f32; 4]>,
+}
+
+/// A glyph placed in a [`TextLayout`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GlyphQuad {
+    /// Left, top, right and bottom in atlas pixels from the text's center,
+    /// y down.
+    pub rect: [f32; 4],
+    /// The same corners in the atlas, in texture coordinates.
+    pub uv: [f32; 4],
+}
+
+/// Text laid out in lines, each centered on the text's center.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TextLayout {
+    pub quads: Vec<GlyphQuad>,
+    /// The size of the text in atlas pixels.
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Signed distance fields of the glyphs of a TrueType or OpenType font, in
+/// a single-channel atlas. A texel is 0.5 on the outline and moves towards
+/// 1 inside and 0 outside by half a unit every `SPREAD` pixels.
+pub struct SdfAtlas {
+    font: FontVec,
+    glyphs: HashMap<char, Glyph>,
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+impl SdfAtlas {
+    /// Rasterizes the font's glyphs for `characters`, besides printable
+    /// ASCII and Latin-1. Glyphs that would make the atlas taller than
+    /// `max_height`, the device's largest texture, are left out with a
+    /// warning, the lowest code points kept first.
+    pub fn new(
+        font_data: Vec<u8>,
+        characters: impl IntoIterator<Item = char>,
+        max_height: u32,
+    ) -> anyhow::Result<Self> {
+        let font = FontVec::try_from_vec(font_data).map_err(|e| anyhow!("{}", e))?;
+        let mut characters: Vec<char> = default_characters().chain(characters).collect();
+        characters.sort_unstable();
+        characters.dedup();
+
+        // Glyphs are packed in shelves, with a texel between them so they
+        // don't bleed into each other
+        let padding = SPREAD.ceil() as u32;
+        let scaled = font.as_scaled(PxScale::from(RASTER_SIZE));
+        let mut fields = Vec::new();
+        let mut glyphs = HashMap::new();
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        let mut left_out = 0;
+        for c in characters {
+            let glyph = scaled.scaled_glyph(c);
+            let id = glyph.id;
+            let outlined = match font.outline_glyph(glyph) {
+                Some(outlined) => outlined,
+                None => {
+                    let blank = Glyph {
+                        id,
+                        offset: [0.0; 2],
+                        size: [0.0; 2],
+                        uv: None,
+                    };
+                    glyphs.insert(c, blank);
+                    continue;
+                }
+            };
+            let bounds = outlined.px_bounds();
+            let width = bounds.width() as u32 + 2 * padding;
+            let height = bounds.height() as u32 + 2 * padding;
+            let mut inside = vec![false; (width * height) as usize];
+            outlined.draw(|gx, gy, coverage| {
+                inside[((gy + padding) * width + gx + padding) as usize] = coverage >= 0.5;
+            });
+
+            if x + width > ATLAS_WIDTH {
+                x = 0;
+                y += shelf_height + 1;
+                shelf_height = 0;
+            }
+            if y + height > max_height {
+                left_out += 1;
+                continue;
+            }
+            fields.push((x, y, width, height, distance_field(&inside, width, height)));
+            glyphs.insert(
+                c,
+                Glyph {
+                    id,
+                    offset: [bounds.min.x - padding as f32, bounds.min.y - padding as f32],
+                    size: [width as f32, height as f32],
+                    uv: Some([x as f32, y as f32, (x + width) as f32, (y + height) as f32]),
+                },
+            );
+            x += width + 1;
+            shelf_height = shelf_height.max(height);
+        }
+
+        if left_out > 0 {
+            log::warn!(
+                "The font atlas is full at {} texels, leaving out {} characters",
+                max_height,
+                left_out
+            );
+        }
+
+        let atlas_height = fields
+            .iter()
+            .map(|(_, y, _, height, _)| y + height)
+            .max()
+            .unwrap_or(0)
+            .max(1);
+        let mut pixels = vec![0; (ATLAS_WIDTH * atlas_height) as usize];
+        for (x, y, width, height, field) in fields {
+            for row in 0..height {
+                let start = ((y + row) * ATLAS_WIDTH + x) as usize;
+                let texels = &field[(row * width) as usize..((row + 1) * width) as usize];
+                for (pixel, distance) in pixels[start..].iter_mut().zip(texels) {
+                    *pixel =
+                        ((0.5 + distance / (2.0 * SPREAD)).clamp(0.0, 1.0) * 255.0).round() as u8;
+                }
+            }
+        }
+        // Texture coordinates are only known now the atlas height is
+        let scale = [ATLAS_WIDTH as f32, atlas_height as f32];
+        for glyph in glyphs.values_mut() {
+            if let Some(uv) = &mut glyph.uv {
+                *uv = [
+                    uv[0] / scale[0],
+                    uv[1] / scale[1],
+                    uv[2] / scale[0],
+                    uv[3] / scale[1],
+                ];
+            }
+        }
+
+        Ok(Self {
+            font,
+            glyphs,
+            width: ATLAS_WIDTH,
+            height: atlas_height,
+            pixels,
+        })
+    }
+
+    /// Lays out `text` at `RASTER_SIZE`, with kerning. Lines are split at
+    /// newlines, and characters missing from the atlas are left out.
+    pub fn layout(&self, text: &str) -> TextLayout {
+        let scaled = self.font.as_scaled(PxScale::from(RASTER_SIZE));
+        let line_height = scaled.ascent() - scaled.descent() + scaled.line_gap();
+        let lines: Vec<&str> = text.lines().collect();
+        let height = lines.len() as f32 * line_height - scaled.line_gap();
+        let mut layout = TextLayout {
+            quads: Vec::new(),
+            width: 0.0,
+            height: height.max(0.0),
+        };
+        for (i, line) in lines.iter().enumerate() {
+            let baseline = -height / 2.0 + i as f32 * line_height + scaled.ascent();
+            let first = layout.quads.len();
+            let mut pen = 0.0;
+            let mut previous: Option<GlyphId> = None;
+            for glyph in line.chars().filter_map(|c| self.glyphs.get(&c)) {
+                if let Some(previous) = previous {
+                    pen += scaled.kern(previous, glyph.id);
+                }
+                if let Some(uv) = glyph.uv {
+                    let left = pen + glyph.offset[0];
+                    let top = baseline + glyph.offset[1];
+                    layout.quads.push(GlyphQuad {
+                        rect: [left, top, left + glyph.size[0], top + glyph.size[1]],
+                        uv,
+                    });
+                }
+                pen += scaled.h_advance(glyph.id);
+                previous = Some(glyph.id);
+            }
+            // Center the line
+            for quad in &mut layout.quads[first..] {
+                quad.rect[0] -= pen / 2.0;
+                quad.rect[2] -= pen / 2.0;
+            }
+            layout.width = layout.width.max(pen);
+        }
+        layout
+    }
+}
+
+/// The signed distance in pixels from each pixel's center to the outline of
+/// a rasterized shape, positive inside.
+fn distance_field(inside: &[bool], width: u32, height: u32) -> Vec<f32> {
+    let to_inside = distance_transform(inside, width, height, |i| inside[i]);
+    let to_outside = distance_transform(inside, width, height, |i| !inside[i]);
+    // The outline lies halfway between pixels either side of it
+    (0..inside.len())
+        .map(|i| {
+            if inside[i] {
+                to_outside[i] - 0.5
+            } else {
+                0.5 - to_inside[i]
+            }
+        })
+        .collect()
+}
+
+/// The distance from each pixel to the nearest seed pixel, by dead
+/// reckoning: two passes over the image, each pixel taking its neighbours'
+/// nearest seed if that's nearer.
+fn distance_transform(
+    inside: &[bool],
+    width: u32,
+    height: u32,
+    seed: impl Fn(usize) -> bool,
+) -> Vec<f32> {
+    let (width, height) = (width as i32, height as i32);
+    let mut nearest = vec![(i32::MIN / 2, i32::MIN / 2); inside.len()];
+    let mut distance = vec![f32::INFINITY; inside.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as usize;
+            if seed(i) {
+                nearest[i] = (x, y);
+                distance[i] = 0.0;
+            }
+        }
+    }
+
+    let mut relax = |x: i32, y: i32, dx: i32, dy: i32| {
+        let (nx, ny) = (x + dx, y + dy);
+        if nx < 0 || ny < 0 || nx >= width || ny >= height {
+            return;
+        }
+        let (i, n) = ((y * width + x) as usize, (ny * width + nx) as usize);
+        let (sx, sy) = nearest[n];
+        let candidate = (((x - sx) as f32).powi(2) + ((y - sy) as f32).powi(2)).sqrt();
+        if candidate < distance[i] {
+            nearest[i] = nearest[n];
+            distance[i] = candidate;
+        }
+    };
+    for y in 0..height {
+        for x in 0..width {
+            for (dx, dy) in [(-1, -1), (0, -1), (1, -1), (-1, 0)] {
+                relax(x, y, dx, dy);
+            }
+        }
+    }
+    for y in (0..height).rev() {
+        for x in (0..width).rev() {
+            for (dx, dy) in [(1, 0), (-1, 1), (0, 1), (1, 1)] {
+                relax(x, y, dx, dy);
+            }
+        }
+    }
+    distance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_field_of_a_disc() {
+        let (size, radius) = (41, 12.0);
+        let center = (size / 2) as f32;
+        let inside: Vec<bool> = (0..size * size)
+            .map(|i| {
+                let (x, y) = ((i % size) as f32, (i / size) as f32);
+                (x - center).hypot(y - center) <= radius
+            })
+            .collect();
+        let field = distance_field(&inside, size, size);
+        for (i, distance) in field.iter().enumerate() {
+            let (x, y) = ((i % size as usize) as f32, (i / size as usize) as f32);
+            let exact = radius - (x - center).hypot(y - center);
+            assert!(
+                (distance - exact).abs() < 1.0,
+                "{} vs {} at {}, {}",
+                distance,
+                exact,
+                x,
+                y
+            );
+        }
+        assert!(field[(20 * size + 20) as usize] > 11.0);
+        assert!(field[0] < -15.0);
+    }
+}
@@ -0,0 +1,264 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Matrix4, Vector3};
+
+/// WGS84 semi-major axis in meters.
+pub const WGS84_A: f64 = 6_378_137.0;
+/// WGS84 flattening.
+pub const WGS84_F: f64 = 1.0 / 298.257_223_563;
+/// WGS84 first eccentricity squared.
+pub const WGS84_E2: f64 = WGS84_F * (2.0 - WGS84_F);
+
+/// A position relative to the WGS84 ellipsoid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geodetic {
+    /// Radians, positive north.
+    pub latitude: f64,
+    /// Radians, positive east.
+    pub longitude: f64,
+    /// Meters above the ellipsoid.
+    pub height: f64,
+}
+
+impl Geodetic {
+    pub fn new(latitude: f64, longitude: f64, height: f64) -> Self {
+        Self {
+            latitude,
+            longitude,
+            height,
+        }
+    }
+
+    pub fn from_degrees(latitude: f64, longitude: f64, height: f64) -> Self {
+        Self::new(latitude.to_radians(), longitude.to_radians(), height)
+    }
+
+    pub fn to_ecef(self) -> Vector3<f64> {
+        geodetic_to_ecef(self.latitude, self.longitude, self.height)
+    }
+
+    pub fn from_ecef(position: Vector3<f64>) -> Self {
+        ecef_to_geodetic(position)
+    }
+}
+
+/// The radius of curvature in the prime vertical at a geodetic latitude.
+fn prime_vertical_radius(sin_latitude: f64) -> f64 {
+    WGS84_A / (1.0 - WGS84_E2 * sin_latitude * sin_latitude).sqrt()
+}
+
+/// ECEF position of a point at a geodetic latitude and longitude in radians
+/// and a height above the WGS84 ellipsoid in meters.
+pub fn geodetic_to_ecef(latitude: f64, longitude: f64, height: f64) -> Vector3<f64> {
+    let (sin_lat, cos_lat) = latitude.sin_cos();
+    let (sin_lon, cos_lon) = longitude.sin_cos();
+    let n = prime_vertical_radius(sin_lat);
+    Vector3::new(
+        (n + height) * cos_lat * cos_lon,
+        (n + height) * cos_lat * sin_lon,
+        (n * (1.0 - WGS84_E2) + height) * sin_lat,
+    )
+}
+
+/// Converts an ECEF position back to geodetic coordinates.
+///
+/// Uses Bowring's fixed point iteration on the latitude, which converges to
+/// well below a millimeter within a handful of steps anywhere from the
+/// surface out past geostationary orbit.
+pub fn ecef_to_geodetic(position: Vector3<f64>) -> Geodetic {
+    let p = position.x.hypot(position.y);
+    let longitude = position.y.atan2(position.x);
+
+    let mut latitude = position.z.atan2(p * (1.0 - WGS84_E2));
+    for _ in 0..10 {
+        let sin_lat = latitude.sin();
+        let next = (position.z + prime_vertical_radius(sin_lat) * WGS84_E2 * sin_lat).atan2(p);
+        let converged = (next - latitude).abs() < 1e-14;
+        latitude = next;
+        if converged {
+            break;
+        }
+    }
+
+    // Unlike p / cos(latitude) - N this stays well conditioned at the poles
+    let (sin_lat, cos_lat) = latitude.sin_cos();
+    let height =
+        p * cos_lat + position.z * sin_lat - WGS84_A * (1.0 - WGS84_E2 * sin_lat * sin_lat).sqrt();
+
+    Geodetic {
+        latitude,
+        longitude,
+        height,
+    }
+}
+
+/// The east, north and up unit vectors of the local frame at a geodetic
+/// latitude and longitude. Up is the ellipsoid surface normal.
+pub fn enu_axes(latitude: f64, longitude: f64) -> (Vector3<f64>, Vector3<f64>, Vector3<f64>) {
+    let (sin_lat, cos_lat) = latitude.sin_cos();
+    let (sin_lon, cos_lon) = longitude.sin_cos();
+    (
+        Vector3::new(-sin_lon, cos_lon, 0.0),
+        Vector3::new(-sin_lat * cos_lon, -sin_lat * sin_lon, cos_lat),
+        Vector3::new(cos_lat * cos_lon, cos_lat * sin_lon, sin_lat),
+    )
+}
+
+/// Rotates vectors from the local east/north/up frame into ECEF.
+pub fn enu_to_ecef_rotation(latitude: f64, longitude: f64) -> Matrix3<f64> {
+    let (east, north, up) = enu_axes(latitude, longitude);
+    Matrix3::from_cols(east, north, up)
+}
+
+/// Rotates vectors from the local north/east/down frame into ECEF.
+pub fn ned_to_ecef_rotation(latitude: f64, longitude: f64) -> Matrix3<f64> {
+    let (east, north, up) = enu_axes(latitude, longitude);
+    Matrix3::from_cols(north, east, -up)
+}
+
+/// A model transform placing a local east/north/up frame at `origin`, so
+/// local x points east, y north and z up.
+// Models are still placed with heading, pitch and roll instead
+#[allow(dead_code)]
+pub fn enu_to_ecef_transform(origin: &Geodetic) -> Matrix4<f64> {
+    let mut transform = Matrix4::from(enu_to_ecef_rotation(origin.latitude, origin.longitude));
+    transform.w = origin.to_ecef().extend(1.0);
+    transform
+}
+
+/// A model transform placing a local north/east/down frame at `origin`.
+// For aircraft models built in NED, which none of the samples are
+#[allow(dead_code)]
+pub fn ned_to_ecef_transform(origin: &Geodetic) -> Matrix4<f64> {
+    let mut transform = Matrix4::from(ned_to_ecef_rotation(origin.latitude, origin.longitude));
+    transform.w = origin.to_ecef().extend(1.0);
+    transform
+}
+
+/// Expresses an ECEF position in the east/north/up frame at `origin`.
+// Nothing reads positions back in local frames yet
+#[allow(dead_code)]
+pub fn ecef_to_enu(position: Vector3<f64>, origin: &Geodetic) -> Vector3<f64> {
+    enu_to_ecef_rotation(origin.latitude, origin.longitude).transpose()
+        * (position - origin.to_ecef())
+}
+
+/// Converts a position in the east/north/up frame at `origin` to ECEF.
+pub fn enu_to_ecef(enu: Vector3<f64>, origin: &Geodetic) -> Vector3<f64> {
+    origin.to_ecef() + enu_to_ecef_rotation(origin.latitude, origin.longitude) * enu
+}
+
+/// Expresses an ECEF position in the north/east/down frame at `origin`.
+// The NED counterpart of `ecef_to_enu`, unused for the same reason
+#[allow(dead_code)]
+pub fn ecef_to_ned(position: Vector3<f64>, origin: &Geodetic) -> Vector3<f64> {
+    ned_to_ecef_rotation(origin.latitude, origin.longitude).transpose()
+        * (position - origin.to_ecef())
+}
+
+/// Converts a position in the north/east/down frame at `origin` to ECEF.
+// CZML and the tile formats only give offsets in ENU
+#[allow(dead_code)]
+pub fn ned_to_ecef(ned: Vector3<f64>, origin: &Geodetic) -> Vector3<f64> {
+    origin.to_ecef() + ned_to_ecef_rotation(origin.latitude, origin.longitude) * ned
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Vector3<f64>, expected: Vector3<f64>, tolerance: f64) {
+        assert!(
+            (actual - expected).magnitude() < tolerance,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn geodetic_to_ecef_reference_points() {
+        // Where the equator meets the prime meridian
+        assert_close(
+            Geodetic::from_degrees(0.0, 0.0, 0.0).to_ecef(),
+            Vector3::new(6_378_137.0, 0.0, 0.0),
+            1e-6,
+        );
+        // The north pole sits on the semi-minor axis
+        assert_close(
+            Geodetic::from_degrees(90.0, 0.0, 0.0).to_ecef(),
+            Vector3::new(0.0, 0.0, 6_356_752.314_245),
+            1e-6,
+        );
+        assert_close(
+            Geodetic::from_degrees(0.0, 90.0, 1000.0).to_ecef(),
+            Vector3::new(0.0, 6_379_137.0, 0.0),
+            1e-6,
+        );
+        assert_close(
+            Geodetic::from_degrees(45.0, 45.0, 0.0).to_ecef(),
+            Vector3::new(3_194_419.145_061, 3_194_419.145_061, 4_487_348.408_866),
+            1e-5,
+        );
+    }
+
+    #[test]
+    fn ecef_to_geodetic_round_trips() {
+        use std::f64::consts::TAU;
+        for &latitude in &[-90.0, -89.999, -45.0, -0.5, 0.0, 12.3, 60.0, 89.9, 90.0] {
+            for &longitude in &[-180.0, -120.0, 0.0, 33.3, 179.9] {
+                for &height in &[-400.0, 0.0, 8848.0, 400e3, 35_786e3] {
+                    let expected = Geodetic::from_degrees(latitude, longitude, height);
+                    let actual = Geodetic::from_ecef(expected.to_ecef());
+                    assert!((actual.latitude - expected.latitude).abs() < 1e-11);
+                    assert!((actual.height - expected.height).abs() < 1e-4);
+                    if latitude.abs() < 90.0 {
+                        let delta = (actual.longitude - expected.longitude).rem_euclid(TAU);
+                        assert!(!(1e-11..=TAU - 1e-11).contains(&delta));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn local_frames() {
+        let origin = Geodetic::from_degrees(46.017, 7.75, 1673.0);
+        let above = Geodetic::from_degrees(46.017, 7.75, 1773.0).to_ecef();
+        assert_close(
+            ecef_to_enu(above, &origin),
+            Vector3::new(0.0, 0.0, 100.0),
+            1e-6,
+        );
+        assert_close(
+            ecef_to_ned(above, &origin),
+            Vector3::new(0.0, 0.0, -100.0),
+            1e-6,
+        );
+        let east = enu_to_ecef(Vector3::new(1000.0, 0.0, 0.0), &origin);
+        assert!(Geodetic::from_ecef(east).longitude > origin.longitude);
+
+        // Each conversion round trips through ECEF at reference points
+        for (latitude, longitude) in [(0.0, 0.0), (46.017, 7.75), (-33.9, 151.2), (89.9, -120.0)] {
+            let origin = Geodetic::from_degrees(latitude, longitude, 250.0);
+            let enu = Vector3::new(1200.0, -350.0, 40.0);
+            let ned = Vector3::new(-350.0, 1200.0, -40.0);
+            let ecef = enu_to_ecef(enu, &origin);
+            assert_close(ned_to_ecef(ned, &origin), ecef, 1e-6);
+            assert_close(ecef_to_enu(ecef, &origin), enu, 1e-6);
+            assert_close(ecef_to_ned(ecef, &origin), ned, 1e-6);
+            assert_close(ecef_to_ned(ned_to_ecef(ned, &origin), &origin), ned, 1e-6);
+
+            let transform = enu_to_ecef_transform(&origin);
+            assert_close((transform * enu.extend(1.0)).truncate(), ecef, 1e-6);
+            let transform = ned_to_ecef_transform(&origin);
+            assert_close((transform * ned.extend(1.0)).truncate(), ecef, 1e-6);
+            let inverse = transform.invert().unwrap();
+            assert_close((inverse * ecef.extend(1.0)).truncate(), ned, 1e-6);
+
+            let rotation = enu_to_ecef_rotation(origin.latitude, origin.longitude);
+            assert!((rotation.determinant() - 1.0).abs() < 1e-12);
+            let rotation = ned_to_ecef_rotation(origin.latitude, origin.longitude);
+            assert!((rotation.determinant() - 1.0).abs() < 1e-12);
+        }
+    }
+}
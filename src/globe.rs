@@ -0,0 +1,408 @@
+use cgmath::prelude::*;
+use cgmath::Vector3;
+use std::f64::consts::{FRAC_PI_2, FRAC_PI_4, PI};
+
+use crate::{geodesy, model, texture};
+
+/// The latitude where the Web Mercator projection is cut off, making the
+/// projected world square.
+pub const WEB_MERCATOR_MAX_LATITUDE: f64 = 1.484_422_229_745_332_4;
+
+/// A geographic rectangle in radians.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Extent {
+    pub west: f64,
+    pub south: f64,
+    pub east: f64,
+    pub north: f64,
+}
+
+impl Extent {
+    pub const WORLD: Extent = Extent {
+        west: -PI,
+        south: -FRAC_PI_2,
+        east: PI,
+        north: FRAC_PI_2,
+    };
+
+    pub fn width(&self) -> f64 {
+        self.east - self.west
+    }
+
+    pub fn height(&self) -> f64 {
+        self.north - self.south
+    }
+
+    pub fn center(&self) -> (f64, f64) {
+        (
+            (self.south + self.north) / 2.0,
+            (self.west + self.east) / 2.0,
+        )
+    }
+}
+
+/// How the imagery draped over a tile maps to latitude and longitude.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureProjection {
+    /// Plate carrée: rows are evenly spaced in latitude.
+    Geographic,
+    /// Rows are evenly spaced in Mercator y, as in slippy-map tiles.
+    WebMercator,
+}
+
+impl TextureProjection {
+    /// The texture v coordinate of `latitude` in an image covering `extent`,
+    /// growing southwards.
+    pub fn v(&self, latitude: f64, extent: &Extent) -> f64 {
+        match self {
+            TextureProjection::Geographic => (extent.north - latitude) / extent.height(),
+            TextureProjection::WebMercator => {
+                let north = mercator_y(extent.north);
+                (north - mercator_y(latitude)) / (north - mercator_y(extent.south))
+            }
+        }
+    }
+}
+
+fn mercator_y(latitude: f64) -> f64 {
+    let latitude = latitude.clamp(-WEB_MERCATOR_MAX_LATITUDE, WEB_MERCATOR_MAX_LATITUDE);
+    (FRAC_PI_4 + latitude / 2.0).tan().ln()
+}
+
+/// CPU-side geometry of one globe tile. Vertex positions are relative to
+/// `center` so they keep their precision as `f32`.
+pub struct TileGeometry {
+    pub center: Vector3<f64>,
+    pub vertices: Vec<model::ModelVertex>,
+    pub indices: Vec<u32>,
+}
+
+/// Tessellates the part of the WGS84 ellipsoid inside `extent` into a
+/// `segments` x `segments` grid. Texture coordinates address an image
+/// covering `texture_extent` in the given projection, which is the tile
+/// itself for tiled imagery or the whole world for a single image.
+///
+/// Each edge gets a skirt hanging `skirt_height` meters below the surface to
+/// hide cracks against neighbouring tiles of a different level of detail.
+pub fn tessellate(
+    extent: &Extent,
+    segments: u32,
+    texture_extent: &Extent,
+    projection: TextureProjection,
+    skirt_height: f64,
+) -> TileGeometry {
+    tessellate_terrain(
+        extent,
+        segments,
+        texture_extent,
+        projection,
+        skirt_height,
+        |_, _| 0.0,
+    )
+}
+
+/// Like [`tessellate`], with every vertex raised `height(latitude,
+/// longitude)` meters along the ellipsoid normal.
+///
+/// Normals are central differences of the displaced grid. The grid is
+/// extended by one row and column on every side for them, so tiles that
+/// share an edge shade alike; `height` is only ever asked about points
+/// inside `extent`, those outside use the height at the nearest edge.
+pub fn tessellate_terrain(
+    extent: &Extent,
+    segments: u32,
+    texture_extent: &Extent,
+    projection: TextureProjection,
+    skirt_height: f64,
+    height: impl Fn(f64, f64) -> f64,
+) -> TileGeometry {
+    let (center_latitude, center_longitude) = extent.center();
+    let center = geodesy::geodetic_to_ecef(center_latitude, center_longitude, 0.0);
+    let side = segments as usize + 1;
+
+    // Rows run from north to south and columns from west to east, with a
+    // ring of extra samples around the tile
+    let ring_side = side + 2;
+    let mut coordinates = Vec::with_capacity(ring_side * ring_side);
+    let mut positions = Vec::with_capacity(ring_side * ring_side);
+    for row in -1..=segments as i64 + 1 {
+        let latitude = (extent.north - extent.height() * row as f64 / segments as f64)
+            .clamp(-FRAC_PI_2, FRAC_PI_2);
+        for column in -1..=segments as i64 + 1 {
+            let longitude = extent.west + extent.width() * column as f64 / segments as f64;
+            let h = height(
+                latitude.clamp(extent.south, extent.north),
+                longitude.clamp(extent.west, extent.east),
+            );
+            coordinates.push((latitude, longitude, h));
+            positions.push(geodesy::geodetic_to_ecef(latitude, longitude, h));
+        }
+    }
+
+    let vertex = |row: usize, column: usize, depth: f64| {
+        let i = (row + 1) * ring_side + column + 1;
+        let (latitude, longitude, h) = coordinates[i];
+        let (east, _, up) = geodesy::enu_axes(latitude, longitude);
+        let normal = (positions[i + 1] - positions[i - 1])
+            .cross(positions[i - ring_side] - positions[i + ring_side]);
+        // Neighbours collapse into a point at the poles
+        let normal = if normal.magnitude2() > 0.0 {
+            normal.normalize()
+        } else {
+            up
+        };
+        let tangent = (east - normal * east.dot(normal)).normalize();
+        let position = if depth == 0.0 {
+            positions[i]
+        } else {
+            geodesy::geodetic_to_ecef(latitude, longitude, h - depth)
+        };
+        model::ModelVertex {
+            position: (position - center).cast().unwrap().into(),
+            tex_coords: [
+                ((longitude - texture_extent.west) / texture_extent.width()) as f32,
+                projection.v(latitude, texture_extent) as f32,
+            ],
+            normal: normal.cast().unwrap().into(),
+            tangent: tangent.cast().unwrap().into(),
+            bitangent: normal.cross(tangent).cast().unwrap().into(),
+        }
+    };
+
+    let mut vertices = Vec::with_capacity(side * side + side * 4);
+    for row in 0..side {
+        for column in 0..side {
+            vertices.push(vertex(row, column, 0.0));
+        }
+    }
+
+    let mut indices = Vec::with_capacity(segments as usize * segments as usize * 6);
+    for row in 0..segments {
+        for column in 0..segments {
+            let top_left = row * side as u32 + column;
+            let bottom_left = top_left + side as u32;
+            indices.extend_from_slice(&[
+                top_left,
+                bottom_left,
+                top_left + 1,
+                top_left + 1,
+                bottom_left,
+                bottom_left + 1,
+            ]);
+        }
+    }
+
+    // Walk the border counter-clockwise seen from above, starting at the
+    // north-west corner, so the skirt triangles face outwards.
+    let mut border = Vec::with_capacity(side * 4);
+    border.extend((0..side).map(|row| row * side));
+    border.extend((1..side).map(|column| (side - 1) * side + column));
+    border.extend((0..side - 1).rev().map(|row| row * side + side - 1));
+    border.extend((0..side - 1).rev());
+
+    let first_skirt = vertices.len() as u32;
+    for &edge in &border {
+        vertices.push(vertex(edge / side, edge % side, skirt_height));
+    }
+    for i in 0..border.len() - 1 {
+        let edge = border[i] as u32;
+        let next_edge = border[i + 1] as u32;
+        let skirt = first_skirt + i as u32;
+        indices.extend_from_slice(&[edge, skirt, next_edge, next_edge, skirt, skirt + 1]);
+    }
+
+    TileGeometry {
+        center,
+        vertices,
+        indices,
+    }
+}
+
+/// Splits the world into a geographic tiling scheme with two root tiles,
+/// returning the `2^(level+1)` x `2^level` tile extents of `level`.
+pub fn geographic_tiles(level: u32) -> Vec<Extent> {
+    let rows = 1 << level;
+    let columns = rows * 2;
+    let size = PI / rows as f64;
+    (0..rows)
+        .flat_map(|row| {
+            (0..columns).map(move |column| Extent {
+                west: -PI + column as f64 * size,
+                south: FRAC_PI_2 - (row + 1) as f64 * size,
+                east: -PI + (column + 1) as f64 * size,
+                north: FRAC_PI_2 - row as f64 * size,
+            })
+        })
+        .collect()
+}
+
+/// A globe made of geographic tiles with one image draped over all of them.
+pub struct Globe {
+    pub model: model::Model,
+    /// The ECEF origin of each mesh in `model`.
+    pub centers: Vec<Vector3<f64>>,
+}
+
+impl Globe {
+    pub fn new(
+        device: &wgpu::Device,
+        level: u32,
+        segments: u32,
+        material: model::Material,
+        projection: TextureProjection,
+    ) -> Self {
+        let (centers, meshes) = geographic_tiles(level)
+            .iter()
+            .enumerate()
+            .map(|(i, extent)| {
+                // Deep enough to cover the curvature between neighbouring tiles
+                let skirt_height = extent.width() * geodesy::WGS84_A * 0.01;
+                let tile = tessellate(extent, segments, &Extent::WORLD, projection, skirt_height);
+                let mesh = model::Mesh::new(
+                    device,
+                    &format!("globe tile {}", i),
+                    &tile.vertices,
+                    &tile.indices,
+                    0,
+                );
+                (tile.center, mesh)
+            })
+            .unzip();
+
+        Self {
+            model: model::Model {
+                meshes,
+                materials: vec![material],
+            },
+            centers,
+        }
+    }
+}
+
+/// A flat normal map for surfaces without one.
+pub fn flat_normal_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let image = image::RgbaImage::from_pixel(1, 1, image::Rgba([128, 128, 255, 255]));
+    texture::Texture::from_image(
+        device,
+        queue,
+        &image::DynamicImage::ImageRgba8(image),
+        Some("flat normal"),
+        true,
+    )
+}
+
+/// A black 1x1 texture, for a globe without night lights.
+pub fn dark_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    let image = image::RgbaImage::from_pixel(1, 1, image::Rgba([0, 0, 0, 255]));
+    texture::Texture::from_image(
+        device,
+        queue,
+        &image::DynamicImage::ImageRgba8(image),
+        Some("dark"),
+        false,
+    )
+}
+
+/// An equirectangular image of the graticule, used until real imagery is
+/// available.
+pub fn graticule_texture(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+) -> anyhow::Result<texture::Texture> {
+    const WIDTH: u32 = 1440;
+    const HEIGHT: u32 = 720;
+    const PIXELS_PER_LINE: u32 = 60; // every 15 degrees
+    let image = image::RgbaImage::from_fn(WIDTH, HEIGHT, |x, y| {
+        if x % PIXELS_PER_LINE == 0 || y % PIXELS_PER_LINE == 0 {
+            image::Rgba([200, 210, 220, 255])
+        } else {
+            image::Rgba([25, 60, 110, 255])
+        }
+    });
+    texture::Texture::from_image(
+        device,
+        queue,
+        &image::DynamicImage::ImageRgba8(image),
+        Some("graticule"),
+        false,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tile_normals_point_away_from_the_surface() {
+        let extent = Extent {
+            west: 0.0,
+            south: 0.0,
+            east: 0.5,
+            north: 0.5,
+        };
+        let tile = tessellate(&extent, 4, &extent, TextureProjection::Geographic, 1000.0);
+        assert_eq!(tile.vertices.len(), 25 + 4 * 4 + 1);
+        for triangle in tile.indices.chunks(3).take(4 * 4 * 2) {
+            let [a, b, c] = [0, 1, 2].map(|i| {
+                Vector3::from(tile.vertices[triangle[i] as usize].position)
+                    .cast::<f64>()
+                    .unwrap()
+            });
+            let face_normal = (b - a).cross(c - a);
+            assert!(face_normal.dot(tile.center) > 0.0, "triangle winds inwards");
+        }
+    }
+
+    #[test]
+    fn terrain_normals_lean_away_from_slopes() {
+        let extent = Extent {
+            west: 0.0,
+            south: 0.0,
+            east: 0.01,
+            north: 0.01,
+        };
+        // Rising 10 km towards the east across the tile
+        let tile = tessellate_terrain(
+            &extent,
+            4,
+            &extent,
+            TextureProjection::Geographic,
+            1000.0,
+            |_, longitude| longitude / extent.width() * 10_000.0,
+        );
+        let (latitude, longitude) = extent.center();
+        let (east, _, up) = geodesy::enu_axes(latitude, longitude);
+        let middle = &tile.vertices[2 * 5 + 2];
+        let position = Vector3::from(middle.position).cast::<f64>().unwrap() + tile.center;
+        assert!(
+            (position - geodesy::geodetic_to_ecef(latitude, longitude, 5000.0)).magnitude() < 1.0
+        );
+
+        let slope = 10_000.0 / (extent.width() * geodesy::WGS84_A);
+        let normal = Vector3::from(middle.normal).cast::<f64>().unwrap();
+        assert!((normal.magnitude() - 1.0).abs() < 1e-6);
+        assert!((normal.dot(east) + slope.atan().sin()).abs() < 1e-3);
+        assert!(normal.dot(up) > 0.9);
+        let tangent = Vector3::from(middle.tangent).cast::<f64>().unwrap();
+        assert!(tangent.dot(normal).abs() < 1e-6);
+    }
+
+    #[test]
+    fn web_mercator_v_spans_the_tile() {
+        let extent = Extent {
+            west: 0.0,
+            south: 0.0,
+            east: PI / 2.0,
+            north: WEB_MERCATOR_MAX_LATITUDE,
+        };
+        let projection = TextureProjection::WebMercator;
+        assert!(projection.v(extent.north, &extent).abs() < 1e-12);
+        assert!((projection.v(extent.south, &extent) - 1.0).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,475 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use reqwest::StatusCode;
+
+use crate::tiles::TileId;
+
+/// How the row in a URL counts tiles.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TileScheme {
+    /// Slippy-map rows, counted from the north.
+    Xyz,
+    /// Tile Map Service rows, counted from the south.
+    Tms,
+}
+
+/// Expands a URL template into the address of a tile.
+///
+/// `{z}`, `{x}` and `{y}` are replaced by the tile coordinates, with `{y}`
+/// following `scheme`; `{-y}` is always the row counted from the south. The
+/// WMTS names `{TileMatrix}`, `{TileCol}` and `{TileRow}` are accepted as
+/// well. `{s}` picks one of `subdomains`.
+#[derive(Debug, Clone)]
+pub struct UrlTemplate {
+    pub template: String,
+    pub subdomains: Vec<String>,
+    pub scheme: TileScheme,
+}
+
+impl UrlTemplate {
+    pub fn xyz(template: &str) -> Self {
+        Self {
+            template: template.to_string(),
+            subdomains: Vec::new(),
+            scheme: TileScheme::Xyz,
+        }
+    }
+
+    pub fn tms(template: &str) -> Self {
+        Self {
+            scheme: TileScheme::Tms,
+            ..Self::xyz(template)
+        }
+    }
+
+    pub fn with_subdomains(mut self, subdomains: &[&str]) -> Self {
+        self.subdomains = subdomains.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    pub fn expand(&self, tile: TileId) -> String {
+        let flipped_y = (1u32 << tile.z) - 1 - tile.y;
+        let y = match self.scheme {
+            TileScheme::Xyz => tile.y,
+            TileScheme::Tms => flipped_y,
+        };
+        let mut url = self
+            .template
+            .replace("{z}", &tile.z.to_string())
+            .replace("{x}", &tile.x.to_string())
+            .replace("{-y}", &flipped_y.to_string())
+            .replace("{y}", &y.to_string())
+            .replace("{TileMatrix}", &tile.z.to_string())
+            .replace("{TileCol}", &tile.x.to_string())
+            .replace("{TileRow}", &y.to_string());
+        if !self.subdomains.is_empty() {
+            // Always the same subdomain for a tile, or the cache would miss
+            let index = (tile.x + tile.y + tile.z) as usize % self.subdomains.len();
+            url = url.replace("{s}", &self.subdomains[index]);
+        }
+        url
+    }
+}
+
+/// Tiles stored on disk under a hash of the URL they were downloaded from.
+///
+/// Entries never expire; delete the directory to refresh them.
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    pub directory: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        let hash = format!("{:016x}", fnv1a(url.as_bytes()));
+        self.directory.join(&hash[..2]).join(hash)
+    }
+
+    /// The cached body for `url`, if any. Each entry starts with its URL on
+    /// a line of its own, so a hash collision reads as a miss.
+    pub fn get(&self, url: &str) -> Option<Vec<u8>> {
+        let mut data = fs::read(self.path(url)).ok()?;
+        let header = data.iter().position(|&byte| byte == b'\n')?;
+        if &data[..header] != url.as_bytes() {
+            return None;
+        }
+        data.drain(..=header);
+        Some(data)
+    }
+
+    pub fn put(&self, url: &str, body: &[u8]) -> io::Result<()> {
+        let path = self.path(url);
+        fs::create_dir_all(path.parent().unwrap())?;
+
+        // Write next to the entry and rename over it, so readers on other
+        // threads or a crash halfway never see a truncated tile.
+        let temporary = path.with_extension(format!("{:?}.tmp", thread::current().id()));
+        let mut data = Vec::with_capacity(url.len() + 1 + body.len());
+        data.extend_from_slice(url.as_bytes());
+        data.push(b'\n');
+        data.extend_from_slice(body);
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, &path)
+    }
+}
+
+/// 64-bit FNV-1a. Unlike `std`'s hashers its output is fixed, which matters
+/// for file names that have to survive a compiler upgrade.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Caps how many threads are inside a section at once.
+#[derive(Debug)]
+struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    fn new(permits: usize) -> Self {
+        Self {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    fn acquire(&self) -> Permit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        Permit { semaphore: self }
+    }
+}
+
+struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
+
+/// Downloads tiles from a web server, going through a [`DiskCache`] first
+/// when one is set.
+///
+/// Requests block, so `fetch` is meant to run on a worker thread. At most
+/// `max_concurrent` of them are in flight at a time however many threads
+/// call it. Connection errors, bodies cut short, `429` and `5xx` answers
+/// are retried up to `retries` times with exponential backoff; any other
+/// failure is final.
+#[derive(Debug)]
+pub struct HttpTileProvider {
+    pub template: UrlTemplate,
+    pub cache: Option<DiskCache>,
+    pub retries: u32,
+    pub retry_delay: Duration,
+    /// Extra request headers, such as the `Accept` header terrain servers
+    /// use to negotiate extensions.
+    pub headers: Vec<(String, String)>,
+    client: reqwest::blocking::Client,
+    permits: Semaphore,
+}
+
+impl HttpTileProvider {
+    pub fn new(
+        template: UrlTemplate,
+        cache: Option<DiskCache>,
+        max_concurrent: usize,
+    ) -> anyhow::Result<Self> {
+        let client = reqwest::blocking::Client::builder()
+            .user_agent(concat!(
+                env!("CARGO_PKG_NAME"),
+                "/",
+                env!("CARGO_PKG_VERSION")
+            ))
+            .timeout(Duration::from_secs(30))
+            .build()?;
+        Ok(Self {
+            template,
+            cache,
+            retries: 3,
+            retry_delay: Duration::from_millis(250),
+            headers: Vec::new(),
+            client,
+            permits: Semaphore::new(max_concurrent.max(1)),
+        })
+    }
+
+    pub fn fetch(&self, tile: TileId) -> anyhow::Result<Vec<u8>> {
+        let url = self.template.expand(tile);
+        if let Some(body) = self.cache.as_ref().and_then(|cache| cache.get(&url)) {
+            return Ok(body);
+        }
+
+        let body = self.download(&url)?;
+        if let Some(cache) = &self.cache {
+            if let Err(e) = cache.put(&url, &body) {
+                log::warn!("Could not cache {}: {}", url, e);
+            }
+        }
+        Ok(body)
+    }
+
+    fn download(&self, url: &str) -> anyhow::Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            let permit = self.permits.acquire();
+            let mut request = self.client.get(url);
+            for (name, value) in &self.headers {
+                request = request.header(name, value);
+            }
+            let error = match request.send() {
+                Ok(response) if response.status().is_success() => match response.bytes() {
+                    Ok(body) => return Ok(body.to_vec()),
+                    Err(e) => anyhow::Error::new(e).context(format!("reading {}", url)),
+                },
+                Ok(response) => {
+                    let status = response.status();
+                    let error = anyhow!("{} answered {}", url, status);
+                    if !status.is_server_error() && status != StatusCode::TOO_MANY_REQUESTS {
+                        return Err(error);
+                    }
+                    error
+                }
+                Err(e) => anyhow::Error::new(e).context(format!("requesting {}", url)),
+            };
+            drop(permit);
+            if attempt >= self.retries {
+                return Err(error);
+            }
+            thread::sleep(self.retry_delay * 2u32.pow(attempt));
+            attempt += 1;
+        }
+    }
+}
+
+/// A disk cache directory for tiles from `provider`, below the platform's
+/// cache location.
+pub fn default_cache_directory(provider: &str) -> PathBuf {
+    let base = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+        .unwrap_or_else(std::env::temp_dir);
+    base.join(env!("CARGO_PKG_NAME")).join(provider)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// A stand-in tile server. Every path answers with its own text, except
+    /// that paths containing `missing` are `404`, and paths containing
+    /// `flaky` are `503` and those containing `truncated` end early for the
+    /// first two requests.
+    struct TestServer {
+        address: String,
+        requests: Arc<AtomicUsize>,
+        peak_concurrency: Arc<AtomicUsize>,
+    }
+
+    impl TestServer {
+        fn start(delay: Duration) -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let address = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(AtomicUsize::new(0));
+            let peak_concurrency = Arc::new(AtomicUsize::new(0));
+            let active = Arc::new(AtomicUsize::new(0));
+            let flaky_failures = Arc::new(AtomicUsize::new(0));
+
+            let server_requests = requests.clone();
+            let server_peak = peak_concurrency.clone();
+            thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let mut stream = stream.unwrap();
+                    let requests = server_requests.clone();
+                    let peak = server_peak.clone();
+                    let active = active.clone();
+                    let flaky_failures = flaky_failures.clone();
+                    thread::spawn(move || {
+                        let mut reader = BufReader::new(stream.try_clone().unwrap());
+                        let mut request_line = String::new();
+                        reader.read_line(&mut request_line).unwrap();
+                        let mut header = String::new();
+                        while reader.read_line(&mut header).unwrap() > 2 {
+                            header.clear();
+                        }
+
+                        requests.fetch_add(1, Ordering::SeqCst);
+                        let now_active = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        peak.fetch_max(now_active, Ordering::SeqCst);
+                        thread::sleep(delay);
+                        active.fetch_sub(1, Ordering::SeqCst);
+
+                        let path = request_line.split(' ').nth(1).unwrap_or("").to_string();
+                        let status = if path.contains("missing") {
+                            "404 Not Found"
+                        } else if path.contains("flaky")
+                            && flaky_failures.fetch_add(1, Ordering::SeqCst) < 2
+                        {
+                            "503 Service Unavailable"
+                        } else {
+                            "200 OK"
+                        };
+                        let truncated = path.contains("truncated")
+                            && flaky_failures.fetch_add(1, Ordering::SeqCst) < 2;
+                        let length = path.len() + if truncated { 10 } else { 0 };
+                        let _ = write!(
+                            stream,
+                            "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            status, length, path
+                        );
+                    });
+                }
+            });
+
+            Self {
+                address,
+                requests,
+                peak_concurrency,
+            }
+        }
+
+        fn requests(&self) -> usize {
+            self.requests.load(Ordering::SeqCst)
+        }
+    }
+
+    fn temporary_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!(
+            "{}-{}-{}",
+            env!("CARGO_PKG_NAME"),
+            name,
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn provider(template: UrlTemplate, cache: Option<DiskCache>) -> HttpTileProvider {
+        let mut provider = HttpTileProvider::new(template, cache, 2).unwrap();
+        provider.retry_delay = Duration::from_millis(1);
+        provider
+    }
+
+    #[test]
+    fn templates_expand() {
+        let tile = TileId::new(3, 5, 1);
+        assert_eq!(
+            UrlTemplate::xyz("https://t/{z}/{x}/{y}.png").expand(tile),
+            "https://t/3/5/1.png"
+        );
+        assert_eq!(
+            UrlTemplate::tms("https://t/{z}/{x}/{y}.png").expand(tile),
+            "https://t/3/5/6.png"
+        );
+        assert_eq!(
+            UrlTemplate::xyz("https://t/{z}/{x}/{-y}.png").expand(tile),
+            "https://t/3/5/6.png"
+        );
+        assert_eq!(
+            UrlTemplate::xyz("https://t/wmts/{TileMatrix}/{TileRow}/{TileCol}").expand(tile),
+            "https://t/wmts/3/1/5"
+        );
+
+        let template =
+            UrlTemplate::xyz("https://{s}.t/{z}/{x}/{y}.png").with_subdomains(&["a", "b", "c"]);
+        assert_eq!(template.expand(tile), "https://a.t/3/5/1.png");
+        assert_eq!(template.expand(tile), template.expand(tile));
+        assert_eq!(
+            template.expand(TileId::new(3, 6, 1)),
+            "https://b.t/3/6/1.png"
+        );
+    }
+
+    #[test]
+    fn cached_tiles_are_served_offline() {
+        let server = TestServer::start(Duration::ZERO);
+        let cache = DiskCache::new(temporary_directory("offline"));
+        let template = UrlTemplate::xyz(&format!("{}/{{z}}/{{x}}/{{y}}.png", server.address));
+
+        let online = provider(template.clone(), Some(cache.clone()));
+        assert_eq!(online.fetch(TileId::new(2, 1, 3)).unwrap(), b"/2/1/3.png");
+        assert_eq!(online.fetch(TileId::new(2, 1, 3)).unwrap(), b"/2/1/3.png");
+        assert_eq!(server.requests(), 1);
+
+        // A later session finds the tile on disk without asking the server
+        let offline = provider(template, Some(cache.clone()));
+        assert_eq!(offline.fetch(TileId::new(2, 1, 3)).unwrap(), b"/2/1/3.png");
+        assert_eq!(server.requests(), 1);
+
+        fs::remove_dir_all(&cache.directory).unwrap();
+    }
+
+    #[test]
+    fn server_errors_are_retried() {
+        let server = TestServer::start(Duration::ZERO);
+        let flaky = provider(
+            UrlTemplate::xyz(&format!("{}/flaky/{{z}}/{{x}}/{{y}}", server.address)),
+            None,
+        );
+        assert_eq!(flaky.fetch(TileId::new(0, 0, 0)).unwrap(), b"/flaky/0/0/0");
+        assert_eq!(server.requests(), 3);
+
+        let missing = provider(
+            UrlTemplate::xyz(&format!("{}/missing/{{z}}/{{x}}/{{y}}", server.address)),
+            None,
+        );
+        assert!(missing.fetch(TileId::new(0, 0, 0)).is_err());
+        assert_eq!(server.requests(), 4, "a 404 should not be retried");
+
+        let server = TestServer::start(Duration::ZERO);
+        let truncated = provider(
+            UrlTemplate::xyz(&format!("{}/truncated/{{z}}/{{x}}/{{y}}", server.address)),
+            None,
+        );
+        assert_eq!(
+            truncated.fetch(TileId::new(0, 0, 0)).unwrap(),
+            b"/truncated/0/0/0"
+        );
+        assert_eq!(server.requests(), 3);
+    }
+
+    #[test]
+    fn concurrent_requests_are_limited() {
+        let server = TestServer::start(Duration::from_millis(50));
+        let provider = Arc::new(provider(
+            UrlTemplate::xyz(&format!("{}/{{z}}/{{x}}/{{y}}", server.address)),
+            None,
+        ));
+        let workers = (0..8)
+            .map(|x| {
+                let provider = provider.clone();
+                thread::spawn(move || provider.fetch(TileId::new(3, x, 0)).unwrap())
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(server.requests(), 8);
+        assert!(server.peak_concurrency.load(Ordering::SeqCst) <= 2);
+    }
+}
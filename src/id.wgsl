@@ -0,0 +1,68 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+struct Object {
+    id: u32;
+};
+[[group(1), binding(0)]]
+var<uniform> object: Object;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+};
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    // Relative to the camera, like everything else
+    [[location(0)]] position: vec3<f32>;
+    [[location(1), interpolate(flat)]] id: u32;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+    [[builtin(instance_index)]] instance_index: u32,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let world_position = model_matrix * vec4<f32>(model.position, 1.0);
+
+    var out: VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.position = world_position.xyz;
+    // Keep in step with id_buffer.rs
+    out.id = ((object.id + 1u) << 20u) | (instance_index & 0xfffffu);
+    return out;
+}
+
+// Fragment shader
+
+struct FragmentOutput {
+    [[location(0)]] id: u32;
+    [[location(1)]] position: vec4<f32>;
+};
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    out.id = in.id;
+    out.position = vec4<f32>(in.position, 1.0);
+    return out;
+}
@@ -0,0 +1,482 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+use std::num::{NonZeroU32, NonZeroU64};
+use std::ops::Range;
+use std::sync::mpsc;
+use wgpu::util::DeviceExt;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+use crate::model::{self, Vertex};
+use crate::{camera, texture};
+
+/// How many objects an ID buffer tells apart.
+pub const MAX_OBJECTS: u32 = 64;
+/// Bits of an ID holding the instance index; the object is above them.
+const INSTANCE_BITS: u32 = 20;
+/// Dynamic uniform offsets must be multiples of this.
+const OBJECT_STRIDE: u32 = 256;
+/// Rows copied out of a texture must be multiples of this many bytes.
+const ROW_ALIGNMENT: u64 = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as u64;
+
+const ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+const POSITION_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba32Float;
+
+/// The object and instance an ID was drawn by, `None` for the background.
+/// id.wgsl writes `object + 1` above the instance index, leaving 0 for the
+/// background; instances past 2^20 wrap around.
+pub fn decode_id(id: u32) -> Option<(u32, u32)> {
+    match id >> INSTANCE_BITS {
+        0 => None,
+        object => Some((object - 1, id & ((1 << INSTANCE_BITS) - 1))),
+    }
+}
+
+/// What was drawn at a pixel of the ID buffer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdPick {
+    pub object: u32,
+    pub instance: u32,
+    /// ECEF position of the surface there.
+    pub position: Vector3<f64>,
+    /// Meters from the camera.
+    pub distance: f64,
+}
+
+enum Readback {
+    Idle,
+    /// The pixel to read once the scene is next drawn.
+    Requested(PhysicalPosition<u32>),
+    /// Copied into the readback buffer, waiting to be mapped.
+    Copied(Point3<f64>),
+    Mapping(Point3<f64>),
+}
+
+/// An offscreen pass drawing the ID of the object and instance at every
+/// pixel, with the camera-relative position of the surface there, for exact
+/// picking of whatever is drawn into it.
+///
+/// The pass only runs when a pick has been requested. The pixel under the
+/// cursor is read back without stalling the frame and turns up in
+/// [`IdBuffer::poll`] a frame or two later.
+///
+/// Instance IDs come from the `instance_index` builtin, which some backends
+/// count from 0 in every draw whatever the instance range.
+pub struct IdBuffer {
+    id_texture: wgpu::Texture,
+    id_view: wgpu::TextureView,
+    position_texture: wgpu::Texture,
+    position_view: wgpu::TextureView,
+    depth_texture: texture::Texture,
+    /// The size of the targets in pixels.
+    size: PhysicalSize<u32>,
+    pipeline: wgpu::RenderPipeline,
+    object_bind_group: wgpu::BindGroup,
+    readback_buffer: wgpu::Buffer,
+    readback: Readback,
+    sender: mpsc::Sender<bool>,
+    receiver: mpsc::Receiver<bool>,
+}
+
+impl IdBuffer {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        projection: &camera::Projection,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        // Every object has a slot holding its number, picked by the dynamic
+        // offset of the draw
+        let mut objects = vec![0u8; (MAX_OBJECTS * OBJECT_STRIDE) as usize];
+        for object in 0..MAX_OBJECTS {
+            let start = (object * OBJECT_STRIDE) as usize;
+            objects[start..start + 4].copy_from_slice(&object.to_le_bytes());
+        }
+        let object_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("ID Object Buffer"),
+            contents: &objects,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let object_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: NonZeroU64::new(4),
+                    },
+                    count: None,
+                }],
+                label: Some("id_object_bind_group_layout"),
+            });
+        let object_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &object_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                    buffer: &object_buffer,
+                    offset: 0,
+                    size: NonZeroU64::new(4),
+                }),
+            }],
+            label: Some("id_object_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("ID Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &object_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("ID Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("id.wgsl").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("ID Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[
+                    wgpu::ColorTargetState {
+                        format: ID_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    },
+                    wgpu::ColorTargetState {
+                        format: POSITION_FORMAT,
+                        blend: None,
+                        write_mask: wgpu::ColorWrites::ALL,
+                    },
+                ],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: Some(wgpu::Face::Back),
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: projection.depth_compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        // One aligned row for the ID, then one for the position
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("ID Readback Buffer"),
+            size: ROW_ALIGNMENT * 2,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let (id_texture, id_view) = Self::create_target(device, config, ID_FORMAT, "ID Texture");
+        let (position_texture, position_view) =
+            Self::create_target(device, config, POSITION_FORMAT, "ID Position Texture");
+        let depth_texture = texture::Texture::create_depth_texture(
+            device,
+            config,
+            projection.sampler_compare(),
+            "id_depth_texture",
+        );
+        let (sender, receiver) = mpsc::channel();
+
+        Self {
+            id_texture,
+            id_view,
+            position_texture,
+            position_view,
+            depth_texture,
+            size: PhysicalSize::new(config.width, config.height),
+            pipeline,
+            object_bind_group,
+            readback_buffer,
+            readback: Readback::Idle,
+            sender,
+            receiver,
+        }
+    }
+
+    fn create_target(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        label: &str,
+    ) -> (wgpu::Texture, wgpu::TextureView) {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: config.width,
+                height: config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        (texture, view)
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        projection: &camera::Projection,
+    ) {
+        (self.id_texture, self.id_view) =
+            Self::create_target(device, config, ID_FORMAT, "ID Texture");
+        (self.position_texture, self.position_view) =
+            Self::create_target(device, config, POSITION_FORMAT, "ID Position Texture");
+        self.depth_texture = texture::Texture::create_depth_texture(
+            device,
+            config,
+            projection.sampler_compare(),
+            "id_depth_texture",
+        );
+        self.size = PhysicalSize::new(config.width, config.height);
+        // The pixel asked for may be outside the new size
+        if let Readback::Requested(pixel) = self.readback {
+            self.readback = Readback::Requested(self.clamp(pixel));
+        }
+    }
+
+    fn clamp(&self, pixel: PhysicalPosition<u32>) -> PhysicalPosition<u32> {
+        PhysicalPosition::new(
+            pixel.x.min(self.size.width - 1),
+            pixel.y.min(self.size.height - 1),
+        )
+    }
+
+    /// Asks for the pixel under `cursor` to be read the next time the pass
+    /// is drawn. A request made while another is being read waits for it.
+    pub fn request(&mut self, cursor: PhysicalPosition<f64>) {
+        let pixel = self.clamp(PhysicalPosition::new(
+            cursor.x.max(0.0) as u32,
+            cursor.y.max(0.0) as u32,
+        ));
+        match self.readback {
+            Readback::Idle | Readback::Requested(_) => self.readback = Readback::Requested(pixel),
+            // Only the latest click matters
+            Readback::Copied(_) | Readback::Mapping(_) => {}
+        }
+    }
+
+    /// Whether the pass needs drawing this frame.
+    pub fn is_requested(&self) -> bool {
+        matches!(self.readback, Readback::Requested(_))
+    }
+
+    /// Starts the ID pass, clearing the targets, with the ID pipeline set.
+    pub fn begin_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth_clear_value: f32,
+    ) -> wgpu::RenderPass<'a> {
+        let clear = wgpu::Operations {
+            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+            store: true,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("ID Pass"),
+            color_attachments: &[
+                wgpu::RenderPassColorAttachment {
+                    view: &self.id_view,
+                    resolve_target: None,
+                    ops: clear,
+                },
+                wgpu::RenderPassColorAttachment {
+                    view: &self.position_view,
+                    resolve_target: None,
+                    ops: clear,
+                },
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: &self.depth_texture.view,
+                depth_ops: Some(wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(depth_clear_value),
+                    store: true,
+                }),
+                stencil_ops: None,
+            }),
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass
+    }
+
+    /// Copies the requested pixel out after the pass, remembering where the
+    /// camera was so the position can be made absolute again.
+    pub fn copy_pixel(&mut self, encoder: &mut wgpu::CommandEncoder, camera_position: Point3<f64>) {
+        let pixel = match self.readback {
+            Readback::Requested(pixel) => pixel,
+            _ => return,
+        };
+        for (i, texture) in [&self.id_texture, &self.position_texture]
+            .into_iter()
+            .enumerate()
+        {
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: pixel.x,
+                        y: pixel.y,
+                        z: 0,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &self.readback_buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: i as u64 * ROW_ALIGNMENT,
+                        bytes_per_row: NonZeroU32::new(ROW_ALIGNMENT as u32),
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        self.readback = Readback::Copied(camera_position);
+    }
+
+    /// Starts mapping the copied pixel. Call after submitting the commands
+    /// [`IdBuffer::copy_pixel`] recorded.
+    pub fn submitted(&mut self) {
+        let camera_position = match self.readback {
+            Readback::Copied(camera_position) => camera_position,
+            _ => return,
+        };
+        let mapped = self
+            .readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read);
+        let sender = self.sender.clone();
+        let task = async move {
+            let _ = sender.send(mapped.await.is_ok());
+        };
+        cfg_if::cfg_if! {
+            if #[cfg(target_arch = "wasm32")] {
+                async_std::task::spawn_local(task);
+            } else {
+                async_std::task::spawn(task);
+            }
+        }
+        self.readback = Readback::Mapping(camera_position);
+    }
+
+    /// The result of the last request once it has been read back, `None`
+    /// while it is still on its way or if the pixel showed nothing.
+    pub fn poll(&mut self, device: &wgpu::Device) -> Option<IdPick> {
+        let camera_position = match self.readback {
+            Readback::Mapping(camera_position) => camera_position,
+            _ => return None,
+        };
+        device.poll(wgpu::Maintain::Poll);
+        let mapped = self.receiver.try_recv().ok()?;
+        self.readback = Readback::Idle;
+        if !mapped {
+            log::warn!("Couldn't read the ID buffer back");
+            return None;
+        }
+
+        let (id, relative) = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let id: u32 = bytemuck::pod_read_unaligned(&data[..4]);
+            let position: [f32; 4] =
+                bytemuck::pod_read_unaligned(&data[ROW_ALIGNMENT as usize..][..16]);
+            (id, position)
+        };
+        self.readback_buffer.unmap();
+
+        let (object, instance) = decode_id(id)?;
+        let relative = Vector3::new(relative[0], relative[1], relative[2]).cast::<f64>()?;
+        Some(IdPick {
+            object,
+            instance,
+            position: camera_position.to_vec() + relative,
+            distance: relative.magnitude(),
+        })
+    }
+}
+
+pub trait DrawIds<'a> {
+    /// Draws `mesh` into the ID pass as `object`, with the instance buffer
+    /// already set in slot 1.
+    fn draw_mesh_ids(
+        &mut self,
+        ids: &'a IdBuffer,
+        object: u32,
+        mesh: &'a model::Mesh,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawIds<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_mesh_ids(
+        &mut self,
+        ids: &'b IdBuffer,
+        object: u32,
+        mesh: &'b model::Mesh,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        assert!(object < MAX_OBJECTS, "object {} out of range", object);
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(1, &ids.object_bind_group, &[object * OBJECT_STRIDE]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// As id.wgsl writes them.
+    fn encode_id(object: u32, instance: u32) -> u32 {
+        ((object + 1) << INSTANCE_BITS) | (instance & ((1 << INSTANCE_BITS) - 1))
+    }
+
+    #[test]
+    fn ids_round_trip_and_leave_zero_for_the_background() {
+        assert_eq!(decode_id(0), None);
+        assert_eq!(decode_id(encode_id(0, 0)), Some((0, 0)));
+        assert_eq!(decode_id(encode_id(5, 123_456)), Some((5, 123_456)));
+        assert_eq!(
+            decode_id(encode_id(MAX_OBJECTS - 1, (1 << 20) - 1)),
+            Some((MAX_OBJECTS - 1, (1 << 20) - 1))
+        );
+        // Instances past the limit wrap around
+        assert_eq!(decode_id(encode_id(1, 1 << 20)), Some((1, 0)));
+    }
+}
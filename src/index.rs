@@ -10,11 +10,29 @@ use winit::{
 
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
-use crate::{camera,model,resources,texture};
+use crate::{atmosphere,camera,clock,collision,entities,font,geodesy,globe,id_buffer,labels,model,overlay,picking,resources,satellites,stars,sun,terrain,texture,tiles,vector,vector_tiles};
 
+use atmosphere::DrawSky;
+use entities::DrawEntityPaths;
+use id_buffer::DrawIds;
+use labels::DrawLabels;
 use model::{DrawLight, DrawModel, Vertex};
+use overlay::DrawOverlay;
+use satellites::DrawSatellites;
+use stars::DrawStars;
+use tiles::DrawTiles;
+use vector_tiles::DrawVectorTiles;
 
 const NUM_INSTANCES_PER_ROW: u32 = 10;
+/// The cube model spans -1 to 1, so this is also how high its center sits
+/// above the ground.
+const CUBE_SCALE: f32 = 50.0;
+/// How far in pixels the cursor may move between pressing and releasing the
+/// left button for it to count as a click rather than a drag.
+const CLICK_TOLERANCE: f64 = 4.0;
+/// Objects in the ID buffer.
+const GLOBE_ID: u32 = 0;
+const CUBES_ID: u32 = 1;
 
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -31,91 +49,14 @@ impl CameraUniform {
         }
     }
 
-    // UPDATED!
-    fn update_view_proj(&mut self, camera: &camera::Camera, projection: &camera::Projection) {
-        self.view_position = camera.position.to_homogeneous().into();
+    // Everything on the GPU is relative to the camera, so the camera itself
+    // always sits at the origin.
+    fn update_view_proj(&mut self, camera: &camera::GlobeCamera, projection: &camera::Projection) {
+        self.view_position = cgmath::Point3::origin().to_homogeneous().into();
         self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into()
     }
 }
 
-struct Instance {
-    position: cgmath::Vector3<f32>,
-    rotation: cgmath::Quaternion<f32>,
-}
-
-impl Instance {
-    fn to_raw(&self) -> InstanceRaw {
-        InstanceRaw {
-            model: (cgmath::Matrix4::from_translation(self.position)
-                * cgmath::Matrix4::from(self.rotation))
-            .into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
-        }
-    }
-}
-
-#[repr(C)]
-#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-#[allow(dead_code)]
-struct InstanceRaw {
-    model: [[f32; 4]; 4],
-    normal: [[f32; 3]; 3],
-}
-
-impl model::Vertex for InstanceRaw {
-    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
-        use std::mem;
-        wgpu::VertexBufferLayout {
-            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
-            // We need to switch from using a step mode of Vertex to Instance
-            // This means that our shaders will only change to use the next
-            // instance when the shader starts processing a new instance
-            step_mode: wgpu::VertexStepMode::Instance,
-            attributes: &[
-                wgpu::VertexAttribute {
-                    offset: 0,
-                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials we'll
-                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5 not conflict with them later
-                    shader_location: 5,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
-                // for each vec4. We don't have to do this in code though.
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
-                    shader_location: 6,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
-                    shader_location: 7,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
-                    shader_location: 8,
-                    format: wgpu::VertexFormat::Float32x4,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
-                    shader_location: 9,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
-                    shader_location: 10,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-                wgpu::VertexAttribute {
-                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
-                    shader_location: 11,
-                    format: wgpu::VertexFormat::Float32x3,
-                },
-            ],
-        }
-    }
-}
-
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 struct LightUniform {
@@ -132,34 +73,64 @@ struct State {
     queue: wgpu::Queue,
     config: wgpu::SurfaceConfiguration,
     render_pipeline: wgpu::RenderPipeline,
+    globe_render_pipeline: wgpu::RenderPipeline,
     obj_model: model::Model,
-    camera: camera::Camera,
+    globe: globe::Globe,
+    globe_instances: Vec<model::Instance>,
+    globe_instance_buffer: wgpu::Buffer,
+    imagery: tiles::ImageryLayer,
+    /// The tiles the cubes are clamped to the ground with.
+    ground_level: tiles::HeightLevel,
+    overlays: Vec<overlay::VectorOverlay>,
+    vector_tiles: Option<vector_tiles::VectorTileLayer>,
+    labels: Option<labels::LabelLayer>,
+    overlay_renderer: overlay::OverlayRenderer,
+    satellites: satellites::Satellites,
+    entities: entities::EntityLayer,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    camera: camera::GlobeCamera,
     projection: camera::Projection,
-    camera_controller: camera::CameraController,
+    camera_controller: camera::GlobeCameraController,
     camera_uniform: CameraUniform,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
-    instances: Vec<Instance>,
+    instances: Vec<model::Instance>,
     #[allow(dead_code)]
     instance_buffer: wgpu::Buffer,
     depth_texture: texture::Texture,
     size: winit::dpi::PhysicalSize<u32>,
+    light_position: cgmath::Vector3<f64>,
+    /// The simulated time, which puts the sun, and with it the light, where
+    /// it is.
+    clock: clock::Clock,
     light_uniform: LightUniform,
     light_buffer: wgpu::Buffer,
     light_bind_group: wgpu::BindGroup,
     light_render_pipeline: wgpu::RenderPipeline,
+    atmosphere: atmosphere::Atmosphere,
+    stars: stars::StarField,
     #[allow(dead_code)]
     debug_material: model::Material,
     mouse_pressed: bool,
+    cursor_position: winit::dpi::PhysicalPosition<f64>,
+    /// Where the left button went down, to tell clicks from drags.
+    press_position: Option<winit::dpi::PhysicalPosition<f64>>,
+    pick_listeners: Vec<picking::PickListener>,
+    /// Picks by reading back an ID buffer rather than casting rays on the
+    /// CPU, with `CHAIN_EARTH_GPU_PICKING` set.
+    id_buffer: Option<id_buffer::IdBuffer>,
 }
 
+#[allow(clippy::too_many_arguments)]
 fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
     color_format: wgpu::TextureFormat,
     depth_format: Option<wgpu::TextureFormat>,
+    depth_compare: wgpu::CompareFunction,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
+    fragment_entry_point: &str,
 ) -> wgpu::RenderPipeline {
     let shader = device.create_shader_module(&shader);
 
@@ -173,7 +144,7 @@ fn create_render_pipeline(
         },
         fragment: Some(wgpu::FragmentState {
             module: &shader,
-            entry_point: "fs_main",
+            entry_point: fragment_entry_point,
             targets: &[wgpu::ColorTargetState {
                 format: color_format,
                 blend: Some(wgpu::BlendState {
@@ -198,7 +169,7 @@ fn create_render_pipeline(
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
             depth_write_enabled: true,
-            depth_compare: wgpu::CompareFunction::Less,
+            depth_compare,
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
@@ -213,6 +184,340 @@ fn create_render_pipeline(
     })
 }
 
+/// Imagery comes from the URL template in `CHAIN_EARTH_IMAGERY_URL`, e.g.
+/// `https://{s}.tile.example.org/{z}/{x}/{y}.png`, when it is set, and from
+/// the bundled tile directory otherwise. With `CHAIN_EARTH_IMAGERY_TMS` set,
+/// `{y}` counts rows from the south as TMS servers do.
+fn imagery_source() -> tiles::TileSource {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(template) = std::env::var("CHAIN_EARTH_IMAGERY_URL") {
+        use crate::http_tiles::{
+            default_cache_directory, DiskCache, HttpTileProvider, UrlTemplate,
+        };
+
+        let template = if std::env::var_os("CHAIN_EARTH_IMAGERY_TMS").is_some() {
+            UrlTemplate::tms(&template)
+        } else {
+            UrlTemplate::xyz(&template)
+        };
+        let template = template.with_subdomains(&["a", "b", "c"]);
+        let cache = DiskCache::new(default_cache_directory("imagery"));
+        match HttpTileProvider::new(template, Some(cache), 6) {
+            Ok(provider) => return provider.into(),
+            Err(e) => log::error!("Falling back to local imagery: {}", e),
+        }
+    }
+    tiles::TileDirectory::new("tiles", "png").into()
+}
+
+/// Placed models stand on the most detailed terrain loaded, or only on the
+/// tiles of the zoom level in `CHAIN_EARTH_GROUND_LEVEL`, if set, so they
+/// don't move as finer tiles come and go.
+fn ground_level() -> tiles::HeightLevel {
+    match std::env::var("CHAIN_EARTH_GROUND_LEVEL") {
+        Ok(level) => match level.parse() {
+            Ok(level) => tiles::HeightLevel::Exact(level),
+            Err(e) => {
+                log::error!("Bad ground level {:?}: {}", level, e);
+                tiles::HeightLevel::BestAvailable
+            }
+        },
+        Err(_) => tiles::HeightLevel::BestAvailable,
+    }
+}
+
+/// The clock starts at the ISO 8601 UTC time in `CHAIN_EARTH_TIME`, if set,
+/// and now otherwise, running `CHAIN_EARTH_CLOCK_MULTIPLIER` times faster
+/// than real time.
+fn simulation_clock() -> clock::Clock {
+    let time = match std::env::var("CHAIN_EARTH_TIME") {
+        Ok(text) => clock::UtcTime::parse(&text).unwrap_or_else(|e| {
+            log::error!("{}", e);
+            clock::UtcTime::now()
+        }),
+        Err(_) => clock::UtcTime::now(),
+    };
+    let mut clock = clock::Clock::new(time);
+    if let Ok(multiplier) = std::env::var("CHAIN_EARTH_CLOCK_MULTIPLIER") {
+        match multiplier.parse() {
+            Ok(multiplier) => clock.set_multiplier(multiplier),
+            Err(e) => log::error!("Bad clock multiplier {:?}: {}", multiplier, e),
+        }
+    }
+    clock
+}
+
+/// Night lights come from the equirectangular image named by
+/// `CHAIN_EARTH_NIGHT_LIGHTS` in the resource directory, such as NASA's
+/// Black Marble. Without one the night side stays dark.
+async fn night_lights_texture(device: &wgpu::Device, queue: &wgpu::Queue) -> texture::Texture {
+    if let Ok(file_name) = std::env::var("CHAIN_EARTH_NIGHT_LIGHTS") {
+        match resources::load_texture(&file_name, false, device, queue).await {
+            Ok(texture) => return texture,
+            Err(e) => log::error!("Couldn't load night lights {}: {:?}", file_name, e),
+        }
+    }
+    globe::dark_texture(device, queue).unwrap()
+}
+
+/// Stars come from the catalog named by `CHAIN_EARTH_STAR_CATALOG` in the
+/// resource directory, in the CSV layout of the HYG database. Without one
+/// only the brightest few dozen are shown.
+async fn star_catalog() -> Vec<stars::Star> {
+    if let Ok(file_name) = std::env::var("CHAIN_EARTH_STAR_CATALOG") {
+        let catalog = resources::load_string(&file_name)
+            .await
+            .and_then(|text| stars::parse_catalog(&text, stars::MAGNITUDE_LIMIT));
+        match catalog {
+            Ok(catalog) => return catalog,
+            Err(e) => log::error!("Couldn't load star catalog {}: {:?}", file_name, e),
+        }
+    }
+    stars::parse_catalog(stars::BRIGHT_STARS, stars::MAGNITUDE_LIMIT).unwrap()
+}
+
+/// Satellites come from the two- or three-line element sets in the file
+/// named by `CHAIN_EARTH_TLES` in the resource directory, such as those
+/// CelesTrak publishes.
+async fn tracked_satellites() -> Vec<satellites::Satellite> {
+    let file_name = match std::env::var("CHAIN_EARTH_TLES") {
+        Ok(file_name) => file_name,
+        Err(_) => return Vec::new(),
+    };
+    let satellites = resources::load_string(&file_name)
+        .await
+        .and_then(|text| satellites::satellites_from_tles(&text));
+    match satellites {
+        Ok(satellites) => {
+            log::info!("Tracking {} satellites", satellites.len());
+            satellites
+        }
+        Err(e) => {
+            log::error!("Couldn't load TLEs {}: {:?}", file_name, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Entities replayed along their trajectories come from the CZML document
+/// named by `CHAIN_EARTH_CZML` in the resource directory.
+async fn czml_entities() -> Vec<entities::Entity> {
+    let file_name = match std::env::var("CHAIN_EARTH_CZML") {
+        Ok(file_name) => file_name,
+        Err(_) => return Vec::new(),
+    };
+    let entities = resources::load_string(&file_name)
+        .await
+        .and_then(|text| entities::parse_czml(&text));
+    match entities {
+        Ok(entities) => {
+            log::info!("Replaying {} entities", entities.len());
+            entities
+        }
+        Err(e) => {
+            log::error!("Couldn't load CZML {}: {:?}", file_name, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Vector overlays come from the GeoJSON, Shapefile, KML and KMZ files
+/// listed in `CHAIN_EARTH_VECTOR_FILES`, styled by their simplestyle-spec
+/// properties.
+fn vector_overlays(device: &wgpu::Device) -> Vec<overlay::VectorOverlay> {
+    let mut overlays = Vec::new();
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(paths) = std::env::var_os("CHAIN_EARTH_VECTOR_FILES") {
+        for path in std::env::split_paths(&paths) {
+            match vector::read_features(&path) {
+                Ok(features) => overlays.push(overlay::VectorOverlay::new(
+                    device,
+                    &features,
+                    |feature| {
+                        vector::Style::from_properties(&feature.properties, &Default::default())
+                    },
+                    0.0,
+                )),
+                Err(e) => log::error!("Couldn't load {}: {:?}", path.display(), e),
+            }
+        }
+    }
+    overlays
+}
+
+/// Place names are the `name` or `NAME` of the points in the GeoJSON,
+/// Shapefile, KML or KMZ file named by `CHAIN_EARTH_PLACE_NAMES`. They grow
+/// smaller as the camera moves away, and where they crowd each other the
+/// places with the lowest `scalerank`, or failing that the largest
+/// `population`, are named.
+fn place_names() -> Vec<labels::Label> {
+    let mut names = Vec::new();
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(path) = std::env::var_os("CHAIN_EARTH_PLACE_NAMES") {
+        let style = labels::LabelStyle {
+            scale_by_distance: Some(labels::ScaleByDistance {
+                near: 100_000.0,
+                near_scale: 1.0,
+                far: 10_000_000.0,
+                far_scale: 0.6,
+            }),
+            ..Default::default()
+        };
+        match vector::read_features(std::path::Path::new(&path)) {
+            Ok(features) => {
+                for feature in features {
+                    let name = ["name", "NAME"]
+                        .iter()
+                        .find_map(|key| feature.properties.get(*key)?.as_str());
+                    let points = match &feature.geometry {
+                        vector::Geometry::Point(point) => std::slice::from_ref(point),
+                        vector::Geometry::MultiPoint(points) => points.as_slice(),
+                        _ => continue,
+                    };
+                    // Lower scale ranks are more important, as are more
+                    // populous places
+                    let number = |keys: [&str; 2]| {
+                        keys.iter()
+                            .find_map(|key| feature.properties.get(*key)?.as_f64())
+                    };
+                    let priority = match number(["scalerank", "SCALERANK"]) {
+                        Some(rank) => -rank as f32,
+                        None => number(["population", "POP_MAX"])
+                            .map_or(0.0, |population| population.ln_1p() as f32),
+                    };
+                    if let Some(name) = name {
+                        names.extend(points.iter().map(|point| labels::Label {
+                            priority,
+                            ..labels::Label::at(name, point, style)
+                        }));
+                    }
+                }
+            }
+            Err(e) => log::error!(
+                "Couldn't load place names {}: {:?}",
+                path.to_string_lossy(),
+                e
+            ),
+        }
+    }
+    names
+}
+
+/// Labels are drawn in the TrueType or OpenType font named by
+/// `CHAIN_EARTH_FONT` in the resource directory, and not at all without one.
+async fn label_layer(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    config: &wgpu::SurfaceConfiguration,
+    projection: &camera::Projection,
+    camera_bind_group_layout: &wgpu::BindGroupLayout,
+    labels: Vec<labels::Label>,
+) -> Option<labels::LabelLayer> {
+    let file_name = std::env::var("CHAIN_EARTH_FONT").ok()?;
+    let characters: Vec<char> = labels.iter().flat_map(|label| label.text.chars()).collect();
+    let max_height = device.limits().max_texture_dimension_2d;
+    let atlas = resources::load_binary(&file_name)
+        .await
+        .and_then(|data| font::SdfAtlas::new(data, characters, max_height));
+    match atlas {
+        Ok(atlas) => {
+            log::info!("Labelling {} places", labels.len());
+            Some(labels::LabelLayer::new(
+                device,
+                queue,
+                config,
+                projection,
+                camera_bind_group_layout,
+                &atlas,
+                labels,
+            ))
+        }
+        Err(e) => {
+            log::error!("Couldn't load font {}: {:?}", file_name, e);
+            None
+        }
+    }
+}
+
+/// Vector tiles come from the URL template in `CHAIN_EARTH_VECTOR_TILES_URL`,
+/// drawn with the style in the file `CHAIN_EARTH_VECTOR_STYLE` names or, without
+/// one, with the default style.
+fn vector_tile_layer() -> Option<vector_tiles::VectorTileLayer> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Ok(template) = std::env::var("CHAIN_EARTH_VECTOR_TILES_URL") {
+        use crate::http_tiles::{
+            default_cache_directory, DiskCache, HttpTileProvider, UrlTemplate,
+        };
+
+        let style = match std::env::var_os("CHAIN_EARTH_VECTOR_STYLE") {
+            Some(path) => match std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| vector_tiles::VectorStyle::from_json(&text))
+            {
+                Ok(style) => style,
+                Err(e) => {
+                    log::error!("Couldn't load the vector tile style: {:?}", e);
+                    return None;
+                }
+            },
+            None => Default::default(),
+        };
+        let template = UrlTemplate::xyz(&template).with_subdomains(&["a", "b", "c"]);
+        let cache = DiskCache::new(default_cache_directory("vector"));
+        match HttpTileProvider::new(template, Some(cache), 6) {
+            Ok(provider) => return Some(vector_tiles::VectorTileLayer::new(provider, style)),
+            Err(e) => log::error!("Couldn't set up vector tiles: {}", e),
+        }
+    }
+    None
+}
+
+/// Terrain comes from the URL template in `CHAIN_EARTH_TERRAIN_URL`, read
+/// as quantized-mesh when it names `.terrain` files and as Terrain-RGB
+/// otherwise, or from the `.hgt` and GeoTIFF files listed in
+/// `CHAIN_EARTH_DEM`. Without either the globe is a smooth ellipsoid.
+fn elevation_source() -> Option<terrain::ElevationSource> {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        if let Ok(template) = std::env::var("CHAIN_EARTH_TERRAIN_URL") {
+            use crate::http_tiles::{
+                default_cache_directory, DiskCache, HttpTileProvider, UrlTemplate,
+            };
+
+            let quantized_mesh = template.contains(".terrain");
+            let template = UrlTemplate::xyz(&template).with_subdomains(&["a", "b", "c"]);
+            let cache = DiskCache::new(default_cache_directory("terrain"));
+            match HttpTileProvider::new(template, Some(cache), 6) {
+                Ok(mut provider) if quantized_mesh => {
+                    provider.headers.push((
+                        "Accept".to_string(),
+                        "application/vnd.quantized-mesh;extensions=octvertexnormals".to_string(),
+                    ));
+                    return Some(terrain::ElevationSource::QuantizedMesh {
+                        source: provider.into(),
+                        max_level: 15,
+                    });
+                }
+                Ok(provider) => {
+                    return Some(terrain::ElevationSource::TerrainRgb {
+                        source: provider.into(),
+                        max_level: 15,
+                    })
+                }
+                Err(e) => log::error!("Couldn't set up terrain: {}", e),
+            }
+        }
+        if let Some(paths) = std::env::var_os("CHAIN_EARTH_DEM") {
+            let paths = std::env::split_paths(&paths).collect::<Vec<_>>();
+            match terrain::ElevationSource::from_files(&paths) {
+                Ok(source) => return Some(source),
+                Err(e) => log::error!("Couldn't load terrain: {:?}", e),
+            }
+        }
+    }
+    None
+}
+
 impl State {
     async fn new(window: &Window) -> Self {
         let size = window.inner_size();
@@ -298,10 +603,15 @@ impl State {
             });
 
         // UPDATED!
-        let camera = camera::Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
-        let projection =
-            camera::Projection::new(config.width, config.height, cgmath::Deg(45.0), 0.1, 100.0);
-        let camera_controller = camera::CameraController::new(4.0, 0.4);
+        let camera = camera::GlobeCamera::new(cgmath::Deg(35.0), cgmath::Deg(105.0), 2.0e7);
+        // A conventional depth range is kept for comparison, though it can't
+        // resolve the ground from orbit
+        let projection = if std::env::var_os("CHAIN_EARTH_FORWARD_DEPTH").is_some() {
+            camera::Projection::new(config.width, config.height, cgmath::Deg(45.0), 1.0, 1.0e9)
+        } else {
+            camera::Projection::new_reverse_z(config.width, config.height, cgmath::Deg(45.0), 1.0)
+        };
+        let camera_controller = camera::GlobeCameraController::new(0.0015, 0.15);
 
         let mut camera_uniform = CameraUniform::new();
         camera_uniform.update_view_proj(&camera, &projection);
@@ -312,7 +622,9 @@ impl State {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        const SPACE_BETWEEN: f32 = 3.0;
+        // A grid of cubes standing on the ground below the initial camera
+        const SPACE_BETWEEN: f64 = 300.0;
+        let grid_origin = geodesy::Geodetic::from_degrees(35.0, 105.0, 0.0);
         let iter = {
             cfg_if::cfg_if! {
                 if #[cfg(target_arch = "wasm32")] {
@@ -327,32 +639,41 @@ impl State {
         let instances = iter
             .clone()
             .flat_map(|z| {
-                // UPDATED!
                 iter.clone().map(move |x| {
-                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
+                    let east = SPACE_BETWEEN * (x as f64 - NUM_INSTANCES_PER_ROW as f64 / 2.0);
+                    let north = SPACE_BETWEEN * (z as f64 - NUM_INSTANCES_PER_ROW as f64 / 2.0);
 
-                    let position = cgmath::Vector3 { x, y: 0.0, z };
+                    let mut position = geodesy::Geodetic::from_ecef(geodesy::enu_to_ecef(
+                        cgmath::Vector3::new(east, north, 0.0),
+                        &grid_origin,
+                    ));
+                    position.height = CUBE_SCALE as f64;
 
-                    let rotation = if position.is_zero() {
-                        cgmath::Quaternion::from_axis_angle(
-                            cgmath::Vector3::unit_z(),
-                            cgmath::Deg(0.0),
-                        )
+                    let heading = if east == 0.0 && north == 0.0 {
+                        cgmath::Deg(0.0)
                     } else {
-                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
+                        cgmath::Deg(45.0)
                     };
 
-                    Instance { position, rotation }
+                    model::Instance::from_geodetic(
+                        &position,
+                        heading,
+                        cgmath::Deg(0.0),
+                        cgmath::Deg(0.0),
+                        CUBE_SCALE,
+                    )
                 })
             })
             .collect::<Vec<_>>();
 
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
+        let instance_data = instances
+            .iter()
+            .map(|instance| instance.to_raw(camera.position()))
+            .collect::<Vec<_>>();
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Instance Buffer"),
             contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
 
         let camera_bind_group_layout =
@@ -379,13 +700,96 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
-        let obj_model =
-            resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
-                .await
-                .unwrap();
+        let obj_model = resources::load_model(
+            "cube.obj",
+            &device,
+            &queue,
+            &texture_bind_group_layout,
+            true,
+        )
+        .await
+        .unwrap();
+
+        let globe = {
+            let diffuse_texture = globe::graticule_texture(&device, &queue).unwrap();
+            let normal_texture = globe::flat_normal_texture(&device, &queue).unwrap();
+            let material = model::Material::new(
+                &device,
+                "globe-material",
+                diffuse_texture,
+                normal_texture,
+                &texture_bind_group_layout,
+            );
+            globe::Globe::new(
+                &device,
+                2,
+                16,
+                material,
+                globe::TextureProjection::Geographic,
+            )
+        };
+        let globe_instances = globe
+            .centers
+            .iter()
+            .map(|&position| model::Instance::new(position, cgmath::Quaternion::one()))
+            .collect::<Vec<_>>();
+        let globe_instance_data = globe_instances
+            .iter()
+            .map(|instance| instance.to_raw(camera.position()))
+            .collect::<Vec<_>>();
+        let globe_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Globe Instance Buffer"),
+            contents: bytemuck::cast_slice(&globe_instance_data),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let mut imagery = tiles::ImageryLayer::new(&device, &queue, imagery_source()).unwrap();
+        imagery.terrain = elevation_source();
+        let overlays = vector_overlays(&device);
+        let vector_tiles = vector_tile_layer();
+        let labels = label_layer(
+            &device,
+            &queue,
+            &config,
+            &projection,
+            &camera_bind_group_layout,
+            place_names(),
+        )
+        .await;
+        let overlay_renderer =
+            overlay::OverlayRenderer::new(&device, &config, &projection, &camera_bind_group_layout);
+        let satellites = satellites::Satellites::new(
+            &device,
+            &config,
+            &projection,
+            &camera_bind_group_layout,
+            tracked_satellites().await,
+        );
+        let bounds = obj_model.bounds();
+        let entities = entities::EntityLayer::new(
+            &device,
+            &config,
+            &projection,
+            &camera_bind_group_layout,
+            czml_entities().await,
+            ((bounds.max - bounds.min).magnitude() / 2.0) as f32,
+        );
+        let id_buffer = std::env::var_os("CHAIN_EARTH_GPU_PICKING").map(|_| {
+            id_buffer::IdBuffer::new(&device, &config, &projection, &camera_bind_group_layout)
+        });
 
+        // The light is the sun, where it stands at the simulated time
+        let clock = simulation_clock();
+        let overhead = sun::subsolar_point(clock.time);
+        log::info!(
+            "{}, sun overhead at {:.1}, {:.1}",
+            clock,
+            overhead.latitude.to_degrees(),
+            overhead.longitude.to_degrees()
+        );
+        let light_position = sun::position(clock.time);
         let light_uniform = LightUniform {
-            position: [2.0, 2.0, 2.0],
+            position: model::camera_relative(light_position, camera.position()).into(),
             _padding: 0,
             color: [1.0, 1.0, 1.0],
             _padding2: 0,
@@ -397,32 +801,83 @@ impl State {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
+        let night_lights = night_lights_texture(&device, &queue).await;
+
         let light_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
                     },
-                    count: None,
-                }],
+                    // Night lights for the dark side of the globe
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
                 label: None,
             });
 
         let light_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout: &light_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: light_buffer.as_entire_binding(),
-            }],
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: light_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&night_lights.view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&night_lights.sampler),
+                },
+            ],
             label: None,
         });
 
-        let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+        let depth_texture = texture::Texture::create_depth_texture(
+            &device,
+            &config,
+            projection.sampler_compare(),
+            "depth_texture",
+        );
+
+        let atmosphere = atmosphere::Atmosphere::new(
+            &device,
+            &queue,
+            &config,
+            atmosphere::AtmosphereParams::default(),
+        );
+        atmosphere.update(&queue, &camera, &projection, light_position);
+
+        let stars = stars::StarField::new(
+            &device,
+            &config,
+            &camera_bind_group_layout,
+            &star_catalog().await,
+        );
+        stars.update(&queue, clock.time, config.width, config.height);
 
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -431,22 +886,43 @@ impl State {
                     &texture_bind_group_layout,
                     &camera_bind_group_layout,
                     &light_bind_group_layout,
+                    &atmosphere.bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
 
+        let shader_source = atmosphere::shader_library(3) + include_str!("shader.wgsl");
         let render_pipeline = {
             let shader = wgpu::ShaderModuleDescriptor {
                 label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
+                source: wgpu::ShaderSource::Wgsl(shader_source.as_str().into()),
+            };
+            create_render_pipeline(
+                &device,
+                &render_pipeline_layout,
+                config.format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                projection.depth_compare(),
+                &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
+                shader,
+                "fs_main",
+            )
+        };
+        // The same, with night lights
+        let globe_render_pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Globe Shader"),
+                source: wgpu::ShaderSource::Wgsl(shader_source.as_str().into()),
             };
             create_render_pipeline(
                 &device,
                 &render_pipeline_layout,
                 config.format,
                 Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), InstanceRaw::desc()],
+                projection.depth_compare(),
+                &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
                 shader,
+                "fs_globe",
             )
         };
 
@@ -465,8 +941,10 @@ impl State {
                 &layout,
                 config.format,
                 Some(texture::Texture::DEPTH_FORMAT),
+                projection.depth_compare(),
                 &[model::ModelVertex::desc()],
                 shader,
+                "fs_main",
             )
         };
 
@@ -506,7 +984,20 @@ impl State {
             queue,
             config,
             render_pipeline,
+            globe_render_pipeline,
             obj_model,
+            globe,
+            globe_instances,
+            globe_instance_buffer,
+            imagery,
+            ground_level: ground_level(),
+            overlays,
+            vector_tiles,
+            labels,
+            overlay_renderer,
+            satellites,
+            entities,
+            texture_bind_group_layout,
             camera,
             projection,
             camera_controller,
@@ -517,13 +1008,21 @@ impl State {
             instance_buffer,
             depth_texture,
             size,
+            light_position,
+            clock,
             light_uniform,
             light_buffer,
             light_bind_group,
             light_render_pipeline,
+            atmosphere,
+            stars,
             #[allow(dead_code)]
             debug_material,
             mouse_pressed: false,
+            cursor_position: winit::dpi::PhysicalPosition::new(0.0, 0.0),
+            press_position: None,
+            pick_listeners: Vec::new(),
+            id_buffer,
         }
     }
 
@@ -534,8 +1033,36 @@ impl State {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.depth_texture = texture::Texture::create_depth_texture(
+                &self.device,
+                &self.config,
+                self.projection.sampler_compare(),
+                "depth_texture",
+            );
+            if let Some(id_buffer) = &mut self.id_buffer {
+                id_buffer.resize(&self.device, &self.config, &self.projection);
+            }
+            self.overlay_renderer.resize(
+                &self.queue,
+                new_size.width,
+                new_size.height,
+                self.projection.fovy(),
+            );
+            self.satellites.resize(
+                &self.queue,
+                new_size.width,
+                new_size.height,
+                self.projection.fovy(),
+            );
+            self.entities.resize(
+                &self.queue,
+                new_size.width,
+                new_size.height,
+                self.projection.fovy(),
+            );
+            if let Some(labels) = &self.labels {
+                labels.resize(&self.queue, new_size.width, new_size.height);
+            }
         }
     }
 
@@ -549,24 +1076,125 @@ impl State {
                         ..
                     },
                 ..
-            } => self.camera_controller.process_keyboard(*key, *state),
+            } => {
+                self.camera_controller.process_keyboard(*key, *state)
+                    || self.clock.process_keyboard(*key, *state)
+            }
             WindowEvent::MouseWheel { delta, .. } => {
                 self.camera_controller.process_scroll(delta);
                 true
             }
-            WindowEvent::MouseInput {
-                button: MouseButton::Left,
-                state,
-                ..
-            } => {
-                self.mouse_pressed = *state == ElementState::Pressed;
+            WindowEvent::CursorMoved { position, .. } => {
+                self.cursor_position = *position;
+                true
+            }
+            WindowEvent::MouseInput { button, state, .. } => {
+                self.mouse_pressed = self.camera_controller.process_mouse_button(*button, *state);
+                if *button == MouseButton::Left {
+                    match state {
+                        ElementState::Pressed => self.press_position = Some(self.cursor_position),
+                        ElementState::Released => {
+                            let pressed = self.press_position.take();
+                            let moved = pressed.map(|pressed| {
+                                (pressed.x - self.cursor_position.x)
+                                    .hypot(pressed.y - self.cursor_position.y)
+                            });
+                            if moved.is_some_and(|moved| moved <= CLICK_TOLERANCE) {
+                                self.click();
+                            }
+                        }
+                    }
+                }
                 true
             }
             _ => false,
         }
     }
 
+    /// Calls `listener` with whatever is under the cursor each time the
+    /// scene is clicked.
+    fn on_pick(&mut self, listener: impl FnMut(&picking::Pick) + 'static) {
+        self.pick_listeners.push(Box::new(listener));
+    }
+
+    /// The nearest cube or point on the ground under `cursor`.
+    fn pick(&self, cursor: winit::dpi::PhysicalPosition<f64>) -> Option<picking::Pick> {
+        let ray = picking::Ray::from_cursor(&self.camera, &self.projection, cursor, self.size);
+        let globe = picking::pick_globe(&ray, |latitude, longitude| {
+            self.imagery
+                .height_at(latitude, longitude, tiles::HeightLevel::BestAvailable)
+        });
+        let cubes = picking::pick_instances(&ray, &self.instances, |local| {
+            self.obj_model.intersect_ray(local)
+        });
+        picking::nearest(picking::nearest(globe, cubes), self.pick_satellite(cursor))
+    }
+
+    /// The satellite whose marker is under `cursor`.
+    fn pick_satellite(&self, cursor: winit::dpi::PhysicalPosition<f64>) -> Option<picking::Pick> {
+        let ray = picking::Ray::from_cursor(&self.camera, &self.projection, cursor, self.size);
+        // Markers are the same size at any distance, so they're hit within
+        // an angle rather than a radius
+        let pixel_angle =
+            2.0 * (self.projection.fovy().0 as f64 / 2.0).tan() / self.size.height as f64;
+        self.satellites.pick(&ray, pixel_angle)
+    }
+
+    fn click(&mut self) {
+        // Satellite markers aren't drawn into the ID buffer, so they're
+        // picked on the CPU even with it
+        let pick = if self.id_buffer.is_some() {
+            self.pick_satellite(self.cursor_position)
+        } else {
+            self.pick(self.cursor_position)
+        };
+        if let Some(pick) = pick {
+            self.select(&pick);
+        } else if let Some(id_buffer) = &mut self.id_buffer {
+            // Answered in a later update
+            id_buffer.request(self.cursor_position);
+        }
+    }
+
+    /// Selects what was picked and tells the listeners.
+    fn select(&mut self, pick: &picking::Pick) {
+        self.satellites.selected = match pick.object {
+            picking::PickedObject::Satellite(i) => Some(i),
+            _ => None,
+        };
+        self.notify(pick);
+    }
+
+    fn notify(&mut self, pick: &picking::Pick) {
+        for listener in &mut self.pick_listeners {
+            listener(pick);
+        }
+    }
+
     fn update(&mut self, dt: instant::Duration) {
+        let id_pick = self
+            .id_buffer
+            .as_mut()
+            .and_then(|id_buffer| id_buffer.poll(&self.device));
+        if let Some(id_pick) = id_pick {
+            let (object, geodetic) = match id_pick.object {
+                CUBES_ID => (
+                    picking::PickedObject::Instance(id_pick.instance as usize),
+                    None,
+                ),
+                _ => (
+                    picking::PickedObject::Globe,
+                    Some(geodesy::Geodetic::from_ecef(id_pick.position)),
+                ),
+            };
+            self.select(&picking::Pick {
+                object,
+                position: id_pick.position,
+                geodetic,
+                distance: id_pick.distance,
+            });
+        }
+
         self.camera_controller.update_camera(&mut self.camera, dt);
         self.camera_uniform
             .update_view_proj(&self.camera, &self.projection);
@@ -576,17 +1204,112 @@ impl State {
             bytemuck::cast_slice(&[self.camera_uniform]),
         );
 
+        // Keep the cubes standing on the terrain as finer tiles load
+        let mut positions = self
+            .instances
+            .iter()
+            .map(|instance| geodesy::Geodetic::from_ecef(instance.position))
+            .collect::<Vec<_>>();
+        self.imagery
+            .clamp_to_ground(&mut positions, CUBE_SCALE as f64, self.ground_level);
+        for (instance, position) in self.instances.iter_mut().zip(&positions) {
+            instance.position = position.to_ecef();
+        }
+
+        // Rebase the instances against the new camera position
+        let camera_position = self.camera.position();
+        let instance_data = self
+            .instances
+            .iter()
+            .map(|instance| instance.to_raw(camera_position))
+            .collect::<Vec<_>>();
+        self.queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&instance_data),
+        );
+        let globe_instance_data = self
+            .globe_instances
+            .iter()
+            .map(|instance| instance.to_raw(camera_position))
+            .collect::<Vec<_>>();
+        self.queue.write_buffer(
+            &self.globe_instance_buffer,
+            0,
+            bytemuck::cast_slice(&globe_instance_data),
+        );
+        for overlay in &self.overlays {
+            overlay.update(&self.queue, camera_position);
+        }
+
+        self.imagery.update(
+            &self.device,
+            &self.queue,
+            &self.texture_bind_group_layout,
+            &self.camera,
+            &self.projection,
+            self.config.height,
+        );
+        if let Some(vector_tiles) = &mut self.vector_tiles {
+            vector_tiles.update(
+                &self.device,
+                &self.queue,
+                &self.camera,
+                &self.projection,
+                self.config.height,
+            );
+        }
+
         // Update the light
-        let old_position: cgmath::Vector3<_> = self.light_uniform.position.into();
+        self.clock.tick(dt);
+        self.light_position = sun::position(self.clock.time);
+        self.satellites
+            .update(&self.device, &self.queue, self.clock.time, camera_position);
+        // Satellite markers are placed first, and labels make room for them
+        if let Some(labels) = &mut self.labels {
+            let mut collisions =
+                collision::CollisionGrid::new(self.config.width, self.config.height);
+            let view_proj = self.projection.calc_matrix() * self.camera.calc_matrix();
+            self.satellites
+                .reserve_markers(&mut collisions, view_proj, camera_position);
+            labels.update(
+                &self.device,
+                &self.queue,
+                &self.camera,
+                &self.projection,
+                &mut collisions,
+                dt,
+            );
+        }
+        let pixel_angle =
+            2.0 * (self.projection.fovy().0 as f64 / 2.0).tan() / self.size.height as f64;
+        self.entities.update(
+            &self.device,
+            &self.queue,
+            self.clock.time,
+            camera_position,
+            pixel_angle,
+        );
+        self.stars.update(
+            &self.queue,
+            self.clock.time,
+            self.config.width,
+            self.config.height,
+        );
         self.light_uniform.position =
-            (cgmath::Quaternion::from_axis_angle((0.0, 1.0, 0.0).into(), cgmath::Deg(1.0))
-                * old_position)
-                .into();
+            model::camera_relative(self.light_position, camera_position).into();
         self.queue.write_buffer(
             &self.light_buffer,
             0,
             bytemuck::cast_slice(&[self.light_uniform]),
         );
+        // The light stands in for the sun
+        self.atmosphere.update(
+            &self.queue,
+            &self.camera,
+            &self.projection,
+            self.light_position,
+        );
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -620,13 +1343,16 @@ impl State {
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
                     view: &self.depth_texture.view,
                     depth_ops: Some(wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(1.0),
+                        load: wgpu::LoadOp::Clear(self.projection.depth_clear_value()),
                         store: true,
                     }),
                     stencil_ops: None,
                 }),
             });
 
+            render_pass.draw_sky(&self.atmosphere);
+            render_pass.draw_stars(&self.stars, &self.camera_bind_group);
+
             render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
             render_pass.set_pipeline(&self.light_render_pipeline);
             render_pass.draw_light_model(
@@ -636,14 +1362,106 @@ impl State {
             );
 
             render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(3, &self.atmosphere.bind_group, &[]);
             render_pass.draw_model_instanced(
                 &self.obj_model,
                 0..self.instances.len() as u32,
                 &self.camera_bind_group,
                 &self.light_bind_group,
             );
+            render_pass.set_vertex_buffer(1, self.entities.instance_buffer().slice(..));
+            render_pass.draw_model_instanced(
+                &self.obj_model,
+                0..self.entities.instance_count(),
+                &self.camera_bind_group,
+                &self.light_bind_group,
+            );
+
+            // The placeholder globe is only needed until imagery arrives.
+            // Web Mercator stops short of the poles, which are left open.
+            render_pass.set_pipeline(&self.globe_render_pipeline);
+            if self.imagery.is_ready() {
+                render_pass.draw_tiles(
+                    &self.imagery,
+                    &self.camera_bind_group,
+                    &self.light_bind_group,
+                );
+            } else {
+                // Each globe tile has its own origin, so it uses its own instance
+                render_pass.set_vertex_buffer(1, self.globe_instance_buffer.slice(..));
+                for (i, mesh) in self.globe.model.meshes.iter().enumerate() {
+                    render_pass.draw_mesh_instanced(
+                        mesh,
+                        &self.globe.model.materials[mesh.material],
+                        i as u32..i as u32 + 1,
+                        &self.camera_bind_group,
+                        &self.light_bind_group,
+                    );
+                }
+            }
+
+            if let Some(vector_tiles) = &self.vector_tiles {
+                render_pass.draw_vector_tiles(
+                    &self.overlay_renderer,
+                    vector_tiles,
+                    &self.camera_bind_group,
+                );
+            }
+            for overlay in &self.overlays {
+                render_pass.draw_overlay(&self.overlay_renderer, overlay, &self.camera_bind_group);
+            }
+            render_pass.draw_entity_paths(&self.entities, &self.camera_bind_group);
+            render_pass.draw_satellites(&self.satellites, &self.camera_bind_group);
+            if let Some(labels) = &self.labels {
+                render_pass.draw_labels(labels, &self.camera_bind_group);
+            }
+        }
+        if let Some(id_buffer) = &mut self.id_buffer {
+            if id_buffer.is_requested() {
+                {
+                    let mut id_pass =
+                        id_buffer.begin_pass(&mut encoder, self.projection.depth_clear_value());
+                    id_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+                    for mesh in &self.obj_model.meshes {
+                        id_pass.draw_mesh_ids(
+                            id_buffer,
+                            CUBES_ID,
+                            mesh,
+                            0..self.instances.len() as u32,
+                            &self.camera_bind_group,
+                        );
+                    }
+                    if self.imagery.is_ready() {
+                        id_pass.set_vertex_buffer(1, self.imagery.instance_buffer().slice(..));
+                        for (i, tile) in self.imagery.selected_tiles().enumerate() {
+                            id_pass.draw_mesh_ids(
+                                id_buffer,
+                                GLOBE_ID,
+                                &tile.mesh,
+                                i as u32..i as u32 + 1,
+                                &self.camera_bind_group,
+                            );
+                        }
+                    } else {
+                        id_pass.set_vertex_buffer(1, self.globe_instance_buffer.slice(..));
+                        for (i, mesh) in self.globe.model.meshes.iter().enumerate() {
+                            id_pass.draw_mesh_ids(
+                                id_buffer,
+                                GLOBE_ID,
+                                mesh,
+                                i as u32..i as u32 + 1,
+                                &self.camera_bind_group,
+                            );
+                        }
+                    }
+                }
+                id_buffer.copy_pixel(&mut encoder, self.camera.position());
+            }
         }
         self.queue.submit(iter::once(encoder.finish()));
+        if let Some(id_buffer) = &mut self.id_buffer {
+            id_buffer.submitted();
+        }
         output.present();
 
         Ok(())
@@ -688,7 +1506,18 @@ pub async fn run() {
     }
 
     let mut state = State::new(&window).await; // NEW!
+    state.on_pick(|pick| match pick.geodetic {
+        Some(geodetic) => log::info!(
+            "Picked {:?} at {:.6}, {:.6}, {:.1} m",
+            pick.object,
+            geodetic.latitude.to_degrees(),
+            geodetic.longitude.to_degrees(),
+            geodetic.height,
+        ),
+        None => log::info!("Picked {:?} {:.1} m away", pick.object, pick.distance),
+    });
     let mut last_render_time = instant::Instant::now();
+    let mut shown_title = title.to_string();
     event_loop.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Poll;
         match event {
@@ -731,6 +1560,16 @@ pub async fn run() {
                 let dt = now - last_render_time;
                 last_render_time = now;
                 state.update(dt);
+                // Follow the picked satellite in the title
+                let status = state.satellites.selected_status();
+                let status = match status {
+                    Some(status) => format!("{} - {}", title, status),
+                    None => title.to_string(),
+                };
+                if status != shown_title {
+                    window.set_title(&status);
+                    shown_title = status;
+                }
                 match state.render() {
                     Ok(_) => {}
                     // Reconfigure the surface if it's lost or outdated
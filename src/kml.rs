@@ -0,0 +1,369 @@
+use anyhow::{anyhow, bail, Context};
+use roxmltree::Node;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+
+use crate::geodesy::Geodetic;
+use crate::vector::{Feature, Geometry, Path, Properties};
+
+/// Reads the placemarks of a KML document, wherever they are nested.
+///
+/// A placemark's `name`, `description` and `ExtendedData` become properties
+/// of its feature. Its `Style`, or the shared one `styleUrl` points to, is
+/// added as simplestyle-spec properties unless those are already set, so
+/// [`crate::vector::Style::from_properties`] picks it up. Elements are
+/// matched by name whatever their namespace.
+pub fn parse_kml(text: &str) -> anyhow::Result<Vec<Feature>> {
+    let document = roxmltree::Document::parse(text).context("parsing KML")?;
+
+    let mut styles = HashMap::new();
+    for style in document.descendants().filter(|node| is(node, "Style")) {
+        if let Some(id) = style.attribute("id") {
+            styles.insert(id.to_string(), style_properties(style));
+        }
+    }
+    // A StyleMap switches between styles on hover; only the normal one is
+    // of use here
+    let mut style_maps = HashMap::new();
+    for style_map in document.descendants().filter(|node| is(node, "StyleMap")) {
+        let normal = children(style_map, "Pair")
+            .find(|pair| child_text(*pair, "key") == Some("normal"))
+            .and_then(|pair| child_text(pair, "styleUrl"));
+        if let (Some(id), Some(normal)) = (style_map.attribute("id"), normal) {
+            style_maps.insert(id.to_string(), normal.trim_start_matches('#').to_string());
+        }
+    }
+    let shared_style = |url: &str| {
+        let id = url.trim().strip_prefix('#')?;
+        let id = style_maps.get(id).map_or(id, String::as_str);
+        styles.get(id)
+    };
+
+    let mut features = Vec::new();
+    for placemark in document.descendants().filter(|node| is(node, "Placemark")) {
+        let geometry = match placemark
+            .children()
+            .find_map(|node| parse_geometry(node).transpose())
+        {
+            Some(geometry) => geometry?,
+            None => continue,
+        };
+
+        let mut properties = Properties::new();
+        for key in ["name", "description"] {
+            if let Some(text) = child_text(placemark, key) {
+                properties.insert(key.to_string(), Value::from(text.trim()));
+            }
+        }
+        if let Some(extended) = child(placemark, "ExtendedData") {
+            for data in children(extended, "Data") {
+                if let Some(name) = data.attribute("name") {
+                    let value = child_text(data, "value").unwrap_or_default();
+                    properties.insert(name.to_string(), Value::from(value));
+                }
+            }
+            for schema_data in children(extended, "SchemaData") {
+                for data in children(schema_data, "SimpleData") {
+                    if let Some(name) = data.attribute("name") {
+                        properties.insert(
+                            name.to_string(),
+                            Value::from(data.text().unwrap_or_default()),
+                        );
+                    }
+                }
+            }
+        }
+
+        let mut style = child_text(placemark, "styleUrl")
+            .and_then(shared_style)
+            .cloned()
+            .unwrap_or_default();
+        if let Some(inline) = child(placemark, "Style") {
+            style.extend(style_properties(inline));
+        }
+        for (key, value) in style {
+            properties.entry(key).or_insert(value);
+        }
+
+        features.push(Feature {
+            id: placemark.attribute("id").map(Value::from),
+            geometry,
+            properties,
+        });
+    }
+    Ok(features)
+}
+
+/// Reads the main document of a KMZ archive, `doc.kml` or else the first
+/// `.kml` file in it.
+pub fn parse_kmz(data: &[u8]) -> anyhow::Result<Vec<Feature>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(data)).context("opening KMZ")?;
+    let name = archive
+        .file_names()
+        .filter(|name| name.to_ascii_lowercase().ends_with(".kml"))
+        .min_by_key(|name| {
+            (
+                *name != "doc.kml",
+                name.matches('/').count(),
+                name.to_string(),
+            )
+        })
+        .map(str::to_string)
+        .ok_or_else(|| anyhow!("no KML document in the archive"))?;
+    let mut text = String::new();
+    archive
+        .by_name(&name)?
+        .read_to_string(&mut text)
+        .with_context(|| format!("reading {}", name))?;
+    parse_kml(&text)
+}
+
+fn is(node: &Node, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name
+}
+
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children().filter(move |child| is(child, name))
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|child| is(child, name))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    child(node, name).and_then(|child| child.text())
+}
+
+/// The geometry an element describes, `None` if it isn't one this reads.
+fn parse_geometry(node: Node) -> anyhow::Result<Option<Geometry>> {
+    if !node.is_element() {
+        return Ok(None);
+    }
+    let coordinates = |node: Node| -> anyhow::Result<Path> {
+        parse_coordinates(child_text(node, "coordinates").unwrap_or_default())
+    };
+    Ok(Some(match node.tag_name().name() {
+        "Point" => Geometry::Point(
+            *coordinates(node)?
+                .first()
+                .ok_or_else(|| anyhow!("a Point without coordinates"))?,
+        ),
+        // A ring on its own is drawn as the closed line it is
+        "LineString" | "LinearRing" => Geometry::LineString(coordinates(node)?),
+        "Polygon" => {
+            let ring = |boundary: Node| -> anyhow::Result<Path> {
+                let ring = child(boundary, "LinearRing")
+                    .ok_or_else(|| anyhow!("a polygon boundary without a LinearRing"))?;
+                let mut path = coordinates(ring)?;
+                if path.len() > 1 && path.first() == path.last() {
+                    path.pop();
+                }
+                Ok(path)
+            };
+            let outer = child(node, "outerBoundaryIs")
+                .ok_or_else(|| anyhow!("a Polygon without an outerBoundaryIs"))?;
+            let mut rings = vec![ring(outer)?];
+            for inner in children(node, "innerBoundaryIs") {
+                rings.push(ring(inner)?);
+            }
+            Geometry::Polygon(rings)
+        }
+        "MultiGeometry" => {
+            let mut geometries = node
+                .children()
+                .filter_map(|node| parse_geometry(node).transpose())
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            match geometries.len() {
+                0 => return Ok(None),
+                1 => geometries.remove(0),
+                _ => Geometry::Collection(geometries),
+            }
+        }
+        _ => return Ok(None),
+    }))
+}
+
+/// Reads whitespace separated `longitude,latitude[,altitude]` tuples.
+fn parse_coordinates(text: &str) -> anyhow::Result<Path> {
+    text.split_whitespace()
+        .map(|tuple| {
+            let numbers = tuple
+                .split(',')
+                .map(str::parse::<f64>)
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("reading coordinates {}", tuple))?;
+            match numbers[..] {
+                [longitude, latitude] => Ok(Geodetic::from_degrees(latitude, longitude, 0.0)),
+                [longitude, latitude, altitude] => {
+                    Ok(Geodetic::from_degrees(latitude, longitude, altitude))
+                }
+                _ => bail!("coordinates need two or three values, found {}", tuple),
+            }
+        })
+        .collect()
+}
+
+/// The simplestyle-spec properties equivalent to a KML `Style`.
+fn style_properties(style: Node) -> Properties {
+    let mut properties = Properties::new();
+    let mut color = |node: Node, color_key: &str, opacity_key: &str| {
+        if let Some((hex, opacity)) = child_text(node, "color").and_then(parse_kml_color) {
+            properties.insert(color_key.to_string(), Value::from(hex));
+            if !opacity_key.is_empty() {
+                properties.insert(opacity_key.to_string(), Value::from(opacity));
+            }
+        }
+    };
+    let line = child(style, "LineStyle");
+    let polygon = child(style, "PolyStyle");
+    let icon = child(style, "IconStyle");
+    if let Some(line) = line {
+        color(line, "stroke", "stroke-opacity");
+    }
+    if let Some(polygon) = polygon {
+        color(polygon, "fill", "fill-opacity");
+    }
+    if let Some(icon) = icon {
+        color(icon, "marker-color", "");
+    }
+
+    let number = |node: Option<Node>, name: &str| {
+        node.and_then(|node| child_text(node, name))
+            .and_then(|text| text.trim().parse::<f64>().ok())
+    };
+    if let Some(width) = number(line, "width") {
+        properties.insert("stroke-width".to_string(), Value::from(width));
+    }
+    if number(polygon, "fill") == Some(0.0) {
+        properties.insert("fill-opacity".to_string(), Value::from(0.0));
+    }
+    if number(polygon, "outline") == Some(0.0) {
+        properties.insert("stroke-opacity".to_string(), Value::from(0.0));
+    }
+    if let Some(scale) = number(icon, "scale") {
+        let size = match scale {
+            scale if scale < 0.8 => "small",
+            scale if scale > 1.2 => "large",
+            _ => "medium",
+        };
+        properties.insert("marker-size".to_string(), Value::from(size));
+    }
+    properties
+}
+
+/// Converts KML's `aabbggrr` to a `#rrggbb` color and an opacity.
+fn parse_kml_color(text: &str) -> Option<(String, f64)> {
+    let text = text.trim();
+    if text.len() != 8 || !text.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    let alpha = u8::from_str_radix(&text[0..2], 16).ok()?;
+    let color = format!("#{}{}{}", &text[6..8], &text[4..6], &text[2..4]);
+    Some((color, alpha as f64 / 255.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::Style;
+    use std::io::Write;
+
+    const DOCUMENT: &str = r##"<?xml version="1.0" encoding="UTF-8"?>
+<kml xmlns="http://www.opengis.net/kml/2.2">
+  <Document>
+    <Style id="lake">
+      <LineStyle><color>ff0000ff</color><width>3</width></LineStyle>
+      <PolyStyle><color>80ff0000</color></PolyStyle>
+    </Style>
+    <StyleMap id="lake-map">
+      <Pair><key>normal</key><styleUrl>#lake</styleUrl></Pair>
+      <Pair><key>highlight</key><styleUrl>#other</styleUrl></Pair>
+    </StyleMap>
+    <Folder>
+      <Placemark id="thun">
+        <name>Thunersee</name>
+        <styleUrl>#lake-map</styleUrl>
+        <ExtendedData>
+          <Data name="depth"><value>217</value></Data>
+          <SchemaData schemaUrl="#lakes"><SimpleData name="canton">BE</SimpleData></SchemaData>
+        </ExtendedData>
+        <Polygon>
+          <outerBoundaryIs><LinearRing><coordinates>
+            7.6,46.7 7.9,46.7 7.9,46.6 7.6,46.6 7.6,46.7
+          </coordinates></LinearRing></outerBoundaryIs>
+          <innerBoundaryIs><LinearRing><coordinates>
+            7.7,46.68,0 7.8,46.68,0 7.8,46.65,0 7.7,46.68,0
+          </coordinates></LinearRing></innerBoundaryIs>
+        </Polygon>
+      </Placemark>
+      <Placemark>
+        <Style><IconStyle><color>ff00ff00</color><scale>1.5</scale></IconStyle></Style>
+        <MultiGeometry>
+          <Point><coordinates>7.44,46.95,540</coordinates></Point>
+          <LineString><coordinates>7.4,46.9 7.5,47.0</coordinates></LineString>
+        </MultiGeometry>
+      </Placemark>
+      <Placemark><name>No geometry</name></Placemark>
+    </Folder>
+  </Document>
+</kml>"##;
+
+    #[test]
+    fn reads_placemarks_with_data_and_styles() {
+        let features = parse_kml(DOCUMENT).unwrap();
+        assert_eq!(features.len(), 2);
+
+        let lake = &features[0];
+        assert_eq!(lake.id, Some(Value::from("thun")));
+        assert_eq!(lake.properties["name"], "Thunersee");
+        assert_eq!(lake.properties["depth"], "217");
+        assert_eq!(lake.properties["canton"], "BE");
+        match &lake.geometry {
+            Geometry::Polygon(rings) => {
+                assert_eq!(rings.len(), 2);
+                assert_eq!(rings[0].len(), 4);
+                assert_eq!(rings[1].len(), 3);
+                assert_eq!(rings[0][1], Geodetic::from_degrees(46.7, 7.9, 0.0));
+            }
+            geometry => panic!("expected a polygon, found {:?}", geometry),
+        }
+        let style = Style::from_properties(&lake.properties, &Style::default());
+        assert_eq!(style.stroke, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(style.stroke_width, 3.0);
+        assert_eq!(style.fill[..3], [0.0, 0.0, 1.0]);
+        assert!((style.fill[3] - 128.0 / 255.0).abs() < 1e-6);
+
+        let mut points = Vec::new();
+        features[1]
+            .geometry
+            .visit(&mut |point| points.push(*point), &mut |_| {}, &mut |_| {});
+        assert_eq!(points, vec![Geodetic::from_degrees(46.95, 7.44, 540.0)]);
+        let style = Style::from_properties(&features[1].properties, &Style::default());
+        assert_eq!(style.marker, [0.0, 1.0, 0.0, 1.0]);
+        assert_eq!(style.marker_size, 12.0);
+
+        assert!(parse_kml(
+            "<kml><Placemark><Point><coordinates>1</coordinates></Point></Placemark></kml>"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn reads_the_document_inside_kmz() {
+        let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        archive.start_file("files/overlay.kml", options).unwrap();
+        archive.write_all(b"<kml></kml>").unwrap();
+        archive.start_file("doc.kml", options).unwrap();
+        archive.write_all(DOCUMENT.as_bytes()).unwrap();
+        let data = archive.finish().unwrap().into_inner();
+
+        assert_eq!(parse_kmz(&data).unwrap(), parse_kml(DOCUMENT).unwrap());
+        assert!(parse_kmz(b"not a zip").is_err());
+    }
+}
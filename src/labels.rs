@@ -0,0 +1,611 @@
+use std::mem;
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+use wgpu::util::DeviceExt;
+
+use crate::camera::{GlobeCamera, Projection};
+use crate::collision::{self, CollisionGrid, ScreenRect};
+use crate::font::{SdfAtlas, TextLayout, RASTER_SIZE, SPREAD};
+use crate::geodesy::{self, Geodetic};
+use crate::{model, texture};
+
+/// Seconds labels take to fade in once placed, and out once bumped.
+const FADE_TIME: f32 = 0.3;
+/// Pixels kept clear around each label.
+const LABEL_PADDING: f32 = 2.0;
+
+/// Scales a label between two values over a range of distances from the
+/// camera, holding them beyond it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ScaleByDistance {
+    pub near: f64,
+    pub near_scale: f32,
+    pub far: f64,
+    pub far_scale: f32,
+}
+
+impl ScaleByDistance {
+    pub fn scale(&self, distance: f64) -> f32 {
+        let t = ((distance - self.near) / (self.far - self.near)).clamp(0.0, 1.0) as f32;
+        self.near_scale + (self.far_scale - self.near_scale) * t
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LabelStyle {
+    /// Pixels per em.
+    pub size: f32,
+    pub color: [f32; 4],
+    pub outline_color: [f32; 4],
+    /// In pixels, up to `SPREAD` at `RASTER_SIZE` and proportionally less
+    /// at smaller sizes.
+    pub outline_width: f32,
+    /// Pixels from the position to the text's center, y down.
+    pub offset: [f32; 2],
+    pub scale_by_distance: Option<ScaleByDistance>,
+    /// Whether terrain and models in front of the label hide it. Otherwise
+    /// it's drawn over them, unless it's beyond the horizon.
+    pub depth_test: bool,
+}
+
+impl Default for LabelStyle {
+    fn default() -> Self {
+        Self {
+            size: 16.0,
+            color: [1.0; 4],
+            outline_color: [0.0, 0.0, 0.0, 1.0],
+            outline_width: 2.0,
+            offset: [0.0; 2],
+            scale_by_distance: None,
+            depth_test: false,
+        }
+    }
+}
+
+/// Text anchored to a point in the world and drawn facing the screen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub text: String,
+    /// ECEF position in meters.
+    pub position: Vector3<f64>,
+    pub style: LabelStyle,
+    /// Where labels would overlap, those with the higher priority are shown.
+    pub priority: f32,
+}
+
+impl Label {
+    pub fn new(text: impl Into<String>, position: Vector3<f64>, style: LabelStyle) -> Self {
+        Self {
+            text: text.into(),
+            position,
+            style,
+            priority: 0.0,
+        }
+    }
+
+    /// A label at a latitude, longitude and height.
+    pub fn at(text: impl Into<String>, position: &Geodetic, style: LabelStyle) -> Self {
+        Self::new(text, position.to_ecef(), style)
+    }
+}
+
+/// Whether the Earth is between the camera and `position`. Space is scaled
+/// so the ellipsoid becomes the unit sphere, where the line of sight passing
+/// inside it is easy to find.
+pub fn beyond_horizon(position: Vector3<f64>, camera_position: Vector3<f64>) -> bool {
+    let b = geodesy::WGS84_A * (1.0 - geodesy::WGS84_F);
+    let scale = Vector3::new(geodesy::WGS84_A, geodesy::WGS84_A, b);
+    let camera_position = camera_position.div_element_wise(scale);
+    let to_position = position.div_element_wise(scale) - camera_position;
+    // The point along the line of sight nearest the Earth's center
+    let t = -camera_position.dot(to_position) / to_position.magnitude2();
+    (0.0..1.0).contains(&t) && (camera_position + to_position * t).magnitude() < 1.0
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct GlyphInstance {
+    position: [f32; 3],
+    /// Screen pixels per unit of the distance field, and the outline width.
+    sdf: [f32; 2],
+    rect: [f32; 4],
+    uv: [f32; 4],
+    color: [f32; 4],
+    outline_color: [f32; 4],
+}
+
+impl GlyphInstance {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 6] = wgpu::vertex_attr_array![
+        0 => Float32x3,
+        1 => Float32x2,
+        2 => Float32x4,
+        3 => Float32x4,
+        4 => Float32x4,
+        5 => Float32x4,
+    ];
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<GlyphInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// A label in view, with where it goes on screen.
+struct Candidate {
+    /// Into the labels of the layer.
+    index: usize,
+    priority: f32,
+    /// Relative to the camera.
+    position: Vector3<f32>,
+    pixels_per_texel: f32,
+    rect: ScreenRect,
+}
+
+/// Places the `candidates` where they don't overlap each other or what's
+/// already in `collisions`: the higher priorities first and, among equals,
+/// those `placed` last time, so placement holds still as the camera moves.
+/// Leaves the candidates in that order and `placed` marking the winners
+/// among all labels, and moves each label's `opacity` by `step` towards 1
+/// if it was placed and 0 if not.
+fn place(
+    candidates: &mut [Candidate],
+    placed: &mut [bool],
+    opacity: &mut [f32],
+    collisions: &mut CollisionGrid,
+    step: f32,
+) {
+    candidates.sort_by(|a, b| {
+        b.priority
+            .total_cmp(&a.priority)
+            .then(placed[b.index].cmp(&placed[a.index]))
+            .then(a.index.cmp(&b.index))
+    });
+    placed.iter_mut().for_each(|placed| *placed = false);
+    for candidate in candidates.iter() {
+        placed[candidate.index] = collisions.insert_if_free(candidate.rect);
+    }
+    for (opacity, placed) in opacity.iter_mut().zip(placed.iter()) {
+        *opacity = if *placed {
+            (*opacity + step).min(1.0)
+        } else {
+            (*opacity - step).max(0.0)
+        };
+    }
+}
+
+/// Labels drawn from the signed distance fields of one font, so they stay
+/// sharp at any size.
+pub struct LabelLayer {
+    labels: Vec<Label>,
+    layouts: Vec<TextLayout>,
+    /// Whether each label won its place on screen in the last update.
+    placed: Vec<bool>,
+    opacity: Vec<f32>,
+    /// Glyphs of depth tested labels come first.
+    depth_tested_glyphs: u32,
+    glyphs: u32,
+    instance_capacity: usize,
+    instance_buffer: wgpu::Buffer,
+    viewport_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    depth_tested_pipeline: wgpu::RenderPipeline,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl LabelLayer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        config: &wgpu::SurfaceConfiguration,
+        projection: &Projection,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        atlas: &SdfAtlas,
+        labels: Vec<Label>,
+    ) -> Self {
+        let layouts = labels
+            .iter()
+            .map(|label| atlas.layout(&label.text))
+            .collect();
+        let instance_buffer = Self::create_instance_buffer(device, 1);
+        let viewport_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Label Viewport Buffer"),
+            contents: bytemuck::cast_slice(&[config.width as f32, config.height as f32, 0.0, 0.0]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let atlas_texture = device.create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Label Atlas"),
+                size: wgpu::Extent3d {
+                    width: atlas.width,
+                    height: atlas.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::R8Unorm,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            },
+            &atlas.pixels,
+        );
+        let atlas_view = atlas_texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+            ],
+            label: Some("label_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: viewport_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&atlas_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("label_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Label Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Label Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("labels.wgsl").into()),
+        });
+        let create_pipeline = |depth_compare| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Label Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[GlyphInstance::desc()],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[wgpu::ColorTargetState {
+                        format: config.format,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    }],
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    strip_index_format: None,
+                    front_face: wgpu::FrontFace::Ccw,
+                    cull_mode: None,
+                    polygon_mode: wgpu::PolygonMode::Fill,
+                    unclipped_depth: false,
+                    conservative: false,
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: texture::Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: 1,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            })
+        };
+
+        Self {
+            placed: vec![false; labels.len()],
+            opacity: vec![0.0; labels.len()],
+            labels,
+            layouts,
+            depth_tested_glyphs: 0,
+            glyphs: 0,
+            instance_capacity: 1,
+            instance_buffer,
+            viewport_buffer,
+            bind_group,
+            depth_tested_pipeline: create_pipeline(projection.depth_compare()),
+            pipeline: create_pipeline(wgpu::CompareFunction::Always),
+        }
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Label Instance Buffer"),
+            size: (capacity * mem::size_of::<GlyphInstance>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Places the labels in front of the camera and above the horizon,
+    /// scaled for their distance. Where they'd overlap each other or what's
+    /// already in `collisions`, those with the higher priority are placed,
+    /// and those showing already win ties so placement holds still as the
+    /// camera moves. Labels fade in once placed and out once bumped.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &GlobeCamera,
+        projection: &Projection,
+        collisions: &mut CollisionGrid,
+        dt: instant::Duration,
+    ) {
+        let camera_position = camera.position();
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        let mut candidates: Vec<Candidate> = self
+            .labels
+            .iter()
+            .zip(&self.layouts)
+            .enumerate()
+            .filter_map(|(index, (label, layout))| {
+                if beyond_horizon(label.position, camera_position.to_vec()) {
+                    return None;
+                }
+                let position = model::camera_relative(label.position, camera_position);
+                let anchor = collision::project(view_proj, position, collisions.size())?;
+                let style = &label.style;
+                let scale = style
+                    .scale_by_distance
+                    .map_or(1.0, |scale| scale.scale(position.magnitude() as f64));
+                if scale <= 0.0 {
+                    return None;
+                }
+                let pixels_per_texel = style.size * scale / RASTER_SIZE;
+                let center = [anchor[0] + style.offset[0], anchor[1] + style.offset[1]];
+                let half_width = layout.width * pixels_per_texel / 2.0 + LABEL_PADDING;
+                let half_height = layout.height * pixels_per_texel / 2.0 + LABEL_PADDING;
+                Some(Candidate {
+                    index,
+                    priority: label.priority,
+                    position,
+                    pixels_per_texel,
+                    rect: [
+                        center[0] - half_width,
+                        center[1] - half_height,
+                        center[0] + half_width,
+                        center[1] + half_height,
+                    ],
+                })
+            })
+            .collect();
+
+        place(
+            &mut candidates,
+            &mut self.placed,
+            &mut self.opacity,
+            collisions,
+            dt.as_secs_f32() / FADE_TIME,
+        );
+
+        let mut depth_tested = Vec::new();
+        let mut always = Vec::new();
+        for candidate in &candidates {
+            let opacity = self.opacity[candidate.index];
+            if opacity <= 0.0 {
+                continue;
+            }
+            let style = &self.labels[candidate.index].style;
+            let pixels_per_texel = candidate.pixels_per_texel;
+            let sdf = [
+                2.0 * SPREAD * pixels_per_texel,
+                style.outline_width.min(SPREAD * pixels_per_texel - 0.5),
+            ];
+            let [dx, dy] = style.offset;
+            let mut color = style.color;
+            let mut outline_color = style.outline_color;
+            color[3] *= opacity;
+            outline_color[3] *= opacity;
+            let glyphs = if style.depth_test {
+                &mut depth_tested
+            } else {
+                &mut always
+            };
+            let quads = &self.layouts[candidate.index].quads;
+            glyphs.extend(quads.iter().map(|quad| GlyphInstance {
+                position: candidate.position.into(),
+                sdf,
+                rect: [
+                    quad.rect[0] * pixels_per_texel + dx,
+                    quad.rect[1] * pixels_per_texel + dy,
+                    quad.rect[2] * pixels_per_texel + dx,
+                    quad.rect[3] * pixels_per_texel + dy,
+                ],
+                uv: quad.uv,
+                color,
+                outline_color,
+            }));
+        }
+
+        self.depth_tested_glyphs = depth_tested.len() as u32;
+        depth_tested.append(&mut always);
+        self.glyphs = depth_tested.len() as u32;
+        if depth_tested.len() > self.instance_capacity {
+            self.instance_capacity = depth_tested.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+        }
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&depth_tested),
+        );
+    }
+
+    pub fn resize(&self, queue: &wgpu::Queue, width: u32, height: u32) {
+        queue.write_buffer(
+            &self.viewport_buffer,
+            0,
+            bytemuck::cast_slice(&[width as f32, height as f32, 0.0, 0.0]),
+        );
+    }
+}
+
+pub trait DrawLabels<'a> {
+    fn draw_labels(&mut self, labels: &'a LabelLayer, camera_bind_group: &'a wgpu::BindGroup);
+}
+
+impl<'a, 'b> DrawLabels<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_labels(&mut self, labels: &'b LabelLayer, camera_bind_group: &'b wgpu::BindGroup) {
+        if labels.glyphs == 0 {
+            return;
+        }
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(1, &labels.bind_group, &[]);
+        self.set_vertex_buffer(0, labels.instance_buffer.slice(..));
+        self.set_pipeline(&labels.depth_tested_pipeline);
+        self.draw(0..6, 0..labels.depth_tested_glyphs);
+        self.set_pipeline(&labels.pipeline);
+        self.draw(0..6, labels.depth_tested_glyphs..labels.glyphs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn labels_beyond_the_horizon_are_hidden() {
+        let camera = Geodetic::from_degrees(0.0, 0.0, 1_000_000.0).to_ecef();
+        let below = Geodetic::from_degrees(0.0, 0.0, 0.0).to_ecef();
+        let far_side = Geodetic::from_degrees(0.0, 180.0, 0.0).to_ecef();
+        // From 1000 km up the horizon is about 30° away
+        let near_horizon = Geodetic::from_degrees(0.0, 27.0, 0.0).to_ecef();
+        let past_horizon = Geodetic::from_degrees(0.0, 35.0, 0.0).to_ecef();
+        assert!(!beyond_horizon(below, camera));
+        assert!(!beyond_horizon(near_horizon, camera));
+        assert!(beyond_horizon(past_horizon, camera));
+        assert!(beyond_horizon(far_side, camera));
+        // Satellites above the far side are still in view
+        let above_far_side = Geodetic::from_degrees(0.0, 90.0, 30_000_000.0).to_ecef();
+        assert!(!beyond_horizon(above_far_side, camera));
+
+        let scale = ScaleByDistance {
+            near: 1000.0,
+            near_scale: 1.5,
+            far: 11_000.0,
+            far_scale: 0.5,
+        };
+        assert_eq!(scale.scale(0.0), 1.5);
+        assert_eq!(scale.scale(6000.0), 1.0);
+        assert_eq!(scale.scale(1e9), 0.5);
+    }
+
+    fn candidate(index: usize, priority: f32, x: f32) -> Candidate {
+        Candidate {
+            index,
+            priority,
+            position: Vector3::zero(),
+            pixels_per_texel: 1.0,
+            rect: [x, 100.0, x + 50.0, 120.0],
+        }
+    }
+
+    /// Places the labels in a fresh frame, returning the winners.
+    fn place_all(
+        mut candidates: Vec<Candidate>,
+        placed: &mut [bool],
+        opacity: &mut [f32],
+    ) -> Vec<usize> {
+        let mut collisions = CollisionGrid::new(800, 600);
+        place(&mut candidates, placed, opacity, &mut collisions, 0.25);
+        (0..placed.len()).filter(|&i| placed[i]).collect()
+    }
+
+    #[test]
+    fn labels_are_placed_by_priority_and_hold_ties() {
+        // The higher priority wins an overlap, whatever the order
+        let (mut placed, mut opacity) = ([false; 3], [0.0; 3]);
+        let candidates = vec![
+            candidate(0, 1.0, 100.0),
+            candidate(1, 2.0, 120.0),
+            candidate(2, 0.0, 400.0),
+        ];
+        assert_eq!(place_all(candidates, &mut placed, &mut opacity), [1, 2]);
+
+        // Equals go by order at first
+        let (mut placed, mut opacity) = ([false; 2], [0.0; 2]);
+        let both = || vec![candidate(0, 0.0, 100.0), candidate(1, 0.0, 120.0)];
+        assert_eq!(place_all(both(), &mut placed, &mut opacity), [0]);
+        // but the one showing keeps its place when the other comes into view
+        let (mut placed, mut opacity) = ([false; 2], [0.0; 2]);
+        let second = vec![candidate(1, 0.0, 120.0)];
+        assert_eq!(place_all(second, &mut placed, &mut opacity), [1]);
+        assert_eq!(place_all(both(), &mut placed, &mut opacity), [1]);
+        assert_eq!(place_all(both(), &mut placed, &mut opacity), [1]);
+    }
+
+    #[test]
+    fn labels_fade_in_and_out() {
+        let (mut placed, mut opacity) = ([false; 2], [0.0; 2]);
+        let apart = || vec![candidate(0, 0.0, 100.0), candidate(1, 0.0, 300.0)];
+        place_all(apart(), &mut placed, &mut opacity);
+        assert_eq!(opacity, [0.25, 0.25]);
+        for _ in 0..5 {
+            place_all(apart(), &mut placed, &mut opacity);
+        }
+        assert_eq!(opacity, [1.0, 1.0]);
+
+        // Bumped by a higher priority, or out of view, labels fade out
+        let bumped = vec![candidate(0, 0.0, 100.0), candidate(1, 1.0, 120.0)];
+        place_all(bumped, &mut placed, &mut opacity);
+        assert_eq!(opacity, [0.75, 1.0]);
+        for _ in 0..5 {
+            place_all(Vec::new(), &mut placed, &mut opacity);
+        }
+        assert_eq!(opacity, [0.0, 0.0]);
+        assert_eq!(placed, [false, false]);
+    }
+}
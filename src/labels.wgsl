@@ -0,0 +1,88 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+struct Viewport {
+    // Width and height in pixels
+    size: vec4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> viewport: Viewport;
+[[group(1), binding(1)]]
+var atlas: texture_2d<f32>;
+[[group(1), binding(2)]]
+var atlas_sampler: sampler;
+
+struct InstanceInput {
+    // Camera-relative position of the label
+    [[location(0)]] position: vec3<f32>;
+    // Screen pixels per unit of the distance field, and the outline's
+    // width in pixels
+    [[location(1)]] sdf: vec2<f32>;
+    // Left, top, right and bottom in pixels from the label, y down
+    [[location(2)]] rect: vec4<f32>;
+    [[location(3)]] uv: vec4<f32>;
+    [[location(4)]] color: vec4<f32>;
+    [[location(5)]] outline_color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] sdf: vec2<f32>;
+    [[location(2)]] color: vec4<f32>;
+    [[location(3)]] outline_color: vec4<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    [[builtin(vertex_index)]] index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(0.0, 1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(0.0, 0.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(1.0, 0.0),
+    );
+    let corner = corners[index];
+    let pixels = mix(instance.rect.xy, instance.rect.zw, corner);
+
+    // Labels on the ground are pulled slightly towards the camera so the
+    // ground under them doesn't hide them when they're depth tested
+    let clip = camera.view_proj * vec4<f32>(instance.position * 0.9995, 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(
+        clip.xy + vec2<f32>(pixels.x, -pixels.y) * 2.0 / viewport.size.xy * clip.w,
+        clip.zw,
+    );
+    out.uv = mix(instance.uv.xy, instance.uv.zw, corner);
+    out.sdf = instance.sdf;
+    out.color = instance.color;
+    out.outline_color = instance.outline_color;
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let field = textureSample(atlas, atlas_sampler, in.uv).r;
+    let distance = (field - 0.5) * in.sdf.x;
+    // The fill is drawn over the outline around it
+    let fill = clamp(distance + 0.5, 0.0, 1.0) * in.color.a;
+    let outline = clamp(distance + in.sdf.y + 0.5, 0.0, 1.0) * in.outline_color.a;
+    let alpha = fill + (1.0 - fill) * outline;
+    if (alpha <= 0.0) {
+        discard;
+    }
+    let color = (in.color.rgb * fill + in.outline_color.rgb * (1.0 - fill) * outline) / alpha;
+    return vec4<f32>(color, alpha);
+}
@@ -1,8 +1,37 @@
 mod index;
+mod atmosphere;
+mod bvh;
 mod camera;
+mod clock;
+mod collision;
+mod entities;
+mod font;
+mod geodesy;
+mod globe;
+#[cfg(not(target_arch = "wasm32"))]
+mod http_tiles;
+mod id_buffer;
+mod kml;
+mod labels;
 mod model;
+mod mvt;
+mod overlay;
+mod picking;
+mod polyline;
+mod quantized_mesh;
 mod resources;
+mod satellites;
+mod sdp4;
+mod sgp4;
+mod shapefile;
+mod stars;
+mod sun;
+mod terrain;
 mod texture;
+mod tile_cache;
+mod tiles;
+mod vector;
+mod vector_tiles;
 
 use crate::index::run;
 
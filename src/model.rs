@@ -1,6 +1,10 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Quaternion, Rad};
 use std::ops::Range;
 
-use crate::texture;
+use wgpu::util::DeviceExt;
+
+use crate::{bvh, geodesy, picking, texture};
 
 pub trait Vertex {
     fn desc<'a>() -> wgpu::VertexBufferLayout<'a>;
@@ -54,10 +58,144 @@ impl Vertex for ModelVertex {
     }
 }
 
+/// Rebases a double precision world position against the camera. Only the
+/// difference is small enough to survive the conversion to `f32` without the
+/// meter-sized jitter an ECEF coordinate would get.
+pub fn camera_relative(
+    position: cgmath::Vector3<f64>,
+    camera_position: cgmath::Point3<f64>,
+) -> cgmath::Vector3<f32> {
+    (position - camera_position.to_vec()).cast().unwrap()
+}
+
+pub struct Instance {
+    /// ECEF position of the model origin in meters.
+    pub position: cgmath::Vector3<f64>,
+    pub rotation: cgmath::Quaternion<f32>,
+    /// Uniform scale from model units to meters.
+    pub scale: f32,
+}
+
+impl Instance {
+    pub fn new(position: cgmath::Vector3<f64>, rotation: cgmath::Quaternion<f32>) -> Self {
+        Self {
+            position,
+            rotation,
+            scale: 1.0,
+        }
+    }
+
+    /// Places a Y-up model at a geodetic position. With all angles zero the
+    /// model's -Z axis faces north, +X east and +Y up. `heading` then turns
+    /// it clockwise seen from above, `pitch` raises the nose and `roll` lowers
+    /// the right side, all in the local east/north/up frame.
+    pub fn from_geodetic<H: Into<Rad<f64>>, P: Into<Rad<f64>>, R: Into<Rad<f64>>>(
+        position: &geodesy::Geodetic,
+        heading: H,
+        pitch: P,
+        roll: R,
+        scale: f32,
+    ) -> Self {
+        #[rustfmt::skip]
+        let y_up_to_enu = Matrix3::new(
+            1.0, 0.0, 0.0,
+            0.0, 0.0, 1.0,
+            0.0, -1.0, 0.0,
+        );
+        let orientation = geodesy::enu_to_ecef_rotation(position.latitude, position.longitude)
+            * Matrix3::from_angle_z(-heading.into())
+            * Matrix3::from_angle_x(pitch.into())
+            * Matrix3::from_angle_y(roll.into())
+            * y_up_to_enu;
+
+        Self {
+            position: position.to_ecef(),
+            rotation: Quaternion::from(orientation).cast().unwrap(),
+            scale,
+        }
+    }
+
+    pub fn to_raw(&self, camera_position: cgmath::Point3<f64>) -> InstanceRaw {
+        InstanceRaw {
+            model: (cgmath::Matrix4::from_translation(camera_relative(
+                self.position,
+                camera_position,
+            )) * cgmath::Matrix4::from(self.rotation)
+                * cgmath::Matrix4::from_scale(self.scale))
+            .into(),
+            // A uniform scale doesn't change the direction of normals
+            normal: cgmath::Matrix3::from(self.rotation).into(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+#[allow(dead_code)]
+pub struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    normal: [[f32; 3]; 3],
+}
+
+impl Vertex for InstanceRaw {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            // We need to switch from using a step mode of Vertex to Instance
+            // This means that our shaders will only change to use the next
+            // instance when the shader starts processing a new instance
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    // While our vertex shader only uses locations 0, and 1 now, in later tutorials we'll
+                    // be using 2, 3, and 4, for Vertex. We'll start at slot 5 not conflict with them later
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                // A mat4 takes up 4 vertex slots as it is technically 4 vec4s. We need to define a slot
+                // for each vec4. We don't have to do this in code though.
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 19]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 22]>() as wgpu::BufferAddress,
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+            ],
+        }
+    }
+}
+
 pub struct Material {
     pub name: String,
     pub diffuse_texture: texture::Texture,
-    pub normal_texture: texture::Texture,
+    /// `None` for a normal texture shared with other materials.
+    pub normal_texture: Option<texture::Texture>,
     pub bind_group: wgpu::BindGroup,
 }
 
@@ -68,6 +206,21 @@ impl Material {
         diffuse_texture: texture::Texture,
         normal_texture: texture::Texture,
         layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let mut material =
+            Self::with_shared_normal(device, name, diffuse_texture, &normal_texture, layout);
+        material.normal_texture = Some(normal_texture);
+        material
+    }
+
+    /// A material whose normal texture is shared with others, and owned by
+    /// whoever shares it.
+    pub fn with_shared_normal(
+        device: &wgpu::Device,
+        name: &str,
+        diffuse_texture: texture::Texture,
+        normal_texture: &texture::Texture,
+        layout: &wgpu::BindGroupLayout,
     ) -> Self {
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
             layout,
@@ -95,7 +248,7 @@ impl Material {
         Self {
             name: String::from(name),
             diffuse_texture,
-            normal_texture,
+            normal_texture: None,
             bind_group,
         }
     }
@@ -107,6 +260,45 @@ pub struct Mesh {
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
     pub material: usize,
+    /// The extent of the vertices in model space.
+    pub bounds: picking::Aabb,
+    /// The triangles kept on the CPU, if asked for when loading.
+    pub triangles: Option<bvh::TriangleMesh>,
+}
+
+impl Mesh {
+    pub fn new(
+        device: &wgpu::Device,
+        name: &str,
+        vertices: &[ModelVertex],
+        indices: &[u32],
+        material: usize,
+    ) -> Self {
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Vertex Buffer", name)),
+            contents: bytemuck::cast_slice(vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some(&format!("{:?} Index Buffer", name)),
+            contents: bytemuck::cast_slice(indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+
+        Self {
+            name: name.to_string(),
+            vertex_buffer,
+            index_buffer,
+            num_elements: indices.len() as u32,
+            material,
+            bounds: picking::Aabb::from_points(
+                vertices
+                    .iter()
+                    .map(|vertex| cgmath::Vector3::from(vertex.position).cast().unwrap()),
+            ),
+            triangles: None,
+        }
+    }
 }
 
 pub struct Model {
@@ -114,6 +306,30 @@ pub struct Model {
     pub materials: Vec<Material>,
 }
 
+impl Model {
+    /// The extent of all meshes in model space.
+    pub fn bounds(&self) -> picking::Aabb {
+        self.meshes
+            .iter()
+            .map(|mesh| mesh.bounds)
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or_else(|| picking::Aabb::from_points([]))
+    }
+
+    /// The distance along a model space ray to the nearest mesh, using the
+    /// triangles where kept and the bounds otherwise.
+    pub fn intersect_ray(&self, ray: &picking::Ray) -> Option<f64> {
+        ray.intersect_box(&self.bounds())?;
+        self.meshes
+            .iter()
+            .filter_map(|mesh| match &mesh.triangles {
+                Some(triangles) => triangles.intersect_ray(ray).map(|hit| hit.distance),
+                None => ray.intersect_box(&mesh.bounds),
+            })
+            .min_by(f64::total_cmp)
+    }
+}
+
 pub trait DrawModel<'a> {
     fn draw_mesh(
         &mut self,
@@ -314,3 +530,83 @@ where
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, Vector3};
+
+    fn assert_close(actual: Vector3<f32>, expected: Vector3<f64>) {
+        let expected = expected.cast::<f32>().unwrap();
+        assert!(
+            (actual - expected).magnitude() < 1e-5,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn from_geodetic_follows_heading_pitch_and_roll() {
+        let position = geodesy::Geodetic::from_degrees(51.5, -0.12, 30.0);
+        let (east, north, up) = geodesy::enu_axes(position.latitude, position.longitude);
+        let axes = |instance: Instance| {
+            let rotation = instance.rotation;
+            (
+                rotation * -Vector3::unit_z(),
+                rotation * Vector3::unit_x(),
+                rotation * Vector3::unit_y(),
+            )
+        };
+
+        let (forward, right, model_up) = axes(Instance::from_geodetic(
+            &position,
+            Deg(0.0),
+            Deg(0.0),
+            Deg(0.0),
+            1.0,
+        ));
+        assert_close(forward, north);
+        assert_close(right, east);
+        assert_close(model_up, up);
+        assert_eq!(
+            Instance::from_geodetic(&position, Deg(0.0), Deg(0.0), Deg(0.0), 1.0).position,
+            position.to_ecef()
+        );
+
+        // Heading turns clockwise seen from above, so 90° faces east
+        let (forward, right, _) = axes(Instance::from_geodetic(
+            &position,
+            Deg(90.0),
+            Deg(0.0),
+            Deg(0.0),
+            1.0,
+        ));
+        assert_close(forward, east);
+        assert_close(right, -north);
+
+        // Pitch raises the nose towards up
+        let (sin, cos) = 30f64.to_radians().sin_cos();
+        let (forward, right, _) = axes(Instance::from_geodetic(
+            &position,
+            Deg(0.0),
+            Deg(30.0),
+            Deg(0.0),
+            1.0,
+        ));
+        assert_close(forward, north * cos + up * sin);
+        assert_close(right, east);
+
+        // Roll banks about the forward axis, lowering the right side
+        let (forward, right, model_up) = axes(Instance::from_geodetic(
+            &position,
+            Deg(0.0),
+            Deg(0.0),
+            Deg(30.0),
+            1.0,
+        ));
+        assert_close(forward, north);
+        assert_close(right, east * cos - up * sin);
+        assert_close(model_up, up * cos + east * sin);
+    }
+}
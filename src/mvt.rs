@@ -0,0 +1,625 @@
+use anyhow::{anyhow, bail, Context};
+use serde_json::Value;
+use std::io::Read;
+
+use crate::geodesy::Geodetic;
+use crate::tiles::{self, TileId};
+use crate::vector::{Feature, Geometry, Path, Properties};
+
+/// A named layer of a Mapbox Vector Tile, with its features in geodetic
+/// coordinates.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layer {
+    pub name: String,
+    pub features: Vec<Feature>,
+}
+
+/// A position in tile units, `y` counted down from the north edge.
+type TilePoint = (f64, f64);
+
+/// Decodes a Mapbox Vector Tile (version 2) covering `tile`. Gzipped tiles
+/// are inflated first.
+///
+/// Features are clipped to the tile, dropping the buffer around it that
+/// neighbouring tiles draw. Points and lines keep a height of 0.
+pub fn decode(data: &[u8], tile: TileId) -> anyhow::Result<Vec<Layer>> {
+    if data.starts_with(&[0x1f, 0x8b]) {
+        let mut inflated = Vec::new();
+        flate2::read::GzDecoder::new(data)
+            .read_to_end(&mut inflated)
+            .context("inflating vector tile")?;
+        return decode(&inflated, tile);
+    }
+
+    let mut reader = Reader::new(data);
+    let mut layers = Vec::new();
+    while let Some((field, wire_type)) = reader.key()? {
+        match (field, wire_type) {
+            (3, WireType::LengthDelimited) => {
+                layers.push(decode_layer(reader.bytes()?, tile).context("decoding layer")?)
+            }
+            _ => reader.skip(wire_type)?,
+        }
+    }
+    Ok(layers)
+}
+
+fn decode_layer(data: &[u8], tile: TileId) -> anyhow::Result<Layer> {
+    let mut reader = Reader::new(data);
+    let mut name = String::new();
+    let mut raw_features = Vec::new();
+    let mut keys = Vec::new();
+    let mut values = Vec::new();
+    let mut extent = 4096;
+    while let Some((field, wire_type)) = reader.key()? {
+        match (field, wire_type) {
+            (1, WireType::LengthDelimited) => name = reader.string()?,
+            (2, WireType::LengthDelimited) => raw_features.push(reader.bytes()?),
+            (3, WireType::LengthDelimited) => keys.push(reader.string()?),
+            (4, WireType::LengthDelimited) => values.push(decode_value(reader.bytes()?)?),
+            (5, WireType::Varint) => extent = reader.varint()? as u32,
+            (15, WireType::Varint) => {
+                let version = reader.varint()?;
+                if version > 2 {
+                    bail!("unsupported vector tile version {}", version);
+                }
+            }
+            _ => reader.skip(wire_type)?,
+        }
+    }
+    if extent == 0 {
+        bail!("layer {} has an extent of 0", name);
+    }
+
+    let mut features = Vec::with_capacity(raw_features.len());
+    for data in raw_features {
+        if let Some(feature) = decode_feature(data, &keys, &values, extent as f64, tile)
+            .with_context(|| format!("decoding a feature of {}", name))?
+        {
+            features.push(feature);
+        }
+    }
+    Ok(Layer { name, features })
+}
+
+fn decode_value(data: &[u8]) -> anyhow::Result<Value> {
+    let mut reader = Reader::new(data);
+    let mut value = Value::Null;
+    while let Some((field, wire_type)) = reader.key()? {
+        value = match (field, wire_type) {
+            (1, WireType::LengthDelimited) => Value::from(reader.string()?),
+            (2, WireType::Fixed32) => Value::from(f32::from_bits(reader.fixed32()?) as f64),
+            (3, WireType::Fixed64) => Value::from(f64::from_bits(reader.fixed64()?)),
+            (4, WireType::Varint) => Value::from(reader.varint()? as i64),
+            (5, WireType::Varint) => Value::from(reader.varint()?),
+            (6, WireType::Varint) => Value::from(zigzag(reader.varint()?)),
+            (7, WireType::Varint) => Value::from(reader.varint()? != 0),
+            _ => {
+                reader.skip(wire_type)?;
+                continue;
+            }
+        };
+    }
+    Ok(value)
+}
+
+fn decode_feature(
+    data: &[u8],
+    keys: &[String],
+    values: &[Value],
+    extent: f64,
+    tile: TileId,
+) -> anyhow::Result<Option<Feature>> {
+    let mut reader = Reader::new(data);
+    let mut id = None;
+    let mut tags = Vec::new();
+    let mut kind = 0;
+    let mut commands = Vec::new();
+    while let Some((field, wire_type)) = reader.key()? {
+        match (field, wire_type) {
+            (1, WireType::Varint) => id = Some(Value::from(reader.varint()?)),
+            (2, WireType::LengthDelimited) => tags = reader.packed()?,
+            (3, WireType::Varint) => kind = reader.varint()?,
+            (4, WireType::LengthDelimited) => commands = reader.packed()?,
+            _ => reader.skip(wire_type)?,
+        }
+    }
+
+    let mut properties = Properties::new();
+    for pair in tags.chunks(2) {
+        let (key, value) = match pair {
+            [key, value] => (keys.get(*key as usize), values.get(*value as usize)),
+            _ => bail!("odd number of tags"),
+        };
+        let (key, value) = key
+            .zip(value)
+            .ok_or_else(|| anyhow!("a tag refers past the layer's keys or values"))?;
+        properties.insert(key.clone(), value.clone());
+    }
+
+    let paths = decode_commands(&commands)?;
+    let to_geodetic = |path: &[TilePoint]| -> Path {
+        let tiles = (1u64 << tile.z) as f64;
+        path.iter()
+            .map(|&(x, y)| {
+                Geodetic::new(
+                    tiles::web_mercator_latitude(tile.y as f64 + y / extent, tiles),
+                    tiles::web_mercator_longitude(tile.x as f64 + x / extent, tiles),
+                    0.0,
+                )
+            })
+            .collect()
+    };
+
+    let geometry = match kind {
+        // Point
+        1 => {
+            let mut points = paths
+                .iter()
+                .flatten()
+                .filter(|&&(x, y)| (0.0..extent).contains(&x) && (0.0..extent).contains(&y))
+                .map(|&point| to_geodetic(&[point])[0])
+                .collect::<Vec<_>>();
+            match points.len() {
+                0 => None,
+                1 => points.pop().map(Geometry::Point),
+                _ => Some(Geometry::MultiPoint(points)),
+            }
+        }
+        // LineString
+        2 => {
+            let mut lines = paths
+                .iter()
+                .flat_map(|path| clip_line(path, extent))
+                .map(|path| to_geodetic(&path))
+                .collect::<Vec<_>>();
+            match lines.len() {
+                0 => None,
+                1 => lines.pop().map(Geometry::LineString),
+                _ => Some(Geometry::MultiLineString(lines)),
+            }
+        }
+        // Polygon
+        3 => {
+            let mut polygons = Vec::<Vec<Path>>::new();
+            let mut outer_clipped_away = false;
+            for ring in paths {
+                let is_outer = signed_area(&ring) > 0.0;
+                let clipped = clip_ring(&ring, extent);
+                if is_outer {
+                    outer_clipped_away = clipped.len() < 3;
+                    if !outer_clipped_away {
+                        polygons.push(vec![to_geodetic(&clipped)]);
+                    }
+                } else if clipped.len() >= 3 && !outer_clipped_away {
+                    // A hole belongs to the exterior ring before it
+                    if let Some(polygon) = polygons.last_mut() {
+                        polygon.push(to_geodetic(&clipped));
+                    }
+                }
+            }
+            match polygons.len() {
+                0 => None,
+                1 => polygons.pop().map(Geometry::Polygon),
+                _ => Some(Geometry::MultiPolygon(polygons)),
+            }
+        }
+        _ => None,
+    };
+
+    Ok(geometry.map(|geometry| Feature {
+        id,
+        geometry,
+        properties,
+    }))
+}
+
+/// Runs the MoveTo, LineTo and ClosePath commands of a feature, returning
+/// the paths they trace. Closed rings don't repeat their first point.
+fn decode_commands(commands: &[u32]) -> anyhow::Result<Vec<Vec<TilePoint>>> {
+    let mut paths = Vec::<Vec<TilePoint>>::new();
+    let (mut x, mut y) = (0i64, 0i64);
+    let mut i = 0;
+    while i < commands.len() {
+        let (command, count) = (commands[i] & 0x7, (commands[i] >> 3) as usize);
+        i += 1;
+        match command {
+            // MoveTo and LineTo
+            1 | 2 => {
+                let parameters = commands
+                    .get(i..i + 2 * count)
+                    .ok_or_else(|| anyhow!("geometry ends in the middle of a command"))?;
+                i += 2 * count;
+                for pair in parameters.chunks(2) {
+                    x += zigzag(pair[0] as u64);
+                    y += zigzag(pair[1] as u64);
+                    if command == 1 {
+                        paths.push(Vec::new());
+                    }
+                    paths
+                        .last_mut()
+                        .ok_or_else(|| anyhow!("LineTo before MoveTo"))?
+                        .push((x as f64, y as f64));
+                }
+            }
+            // ClosePath
+            7 => {}
+            _ => bail!("unknown geometry command {}", command),
+        }
+    }
+    Ok(paths)
+}
+
+fn zigzag(value: u64) -> i64 {
+    (value >> 1) as i64 ^ -((value & 1) as i64)
+}
+
+/// Twice the area of a ring by the surveyor's formula, positive for the
+/// exterior rings of the tile's y-down coordinates.
+fn signed_area(ring: &[TilePoint]) -> f64 {
+    (0..ring.len())
+        .map(|i| {
+            let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum()
+}
+
+/// Clips a ring to the `[0, extent]` square with Sutherland-Hodgman.
+fn clip_ring(ring: &[TilePoint], extent: f64) -> Vec<TilePoint> {
+    type Edge = fn(TilePoint, f64) -> f64;
+    // Signed distances inside each edge of the square
+    let edges: [Edge; 4] = [
+        |p, _| p.0,
+        |p, extent| extent - p.0,
+        |p, _| p.1,
+        |p, extent| extent - p.1,
+    ];
+
+    let mut points = ring.to_vec();
+    for inside in edges {
+        let input = std::mem::take(&mut points);
+        for (i, &current) in input.iter().enumerate() {
+            let previous = input[(i + input.len() - 1) % input.len()];
+            let (d0, d1) = (inside(previous, extent), inside(current, extent));
+            if (d0 >= 0.0) != (d1 >= 0.0) {
+                let t = d0 / (d0 - d1);
+                points.push((
+                    previous.0 + (current.0 - previous.0) * t,
+                    previous.1 + (current.1 - previous.1) * t,
+                ));
+            }
+            if d1 >= 0.0 {
+                points.push(current);
+            }
+        }
+    }
+    points
+}
+
+/// Clips a line to the `[0, extent]` square, splitting it wherever it
+/// leaves and comes back.
+fn clip_line(line: &[TilePoint], extent: f64) -> Vec<Vec<TilePoint>> {
+    let mut lines = Vec::new();
+    let mut current = Vec::<TilePoint>::new();
+    for segment in line.windows(2) {
+        let (a, b) = (segment[0], segment[1]);
+        // Liang-Barsky
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let (mut t0, mut t1) = (0.0f64, 1.0f64);
+        let mut visible = true;
+        for (p, q) in [
+            (-dx, a.0),
+            (dx, extent - a.0),
+            (-dy, a.1),
+            (dy, extent - a.1),
+        ] {
+            if p == 0.0 {
+                visible &= q >= 0.0;
+            } else if p < 0.0 {
+                t0 = t0.max(q / p);
+            } else {
+                t1 = t1.min(q / p);
+            }
+        }
+        if !visible || t0 > t1 {
+            if current.len() > 1 {
+                lines.push(std::mem::take(&mut current));
+            }
+            current.clear();
+            continue;
+        }
+
+        let start = (a.0 + dx * t0, a.1 + dy * t0);
+        let end = (a.0 + dx * t1, a.1 + dy * t1);
+        if current.last() != Some(&start) {
+            if current.len() > 1 {
+                lines.push(std::mem::take(&mut current));
+            }
+            current = vec![start];
+        }
+        current.push(end);
+        if t1 < 1.0 {
+            lines.push(std::mem::take(&mut current));
+        }
+    }
+    if current.len() > 1 {
+        lines.push(current);
+    }
+    lines
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum WireType {
+    Varint,
+    Fixed64,
+    LengthDelimited,
+    Fixed32,
+}
+
+/// Reads the parts of the protocol buffer encoding vector tiles use.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    /// The next field number and wire type, or `None` at the end.
+    fn key(&mut self) -> anyhow::Result<Option<(u32, WireType)>> {
+        if self.offset == self.data.len() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let wire_type = match key & 0x7 {
+            0 => WireType::Varint,
+            1 => WireType::Fixed64,
+            2 => WireType::LengthDelimited,
+            5 => WireType::Fixed32,
+            other => bail!("unsupported wire type {}", other),
+        };
+        Ok(Some(((key >> 3) as u32, wire_type)))
+    }
+
+    fn varint(&mut self) -> anyhow::Result<u64> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = *self
+                .data
+                .get(self.offset)
+                .ok_or_else(|| anyhow!("truncated varint"))?;
+            self.offset += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("varint longer than 64 bits")
+    }
+
+    fn take(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset.saturating_add(length))
+            .ok_or_else(|| anyhow!("field runs past the end of its message"))?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+        let length = self.varint()? as usize;
+        self.take(length)
+    }
+
+    fn string(&mut self) -> anyhow::Result<String> {
+        Ok(std::str::from_utf8(self.bytes()?)?.to_string())
+    }
+
+    fn fixed32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn fixed64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    /// A packed repeated `uint32` field.
+    fn packed(&mut self) -> anyhow::Result<Vec<u32>> {
+        let mut packed = Reader::new(self.bytes()?);
+        let mut values = Vec::new();
+        while packed.offset < packed.data.len() {
+            values.push(packed.varint()? as u32);
+        }
+        Ok(values)
+    }
+
+    fn skip(&mut self, wire_type: WireType) -> anyhow::Result<()> {
+        match wire_type {
+            WireType::Varint => self.varint().map(drop),
+            WireType::Fixed64 => self.take(8).map(drop),
+            WireType::LengthDelimited => self.bytes().map(drop),
+            WireType::Fixed32 => self.take(4).map(drop),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes protocol buffer messages for the tests.
+    #[derive(Default)]
+    struct Writer(Vec<u8>);
+
+    impl Writer {
+        fn varint(mut self, mut value: u64) -> Self {
+            while value >= 0x80 {
+                self.0.push(value as u8 | 0x80);
+                value >>= 7;
+            }
+            self.0.push(value as u8);
+            self
+        }
+
+        fn field(self, field: u32, value: u64) -> Self {
+            self.varint((field as u64) << 3).varint(value)
+        }
+
+        fn message(self, field: u32, bytes: &[u8]) -> Self {
+            let mut writer = self
+                .varint((field as u64) << 3 | 2)
+                .varint(bytes.len() as u64);
+            writer.0.extend_from_slice(bytes);
+            writer
+        }
+
+        fn packed(self, field: u32, values: &[u32]) -> Self {
+            let inner = values.iter().fold(Writer::default(), |writer, &value| {
+                writer.varint(value as u64)
+            });
+            self.message(field, &inner.0)
+        }
+    }
+
+    fn command(id: u32, count: u32) -> u32 {
+        id | count << 3
+    }
+
+    fn zz(value: i32) -> u32 {
+        ((value << 1) ^ (value >> 31)) as u32
+    }
+
+    fn layer() -> Vec<u8> {
+        // A point, a line leaving the tile and a square with a hole
+        let point = Writer::default()
+            .field(1, 9)
+            .packed(2, &[0, 0, 1, 1])
+            .field(3, 1)
+            .packed(4, &[command(1, 1), zz(25), zz(16)]);
+        let line = Writer::default().field(3, 2).packed(
+            4,
+            &[
+                command(1, 1),
+                zz(2),
+                zz(2),
+                command(2, 2),
+                zz(40),
+                zz(0),
+                zz(0),
+                zz(10),
+            ],
+        );
+        let polygon = Writer::default().field(3, 3).packed(
+            4,
+            &[
+                // Clockwise on screen, so exterior
+                command(1, 1),
+                zz(0),
+                zz(0),
+                command(2, 3),
+                zz(8),
+                zz(0),
+                zz(0),
+                zz(8),
+                zz(-8),
+                zz(0),
+                command(7, 1),
+                // Counterclockwise, so a hole
+                command(1, 1),
+                zz(2),
+                zz(-6),
+                command(2, 3),
+                zz(0),
+                zz(4),
+                zz(4),
+                zz(0),
+                zz(0),
+                zz(-4),
+                command(7, 1),
+            ],
+        );
+        Writer::default()
+            .field(15, 2)
+            .message(1, b"places")
+            .message(2, &point.0)
+            .message(2, &line.0)
+            .message(2, &polygon.0)
+            .message(3, b"name")
+            .message(3, b"rank")
+            .message(4, &Writer::default().message(1, b"Bern").0)
+            .message(4, &Writer::default().field(6, zz(-3) as u64).0)
+            .field(5, 32)
+            .0
+    }
+
+    #[test]
+    fn decodes_layers_and_properties() {
+        let data = Writer::default().message(3, &layer()).0;
+        let layers = decode(&data, TileId::new(0, 0, 0)).unwrap();
+        assert_eq!(layers.len(), 1);
+        assert_eq!(layers[0].name, "places");
+        let features = &layers[0].features;
+        assert_eq!(features.len(), 3);
+
+        assert_eq!(features[0].id, Some(Value::from(9)));
+        assert_eq!(features[0].properties["name"], "Bern");
+        assert_eq!(features[0].properties["rank"], -3);
+        match features[0].geometry {
+            // The middle of a 32 unit wide world is on the equator
+            Geometry::Point(point) => {
+                assert!(point.latitude.abs() < 1e-12);
+                assert!(point.longitude > 0.0);
+            }
+            ref geometry => panic!("expected a point, found {:?}", geometry),
+        }
+
+        // The line leaves the tile on its way to (42, 2) and doesn't come back
+        match &features[1].geometry {
+            Geometry::LineString(line) => assert_eq!(line.len(), 2),
+            geometry => panic!("expected a line, found {:?}", geometry),
+        }
+
+        match &features[2].geometry {
+            Geometry::Polygon(rings) => {
+                assert_eq!(rings.len(), 2);
+                assert_eq!(rings[0].len(), 4);
+                assert_eq!(rings[1].len(), 4);
+            }
+            geometry => panic!("expected a polygon, found {:?}", geometry),
+        }
+    }
+
+    #[test]
+    fn gzipped_tiles_are_inflated() {
+        use std::io::Write;
+
+        let data = Writer::default().message(3, &layer()).0;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        encoder.write_all(&data).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        assert_eq!(
+            decode(&gzipped, TileId::new(3, 2, 1)).unwrap(),
+            decode(&data, TileId::new(3, 2, 1)).unwrap()
+        );
+        assert!(decode(&data[..data.len() - 1], TileId::new(0, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn geometry_is_clipped_to_the_tile() {
+        let square = [(-5.0, -5.0), (5.0, -5.0), (5.0, 5.0), (-5.0, 5.0)];
+        let clipped = clip_ring(&square, 10.0);
+        assert!((signed_area(&clipped) - 2.0 * 25.0).abs() < 1e-9);
+        assert!(clipped.iter().all(|&(x, y)| x >= 0.0 && y >= 0.0));
+
+        // In, out and back in again makes two lines
+        let lines = clip_line(&[(5.0, 5.0), (15.0, 5.0), (15.0, 8.0), (5.0, 8.0)], 10.0);
+        assert_eq!(
+            lines,
+            vec![vec![(5.0, 5.0), (10.0, 5.0)], vec![(10.0, 8.0), (5.0, 8.0)]]
+        );
+        assert!(clip_line(&[(11.0, 0.0), (11.0, 10.0)], 10.0).is_empty());
+    }
+}
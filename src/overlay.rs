@@ -0,0 +1,721 @@
+use anyhow::anyhow;
+use cgmath::prelude::*;
+use cgmath::{Point3, Rad, Vector3};
+use std::cell::RefCell;
+use std::ops::Range;
+use wgpu::util::DeviceExt;
+
+use crate::geodesy::{self, Geodetic};
+use crate::model::{self, Vertex};
+use crate::polyline::{self, DrawPolylines, LineStyle, Polyline, Polylines};
+use crate::vector::{Feature, Path, Style};
+use crate::{camera, texture};
+
+/// Overlay triangles and line segments longer than this many radians are
+/// split, so they follow the curve of the ellipsoid instead of cutting
+/// through it.
+pub const GRANULARITY: f64 = 0.5 * std::f64::consts::PI / 180.0;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct OverlayVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4],
+    /// Pixels to move the vertex by on screen.
+    pub offset: [f32; 2],
+    /// Marker radius in pixels, 0 for anything but markers.
+    pub radius: f32,
+}
+
+impl Vertex for OverlayVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<OverlayVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 7]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x2,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
+
+/// Latitude and longitude pairs with the indices of the triangles between
+/// them.
+pub type Triangulation = (Vec<(f64, f64)>, Vec<u32>);
+
+/// Triangulates a polygon given as an outer ring and holes, returning the
+/// latitude and longitude of each vertex and the triangle indices.
+///
+/// Triangulation happens in the latitude/longitude plane, so polygons must
+/// not cross the antimeridian. Large triangles are then subdivided down to
+/// `granularity` radians.
+pub fn triangulate_polygon(rings: &[Path], granularity: f64) -> anyhow::Result<Triangulation> {
+    let mut flat = Vec::new();
+    let mut holes = Vec::new();
+    for (i, ring) in rings.iter().enumerate() {
+        if i > 0 {
+            holes.push(flat.len() / 2);
+        }
+        for position in ring {
+            flat.extend_from_slice(&[position.longitude, position.latitude]);
+        }
+    }
+    let triangles = earcutr::earcut(&flat, &holes, 2)
+        .map_err(|e| anyhow!("couldn't triangulate polygon: {:?}", e))?;
+
+    let corners = flat
+        .chunks_exact(2)
+        .map(|lon_lat| (lon_lat[1], lon_lat[0]))
+        .collect::<Vec<_>>();
+    let mut coordinates = Vec::new();
+    let mut indices = Vec::new();
+    for triangle in triangles.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|i| corners[triangle[i]]);
+        subdivide_triangle(a, b, c, granularity, &mut coordinates, &mut indices);
+    }
+    Ok((coordinates, indices))
+}
+
+/// Splits a triangle into a regular grid of smaller ones whose edges are no
+/// longer than `granularity`.
+fn subdivide_triangle(
+    a: (f64, f64),
+    b: (f64, f64),
+    c: (f64, f64),
+    granularity: f64,
+    coordinates: &mut Vec<(f64, f64)>,
+    indices: &mut Vec<u32>,
+) {
+    let length = |p: (f64, f64), q: (f64, f64)| (p.0 - q.0).hypot(p.1 - q.1);
+    let longest = length(a, b).max(length(b, c)).max(length(c, a));
+    let steps = ((longest / granularity).ceil() as usize).clamp(1, 64);
+
+    // Rows of points from the `a` corner towards the `b`-`c` edge
+    let first = coordinates.len() as u32;
+    let row_start = |row: usize| first + (row * (row + 1) / 2) as u32;
+    for row in 0..=steps {
+        for column in 0..=row {
+            let (u, v) = (row as f64 / steps as f64, column as f64 / steps as f64);
+            let (wa, wb, wc) = (1.0 - u, u - v, v);
+            coordinates.push((
+                wa * a.0 + wb * b.0 + wc * c.0,
+                wa * a.1 + wb * b.1 + wc * c.1,
+            ));
+        }
+    }
+    for row in 0..steps {
+        for column in 0..=row {
+            let top = row_start(row) + column as u32;
+            let bottom = row_start(row + 1) + column as u32;
+            indices.extend_from_slice(&[top, bottom, bottom + 1]);
+            if column < row {
+                indices.extend_from_slice(&[top, bottom + 1, top + 1]);
+            }
+        }
+    }
+}
+
+/// Adds points along each segment of a path so none is longer than
+/// `granularity` radians.
+fn densify(path: &[Geodetic], granularity: f64) -> Vec<Geodetic> {
+    let mut points = Vec::with_capacity(path.len());
+    for pair in path.windows(2) {
+        let (from, to) = (pair[0], pair[1]);
+        let length = (to.latitude - from.latitude).hypot(to.longitude - from.longitude);
+        let steps = ((length / granularity).ceil() as usize).clamp(1, 1024);
+        for step in 0..steps {
+            let t = step as f64 / steps as f64;
+            points.push(Geodetic::new(
+                from.latitude + (to.latitude - from.latitude) * t,
+                from.longitude + (to.longitude - from.longitude) * t,
+                from.height + (to.height - from.height) * t,
+            ));
+        }
+    }
+    points.extend(path.last());
+    points
+}
+
+/// The geometry of one feature, relative to its own center, with its lines
+/// kept apart for the polyline pipeline.
+#[derive(Debug)]
+pub struct FeatureGeometry {
+    pub center: Vector3<f64>,
+    pub vertices: Vec<OverlayVertex>,
+    pub indices: Vec<u32>,
+    pub lines: Vec<Polyline>,
+}
+
+impl FeatureGeometry {
+    /// Builds polygons, lines and markers for `feature`, `height` meters
+    /// above its coordinates. Lines are left out when the stroke is
+    /// invisible.
+    pub fn new(feature: &Feature, style: &Style, height: f64) -> Self {
+        let stroked = style.stroke[3] > 0.0 && style.stroke_width > 0.0;
+        let line_style = LineStyle {
+            color: style.stroke,
+            width: style.stroke_width,
+            join: style.stroke_join,
+            cap: style.stroke_cap,
+            ..Default::default()
+        };
+        let lines = RefCell::new(Vec::new());
+        let add_line = |path: &[Geodetic], closed| {
+            let mut points = path
+                .iter()
+                .map(|p| Geodetic::new(p.latitude, p.longitude, p.height + height))
+                .collect::<Vec<_>>();
+            if closed {
+                points.extend(points.first().copied());
+            }
+            let mut points = densify(&points, GRANULARITY)
+                .into_iter()
+                .map(Geodetic::to_ecef)
+                .collect::<Vec<_>>();
+            if closed {
+                points.pop();
+            }
+            lines.borrow_mut().push(Polyline {
+                points,
+                closed,
+                style: line_style,
+            });
+        };
+        // Markers, lines and polygons each add a part: vertices with their
+        // screen offset and marker radius, indices and a color
+        let parts = RefCell::new(Vec::new());
+        let add = |vertices: Vec<(Vector3<f64>, [f32; 2], f32)>, indices: Vec<u32>, color| {
+            parts.borrow_mut().push((vertices, indices, color))
+        };
+
+        feature.geometry.visit(
+            &mut |point| {
+                let lifted = Geodetic::new(point.latitude, point.longitude, point.height + height);
+                let r = style.marker_size;
+                let corners = [[-r, -r], [r, -r], [r, r], [-r, r]]
+                    .map(|offset| (lifted.to_ecef(), offset, r))
+                    .to_vec();
+                add(corners, vec![0, 1, 2, 0, 2, 3], style.marker);
+            },
+            &mut |path| {
+                if stroked {
+                    add_line(path, false);
+                }
+            },
+            &mut |rings| {
+                match triangulate_polygon(rings, GRANULARITY) {
+                    Ok((coordinates, indices)) => {
+                        let vertices = coordinates
+                            .into_iter()
+                            .map(|(latitude, longitude)| {
+                                let position =
+                                    geodesy::geodetic_to_ecef(latitude, longitude, height);
+                                (position, [0.0; 2], 0.0)
+                            })
+                            .collect();
+                        add(vertices, indices, style.fill);
+                    }
+                    Err(e) => log::warn!("Skipping polygon: {}", e),
+                }
+                // Outline every ring at the height of the fill
+                for ring in rings.iter().filter(|_| stroked) {
+                    let flat = ring
+                        .iter()
+                        .map(|p| Geodetic::new(p.latitude, p.longitude, 0.0))
+                        .collect::<Vec<_>>();
+                    add_line(&flat, true);
+                }
+            },
+        );
+
+        let parts = parts.into_inner();
+        let lines = lines.into_inner();
+        let first_line_point = lines.iter().find_map(|line| line.points.first());
+        let center = match parts.iter().find_map(|(vertices, _, _)| vertices.first()) {
+            Some(&(first, _, _)) => first,
+            None => first_line_point.copied().unwrap_or_else(Vector3::zero),
+        };
+        let mut geometry = FeatureGeometry {
+            center,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            lines,
+        };
+        for (vertices, indices, color) in parts {
+            let base = geometry.vertices.len() as u32;
+            geometry
+                .indices
+                .extend(indices.into_iter().map(|index| base + index));
+            geometry
+                .vertices
+                .extend(
+                    vertices
+                        .into_iter()
+                        .map(|(position, offset, radius)| OverlayVertex {
+                            position: (position - center).cast().unwrap().into(),
+                            color,
+                            offset,
+                            radius,
+                        }),
+                );
+        }
+        geometry
+    }
+
+    /// Whether there is nothing to draw.
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty() && self.lines.is_empty()
+    }
+
+    /// Joins several geometries into one positioned relative to `center`,
+    /// so they can be drawn together.
+    pub fn merge(
+        geometries: impl IntoIterator<Item = FeatureGeometry>,
+        center: Vector3<f64>,
+    ) -> Self {
+        let mut merged = FeatureGeometry {
+            center,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+            lines: Vec::new(),
+        };
+        for geometry in geometries {
+            let base = merged.vertices.len() as u32;
+            let shift = geometry.center - center;
+            merged
+                .indices
+                .extend(geometry.indices.iter().map(|index| base + index));
+            merged
+                .vertices
+                .extend(geometry.vertices.iter().map(|vertex| {
+                    OverlayVertex {
+                        position: (Vector3::from(vertex.position).cast().unwrap() + shift)
+                            .cast()
+                            .unwrap()
+                            .into(),
+                        ..*vertex
+                    }
+                }));
+            // Lines are in ECEF and batch themselves
+            merged.lines.extend(geometry.lines);
+        }
+        merged
+    }
+}
+
+/// A range of the overlay's buffers with its own origin.
+struct Batch {
+    center: Vector3<f64>,
+    indices: Range<u32>,
+    base_vertex: i32,
+}
+
+/// Vector features draped over the globe, with each feature positioned
+/// relative to its own center.
+pub struct VectorOverlay {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    instance_buffer: wgpu::Buffer,
+    batches: Vec<Batch>,
+    lines: Polylines,
+}
+
+impl VectorOverlay {
+    /// Builds an overlay of `features` styled by `style`, `height` meters
+    /// above their coordinates.
+    pub fn new(
+        device: &wgpu::Device,
+        features: &[Feature],
+        style: impl Fn(&Feature) -> Style,
+        height: f64,
+    ) -> Self {
+        Self::from_geometries(
+            device,
+            features
+                .iter()
+                .map(|feature| FeatureGeometry::new(feature, &style(feature), height)),
+        )
+    }
+
+    /// Builds an overlay drawing each geometry with its own origin.
+    pub fn from_geometries(
+        device: &wgpu::Device,
+        geometries: impl IntoIterator<Item = FeatureGeometry>,
+    ) -> Self {
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut batches = Vec::new();
+        let mut lines = Vec::new();
+        for geometry in geometries {
+            lines.extend(geometry.lines);
+            if geometry.indices.is_empty() {
+                continue;
+            }
+            batches.push(Batch {
+                center: geometry.center,
+                indices: indices.len() as u32..(indices.len() + geometry.indices.len()) as u32,
+                base_vertex: vertices.len() as i32,
+            });
+            vertices.extend(geometry.vertices);
+            indices.extend(geometry.indices);
+        }
+
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX,
+        });
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overlay Instance Buffer"),
+            size: (batches.len().max(1) * std::mem::size_of::<model::InstanceRaw>())
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            vertex_buffer,
+            index_buffer,
+            instance_buffer,
+            batches,
+            lines: Polylines::new(device, lines),
+        }
+    }
+
+    /// Rebases the features against the camera.
+    pub fn update(&self, queue: &wgpu::Queue, camera_position: Point3<f64>) {
+        let instance_data = self
+            .batches
+            .iter()
+            .map(|batch| {
+                model::Instance::new(batch.center, cgmath::Quaternion::one())
+                    .to_raw(camera_position)
+            })
+            .collect::<Vec<_>>();
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&instance_data),
+        );
+        self.lines.update(queue, camera_position);
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ViewportUniform {
+    size: [f32; 4],
+}
+
+/// The pipelines overlays are drawn with. Overlays blend over the globe and
+/// are depth tested against it without writing depth themselves.
+pub struct OverlayRenderer {
+    pipeline: wgpu::RenderPipeline,
+    viewport_buffer: wgpu::Buffer,
+    viewport_bind_group: wgpu::BindGroup,
+    lines: polyline::PolylineRenderer,
+}
+
+impl OverlayRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        projection: &camera::Projection,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let depth_compare = projection.depth_compare();
+        let viewport_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Viewport Buffer"),
+            contents: bytemuck::cast_slice(&[ViewportUniform {
+                size: [config.width as f32, config.height as f32, 0.0, 0.0],
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let viewport_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("viewport_bind_group_layout"),
+            });
+        let viewport_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &viewport_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: viewport_buffer.as_entire_binding(),
+            }],
+            label: Some("viewport_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Overlay Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &viewport_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Overlay Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("overlay.wgsl").into()),
+        });
+        // Pull the overlay towards the camera so it wins against the surface
+        // it lies on, whichever way depth runs
+        let bias_direction = if depth_compare == wgpu::CompareFunction::Greater {
+            1
+        } else {
+            -1
+        };
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Overlay Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[OverlayVertex::desc(), model::InstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Polygons are seen from above, whatever their winding
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 64 * bias_direction,
+                    slope_scale: 2.0 * bias_direction as f32,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            viewport_buffer,
+            viewport_bind_group,
+            lines: polyline::PolylineRenderer::new(
+                device,
+                config,
+                projection,
+                camera_bind_group_layout,
+            ),
+        }
+    }
+
+    pub fn resize(&self, queue: &wgpu::Queue, width: u32, height: u32, fovy: Rad<f32>) {
+        queue.write_buffer(
+            &self.viewport_buffer,
+            0,
+            bytemuck::cast_slice(&[ViewportUniform {
+                size: [width as f32, height as f32, 0.0, 0.0],
+            }]),
+        );
+        self.lines.resize(queue, width, height, fovy);
+    }
+}
+
+pub trait DrawOverlay<'a> {
+    fn draw_overlay(
+        &mut self,
+        renderer: &'a OverlayRenderer,
+        overlay: &'a VectorOverlay,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawOverlay<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_overlay(
+        &mut self,
+        renderer: &'b OverlayRenderer,
+        overlay: &'b VectorOverlay,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        if !overlay.batches.is_empty() {
+            self.set_pipeline(&renderer.pipeline);
+            self.set_bind_group(0, camera_bind_group, &[]);
+            self.set_bind_group(1, &renderer.viewport_bind_group, &[]);
+            self.set_vertex_buffer(0, overlay.vertex_buffer.slice(..));
+            self.set_vertex_buffer(1, overlay.instance_buffer.slice(..));
+            self.set_index_buffer(overlay.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            for (i, batch) in overlay.batches.iter().enumerate() {
+                self.draw_indexed(
+                    batch.indices.clone(),
+                    batch.base_vertex,
+                    i as u32..i as u32 + 1,
+                );
+            }
+        }
+        self.draw_polylines(&renderer.lines, &overlay.lines, camera_bind_group);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vector::Geometry;
+
+    fn degrees(points: &[(f64, f64)]) -> Path {
+        points
+            .iter()
+            .map(|&(longitude, latitude)| Geodetic::from_degrees(latitude, longitude, 0.0))
+            .collect()
+    }
+
+    /// Twice the signed area of a triangle in the latitude/longitude plane.
+    fn area(coordinates: &[(f64, f64)], triangle: &[u32]) -> f64 {
+        let [a, b, c] = [0, 1, 2].map(|i| coordinates[triangle[i] as usize]);
+        ((b.1 - a.1) * (c.0 - a.0) - (c.1 - a.1) * (b.0 - a.0)).abs()
+    }
+
+    #[test]
+    fn polygons_with_holes_are_triangulated_and_subdivided() {
+        let square = degrees(&[(0.0, 0.0), (4.0, 0.0), (4.0, 4.0), (0.0, 4.0)]);
+        let hole = degrees(&[(1.0, 1.0), (1.0, 3.0), (3.0, 3.0), (3.0, 1.0)]);
+        let (coordinates, indices) =
+            triangulate_polygon(&[square.clone(), hole], GRANULARITY).unwrap();
+
+        let covered: f64 = indices
+            .chunks(3)
+            .map(|t| area(&coordinates, t))
+            .sum::<f64>()
+            / 2.0;
+        let expected = (16.0 - 4.0) * (1f64.to_radians()).powi(2);
+        assert!((covered - expected).abs() < expected * 1e-9);
+
+        // Nothing inside the hole
+        let hole_center = (2f64.to_radians(), 2f64.to_radians());
+        for &(latitude, longitude) in &coordinates {
+            assert!(
+                (latitude - hole_center.0).abs() >= 1f64.to_radians() - 1e-12
+                    || (longitude - hole_center.1).abs() >= 1f64.to_radians() - 1e-12
+            );
+        }
+
+        let (coarse, _) = triangulate_polygon(&[square], 10f64.to_radians()).unwrap();
+        assert!(coordinates.len() > coarse.len());
+    }
+
+    #[test]
+    fn lines_follow_the_ellipsoid_in_pixel_widths() {
+        let path = degrees(&[(10.0, 45.0), (10.0, 46.8), (11.8, 46.8)]);
+        let feature = Feature {
+            id: None,
+            geometry: Geometry::Polygon(vec![path]),
+            properties: Default::default(),
+        };
+        let style = Style::default();
+        let geometry = FeatureGeometry::new(&feature, &style, 5.0);
+        assert_eq!(geometry.lines.len(), 1);
+        let outline = &geometry.lines[0];
+        assert!(outline.closed);
+        assert_eq!(outline.style.width, style.stroke_width);
+        assert_eq!(outline.style.color, style.stroke);
+        // Split every half degree, the closing edge included but without
+        // repeating the first point
+        assert_eq!(outline.points.len(), 4 + 4 + 6);
+        for point in &outline.points {
+            assert!((Geodetic::from_ecef(*point).height - 5.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn features_are_relative_to_their_center() {
+        let feature = Feature {
+            id: None,
+            geometry: Geometry::MultiPoint(degrees(&[(7.0, 46.0), (7.1, 46.0)])),
+            properties: Default::default(),
+        };
+        let geometry = FeatureGeometry::new(&feature, &Style::default(), 10.0);
+        assert_eq!(geometry.vertices.len(), 8);
+        assert_eq!(geometry.indices.len(), 12);
+        assert!(Vector3::from(geometry.vertices[0].position).magnitude() < 1e-3);
+        assert!((Geodetic::from_ecef(geometry.center).height - 10.0).abs() < 1e-6);
+        assert_eq!(geometry.vertices[4].offset, [-8.0, -8.0]);
+        assert_eq!(geometry.indices[6], 4);
+    }
+
+    #[test]
+    fn merged_geometries_share_one_center() {
+        let line = Feature {
+            id: None,
+            geometry: Geometry::LineString(degrees(&[(7.0, 46.0), (7.001, 46.0)])),
+            properties: Default::default(),
+        };
+        let marker = Feature {
+            id: None,
+            geometry: Geometry::Point(degrees(&[(7.0, 46.0)])[0]),
+            properties: Default::default(),
+        };
+        let mut style = Style::default();
+        let stroked = FeatureGeometry::new(&line, &style, 0.0);
+        assert_eq!(stroked.lines.len(), 1);
+        assert!(stroked.indices.is_empty());
+        style.stroke[3] = 0.0;
+        assert!(FeatureGeometry::new(&line, &style, 0.0).is_empty());
+
+        let marked = FeatureGeometry::new(&marker, &style, 0.0);
+        let first = marked.vertices[0].position;
+        let center = marked.center + Vector3::new(100.0, 0.0, 0.0);
+        let merged = FeatureGeometry::merge([stroked, marked], center);
+        assert_eq!(merged.vertices.len(), 4);
+        assert_eq!(merged.indices.len(), 6);
+        assert_eq!(merged.lines.len(), 1);
+        let shifted = Vector3::from(merged.vertices[0].position) - Vector3::from(first);
+        assert!((shifted - Vector3::new(-100.0, 0.0, 0.0)).magnitude() < 1e-3);
+    }
+}
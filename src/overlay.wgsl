@@ -0,0 +1,75 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+struct Viewport {
+    // Width and height in pixels in xy
+    size: vec4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> viewport: Viewport;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] color: vec4<f32>;
+    [[location(2)]] offset: vec2<f32>;
+    [[location(3)]] radius: f32;
+};
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+    [[location(1)]] offset: vec2<f32>;
+    [[location(2)]] radius: f32;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    var out: VertexOutput;
+    var clip = camera.view_proj * model_matrix * vec4<f32>(model.position, 1.0);
+    // Markers are pushed apart in screen space so they keep their size
+    let offset = model.offset * 2.0 / viewport.size.xy * clip.w;
+    out.clip_position = vec4<f32>(clip.xy + offset, clip.zw);
+    out.color = model.color;
+    out.offset = model.offset;
+    out.radius = model.radius;
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    if (in.radius <= 0.0) {
+        return in.color;
+    }
+    // Round markers with a dark rim
+    let distance = length(in.offset);
+    if (distance > in.radius) {
+        discard;
+    }
+    if (distance > in.radius - 1.5) {
+        return vec4<f32>(in.color.rgb * 0.4, in.color.a);
+    }
+    return in.color;
+}
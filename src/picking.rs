@@ -0,0 +1,395 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+
+use crate::camera;
+use crate::geodesy::{self, Geodetic};
+use crate::model;
+
+/// A half-line in ECEF meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ray {
+    pub origin: Point3<f64>,
+    /// Unit length, so distances along the ray are in meters.
+    pub direction: Vector3<f64>,
+}
+
+impl Ray {
+    pub fn new(origin: Point3<f64>, direction: Vector3<f64>) -> Self {
+        Self {
+            origin,
+            direction: direction.normalize(),
+        }
+    }
+
+    /// The ray from the camera through the cursor, given in physical pixels
+    /// from the top left corner of a window of `size`.
+    pub fn from_cursor(
+        camera: &camera::GlobeCamera,
+        projection: &camera::Projection,
+        cursor: PhysicalPosition<f64>,
+        size: PhysicalSize<u32>,
+    ) -> Self {
+        let (forward, up) = camera.orientation();
+        let right = forward.cross(up);
+        let x = 2.0 * cursor.x / size.width as f64 - 1.0;
+        let y = 1.0 - 2.0 * cursor.y / size.height as f64;
+        let tan_half_fovy = (projection.fovy().0 as f64 / 2.0).tan();
+        let direction = forward
+            + right * x * tan_half_fovy * projection.aspect() as f64
+            + up * y * tan_half_fovy;
+        Self::new(camera.position(), direction)
+    }
+
+    pub fn at(&self, distance: f64) -> Point3<f64> {
+        self.origin + self.direction * distance
+    }
+
+    /// The distance to where the ray first meets the WGS84 ellipsoid grown
+    /// by `height` meters along both axes. That is close to, but not quite,
+    /// the surface at that height. Rays starting inside hit the far side.
+    pub fn intersect_ellipsoid(&self, height: f64) -> Option<f64> {
+        let a = geodesy::WGS84_A + height;
+        let b = geodesy::WGS84_A * (1.0 - geodesy::WGS84_F) + height;
+        // Squash the ellipsoid into a unit sphere
+        let scale = Vector3::new(1.0 / a, 1.0 / a, 1.0 / b);
+        let origin = self.origin.to_vec().mul_element_wise(scale);
+        let direction = self.direction.mul_element_wise(scale);
+
+        let qa = direction.magnitude2();
+        let qb = 2.0 * origin.dot(direction);
+        let qc = origin.magnitude2() - 1.0;
+        let discriminant = qb * qb - 4.0 * qa * qc;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let root = discriminant.sqrt();
+        [(-qb - root) / (2.0 * qa), (-qb + root) / (2.0 * qa)]
+            .into_iter()
+            .find(|&distance| distance >= 0.0)
+    }
+
+    /// Slab test against an axis-aligned box, returning the distance to
+    /// where the ray enters it, or 0 when it starts inside.
+    pub fn intersect_box(&self, bounds: &Aabb) -> Option<f64> {
+        let mut near = 0.0f64;
+        let mut far = f64::INFINITY;
+        for axis in 0..3 {
+            let inverse = 1.0 / self.direction[axis];
+            let mut t0 = (bounds.min[axis] - self.origin[axis]) * inverse;
+            let mut t1 = (bounds.max[axis] - self.origin[axis]) * inverse;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // NaN from a ray lying in a slab plane compares false and leaves
+            // the interval alone
+            if t0 > near {
+                near = t0;
+            }
+            if t1 < far {
+                far = t1;
+            }
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    /// Two-sided Möller–Trumbore test against the triangle `a`, `b`, `c`.
+    pub fn intersect_triangle(
+        &self,
+        a: Vector3<f64>,
+        b: Vector3<f64>,
+        c: Vector3<f64>,
+    ) -> Option<f64> {
+        const EPSILON: f64 = 1e-12;
+
+        let (ab, ac) = (b - a, c - a);
+        let p = self.direction.cross(ac);
+        let determinant = ab.dot(p);
+        if determinant.abs() < EPSILON * ab.magnitude2().max(ac.magnitude2()) {
+            // Parallel to the triangle's plane
+            return None;
+        }
+        let inverse = 1.0 / determinant;
+        let t = self.origin.to_vec() - a;
+        let u = t.dot(p) * inverse;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+        let q = t.cross(ab);
+        let v = self.direction.dot(q) * inverse;
+        if v < 0.0 || u + v > 1.0 {
+            return None;
+        }
+        let distance = ac.dot(q) * inverse;
+        (distance >= 0.0).then_some(distance)
+    }
+
+    /// The ray in the model space of `instance`. Its direction is left
+    /// unnormalized, so distances along it stay in world meters.
+    pub fn to_model_space(self, instance: &model::Instance) -> Ray {
+        let inverse = instance.rotation.cast::<f64>().unwrap().invert();
+        let scale = instance.scale as f64;
+        Ray {
+            origin: Point3::from_vec(
+                inverse.rotate_vector(self.origin.to_vec() - instance.position),
+            ) / scale,
+            direction: inverse.rotate_vector(self.direction) / scale,
+        }
+    }
+}
+
+/// An axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+}
+
+impl Aabb {
+    /// The box around `points`, or an inverted one no ray hits if there are
+    /// none.
+    pub fn from_points(points: impl IntoIterator<Item = Vector3<f64>>) -> Self {
+        let empty = Self {
+            min: Vector3::from_value(f64::INFINITY),
+            max: Vector3::from_value(f64::NEG_INFINITY),
+        };
+        points.into_iter().fold(empty, |bounds, point| Self {
+            min: Vector3::new(
+                bounds.min.x.min(point.x),
+                bounds.min.y.min(point.y),
+                bounds.min.z.min(point.z),
+            ),
+            max: Vector3::new(
+                bounds.max.x.max(point.x),
+                bounds.max.y.max(point.y),
+                bounds.max.z.max(point.z),
+            ),
+        })
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        Self::from_points([self.min, self.max, other.min, other.max])
+    }
+}
+
+/// What a pick hit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PickedObject {
+    /// The ground, terrain included where it has loaded.
+    Globe,
+    /// A model instance, by its index in the scene.
+    Instance(usize),
+    /// A satellite, by its index among the tracked ones.
+    Satellite(usize),
+}
+
+/// The result of picking the scene.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pick {
+    pub object: PickedObject,
+    /// ECEF position of the hit.
+    pub position: Vector3<f64>,
+    /// Latitude, longitude and ground height of hits on the globe.
+    pub geodetic: Option<Geodetic>,
+    /// Meters from the camera.
+    pub distance: f64,
+}
+
+/// Called with what a click hit.
+pub type PickListener = Box<dyn FnMut(&Pick)>;
+
+/// Where the ray meets the ground, with the terrain height at a point given
+/// by `height_at`. The ray is intersected with the ellipsoid raised to the
+/// height found at the previous guess until the two agree, which converges
+/// in a few steps on anything but steep terrain seen edge-on.
+pub fn pick_globe(ray: &Ray, height_at: impl Fn(f64, f64) -> Option<f64>) -> Option<Pick> {
+    const ITERATIONS: usize = 8;
+    const TOLERANCE: f64 = 0.01;
+
+    let mut height = 0.0;
+    let mut distance = ray.intersect_ellipsoid(height)?;
+    for _ in 0..ITERATIONS {
+        let hit = Geodetic::from_ecef(ray.at(distance).to_vec());
+        let ground = height_at(hit.latitude, hit.longitude).unwrap_or(0.0);
+        if (ground - height).abs() < TOLERANCE {
+            break;
+        }
+        height = ground;
+        distance = ray.intersect_ellipsoid(height)?;
+    }
+
+    let position = ray.at(distance).to_vec();
+    let mut geodetic = Geodetic::from_ecef(position);
+    // Report the ground height rather than the raised ellipsoid's
+    geodetic.height = height;
+    Some(Pick {
+        object: PickedObject::Globe,
+        position,
+        geodetic: Some(geodetic),
+        distance,
+    })
+}
+
+/// The nearest of `instances` the ray hits, where `hit` gives the distance
+/// along the ray once moved into model space.
+pub fn pick_instances(
+    ray: &Ray,
+    instances: &[model::Instance],
+    hit: impl Fn(&Ray) -> Option<f64>,
+) -> Option<Pick> {
+    instances
+        .iter()
+        .enumerate()
+        .filter_map(|(i, instance)| Some((i, hit(&ray.to_model_space(instance))?)))
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(i, distance)| Pick {
+            object: PickedObject::Instance(i),
+            position: ray.at(distance).to_vec(),
+            geodetic: None,
+            distance,
+        })
+}
+
+/// The nearest of `positions` the ray passes within `angular_radius` of, as
+/// seen from its origin, for markers drawn the same size at any distance.
+/// Returns its index and distance.
+pub fn pick_markers(
+    ray: &Ray,
+    positions: impl IntoIterator<Item = (usize, Vector3<f64>)>,
+    angular_radius: f64,
+) -> Option<(usize, f64)> {
+    let cos_radius = angular_radius.cos();
+    positions
+        .into_iter()
+        .filter_map(|(i, position)| {
+            let offset = position - ray.origin.to_vec();
+            let distance = offset.magnitude();
+            (offset.dot(ray.direction) >= distance * cos_radius).then_some((i, distance))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+/// The nearer of two picks.
+pub fn nearest(a: Option<Pick>, b: Option<Pick>) -> Option<Pick> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(if b.distance < a.distance { b } else { a }),
+        (a, b) => a.or(b),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::{Deg, Quaternion};
+
+    #[test]
+    fn the_center_of_the_screen_looks_straight_down() {
+        let camera = camera::GlobeCamera::new(Deg(46.0), Deg(7.0), 1000.0);
+        let projection = camera::Projection::new_reverse_z(800, 600, Deg(45.0), 0.1);
+        let size = PhysicalSize::new(800, 600);
+        let ray = Ray::from_cursor(
+            &camera,
+            &projection,
+            PhysicalPosition::new(400.0, 300.0),
+            size,
+        );
+
+        let pick = pick_globe(&ray, |_, _| None).unwrap();
+        assert!((pick.distance - 1000.0).abs() < 1e-3);
+        let geodetic = pick.geodetic.unwrap();
+        assert!((geodetic.latitude - 46f64.to_radians()).abs() < 1e-9);
+        assert!((geodetic.longitude - 7f64.to_radians()).abs() < 1e-9);
+
+        // A hill 300 meters high is met that much earlier
+        let hill = pick_globe(&ray, |_, _| Some(300.0)).unwrap();
+        assert!((hill.distance - 700.0).abs() < 1e-3);
+        assert!((hill.geodetic.unwrap().height - 300.0).abs() < 1e-9);
+
+        // Looking away from the globe
+        let up = Ray::new(camera.position(), camera.position().to_vec());
+        assert!(pick_globe(&up, |_, _| None).is_none());
+    }
+
+    #[test]
+    fn cursor_rays_land_where_the_projection_puts_them() {
+        let mut camera = camera::GlobeCamera::new(Deg(-20.0), Deg(130.0), 5000.0);
+        camera.heading = Deg(30.0).into();
+        camera.pitch = Deg(-50.0).into();
+        let projection = camera::Projection::new_reverse_z(640, 480, Deg(60.0), 0.1);
+        let cursor = PhysicalPosition::new(100.0, 400.0);
+        let ray = Ray::from_cursor(&camera, &projection, cursor, PhysicalSize::new(640, 480));
+
+        let relative = (ray.direction * 100.0).cast::<f32>().unwrap();
+        let clip = projection.calc_matrix() * camera.calc_matrix() * relative.extend(1.0);
+        let (x, y) = (clip.x / clip.w, clip.y / clip.w);
+        assert!((x - (100.0 / 320.0 - 1.0)).abs() < 1e-4);
+        assert!((y - (1.0 - 400.0 / 240.0)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn instances_are_hit_through_their_transform() {
+        let ray = Ray::new(Point3::new(0.0, 0.0, 100.0), Vector3::new(0.0, 0.0, -1.0));
+        let bounds = Aabb::from_points([Vector3::from_value(-1.0), Vector3::from_value(1.0)]);
+        let mut instance = model::Instance::new(Vector3::new(0.0, 0.0, 10.0), Quaternion::one());
+        instance.scale = 5.0;
+        assert_eq!(
+            ray.to_model_space(&instance).intersect_box(&bounds),
+            Some(85.0)
+        );
+
+        // Turned 45 degrees about x, the corner edge faces the ray
+        instance.rotation = Quaternion::from_angle_x(Deg(45.0));
+        let distance = ray
+            .to_model_space(&instance)
+            .intersect_box(&bounds)
+            .unwrap();
+        assert!((distance - (90.0 - 5.0 * 2f64.sqrt())).abs() < 1e-4);
+
+        let behind = model::Instance::new(Vector3::new(0.0, 0.0, 200.0), Quaternion::one());
+        let beside = model::Instance::new(Vector3::new(3.0, 0.0, 50.0), Quaternion::one());
+        let mut instances = vec![behind, beside, instance];
+        let in_bounds = |local: &Ray| local.intersect_box(&bounds);
+        let pick = pick_instances(&ray, &instances, in_bounds).unwrap();
+        assert_eq!(pick.object, PickedObject::Instance(2));
+        instances.pop();
+        assert!(pick_instances(&ray, &instances, in_bounds).is_none());
+    }
+
+    #[test]
+    fn triangles_are_hit_from_either_side() {
+        let (a, b, c) = (
+            Vector3::new(0.0, 0.0, 0.0),
+            Vector3::new(2.0, 0.0, 0.0),
+            Vector3::new(0.0, 2.0, 0.0),
+        );
+        let down = Ray::new(Point3::new(0.5, 0.5, 3.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(down.intersect_triangle(a, b, c), Some(3.0));
+        let up = Ray::new(Point3::new(0.5, 0.5, -2.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(up.intersect_triangle(a, b, c), Some(2.0));
+
+        // Past the hypotenuse, behind the origin and along the plane
+        let outside = Ray::new(Point3::new(1.5, 1.5, 3.0), Vector3::new(0.0, 0.0, -1.0));
+        assert_eq!(outside.intersect_triangle(a, b, c), None);
+        let away = Ray::new(Point3::new(0.5, 0.5, 3.0), Vector3::new(0.0, 0.0, 1.0));
+        assert_eq!(away.intersect_triangle(a, b, c), None);
+        let along = Ray::new(Point3::new(-1.0, 0.5, 0.0), Vector3::new(1.0, 0.0, 0.0));
+        assert_eq!(along.intersect_triangle(a, b, c), None);
+    }
+
+    #[test]
+    fn markers_are_hit_within_their_angular_size() {
+        let ray = Ray::new(Point3::origin(), Vector3::unit_x());
+        let markers = [
+            (0, Vector3::new(1000.0, 5.0, 0.0)),
+            (1, Vector3::new(100.0, 5.0, 0.0)),
+            (2, Vector3::new(-1000.0, 0.0, 0.0)),
+        ];
+        // The nearer marker is 2.9 degrees off the ray, the further 0.29
+        assert_eq!(pick_markers(&ray, markers, 0.01).unwrap().0, 0);
+        assert_eq!(pick_markers(&ray, markers, 0.1).unwrap().0, 1);
+        assert_eq!(pick_markers(&ray, markers, 0.001), None);
+    }
+}
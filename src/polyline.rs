@@ -0,0 +1,754 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Matrix4, Point3, Rad, Vector3};
+use std::ops::Range;
+use wgpu::util::DeviceExt;
+
+use crate::model::{self, Vertex};
+use crate::{camera, texture};
+
+/// Lines sharing a style are drawn together while they start within this
+/// many meters of the first one, so their vertices stay precise as `f32`.
+const BATCH_RADIUS: f64 = 100_000.0;
+
+/// Vertex kinds, in `corner.w`.
+const SEGMENT: f32 = 0.0;
+const JOIN: f32 = 1.0;
+const DISC: f32 = 2.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineJoin {
+    Miter,
+    Bevel,
+    Round,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineCap {
+    Butt,
+    Square,
+    Round,
+}
+
+/// How a polyline is drawn. Widths and dash lengths are in pixels, so lines
+/// keep their look at any distance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineStyle {
+    pub color: [f32; 4],
+    pub width: f32,
+    pub join: LineJoin,
+    pub cap: LineCap,
+    /// Miter joins reaching further than this many half widths from the
+    /// line are beveled instead.
+    pub miter_limit: f32,
+    /// Dash and gap lengths, or `None` for a solid line.
+    pub dash: Option<[f32; 2]>,
+}
+
+impl Default for LineStyle {
+    fn default() -> Self {
+        Self {
+            color: [1.0, 1.0, 1.0, 1.0],
+            width: 2.0,
+            join: LineJoin::Miter,
+            cap: LineCap::Butt,
+            miter_limit: 4.0,
+            dash: None,
+        }
+    }
+}
+
+/// A line through ECEF positions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polyline {
+    pub points: Vec<Vector3<f64>>,
+    /// Whether the last point connects back to the first.
+    pub closed: bool,
+    pub style: LineStyle,
+}
+
+/// One corner of the screen-space geometry of a line. The vertex shader
+/// moves it off `position` in pixels depending on where `previous` and
+/// `next` end up on screen:
+///
+/// - segment bodies have the end of the segment in `corner.x` (0 or 1), the
+///   side in `y` and how far to stretch along the segment for square caps
+///   in `z`, in half widths;
+/// - joins have the point itself (0), the outer corner of the segment
+///   before (1) or after (2) it or the miter tip (3) in `corner.x`;
+/// - discs for round joins and caps have their corner in `corner.xy`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PolylineVertex {
+    pub position: [f32; 3],
+    pub previous: [f32; 3],
+    pub next: [f32; 3],
+    pub corner: [f32; 4],
+    /// Meters along the line, for dashes.
+    pub distance: f32,
+}
+
+impl Vertex for PolylineVertex {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<PolylineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 6]>() as wgpu::BufferAddress,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float32x3,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 9]>() as wgpu::BufferAddress,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 13]>() as wgpu::BufferAddress,
+                    shader_location: 4,
+                    format: wgpu::VertexFormat::Float32,
+                },
+            ],
+        }
+    }
+}
+
+/// The model matrix of a batch of lines followed by their style.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct PolylineInstanceRaw {
+    model: [[f32; 4]; 4],
+    color: [f32; 4],
+    /// Width, miter limit, dash and gap.
+    style: [f32; 4],
+}
+
+impl PolylineInstanceRaw {
+    fn new(
+        center: Vector3<f64>,
+        rotation: Matrix3<f64>,
+        style: &LineStyle,
+        camera_position: Point3<f64>,
+    ) -> Self {
+        let translation = model::camera_relative(rotation * center, camera_position);
+        let model =
+            Matrix4::from_translation(translation) * Matrix4::from(rotation.cast().unwrap());
+        let [dash, gap] = style.dash.unwrap_or([0.0, 0.0]);
+        Self {
+            model: model.into(),
+            color: style.color,
+            style: [style.width, style.miter_limit, dash, gap],
+        }
+    }
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<PolylineInstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 5,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 6,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 7,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 12]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 16]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 20]>() as wgpu::BufferAddress,
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Appends the vertices and indices drawing `line` relative to `center`.
+/// Lines with fewer than two distinct points produce nothing.
+pub fn tessellate(
+    line: &Polyline,
+    center: Vector3<f64>,
+    vertices: &mut Vec<PolylineVertex>,
+    indices: &mut Vec<u32>,
+) {
+    let mut points = line.points.clone();
+    points.dedup();
+    if line.closed && points.len() > 2 && points.first() == points.last() {
+        points.pop();
+    }
+    if points.len() < 2 {
+        return;
+    }
+    let closed = line.closed && points.len() > 2;
+    let count = points.len();
+    let segments = if closed { count } else { count - 1 };
+
+    let mut distances = vec![0.0; count + 1];
+    for i in 0..segments {
+        distances[i + 1] = distances[i] + (points[(i + 1) % count] - points[i]).magnitude();
+    }
+    let distances = distances.iter().map(|&d| d as f32).collect::<Vec<_>>();
+
+    let relative = |i: usize| -> [f32; 3] { (points[i] - center).cast().unwrap().into() };
+    // Lines without a neighbor on one side point there at themselves
+    let previous = |i: usize| match i {
+        0 if closed => count - 1,
+        0 => 0,
+        _ => i - 1,
+    };
+    let next = |i: usize| match i {
+        _ if i + 1 < count => i + 1,
+        _ if closed => 0,
+        _ => i,
+    };
+    let push = |vertices: &mut Vec<PolylineVertex>, i: usize, corner, distance| {
+        vertices.push(PolylineVertex {
+            position: relative(i),
+            previous: relative(previous(i)),
+            next: relative(next(i)),
+            corner,
+            distance,
+        });
+    };
+
+    for segment in 0..segments {
+        let (start, end) = (segment, (segment + 1) % count);
+        let square = line.style.cap == LineCap::Square && !closed;
+        let start_extension = if square && start == 0 { -1.0 } else { 0.0 };
+        let end_extension = if square && end == count - 1 { 1.0 } else { 0.0 };
+        let base = vertices.len() as u32;
+        // The closing segment of a ring measures from the end of the line
+        let (start_distance, end_distance) = (distances[segment], distances[segment + 1]);
+        push(
+            vertices,
+            start,
+            [0.0, -1.0, start_extension, SEGMENT],
+            start_distance,
+        );
+        push(
+            vertices,
+            start,
+            [0.0, 1.0, start_extension, SEGMENT],
+            start_distance,
+        );
+        // The end vertex of a segment looks back at its start
+        vertices.push(PolylineVertex {
+            position: relative(end),
+            previous: relative(start),
+            next: relative(next(end)),
+            corner: [1.0, -1.0, end_extension, SEGMENT],
+            distance: end_distance,
+        });
+        vertices.push(PolylineVertex {
+            corner: [1.0, 1.0, end_extension, SEGMENT],
+            ..*vertices.last().unwrap()
+        });
+        indices.extend_from_slice(&[base, base + 2, base + 1, base + 1, base + 2, base + 3]);
+    }
+
+    let joins = if closed { 0..count } else { 1..count - 1 };
+    for i in joins {
+        let base = vertices.len() as u32;
+        match line.style.join {
+            LineJoin::Round => {
+                for corner in [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]] {
+                    push(vertices, i, [corner[0], corner[1], 0.0, DISC], distances[i]);
+                }
+                indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+            }
+            LineJoin::Bevel => {
+                for which in 0..3 {
+                    push(vertices, i, [which as f32, 0.0, 0.0, JOIN], distances[i]);
+                }
+                indices.extend_from_slice(&[base, base + 1, base + 2]);
+            }
+            LineJoin::Miter => {
+                for which in 0..4 {
+                    push(vertices, i, [which as f32, 0.0, 0.0, JOIN], distances[i]);
+                }
+                indices.extend_from_slice(&[base, base + 1, base + 3, base, base + 3, base + 2]);
+            }
+        }
+    }
+
+    if line.style.cap == LineCap::Round && !closed {
+        for i in [0, count - 1] {
+            let base = vertices.len() as u32;
+            for corner in [[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]] {
+                push(vertices, i, [corner[0], corner[1], 0.0, DISC], distances[i]);
+            }
+            indices.extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        }
+    }
+}
+
+/// Lines drawn from one style and origin.
+struct Batch {
+    center: Vector3<f64>,
+    style: LineStyle,
+    indices: Range<u32>,
+}
+
+/// Tessellates `lines`, grouping them into batches.
+fn tessellate_batches(
+    lines: impl IntoIterator<Item = Polyline>,
+) -> (Vec<PolylineVertex>, Vec<u32>, Vec<Batch>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut batches = Vec::<Batch>::new();
+    for line in lines {
+        let first = match line.points.first() {
+            Some(&first) => first,
+            None => continue,
+        };
+        let joins_last = batches.last().is_some_and(|batch| {
+            batch.style == line.style && (first - batch.center).magnitude() < BATCH_RADIUS
+        });
+        if !joins_last {
+            let start = indices.len() as u32;
+            batches.push(Batch {
+                center: first,
+                style: line.style,
+                indices: start..start,
+            });
+        }
+        let batch = batches.last_mut().unwrap();
+        tessellate(&line, batch.center, &mut vertices, &mut indices);
+        batch.indices.end = indices.len() as u32;
+    }
+    batches.retain(|batch| !batch.indices.is_empty());
+    (vertices, indices, batches)
+}
+
+/// A buffer for `count` elements of `T`, rounded up so buffers that grow
+/// are replaced less often.
+fn create_buffer<T>(
+    device: &wgpu::Device,
+    label: &str,
+    count: usize,
+    usage: wgpu::BufferUsages,
+) -> (wgpu::Buffer, usize) {
+    let capacity = count.max(1).next_power_of_two();
+    let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: (capacity * std::mem::size_of::<T>()) as wgpu::BufferAddress,
+        usage: usage | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    });
+    (buffer, capacity)
+}
+
+/// A set of polylines uploaded together. Neighbouring lines with the same
+/// style share a draw call.
+pub struct Polylines {
+    vertex_buffer: wgpu::Buffer,
+    vertex_capacity: usize,
+    index_buffer: wgpu::Buffer,
+    index_capacity: usize,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    batches: Vec<Batch>,
+}
+
+impl Polylines {
+    pub fn new(device: &wgpu::Device, lines: impl IntoIterator<Item = Polyline>) -> Self {
+        let (vertices, indices, batches) = tessellate_batches(lines);
+        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Polyline Vertex Buffer"),
+            contents: bytemuck::cast_slice(&vertices),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Polyline Index Buffer"),
+            contents: bytemuck::cast_slice(&indices),
+            usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+        });
+        let (instance_buffer, instance_capacity) = create_buffer::<PolylineInstanceRaw>(
+            device,
+            "Polyline Instance Buffer",
+            batches.len(),
+            wgpu::BufferUsages::VERTEX,
+        );
+
+        Self {
+            vertex_buffer,
+            vertex_capacity: vertices.len(),
+            index_buffer,
+            index_capacity: indices.len(),
+            instance_buffer,
+            instance_capacity,
+            batches,
+        }
+    }
+
+    /// Replaces the lines, writing them into the buffers they already have
+    /// where they fit. For lines that change every frame.
+    pub fn write(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        lines: impl IntoIterator<Item = Polyline>,
+    ) {
+        let (vertices, indices, batches) = tessellate_batches(lines);
+        if vertices.len() > self.vertex_capacity {
+            (self.vertex_buffer, self.vertex_capacity) = create_buffer::<PolylineVertex>(
+                device,
+                "Polyline Vertex Buffer",
+                vertices.len(),
+                wgpu::BufferUsages::VERTEX,
+            );
+        }
+        if indices.len() > self.index_capacity {
+            (self.index_buffer, self.index_capacity) = create_buffer::<u32>(
+                device,
+                "Polyline Index Buffer",
+                indices.len(),
+                wgpu::BufferUsages::INDEX,
+            );
+        }
+        if batches.len() > self.instance_capacity {
+            (self.instance_buffer, self.instance_capacity) = create_buffer::<PolylineInstanceRaw>(
+                device,
+                "Polyline Instance Buffer",
+                batches.len(),
+                wgpu::BufferUsages::VERTEX,
+            );
+        }
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        queue.write_buffer(&self.index_buffer, 0, bytemuck::cast_slice(&indices));
+        self.batches = batches;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Rebases the lines against the camera.
+    pub fn update(&self, queue: &wgpu::Queue, camera_position: Point3<f64>) {
+        self.update_rotated(queue, Matrix3::identity(), camera_position);
+    }
+
+    /// Rebases lines given in a frame turning against ECEF, such as orbits
+    /// in the inertial frame, by `rotation` into ECEF and against the
+    /// camera. Only the model matrices change, not the vertices.
+    pub fn update_rotated(
+        &self,
+        queue: &wgpu::Queue,
+        rotation: Matrix3<f64>,
+        camera_position: Point3<f64>,
+    ) {
+        let instance_data = self
+            .batches
+            .iter()
+            .map(|batch| {
+                PolylineInstanceRaw::new(batch.center, rotation, &batch.style, camera_position)
+            })
+            .collect::<Vec<_>>();
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&instance_data),
+        );
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ViewportUniform {
+    size: [f32; 4],
+}
+
+impl ViewportUniform {
+    fn new(width: u32, height: u32, fovy: Rad<f32>) -> Self {
+        let focal_length = height as f32 / 2.0 / (fovy.0 / 2.0).tan();
+        Self {
+            size: [width as f32, height as f32, focal_length, 0.0],
+        }
+    }
+}
+
+/// The pipeline polylines are drawn with. Lines blend over the scene and
+/// are depth tested against it without writing depth themselves.
+pub struct PolylineRenderer {
+    pipeline: wgpu::RenderPipeline,
+    viewport_buffer: wgpu::Buffer,
+    viewport_bind_group: wgpu::BindGroup,
+}
+
+impl PolylineRenderer {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        projection: &camera::Projection,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let viewport_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Polyline Viewport Buffer"),
+            contents: bytemuck::cast_slice(&[ViewportUniform::new(
+                config.width,
+                config.height,
+                projection.fovy(),
+            )]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let viewport_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("polyline_viewport_bind_group_layout"),
+            });
+        let viewport_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &viewport_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: viewport_buffer.as_entire_binding(),
+            }],
+            label: Some("polyline_viewport_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Polyline Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &viewport_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Polyline Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("polyline.wgsl").into()),
+        });
+        let depth_compare = projection.depth_compare();
+        // Pull lines towards the camera so they win against the surface they
+        // lie on, whichever way depth runs
+        let bias_direction = if depth_compare == wgpu::CompareFunction::Greater {
+            1
+        } else {
+            -1
+        };
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Polyline Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[PolylineVertex::desc(), PolylineInstanceRaw::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                // Which way a quad faces depends on where the line turns
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState {
+                    constant: 64 * bias_direction,
+                    slope_scale: 2.0 * bias_direction as f32,
+                    clamp: 0.0,
+                },
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            viewport_buffer,
+            viewport_bind_group,
+        }
+    }
+
+    pub fn resize(&self, queue: &wgpu::Queue, width: u32, height: u32, fovy: Rad<f32>) {
+        queue.write_buffer(
+            &self.viewport_buffer,
+            0,
+            bytemuck::cast_slice(&[ViewportUniform::new(width, height, fovy)]),
+        );
+    }
+}
+
+pub trait DrawPolylines<'a> {
+    fn draw_polylines(
+        &mut self,
+        renderer: &'a PolylineRenderer,
+        polylines: &'a Polylines,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawPolylines<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_polylines(
+        &mut self,
+        renderer: &'b PolylineRenderer,
+        polylines: &'b Polylines,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        if polylines.is_empty() {
+            return;
+        }
+        self.set_pipeline(&renderer.pipeline);
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(1, &renderer.viewport_bind_group, &[]);
+        self.set_vertex_buffer(0, polylines.vertex_buffer.slice(..));
+        self.set_vertex_buffer(1, polylines.instance_buffer.slice(..));
+        self.set_index_buffer(polylines.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        for (i, batch) in polylines.batches.iter().enumerate() {
+            self.draw_indexed(batch.indices.clone(), 0, i as u32..i as u32 + 1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(points: &[(f64, f64)], closed: bool, style: LineStyle) -> Polyline {
+        Polyline {
+            points: points
+                .iter()
+                .map(|&(x, y)| Vector3::new(x, y, 0.0))
+                .collect(),
+            closed,
+            style,
+        }
+    }
+
+    fn kinds(vertices: &[PolylineVertex], kind: f32) -> usize {
+        vertices.iter().filter(|v| v.corner[3] == kind).count()
+    }
+
+    #[test]
+    fn open_lines_get_joins_between_segments_and_caps_at_the_ends() {
+        let style = LineStyle {
+            join: LineJoin::Miter,
+            cap: LineCap::Round,
+            ..Default::default()
+        };
+        let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+        let points = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (10.0, 10.0)];
+        tessellate(
+            &line(&points, false, style),
+            Vector3::new(5.0, 0.0, 0.0),
+            &mut vertices,
+            &mut indices,
+        );
+
+        // The repeated point is dropped, leaving two segments and one join
+        assert_eq!(kinds(&vertices, SEGMENT), 8);
+        assert_eq!(kinds(&vertices, JOIN), 4);
+        assert_eq!(kinds(&vertices, DISC), 8);
+        assert_eq!(indices.len(), 2 * 6 + 6 + 2 * 6);
+        assert!(indices.iter().all(|&i| (i as usize) < vertices.len()));
+
+        // Relative to the center, with the neighbors to find directions by
+        assert_eq!(vertices[0].position, [-5.0, 0.0, 0.0]);
+        assert_eq!(vertices[0].previous, vertices[0].position);
+        assert_eq!(vertices[2].previous, [-5.0, 0.0, 0.0]);
+        assert_eq!(vertices[2].next, [5.0, 10.0, 0.0]);
+        assert_eq!(vertices[6].distance, 20.0);
+    }
+
+    #[test]
+    fn rings_join_all_around_without_caps() {
+        let style = LineStyle {
+            join: LineJoin::Bevel,
+            cap: LineCap::Square,
+            ..Default::default()
+        };
+        let square = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0), (0.0, 0.0)];
+        let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+        tessellate(
+            &line(&square, true, style),
+            Vector3::zero(),
+            &mut vertices,
+            &mut indices,
+        );
+        assert_eq!(kinds(&vertices, SEGMENT), 16);
+        assert_eq!(kinds(&vertices, JOIN), 12);
+        assert!(vertices
+            .iter()
+            .all(|v| v.corner[3] != SEGMENT || v.corner[2] == 0.0));
+        // The closing segment runs from 3 to 4 meters along
+        assert_eq!(vertices[14].distance, 4.0);
+
+        // Open, the square caps stretch the first and last segments outwards
+        let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+        tessellate(
+            &line(&square[..4], false, style),
+            Vector3::zero(),
+            &mut vertices,
+            &mut indices,
+        );
+        assert_eq!(vertices[0].corner[2], -1.0);
+        assert_eq!(vertices[2].corner[2], 0.0);
+        assert_eq!(vertices[10].corner[2], 1.0);
+
+        let (mut vertices, mut indices) = (Vec::new(), Vec::new());
+        tessellate(
+            &line(&[(1.0, 1.0)], false, style),
+            Vector3::zero(),
+            &mut vertices,
+            &mut indices,
+        );
+        assert!(vertices.is_empty() && indices.is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+struct Viewport {
+    // Width and height in pixels, then the focal length in pixels
+    size: vec4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> viewport: Viewport;
+
+struct VertexInput {
+    [[location(0)]] position: vec3<f32>;
+    [[location(1)]] previous: vec3<f32>;
+    [[location(2)]] next: vec3<f32>;
+    // Meaning depends on the kind in w, see polyline.rs
+    [[location(3)]] corner: vec4<f32>;
+    [[location(4)]] distance: f32;
+};
+struct InstanceInput {
+    [[location(5)]] model_matrix_0: vec4<f32>;
+    [[location(6)]] model_matrix_1: vec4<f32>;
+    [[location(7)]] model_matrix_2: vec4<f32>;
+    [[location(8)]] model_matrix_3: vec4<f32>;
+    [[location(9)]] color: vec4<f32>;
+    // Width, miter limit, dash and gap length, all in pixels but the limit
+    [[location(10)]] style: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+    // Position inside a disc in xy, 1 in z for discs
+    [[location(1)]] disc: vec3<f32>;
+    [[location(2)]] dash_distance: f32;
+    [[location(3)]] dash: vec2<f32>;
+};
+
+fn to_screen(clip: vec4<f32>) -> vec2<f32> {
+    // Points behind the camera would flip over, keep them just in front
+    return clip.xy / max(clip.w, 1e-6) * 0.5 * viewport.size.xy;
+}
+
+fn direction(start: vec2<f32>, end: vec2<f32>) -> vec2<f32> {
+    let delta = end - start;
+    if (dot(delta, delta) < 1e-12) {
+        return vec2<f32>(1.0, 0.0);
+    }
+    return normalize(delta);
+}
+
+fn left(direction: vec2<f32>) -> vec2<f32> {
+    return vec2<f32>(-direction.y, direction.x);
+}
+
+[[stage(vertex)]]
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    let model_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    let transform = camera.view_proj * model_matrix;
+    let clip = transform * vec4<f32>(model.position, 1.0);
+    let here = to_screen(clip);
+    let previous = to_screen(transform * vec4<f32>(model.previous, 1.0));
+    let next = to_screen(transform * vec4<f32>(model.next, 1.0));
+    let half_width = instance.style.x * 0.5;
+
+    var offset = vec2<f32>(0.0, 0.0);
+    var disc = vec3<f32>(0.0, 0.0, 0.0);
+    let kind = model.corner.w;
+    if (kind < 0.5) {
+        // Segment body: x picks the end, y the side and z stretches square
+        // caps along the segment
+        var along = direction(previous, here);
+        if (model.corner.x < 0.5) {
+            along = direction(here, next);
+        }
+        offset = left(along) * model.corner.y * half_width + along * model.corner.z * half_width;
+    } else if (kind < 1.5) {
+        // Join: x is 0 for the point itself, 1 and 2 for the outer corners
+        // of the segments before and after it and 3 for the miter tip
+        let incoming = direction(previous, here);
+        let outgoing = direction(here, next);
+        let turn = incoming.x * outgoing.y - incoming.y * outgoing.x;
+        var outer = 1.0;
+        if (turn > 0.0) {
+            outer = -1.0;
+        }
+        let before = left(incoming) * outer * half_width;
+        let after = left(outgoing) * outer * half_width;
+        if (model.corner.x > 2.5) {
+            let miter = normalize(before + after + vec2<f32>(1e-6, 0.0));
+            let length = half_width / max(dot(miter, before) / half_width, 1e-4);
+            if (length > instance.style.y * half_width) {
+                offset = (before + after) * 0.5;
+            } else {
+                offset = miter * length;
+            }
+        } else if (model.corner.x > 1.5) {
+            offset = after;
+        } else if (model.corner.x > 0.5) {
+            offset = before;
+        }
+    } else {
+        // Disc for round joins and caps
+        offset = model.corner.xy * half_width;
+        disc = vec3<f32>(model.corner.xy, 1.0);
+    }
+
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(clip.xy + offset * 2.0 / viewport.size.xy * clip.w, clip.zw);
+    out.color = instance.color;
+    out.disc = disc;
+    out.dash_distance = model.distance * viewport.size.z / max(clip.w, 1e-6);
+    out.dash = instance.style.zw;
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    if (in.disc.z > 0.5 && dot(in.disc.xy, in.disc.xy) > 1.0) {
+        discard;
+    }
+    let period = in.dash.x + in.dash.y;
+    if (in.dash.x > 0.0 && in.dash_distance - floor(in.dash_distance / period) * period > in.dash.x) {
+        discard;
+    }
+    return in.color;
+}
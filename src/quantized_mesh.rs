@@ -0,0 +1,736 @@
+use anyhow::{bail, Context};
+use cgmath::prelude::*;
+use cgmath::Vector3;
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::io::Read;
+
+use crate::globe::{Extent, TextureProjection, TileGeometry};
+use crate::tiles::TileId;
+use crate::{geodesy, model};
+
+/// Quantized coordinates run from 0 at the west, south and minimum height
+/// to this at the east, north and maximum height.
+const QUANTIZED_MAX: f64 = 32767.0;
+/// Triangles are bucketed into this many cells along each axis so height
+/// queries don't have to test them all.
+const GRID_SIZE: usize = 16;
+
+const EXTENSION_OCT_NORMALS: u8 = 1;
+
+/// The extent of a tile in the geographic tiling scheme quantized-mesh
+/// terrain uses, with two root tiles and `y` counted from the south as in
+/// the tile URLs.
+pub fn geographic_extent(tile: TileId) -> Extent {
+    let size = PI / (1u64 << tile.z) as f64;
+    Extent {
+        west: -PI + tile.x as f64 * size,
+        south: -FRAC_PI_2 + tile.y as f64 * size,
+        east: -PI + (tile.x + 1) as f64 * size,
+        north: -FRAC_PI_2 + (tile.y + 1) as f64 * size,
+    }
+}
+
+/// The geographic tiles of level `z` that overlap `extent`.
+pub fn covering_tiles(extent: &Extent, z: u32) -> Vec<TileId> {
+    let rows = 1u64 << z;
+    let size = PI / rows as f64;
+    // Shrink a little, so tiles that merely touch the extent are left out
+    let first = |edge: f64, origin: f64, count: u64| {
+        (((edge - origin) / size + 1e-9).floor().max(0.0) as u64).min(count - 1) as u32
+    };
+    let last = |edge: f64, origin: f64, count: u64| {
+        (((edge - origin) / size - 1e-9).ceil().max(1.0) as u64 - 1).min(count - 1) as u32
+    };
+    let (x0, x1) = (
+        first(extent.west, -PI, rows * 2),
+        last(extent.east, -PI, rows * 2),
+    );
+    let (y0, y1) = (
+        first(extent.south, -FRAC_PI_2, rows),
+        last(extent.north, -FRAC_PI_2, rows),
+    );
+    (y0..=y1)
+        .flat_map(|y| (x0..=x1).map(move |x| TileId::new(z, x, y)))
+        .collect()
+}
+
+/// A decoded Cesium `quantized-mesh-1.0` terrain tile.
+#[derive(Debug, Clone)]
+pub struct QuantizedMesh {
+    pub extent: Extent,
+    pub minimum_height: f32,
+    pub maximum_height: f32,
+    /// Quantized vertex coordinates, `[u, v, height]`.
+    pub vertices: Vec<[u16; 3]>,
+    pub indices: Vec<u32>,
+    pub west_indices: Vec<u32>,
+    pub south_indices: Vec<u32>,
+    pub east_indices: Vec<u32>,
+    pub north_indices: Vec<u32>,
+    /// Unit ECEF normals, when the tile has the oct-encoded normals
+    /// extension.
+    pub normals: Option<Vec<Vector3<f32>>>,
+    /// Triangle indices overlapping each cell of a `GRID_SIZE` square grid
+    /// over the tile, row by row from the south.
+    grid: Vec<Vec<u32>>,
+}
+
+impl QuantizedMesh {
+    /// Decodes a tile covering `extent`. Gzipped tiles, as served with
+    /// `Content-Encoding: gzip`, are inflated first.
+    pub fn decode(data: &[u8], extent: Extent) -> anyhow::Result<Self> {
+        if data.starts_with(&[0x1f, 0x8b]) {
+            let mut inflated = Vec::new();
+            flate2::read::GzDecoder::new(data)
+                .read_to_end(&mut inflated)
+                .context("inflating quantized-mesh tile")?;
+            return Self::decode(&inflated, extent);
+        }
+
+        let mut reader = Reader { data, offset: 0 };
+        // The center, bounding sphere and horizon occlusion point aren't
+        // needed, the imagery tiles have their own and do their own culling
+        reader.bytes(3 * 8)?;
+        let minimum_height = reader.f32()?;
+        let maximum_height = reader.f32()?;
+        reader.bytes(4 * 8 + 3 * 8)?;
+
+        // Three u16 per vertex
+        let vertex_count = reader.count(6)?;
+        let mut vertices = vec![[0u16; 3]; vertex_count];
+        for component in 0..3 {
+            // Deltas wrap around, so corrupt tiles can't overflow the sum
+            let mut value = 0u16;
+            for vertex in vertices.iter_mut() {
+                value = value.wrapping_add(zig_zag_decode(reader.u16()?) as u16);
+                vertex[component] = value;
+            }
+        }
+
+        let wide = vertex_count > 65536;
+        reader.align(if wide { 4 } else { 2 });
+        let index_size = if wide { 4 } else { 2 };
+        let triangle_count = reader.count(3 * index_size)?;
+        let mut indices = reader.indices(triangle_count * 3, wide)?;
+        let mut highest = 0u32;
+        for index in indices.iter_mut() {
+            let code = *index;
+            *index = highest.wrapping_sub(code);
+            if code == 0 {
+                highest += 1;
+            }
+        }
+
+        let mut edges = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let count = reader.count(index_size)?;
+            edges.push(reader.indices(count, wide)?);
+        }
+        if indices
+            .iter()
+            .chain(edges.iter().flatten())
+            .any(|&index| index as usize >= vertex_count)
+        {
+            bail!("quantized-mesh index out of range");
+        }
+        let [west_indices, south_indices, east_indices, north_indices]: [Vec<u32>; 4] =
+            edges.try_into().unwrap();
+
+        let mut normals = None;
+        while reader.remaining() > 0 {
+            let id = reader.u8()?;
+            let length = reader.u32()? as usize;
+            let extension = reader.bytes(length)?;
+            if id == EXTENSION_OCT_NORMALS {
+                if length != vertex_count * 2 {
+                    bail!("{} bytes of normals for {} vertices", length, vertex_count);
+                }
+                normals = Some(
+                    extension
+                        .chunks_exact(2)
+                        .map(|normal| oct_decode(normal[0], normal[1]))
+                        .collect(),
+                );
+            }
+        }
+
+        let mut mesh = Self {
+            extent,
+            minimum_height,
+            maximum_height,
+            vertices,
+            indices,
+            west_indices,
+            south_indices,
+            east_indices,
+            north_indices,
+            normals,
+            grid: Vec::new(),
+        };
+        mesh.build_grid();
+        Ok(mesh)
+    }
+
+    fn build_grid(&mut self) {
+        let cell = |quantized: u16| {
+            ((quantized as f64 / QUANTIZED_MAX * GRID_SIZE as f64) as usize).min(GRID_SIZE - 1)
+        };
+        self.grid = vec![Vec::new(); GRID_SIZE * GRID_SIZE];
+        for (triangle, corners) in self.indices.chunks_exact(3).enumerate() {
+            let corners = corners.iter().map(|&i| self.vertices[i as usize]);
+            let (mut column_range, mut row_range) = ((GRID_SIZE, 0), (GRID_SIZE, 0));
+            for [u, v, _] in corners {
+                column_range = (column_range.0.min(cell(u)), column_range.1.max(cell(u)));
+                row_range = (row_range.0.min(cell(v)), row_range.1.max(cell(v)));
+            }
+            for row in row_range.0..=row_range.1 {
+                for column in column_range.0..=column_range.1 {
+                    self.grid[row * GRID_SIZE + column].push(triangle as u32);
+                }
+            }
+        }
+    }
+
+    /// Geodetic latitude, longitude and height of a vertex.
+    fn coordinates(&self, vertex: usize) -> (f64, f64, f64) {
+        let [u, v, height] = self.vertices[vertex].map(|q| q as f64 / QUANTIZED_MAX);
+        (
+            self.extent.south + v * self.extent.height(),
+            self.extent.west + u * self.extent.width(),
+            self.minimum_height as f64
+                + height * (self.maximum_height - self.minimum_height) as f64,
+        )
+    }
+
+    /// The height of the mesh surface at a point inside the tile.
+    pub fn height_at(&self, latitude: f64, longitude: f64) -> Option<f64> {
+        let u = (longitude - self.extent.west) / self.extent.width() * QUANTIZED_MAX;
+        let v = (latitude - self.extent.south) / self.extent.height() * QUANTIZED_MAX;
+        if !(0.0..=QUANTIZED_MAX).contains(&u) || !(0.0..=QUANTIZED_MAX).contains(&v) {
+            return None;
+        }
+        let cell = |q: f64| ((q / QUANTIZED_MAX * GRID_SIZE as f64) as usize).min(GRID_SIZE - 1);
+
+        for &triangle in &self.grid[cell(v) * GRID_SIZE + cell(u)] {
+            let corners = &self.indices[triangle as usize * 3..triangle as usize * 3 + 3];
+            let [a, b, c] = [0, 1, 2].map(|i| self.vertices[corners[i] as usize].map(f64::from));
+            let area = (b[0] - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (b[1] - a[1]);
+            if area == 0.0 {
+                continue;
+            }
+            let wb = ((u - a[0]) * (c[1] - a[1]) - (c[0] - a[0]) * (v - a[1])) / area;
+            let wc = ((b[0] - a[0]) * (v - a[1]) - (u - a[0]) * (b[1] - a[1])) / area;
+            let wa = 1.0 - wb - wc;
+            // Points on a shared edge belong to both triangles
+            const SLACK: f64 = -1e-9;
+            if wa >= SLACK && wb >= SLACK && wc >= SLACK {
+                let height = (wa * a[2] + wb * b[2] + wc * c[2]) / QUANTIZED_MAX;
+                return Some(
+                    self.minimum_height as f64
+                        + height * (self.maximum_height - self.minimum_height) as f64,
+                );
+            }
+        }
+        None
+    }
+
+    /// Unit ECEF normals of the vertices. Without the normals extension they
+    /// are averaged from the faces around each vertex.
+    fn vertex_normals(&self) -> Vec<Vector3<f64>> {
+        if let Some(normals) = &self.normals {
+            return normals.iter().map(|n| n.cast().unwrap()).collect();
+        }
+        let positions = (0..self.vertices.len())
+            .map(|i| {
+                let (latitude, longitude, height) = self.coordinates(i);
+                geodesy::geodetic_to_ecef(latitude, longitude, height)
+            })
+            .collect::<Vec<_>>();
+        let mut normals = vec![Vector3::zero(); positions.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| triangle[i] as usize);
+            // Unnormalized, so larger faces weigh more
+            let face = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+            for i in [a, b, c] {
+                normals[i] += face;
+            }
+        }
+        normals
+            .into_iter()
+            .enumerate()
+            .map(|(i, normal)| {
+                if normal.magnitude2() > 0.0 {
+                    normal.normalize()
+                } else {
+                    let (latitude, longitude, _) = self.coordinates(i);
+                    geodesy::enu_axes(latitude, longitude).2
+                }
+            })
+            .collect()
+    }
+
+    /// The part of the tile inside `extent`, relative to `center`, with
+    /// texture coordinates for an image covering `extent` in `projection`.
+    /// Skirts `skirt_height` meters deep hang along the border of `extent`
+    /// and the edges of the tile.
+    ///
+    /// Imagery tiles don't line up with the geographic terrain tiles, so
+    /// each takes its surface from the terrain tiles covering it this way.
+    pub fn geometry(
+        &self,
+        extent: &Extent,
+        projection: TextureProjection,
+        center: Vector3<f64>,
+        skirt_height: f64,
+    ) -> TileGeometry {
+        let normals = self.vertex_normals();
+        // Skirts hang from the tile's edges as well as from the border, so
+        // no cracks open towards the neighbouring terrain tiles either
+        let mut on_edge = vec![[false; 4]; self.vertices.len()];
+        let edges = [
+            &self.west_indices,
+            &self.south_indices,
+            &self.east_indices,
+            &self.north_indices,
+        ];
+        for (side, edge) in edges.into_iter().enumerate() {
+            for &i in edge {
+                on_edge[i as usize][side] = true;
+            }
+        }
+
+        let vertex = |point: &ClipPoint, depth: f64| {
+            let normal = point.normal;
+            let (east, _, _) = geodesy::enu_axes(point.latitude, point.longitude);
+            let tangent = (east - normal * east.dot(normal)).normalize();
+            let position =
+                geodesy::geodetic_to_ecef(point.latitude, point.longitude, point.height - depth);
+            model::ModelVertex {
+                position: (position - center).cast().unwrap().into(),
+                tex_coords: [
+                    ((point.longitude - extent.west) / extent.width()) as f32,
+                    projection.v(point.latitude, extent) as f32,
+                ],
+                normal: normal.cast().unwrap().into(),
+                tangent: tangent.cast().unwrap().into(),
+                bitangent: normal.cross(tangent).cast().unwrap().into(),
+            }
+        };
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::with_capacity(self.indices.len());
+        let mut skirts = Vec::new();
+        // Where the tile's own vertices went, as they're shared by triangles
+        let mut placed = vec![None; self.vertices.len()];
+        for triangle in self.indices.chunks_exact(3) {
+            let polygon = triangle
+                .iter()
+                .map(|&i| {
+                    let (latitude, longitude, height) = self.coordinates(i as usize);
+                    ClipPoint {
+                        vertex: Some(i),
+                        latitude,
+                        longitude,
+                        height,
+                        normal: normals[i as usize],
+                        border: on_edge[i as usize],
+                    }
+                })
+                .collect::<Vec<_>>();
+            let polygon = clip_to_extent(polygon, extent);
+            if polygon.len() < 3 {
+                continue;
+            }
+
+            let corners = polygon
+                .iter()
+                .map(|point| {
+                    let mut add = || {
+                        vertices.push(vertex(point, 0.0));
+                        vertices.len() as u32 - 1
+                    };
+                    match point.vertex {
+                        Some(i) => *placed[i as usize].get_or_insert_with(add),
+                        None => add(),
+                    }
+                })
+                .collect::<Vec<_>>();
+            for i in 1..corners.len() - 1 {
+                indices.extend_from_slice(&[corners[0], corners[i], corners[i + 1]]);
+            }
+
+            // The polygon winds counter-clockwise seen from above like the
+            // triangle it was cut from, so skirts along it face outwards
+            for i in 0..polygon.len() {
+                let j = (i + 1) % polygon.len();
+                let (a, b) = (&polygon[i], &polygon[j]);
+                if (0..4).any(|side| a.border[side] && b.border[side]) {
+                    skirts.push((corners[i], corners[j], a.clone(), b.clone()));
+                }
+            }
+        }
+        for (a, b, a_point, b_point) in skirts {
+            let skirt = vertices.len() as u32;
+            vertices.push(vertex(&a_point, skirt_height));
+            vertices.push(vertex(&b_point, skirt_height));
+            indices.extend_from_slice(&[a, skirt, b, b, skirt, skirt + 1]);
+        }
+
+        TileGeometry {
+            center,
+            vertices,
+            indices,
+        }
+    }
+}
+
+/// A corner of a triangle being clipped to an extent.
+#[derive(Debug, Clone)]
+struct ClipPoint {
+    /// The tile vertex this is, or `None` where an edge was cut.
+    vertex: Option<u32>,
+    latitude: f64,
+    longitude: f64,
+    height: f64,
+    normal: Vector3<f64>,
+    /// Whether the point lies on the west, south, east and north edge of
+    /// the tile or border of the extent, which skirts hang from.
+    border: [bool; 4],
+}
+
+/// Clips a polygon to `extent` one border at a time, by Sutherland and
+/// Hodgman. Heights and normals are interpolated along the cut edges, which
+/// are straight in latitude and longitude like the tile's triangles.
+fn clip_to_extent(mut polygon: Vec<ClipPoint>, extent: &Extent) -> Vec<ClipPoint> {
+    let borders = [extent.west, extent.south, extent.east, extent.north];
+    for (side, edge) in borders.into_iter().enumerate() {
+        // How far inside the border a point is
+        let inside = |point: &ClipPoint| match side {
+            0 => point.longitude - edge,
+            1 => point.latitude - edge,
+            2 => edge - point.longitude,
+            _ => edge - point.latitude,
+        };
+        let mut clipped = Vec::with_capacity(polygon.len() + 1);
+        for i in 0..polygon.len() {
+            let (a, b) = (&polygon[i], &polygon[(i + 1) % polygon.len()]);
+            let (da, db) = (inside(a), inside(b));
+            if da >= 0.0 {
+                clipped.push(a.clone());
+            }
+            if (da > 0.0 && db < 0.0) || (da < 0.0 && db > 0.0) {
+                let t = da / (da - db);
+                let mut border = [false; 4];
+                for (k, on) in border.iter_mut().enumerate() {
+                    *on = a.border[k] && b.border[k];
+                }
+                border[side] = true;
+                let mut cut = ClipPoint {
+                    vertex: None,
+                    latitude: a.latitude + (b.latitude - a.latitude) * t,
+                    longitude: a.longitude + (b.longitude - a.longitude) * t,
+                    height: a.height + (b.height - a.height) * t,
+                    normal: a.normal.lerp(b.normal, t).normalize(),
+                    border,
+                };
+                // Exactly on the border, so neighbouring tiles meet
+                if side % 2 == 0 {
+                    cut.longitude = edge;
+                } else {
+                    cut.latitude = edge;
+                }
+                clipped.push(cut);
+            }
+        }
+        polygon = clipped;
+    }
+    polygon
+}
+
+fn zig_zag_decode(value: u16) -> i32 {
+    (value >> 1) as i32 ^ -((value & 1) as i32)
+}
+
+/// Decodes a unit vector from two bytes of octahedral encoding.
+fn oct_decode(x: u8, y: u8) -> Vector3<f32> {
+    let x = x as f32 / 255.0 * 2.0 - 1.0;
+    let y = y as f32 / 255.0 * 2.0 - 1.0;
+    let z = 1.0 - x.abs() - y.abs();
+    let (x, y) = if z < 0.0 {
+        (
+            (1.0 - y.abs()) * 1f32.copysign(x),
+            (1.0 - x.abs()) * 1f32.copysign(y),
+        )
+    } else {
+        (x, y)
+    };
+    Vector3::new(x, y, z).normalize()
+}
+
+/// Reads little-endian values from a tile.
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn remaining(&self) -> usize {
+        self.data.len() - self.offset
+    }
+
+    fn bytes(&mut self, count: usize) -> anyhow::Result<&'a [u8]> {
+        if count > self.remaining() {
+            bail!("quantized-mesh tile ends early at byte {}", self.data.len());
+        }
+        let bytes = &self.data[self.offset..self.offset + count];
+        self.offset += count;
+        Ok(bytes)
+    }
+
+    fn align(&mut self, alignment: usize) {
+        self.offset = self.offset.div_ceil(alignment) * alignment;
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    /// Reads a count of items `size` bytes each, checking they fit in what's
+    /// left so a corrupt count can't make a huge allocation.
+    fn count(&mut self, size: usize) -> anyhow::Result<usize> {
+        let count = self.u32()? as usize;
+        match count.checked_mul(size) {
+            Some(bytes) if bytes <= self.remaining() => Ok(count),
+            _ => bail!("quantized-mesh tile too short for {} items", count),
+        }
+    }
+
+    fn f32(&mut self) -> anyhow::Result<f32> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn indices(&mut self, count: usize, wide: bool) -> anyhow::Result<Vec<u32>> {
+        (0..count)
+            .map(|_| {
+                if wide {
+                    self.u32()
+                } else {
+                    self.u16().map(u32::from)
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn zig_zag_encode(value: i32) -> u16 {
+        ((value << 1) ^ (value >> 31)) as u16
+    }
+
+    /// A tile of two triangles over the whole extent whose north-east
+    /// corner rises to the maximum height.
+    fn encode_square(extent: &Extent, normals: bool) -> Vec<u8> {
+        let mut data = Vec::new();
+        let (latitude, longitude) = extent.center();
+        let center: [f64; 3] = geodesy::geodetic_to_ecef(latitude, longitude, 0.0).into();
+        for value in center {
+            data.extend_from_slice(&f64::to_le_bytes(value));
+        }
+        data.extend_from_slice(&100f32.to_le_bytes());
+        data.extend_from_slice(&300f32.to_le_bytes());
+        data.extend_from_slice(&[0; 8 * 4 + 8 * 3]);
+
+        // South-west, south-east, north-east, north-west
+        let vertices: [[i32; 3]; 4] = [
+            [0, 0, 0],
+            [32767, 0, 0],
+            [32767, 32767, 32767],
+            [0, 32767, 0],
+        ];
+        data.extend_from_slice(&4u32.to_le_bytes());
+        for component in 0..3 {
+            let mut previous = 0;
+            for vertex in &vertices {
+                let delta = zig_zag_encode(vertex[component] - previous);
+                data.extend_from_slice(&delta.to_le_bytes());
+                previous = vertex[component];
+            }
+        }
+
+        // Triangles 0 1 2 and 0 2 3 with high-water mark encoding
+        data.extend_from_slice(&2u32.to_le_bytes());
+        for code in [0u16, 0, 0, 3, 1, 0] {
+            data.extend_from_slice(&code.to_le_bytes());
+        }
+        for edge in [&[3u16, 0][..], &[0, 1], &[1, 2], &[2, 3]] {
+            data.extend_from_slice(&(edge.len() as u32).to_le_bytes());
+            for index in edge {
+                data.extend_from_slice(&index.to_le_bytes());
+            }
+        }
+
+        if normals {
+            data.push(EXTENSION_OCT_NORMALS);
+            data.extend_from_slice(&8u32.to_le_bytes());
+            // Straight along +z
+            data.extend_from_slice(&[128, 128].repeat(4));
+        }
+        data
+    }
+
+    #[test]
+    fn decodes_vertices_indices_and_edges() {
+        let extent = geographic_extent(TileId::new(10, 1100, 700));
+        let mesh = QuantizedMesh::decode(&encode_square(&extent, false), extent).unwrap();
+        assert_eq!(mesh.vertices[2], [32767, 32767, 32767]);
+        assert_eq!(mesh.indices, [0, 1, 2, 0, 2, 3]);
+        assert_eq!(mesh.north_indices, [2, 3]);
+        assert!(mesh.normals.is_none());
+
+        let at = |u: f64, v: f64| {
+            mesh.height_at(
+                extent.south + v * extent.height(),
+                extent.west + u * extent.width(),
+            )
+        };
+        assert!((at(0.0, 0.0).unwrap() - 100.0).abs() < 1e-9);
+        assert!((at(1.0, 1.0).unwrap() - 300.0).abs() < 1e-9);
+        assert!((at(0.75, 0.5).unwrap() - 200.0).abs() < 1e-3);
+        assert_eq!(at(1.5, 0.5), None);
+
+        let (latitude, longitude) = extent.center();
+        let center = geodesy::geodetic_to_ecef(latitude, longitude, 0.0);
+        let geometry = mesh.geometry(&extent, TextureProjection::Geographic, center, 50.0);
+        assert_eq!(geometry.vertices.len(), 4 + 4 * 2);
+        assert_eq!(geometry.indices.len(), 6 + 4 * 6);
+        let (latitude, longitude) = extent.center();
+        let up = geodesy::enu_axes(latitude, longitude).2;
+        for triangle in geometry.indices[..6].chunks(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| {
+                Vector3::from(geometry.vertices[triangle[i] as usize].position)
+                    .cast::<f64>()
+                    .unwrap()
+            });
+            assert!((b - a).cross(c - a).dot(up) > 0.0, "triangle winds inwards");
+        }
+        let normal = Vector3::from(geometry.vertices[0].normal)
+            .cast::<f64>()
+            .unwrap();
+        assert!(normal.dot(up) > 0.99);
+
+        // A vertex count the data can't hold is refused before allocating
+        let mut corrupt = encode_square(&extent, false);
+        corrupt[88..92].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(QuantizedMesh::decode(&corrupt, extent).is_err());
+    }
+
+    #[test]
+    fn vertex_deltas_wrap_around() {
+        // Enough of the largest deltas to overflow a 32 bit sum
+        let count = 70_000;
+        let extent = geographic_extent(TileId::new(0, 0, 0));
+        let mut data = encode_square(&extent, false);
+        data.truncate(88);
+        data.extend_from_slice(&(count as u32).to_le_bytes());
+        data.extend_from_slice(&zig_zag_encode(32767).to_le_bytes().repeat(count));
+        data.extend_from_slice(&[0; 2 * 2].repeat(count));
+        // No triangles or edges, with 32 bit indices past 65536 vertices
+        data.extend_from_slice(&[0; 4 * 5]);
+        let mesh = QuantizedMesh::decode(&data, extent).unwrap();
+        assert_eq!(mesh.vertices.len(), count);
+        assert_eq!(mesh.vertices[1], [65534, 0, 0]);
+        assert_eq!(mesh.vertices[2], [32765, 0, 0]);
+        assert_eq!(mesh.vertices[count - 1][0], (32767 * count % 65536) as u16);
+    }
+
+    #[test]
+    fn geometry_is_clipped_to_the_extent() {
+        let tile = geographic_extent(TileId::new(10, 1100, 700));
+        let mesh = QuantizedMesh::decode(&encode_square(&tile, false), tile).unwrap();
+        let extent = Extent {
+            west: tile.west,
+            south: tile.south + tile.height() * 0.25,
+            east: tile.west + tile.width() * 0.5,
+            north: tile.north + 0.1,
+        };
+        let (latitude, longitude) = extent.center();
+        let center = geodesy::geodetic_to_ecef(latitude, longitude, 0.0);
+        let geometry = mesh.geometry(&extent, TextureProjection::WebMercator, center, 50.0);
+        assert!(!geometry.indices.is_empty());
+
+        let mut on_east_border = 0;
+        for vertex in &geometry.vertices {
+            let position = Vector3::from(vertex.position).cast::<f64>().unwrap() + center;
+            let point = geodesy::Geodetic::from_ecef(position);
+            assert!(point.longitude <= extent.east + 1e-7 && point.latitude >= extent.south - 1e-7);
+            // On the surface, or down a skirt
+            let ground = mesh
+                .height_at(
+                    point.latitude.clamp(tile.south, tile.north),
+                    point.longitude.clamp(tile.west, tile.east),
+                )
+                .unwrap();
+            let depth = ground - point.height;
+            assert!(
+                depth.abs() < 0.05 || (depth - 50.0).abs() < 0.05,
+                "{}",
+                depth
+            );
+
+            let [u, v] = vertex.tex_coords;
+            assert!((-1e-6..=1.0 + 1e-6).contains(&u) && v <= 1.0 + 1e-6);
+            if (u - 1.0).abs() < 1e-6 {
+                on_east_border += 1;
+            }
+        }
+        // The cut edge has a skirt, both ends on the surface and below
+        assert!(on_east_border >= 4);
+        // The tile doesn't reach the extent's north border, so stops short
+        let top = TextureProjection::WebMercator.v(tile.north, &extent) as f32;
+        assert!(geometry
+            .vertices
+            .iter()
+            .all(|vertex| vertex.tex_coords[1] > top - 1e-6));
+    }
+
+    #[test]
+    fn decodes_oct_normals_and_gzip() {
+        use flate2::write::GzEncoder;
+        use std::io::Write;
+
+        let extent = geographic_extent(TileId::new(0, 1, 0));
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&encode_square(&extent, true)).unwrap();
+        let mesh = QuantizedMesh::decode(&encoder.finish().unwrap(), extent).unwrap();
+        let normals = mesh.normals.unwrap();
+        assert_eq!(normals.len(), 4);
+        assert!((normals[0].z - 1.0).abs() < 1e-4);
+
+        // From the lower hemisphere, folded over the octahedron's edges
+        let folded = oct_decode(64, 200);
+        assert!((folded.magnitude() - 1.0).abs() < 1e-6);
+        assert!(folded.x < 0.0 && folded.y > 0.0 && folded.z < 0.0);
+    }
+
+    #[test]
+    fn covering_tiles_overlap_the_extent() {
+        let tiles = covering_tiles(&TileId::new(0, 0, 0).extent(), 0);
+        assert_eq!(tiles, [TileId::new(0, 0, 0), TileId::new(0, 1, 0)]);
+
+        let extent = geographic_extent(TileId::new(3, 5, 2));
+        assert_eq!(covering_tiles(&extent, 3), [TileId::new(3, 5, 2)]);
+        assert_eq!(covering_tiles(&extent, 2), [TileId::new(2, 2, 1)]);
+        assert_eq!(covering_tiles(&extent, 4).len(), 4);
+    }
+}
@@ -1,9 +1,8 @@
 use std::io::{BufReader, Cursor};
 
 use cfg_if::cfg_if;
-use wgpu::util::DeviceExt;
 
-use crate::{model, texture};
+use crate::{bvh, model, texture};
 
 #[cfg(target_arch = "wasm32")]
 fn format_url(file_name: &str) -> reqwest::Url {
@@ -66,11 +65,14 @@ pub async fn load_texture(
     texture::Texture::from_bytes(device, queue, &data, file_name, is_normal_map)
 }
 
+/// Loads an OBJ model. With `retain_geometry` each mesh also keeps its
+/// triangles on the CPU for picking and other queries.
 pub async fn load_model(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
+    retain_geometry: bool,
 ) -> anyhow::Result<model::Model> {
     let obj_text = load_string(file_name).await?;
     let obj_cursor = Cursor::new(obj_text);
@@ -194,24 +196,21 @@ pub async fn load_model(
                 v.bitangent = (cgmath::Vector3::from(v.bitangent) * denom).into();
             }
 
-            let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Vertex Buffer", file_name)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
-            });
-            let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                label: Some(&format!("{:?} Index Buffer", file_name)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
-            });
-
-            model::Mesh {
-                name: file_name.to_string(),
-                vertex_buffer,
-                index_buffer,
-                num_elements: m.mesh.indices.len() as u32,
-                material: m.mesh.material_id.unwrap_or(0),
+            let mut mesh = model::Mesh::new(
+                device,
+                file_name,
+                &vertices,
+                &m.mesh.indices,
+                m.mesh.material_id.unwrap_or(0),
+            );
+            if retain_geometry {
+                let positions = vertices
+                    .iter()
+                    .map(|vertex| cgmath::Vector3::from(vertex.position).cast().unwrap())
+                    .collect();
+                mesh.triangles = Some(bvh::TriangleMesh::new(positions, m.mesh.indices.clone()));
             }
+            mesh
         })
         .collect::<Vec<_>>();
 
@@ -0,0 +1,503 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Rad, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::clock::UtcTime;
+use crate::collision::{self, CollisionGrid};
+use crate::geodesy::Geodetic;
+use crate::picking::{self, Pick, PickedObject, Ray};
+use crate::polyline::{self, DrawPolylines, LineStyle, Polyline, Polylines};
+use crate::sgp4::{Sgp4, Tle};
+use crate::{camera, labels, model, sun, texture};
+
+/// Points along the orbit drawn around each satellite, over one period.
+const ORBIT_SAMPLES: usize = 256;
+/// Points along the ground track, over the same period. The track runs
+/// along the ground, so it needs enough of them for its straight segments
+/// not to cut under the curve of the Earth.
+const GROUND_TRACK_SAMPLES: usize = 1024;
+/// Marker radii in pixels.
+const MARKER_RADIUS: f32 = 5.0;
+const SELECTED_MARKER_RADIUS: f32 = 8.0;
+const COLOR: [f32; 4] = [1.0, 0.85, 0.2, 1.0];
+const SELECTED_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+
+/// A tracked satellite.
+pub struct Satellite {
+    pub tle: Tle,
+    sgp4: Sgp4,
+    /// ECEF meters as of the last update, or `None` once propagation fails.
+    pub position: Option<Vector3<f64>>,
+    /// TEME meters over the period around `track_time`, to be turned with
+    /// the Earth as time goes on.
+    orbit: Vec<Vector3<f64>>,
+    /// ECEF meters on the ellipsoid under the same period.
+    ground_track: Vec<Vector3<f64>>,
+    track_time: Option<UtcTime>,
+}
+
+impl Satellite {
+    pub fn new(tle: Tle) -> anyhow::Result<Self> {
+        Ok(Self {
+            sgp4: Sgp4::new(&tle)?,
+            tle,
+            position: None,
+            orbit: Vec::new(),
+            ground_track: Vec::new(),
+            track_time: None,
+        })
+    }
+
+    /// TEME meters at `time`.
+    fn inertial_position(&self, time: UtcTime) -> anyhow::Result<Vector3<f64>> {
+        Ok(self.sgp4.propagate_to(time)?.0 * 1000.0)
+    }
+
+    /// ECEF meters at `time`.
+    pub fn position_at(&self, time: UtcTime) -> anyhow::Result<Vector3<f64>> {
+        Ok(sun::inertial_to_ecef(time) * self.inertial_position(time)?)
+    }
+
+    /// Samples the orbit and ground track again once `time` has moved a
+    /// quarter period away from the last samples. Returns whether it did.
+    fn update_track(&mut self, time: UtcTime) -> bool {
+        let period = self.sgp4.period() * 60.0;
+        let current = self.track_time.is_some_and(|track_time| {
+            (time.unix_seconds - track_time.unix_seconds).abs() < period / 4.0
+        });
+        if current {
+            return false;
+        }
+        let sample = |i: usize, count: usize| {
+            UtcTime::from_unix_seconds(time.unix_seconds + period * (i as f64 / count as f64 - 0.5))
+        };
+        self.orbit = (0..=ORBIT_SAMPLES)
+            .filter_map(|i| self.inertial_position(sample(i, ORBIT_SAMPLES)).ok())
+            .collect();
+        self.ground_track = (0..=GROUND_TRACK_SAMPLES)
+            .filter_map(|i| {
+                let position = self.position_at(sample(i, GROUND_TRACK_SAMPLES)).ok()?;
+                let mut ground = Geodetic::from_ecef(position);
+                ground.height = 0.0;
+                Some(ground.to_ecef())
+            })
+            .collect();
+        self.track_time = Some(time);
+        true
+    }
+
+    /// The orbit and ground track as lines, the orbit in TEME and the track
+    /// in ECEF.
+    fn lines(&self, selected: bool) -> [Polyline; 2] {
+        let [r, g, b, _] = if selected { SELECTED_COLOR } else { COLOR };
+        let width = if selected { 2.5 } else { 1.5 };
+        [
+            Polyline {
+                points: self.orbit.clone(),
+                closed: false,
+                style: LineStyle {
+                    color: [r, g, b, 0.7],
+                    width,
+                    ..Default::default()
+                },
+            },
+            Polyline {
+                points: self.ground_track.clone(),
+                closed: false,
+                style: LineStyle {
+                    color: [r, g, b, 0.45],
+                    width,
+                    dash: Some([8.0, 6.0]),
+                    ..Default::default()
+                },
+            },
+        ]
+    }
+}
+
+/// Reads element sets, leaving out those SGP4 can't propagate.
+pub fn satellites_from_tles(text: &str) -> anyhow::Result<Vec<Satellite>> {
+    Ok(crate::sgp4::parse_tles(text)?
+        .into_iter()
+        .filter_map(|tle| {
+            let label = tle.label();
+            Satellite::new(tle)
+                .map_err(|e| log::warn!("Skipping satellite {}: {}", label, e))
+                .ok()
+        })
+        .collect())
+}
+
+/// A satellite's camera-relative position, with the marker's radius in w,
+/// and its color.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct MarkerInstance {
+    position: [f32; 4],
+    color: [f32; 4],
+}
+
+impl MarkerInstance {
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<MarkerInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+/// Tracked satellites, each drawn as a marker of a fixed size on screen with
+/// its orbit and ground track over one period. Markers and lines are depth
+/// tested against the globe, so satellites behind the Earth are hidden.
+pub struct Satellites {
+    pub satellites: Vec<Satellite>,
+    /// The satellite last picked, drawn highlighted.
+    pub selected: Option<usize>,
+    /// The selection the lines were built for.
+    lines_selected: Option<usize>,
+    /// Orbits in TEME, turned with the Earth each frame.
+    orbits: Polylines,
+    ground_tracks: Polylines,
+    markers: Vec<MarkerInstance>,
+    instance_buffer: wgpu::Buffer,
+    viewport_buffer: wgpu::Buffer,
+    viewport_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    line_renderer: polyline::PolylineRenderer,
+}
+
+impl Satellites {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        projection: &camera::Projection,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        satellites: Vec<Satellite>,
+    ) -> Self {
+        let instance_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Satellite Instance Buffer"),
+            size: (satellites.len().max(1) * std::mem::size_of::<MarkerInstance>())
+                as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let viewport_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Satellite Viewport Buffer"),
+            contents: bytemuck::cast_slice(&[config.width as f32, config.height as f32, 0.0, 0.0]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let viewport_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("satellite_viewport_bind_group_layout"),
+            });
+        let viewport_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &viewport_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: viewport_buffer.as_entire_binding(),
+            }],
+            label: Some("satellite_viewport_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Satellite Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &viewport_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Satellite Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("satellites.wgsl").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Satellite Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[MarkerInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: projection.depth_compare(),
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            satellites,
+            selected: None,
+            lines_selected: None,
+            orbits: Polylines::new(device, []),
+            ground_tracks: Polylines::new(device, []),
+            markers: Vec::new(),
+            instance_buffer,
+            viewport_buffer,
+            viewport_bind_group,
+            pipeline,
+            line_renderer: polyline::PolylineRenderer::new(
+                device,
+                config,
+                projection,
+                camera_bind_group_layout,
+            ),
+        }
+    }
+
+    /// Propagates the satellites to `time` and rebases them against the
+    /// camera. The lines are only rebuilt when a track is sampled again or
+    /// the selection changes.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        time: UtcTime,
+        camera_position: Point3<f64>,
+    ) {
+        let mut resampled = false;
+        for satellite in &mut self.satellites {
+            satellite.position = satellite.position_at(time).ok();
+            resampled |= satellite.update_track(time);
+        }
+        if resampled || self.lines_selected != self.selected {
+            let selected = self.selected;
+            let (orbits, ground_tracks): (Vec<_>, Vec<_>) = self
+                .satellites
+                .iter()
+                .enumerate()
+                .map(|(i, satellite)| {
+                    let [orbit, track] = satellite.lines(selected == Some(i));
+                    (orbit, track)
+                })
+                .unzip();
+            self.orbits = Polylines::new(device, orbits);
+            self.ground_tracks = Polylines::new(device, ground_tracks);
+            self.lines_selected = selected;
+        }
+        self.orbits
+            .update_rotated(queue, sun::inertial_to_ecef(time), camera_position);
+        self.ground_tracks.update(queue, camera_position);
+
+        self.markers = self
+            .satellites
+            .iter()
+            .enumerate()
+            .filter_map(|(i, satellite)| {
+                let position = model::camera_relative(satellite.position?, camera_position);
+                let (radius, color) = if self.selected == Some(i) {
+                    (SELECTED_MARKER_RADIUS, SELECTED_COLOR)
+                } else {
+                    (MARKER_RADIUS, COLOR)
+                };
+                Some(MarkerInstance {
+                    position: position.extend(radius).into(),
+                    color,
+                })
+            })
+            .collect();
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&self.markers),
+        );
+    }
+
+    pub fn resize(&self, queue: &wgpu::Queue, width: u32, height: u32, fovy: Rad<f32>) {
+        queue.write_buffer(
+            &self.viewport_buffer,
+            0,
+            bytemuck::cast_slice(&[width as f32, height as f32, 0.0, 0.0]),
+        );
+        self.line_renderer.resize(queue, width, height, fovy);
+    }
+
+    /// Takes the screen space of the markers in view, so labels avoid them.
+    pub fn reserve_markers(
+        &self,
+        collisions: &mut CollisionGrid,
+        view_proj: Matrix4<f32>,
+        camera_position: Point3<f64>,
+    ) {
+        for (i, satellite) in self.satellites.iter().enumerate() {
+            let position = match satellite.position {
+                Some(position) if !labels::beyond_horizon(position, camera_position.to_vec()) => {
+                    model::camera_relative(position, camera_position)
+                }
+                _ => continue,
+            };
+            let radius = if self.selected == Some(i) {
+                SELECTED_MARKER_RADIUS
+            } else {
+                MARKER_RADIUS
+            };
+            if let Some([x, y]) = collision::project(view_proj, position, collisions.size()) {
+                collisions.insert([x - radius, y - radius, x + radius, y + radius]);
+            }
+        }
+    }
+
+    /// The satellite whose marker is under the cursor ray, with its
+    /// latitude, longitude and height. `pixel_angle` is the angle a pixel
+    /// spans at the center of the screen.
+    pub fn pick(&self, ray: &Ray, pixel_angle: f64) -> Option<Pick> {
+        let positions = self
+            .satellites
+            .iter()
+            .enumerate()
+            .filter_map(|(i, satellite)| Some((i, satellite.position?)));
+        let (i, distance) =
+            picking::pick_markers(ray, positions, pixel_angle * SELECTED_MARKER_RADIUS as f64)?;
+        let position = self.satellites[i].position?;
+        Some(Pick {
+            object: PickedObject::Satellite(i),
+            position,
+            geodetic: Some(Geodetic::from_ecef(position)),
+            distance,
+        })
+    }
+
+    /// Where the selected satellite is, for display.
+    pub fn selected_status(&self) -> Option<String> {
+        let satellite = &self.satellites[self.selected?];
+        let geodetic = Geodetic::from_ecef(satellite.position?);
+        Some(format!(
+            "{} at {:.3}°, {:.3}°, {:.1} km",
+            satellite.tle.label(),
+            geodetic.latitude.to_degrees(),
+            geodetic.longitude.to_degrees(),
+            geodetic.height / 1000.0
+        ))
+    }
+}
+
+pub trait DrawSatellites<'a> {
+    fn draw_satellites(
+        &mut self,
+        satellites: &'a Satellites,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawSatellites<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_satellites(
+        &mut self,
+        satellites: &'b Satellites,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.draw_polylines(
+            &satellites.line_renderer,
+            &satellites.orbits,
+            camera_bind_group,
+        );
+        self.draw_polylines(
+            &satellites.line_renderer,
+            &satellites.ground_tracks,
+            camera_bind_group,
+        );
+        if satellites.markers.is_empty() {
+            return;
+        }
+        self.set_pipeline(&satellites.pipeline);
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(1, &satellites.viewport_bind_group, &[]);
+        self.set_vertex_buffer(0, satellites.instance_buffer.slice(..));
+        self.draw(0..6, 0..satellites.markers.len() as u32);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The ISS, shortly after its epoch
+    const ISS: &str = "\
+ISS (ZARYA)
+1 25544U 98067A   08264.51782528 -.00002182  00000-0 -11606-4 0  2927
+2 25544  51.6416 247.4627 0006703 130.5360 325.0288 15.72125391563537";
+
+    #[test]
+    fn satellites_circle_the_earth_along_their_tracks() {
+        let mut satellite = satellites_from_tles(ISS).unwrap().remove(0);
+        assert_eq!(satellite.tle.label(), "ISS (ZARYA)");
+        let time = UtcTime::from_unix_seconds(satellite.tle.epoch.unix_seconds + 600.0);
+        let position = satellite.position_at(time).unwrap();
+        let height = Geodetic::from_ecef(position).height;
+        assert!((330e3..370e3).contains(&height), "{}", height);
+
+        // The orbit passes through the satellite, the track right under it
+        assert!(satellite.update_track(time));
+        assert!(!satellite.update_track(time));
+        assert_eq!(satellite.orbit.len(), ORBIT_SAMPLES + 1);
+        let [orbit, track] = satellite.lines(false);
+        let middle = sun::inertial_to_ecef(time) * orbit.points[ORBIT_SAMPLES / 2];
+        assert!((middle - position).magnitude() < 1.0);
+        let under = Geodetic::from_ecef(track.points[GROUND_TRACK_SAMPLES / 2]);
+        let above = Geodetic::from_ecef(position);
+        assert!((under.latitude - above.latitude).abs() < 1e-9);
+        assert!(under.height.abs() < 1e-3);
+
+        // Deep space orbits are tracked too
+        let mut geostationary = satellite.tle.clone();
+        geostationary.mean_motion = std::f64::consts::TAU / 1436.0;
+        geostationary.eccentricity = 0.0001;
+        geostationary.inclination = 0.0001;
+        assert!(geostationary.is_deep_space());
+        let position = Satellite::new(geostationary)
+            .unwrap()
+            .position_at(time)
+            .unwrap();
+        let height = Geodetic::from_ecef(position).height;
+        assert!((35_600e3..36_000e3).contains(&height), "{}", height);
+    }
+}
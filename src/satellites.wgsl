@@ -0,0 +1,71 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+struct Viewport {
+    // Width and height in pixels
+    size: vec4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> viewport: Viewport;
+
+struct InstanceInput {
+    // Camera-relative, with the marker's radius in pixels in w
+    [[location(0)]] position: vec4<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+    // In pixels from the center, with the radius in z
+    [[location(1)]] offset: vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    [[builtin(vertex_index)]] index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    // A pixel spare around the disc for its edge to fade in
+    let radius = instance.position.w;
+    let pixels = corners[index] * (radius + 1.0);
+
+    let clip = camera.view_proj * vec4<f32>(instance.position.xyz, 1.0);
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(
+        clip.xy + pixels * 2.0 / viewport.size.xy * clip.w,
+        clip.zw,
+    );
+    out.color = instance.color;
+    out.offset = vec3<f32>(pixels, radius);
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let distance = length(in.offset.xy);
+    let coverage = clamp(in.offset.z + 0.5 - distance, 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    // A dark rim keeps markers visible against bright ground
+    let rim = smoothStep(in.offset.z - 2.0, in.offset.z - 1.0, distance);
+    let color = mix(in.color.rgb, vec3<f32>(0.0), rim * 0.6);
+    return vec4<f32>(color, in.color.a * coverage);
+}
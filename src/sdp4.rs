@@ -0,0 +1,598 @@
+use std::f64::consts::{PI, TAU};
+
+use crate::clock::UtcTime;
+use crate::sun;
+
+/// How fast the Earth turns, in radians per minute.
+const EARTH_ROTATION: f64 = 4.375_269_088_011_3e-3;
+/// Mean motions in radians per minute and eccentricities of the sun's and
+/// the moon's apparent orbits.
+const SOLAR_MEAN_MOTION: f64 = 1.19459e-5;
+const SOLAR_ECCENTRICITY: f64 = 0.01675;
+const LUNAR_MEAN_MOTION: f64 = 1.5835218e-4;
+const LUNAR_ECCENTRICITY: f64 = 0.05490;
+/// Below this inclination, or this close to retrograde, the node is left
+/// alone by the secular terms.
+const EQUATORIAL_INCLINATION: f64 = 5.2359877e-2;
+/// Below this inclination the periodics are applied with Lyddane's
+/// modification, which stays well defined as the node becomes undefined.
+const LYDDANE_INCLINATION: f64 = 0.2;
+/// Minutes per step of the resonance integrator.
+const STEP: f64 = 720.0;
+
+/// Mean orbital elements as the propagator updates them, angles in radians.
+#[derive(Debug, Clone, Copy)]
+pub struct MeanElements {
+    pub eccentricity: f64,
+    pub inclination: f64,
+    pub right_ascension: f64,
+    pub argument_of_perigee: f64,
+    pub mean_anomaly: f64,
+    /// Radians per minute.
+    pub mean_motion: f64,
+}
+
+/// The sun's or the moon's pull on one orbit, worked out at the epoch.
+struct BodyTerms {
+    s: [f64; 7],
+    z1: f64,
+    z2: f64,
+    z3: f64,
+    z11: f64,
+    z12: f64,
+    z13: f64,
+    z21: f64,
+    z22: f64,
+    z23: f64,
+    z31: f64,
+    z32: f64,
+    z33: f64,
+}
+
+/// The orbit at the epoch as the body terms need it.
+struct Orbit {
+    eccentricity: f64,
+    sin_inclination: f64,
+    cos_inclination: f64,
+    sin_perigee: f64,
+    cos_perigee: f64,
+    mean_motion: f64,
+}
+
+impl BodyTerms {
+    /// `body` holds the cosines and sines of the body's argument of
+    /// perigee, inclination and node, all relative to the satellite's
+    /// node, and `cc` its strength.
+    fn new(body: [f64; 6], cc: f64, orbit: &Orbit) -> Self {
+        let [zcosg, zsing, zcosi, zsini, zcosh, zsinh] = body;
+        let (cosim, sinim) = (orbit.cos_inclination, orbit.sin_inclination);
+        let (cosomm, sinomm) = (orbit.cos_perigee, orbit.sin_perigee);
+        let em = orbit.eccentricity;
+        let emsq = em * em;
+        let betasq = 1.0 - emsq;
+        let rtemsq = betasq.sqrt();
+
+        let a1 = zcosg * zcosh + zsing * zcosi * zsinh;
+        let a3 = -zsing * zcosh + zcosg * zcosi * zsinh;
+        let a7 = -zcosg * zsinh + zsing * zcosi * zcosh;
+        let a8 = zsing * zsini;
+        let a9 = zsing * zsinh + zcosg * zcosi * zcosh;
+        let a10 = zcosg * zsini;
+        let a2 = cosim * a7 + sinim * a8;
+        let a4 = cosim * a9 + sinim * a10;
+        let a5 = -sinim * a7 + cosim * a8;
+        let a6 = -sinim * a9 + cosim * a10;
+
+        let x1 = a1 * cosomm + a2 * sinomm;
+        let x2 = a3 * cosomm + a4 * sinomm;
+        let x3 = -a1 * sinomm + a2 * cosomm;
+        let x4 = -a3 * sinomm + a4 * cosomm;
+        let x5 = a5 * sinomm;
+        let x6 = a6 * sinomm;
+        let x7 = a5 * cosomm;
+        let x8 = a6 * cosomm;
+
+        let z31 = 12.0 * x1 * x1 - 3.0 * x3 * x3;
+        let z32 = 24.0 * x1 * x2 - 6.0 * x3 * x4;
+        let z33 = 12.0 * x2 * x2 - 3.0 * x4 * x4;
+        let z1 = 3.0 * (a1 * a1 + a2 * a2) + z31 * emsq;
+        let z2 = 6.0 * (a1 * a3 + a2 * a4) + z32 * emsq;
+        let z3 = 3.0 * (a3 * a3 + a4 * a4) + z33 * emsq;
+        let z11 = -6.0 * a1 * a5 + emsq * (-24.0 * x1 * x7 - 6.0 * x3 * x5);
+        let z12 = -6.0 * (a1 * a6 + a3 * a5)
+            + emsq * (-24.0 * (x2 * x7 + x1 * x8) - 6.0 * (x3 * x6 + x4 * x5));
+        let z13 = -6.0 * a3 * a6 + emsq * (-24.0 * x2 * x8 - 6.0 * x4 * x6);
+        let z21 = 6.0 * a2 * a5 + emsq * (24.0 * x1 * x5 - 6.0 * x3 * x7);
+        let z22 = 6.0 * (a4 * a5 + a2 * a6)
+            + emsq * (24.0 * (x2 * x5 + x1 * x6) - 6.0 * (x4 * x7 + x3 * x8));
+        let z23 = 6.0 * a4 * a6 + emsq * (24.0 * x2 * x6 - 6.0 * x4 * x8);
+        let z1 = z1 + z1 + betasq * z31;
+        let z2 = z2 + z2 + betasq * z32;
+        let z3 = z3 + z3 + betasq * z33;
+        let s3 = cc / orbit.mean_motion;
+        let s2 = -0.5 * s3 / rtemsq;
+        let s4 = s3 * rtemsq;
+        let s1 = -15.0 * em * s4;
+        let s5 = x1 * x3 + x2 * x4;
+        let s6 = x2 * x3 + x1 * x4;
+        let s7 = x2 * x4 - x1 * x3;
+
+        Self {
+            s: [s1, s2, s3, s4, s5, s6, s7],
+            z1,
+            z2,
+            z3,
+            z11,
+            z12,
+            z13,
+            z21,
+            z22,
+            z23,
+            z31,
+            z32,
+            z33,
+        }
+    }
+
+    /// The long period periodics of a body with the given mean anomaly at
+    /// the epoch, mean motion and eccentricity.
+    fn periodics(&self, zm: f64, zn: f64, ze: f64, emsq: f64) -> Periodics {
+        let [s1, s2, s3, s4, _, s6, s7] = self.s;
+        Periodics {
+            zm,
+            zn,
+            ze,
+            e2: 2.0 * s1 * s6,
+            e3: 2.0 * s1 * s7,
+            i2: 2.0 * s2 * self.z12,
+            i3: 2.0 * s2 * (self.z13 - self.z11),
+            l2: -2.0 * s3 * self.z2,
+            l3: -2.0 * s3 * (self.z3 - self.z1),
+            l4: -2.0 * s3 * (-21.0 - 9.0 * emsq) * ze,
+            gh2: 2.0 * s4 * self.z32,
+            gh3: 2.0 * s4 * (self.z33 - self.z31),
+            gh4: -18.0 * s4 * ze,
+            h2: -2.0 * s2 * self.z22,
+            h3: -2.0 * s2 * (self.z23 - self.z21),
+        }
+    }
+
+    /// The secular rates of the eccentricity, inclination, mean anomaly,
+    /// argument of perigee and node the body drives, the last two before
+    /// they're shared out between each other.
+    fn secular_rates(&self, zn: f64, emsq: f64) -> [f64; 5] {
+        let [s1, s2, s3, s4, s5, _, _] = self.s;
+        [
+            s1 * zn * s5,
+            s2 * zn * (self.z11 + self.z13),
+            -zn * s3 * (self.z1 + self.z3 - 14.0 - 6.0 * emsq),
+            s4 * zn * (self.z31 + self.z33 - 6.0),
+            -zn * s2 * (self.z21 + self.z23),
+        ]
+    }
+}
+
+/// The long period periodics of one body.
+#[derive(Debug, Clone)]
+struct Periodics {
+    /// The body's mean anomaly at the epoch, mean motion and eccentricity.
+    zm: f64,
+    zn: f64,
+    ze: f64,
+    e2: f64,
+    e3: f64,
+    i2: f64,
+    i3: f64,
+    l2: f64,
+    l3: f64,
+    l4: f64,
+    gh2: f64,
+    gh3: f64,
+    gh4: f64,
+    h2: f64,
+    h3: f64,
+}
+
+impl Periodics {
+    /// The changes to eccentricity, inclination, mean anomaly, argument of
+    /// perigee and node `t` minutes after the epoch.
+    fn at(&self, t: f64) -> [f64; 5] {
+        let zm = self.zm + self.zn * t;
+        let zf = zm + 2.0 * self.ze * zm.sin();
+        let sinzf = zf.sin();
+        let f2 = 0.5 * sinzf * sinzf - 0.25;
+        let f3 = -0.5 * sinzf * zf.cos();
+        [
+            self.e2 * f2 + self.e3 * f3,
+            self.i2 * f2 + self.i3 * f3,
+            self.l2 * f2 + self.l3 * f3 + self.l4 * sinzf,
+            self.gh2 * f2 + self.gh3 * f3 + self.gh4 * sinzf,
+            self.h2 * f2 + self.h3 * f3,
+        ]
+    }
+}
+
+/// Resonances between the orbit and the Earth's tesseral harmonics, which
+/// build up over orbits that repeat their ground track.
+#[derive(Debug, Clone)]
+enum Resonance {
+    None,
+    /// Orbits of about a day.
+    Synchronous {
+        del1: f64,
+        del2: f64,
+        del3: f64,
+    },
+    /// Eccentric orbits of about half a day, like Molniya's.
+    HalfDay {
+        d: [f64; 10],
+    },
+}
+
+/// The deep space terms of SDP4, after Vallado's revision of Spacetrack
+/// Report #3: the secular and long period pull of the sun and the moon,
+/// and resonances with the Earth's gravity field.
+#[derive(Debug, Clone)]
+pub struct DeepSpace {
+    solar: Periodics,
+    lunar: Periodics,
+    /// Secular rates of the eccentricity, inclination, mean anomaly,
+    /// argument of perigee and node.
+    dedt: f64,
+    didt: f64,
+    dmdt: f64,
+    domdt: f64,
+    dnodt: f64,
+    resonance: Resonance,
+    /// Greenwich sidereal time at the epoch.
+    gsto: f64,
+    argument_of_perigee: f64,
+    argpdot: f64,
+    /// Brouwer mean motion at the epoch.
+    mean_motion: f64,
+    /// The resonant angle at the epoch and its rate, less the mean motion.
+    xlamo: f64,
+    xfact: f64,
+}
+
+impl DeepSpace {
+    /// `elements` are at the epoch, with the near Earth secular rates of
+    /// the mean anomaly, argument of perigee and node.
+    pub fn new(
+        epoch: UtcTime,
+        elements: &MeanElements,
+        mdot: f64,
+        argpdot: f64,
+        nodedot: f64,
+        xke: f64,
+    ) -> Self {
+        let MeanElements {
+            eccentricity: em,
+            inclination: inclm,
+            right_ascension: nodeo,
+            argument_of_perigee: argpo,
+            mean_anomaly: mo,
+            mean_motion: no,
+        } = *elements;
+        let emsq = em * em;
+        let (sinim, cosim) = inclm.sin_cos();
+        let (snodm, cnodm) = nodeo.sin_cos();
+        let (sinomm, cosomm) = argpo.sin_cos();
+        let orbit = Orbit {
+            eccentricity: em,
+            sin_inclination: sinim,
+            cos_inclination: cosim,
+            sin_perigee: sinomm,
+            cos_perigee: cosomm,
+            mean_motion: no,
+        };
+
+        // Where the sun and the moon stand, in days from 1900 January 0.5
+        let day = epoch.julian_date() - 2_415_020.0;
+        let xnodce = (4.5236020 - 9.2422029e-4 * day) % TAU;
+        let (stem, ctem) = xnodce.sin_cos();
+        let zcosil = 0.91375164 - 0.03568096 * ctem;
+        let zsinil = (1.0 - zcosil * zcosil).sqrt();
+        let zsinhl = 0.089683511 * stem / zsinil;
+        let zcoshl = (1.0 - zsinhl * zsinhl).sqrt();
+        let gam = 5.8351514 + 0.0019443680 * day;
+        let zx = (0.39785416 * stem / zsinil).atan2(zcoshl * ctem + 0.91744867 * zsinhl * stem);
+        let zx = gam + zx - xnodce;
+
+        let solar = BodyTerms::new(
+            [0.1945905, -0.98088458, 0.91744867, 0.39785416, cnodm, snodm],
+            2.9864797e-6,
+            &orbit,
+        );
+        let lunar = BodyTerms::new(
+            [
+                zx.cos(),
+                zx.sin(),
+                zcosil,
+                zsinil,
+                zcoshl * cnodm + zsinhl * snodm,
+                snodm * zcoshl - cnodm * zsinhl,
+            ],
+            4.7968065e-7,
+            &orbit,
+        );
+        let zmol = (4.7199672 + 0.22997150 * day - gam) % TAU;
+        let zmos = (6.2565837 + 0.017201977 * day) % TAU;
+
+        // Secular rates, the node's undefined for equatorial orbits
+        let equatorial = !(EQUATORIAL_INCLINATION..=PI - EQUATORIAL_INCLINATION).contains(&inclm);
+        let mut rates = [0.0; 5];
+        let (mut domdt, mut dnodt) = (0.0, 0.0);
+        for (body, zn) in [(&solar, SOLAR_MEAN_MOTION), (&lunar, LUNAR_MEAN_MOTION)] {
+            let [de, di, dm, dgh, dh] = body.secular_rates(zn, emsq);
+            rates[0] += de;
+            rates[1] += di;
+            rates[2] += dm;
+            let mut dh = if equatorial { 0.0 } else { dh };
+            if sinim != 0.0 {
+                dh /= sinim;
+            }
+            domdt += dgh - cosim * dh;
+            dnodt += dh;
+        }
+        let [dedt, didt, dmdt, _, _] = rates;
+
+        let gsto = sun::greenwich_sidereal_time(epoch).0;
+        let aonv = (no / xke).powf(2.0 / 3.0);
+        let mut xlamo = 0.0;
+        let mut xfact = 0.0;
+        let resonance = if no < 0.0052359877 && no > 0.0034906585 {
+            let g200 = 1.0 + emsq * (-2.5 + 0.8125 * emsq);
+            let g310 = 1.0 + 2.0 * emsq;
+            let g300 = 1.0 + emsq * (-6.0 + 6.60937 * emsq);
+            let f220 = 0.75 * (1.0 + cosim) * (1.0 + cosim);
+            let f311 = 0.9375 * sinim * sinim * (1.0 + 3.0 * cosim) - 0.75 * (1.0 + cosim);
+            let f330 = 1.875 * (1.0 + cosim).powi(3);
+            let del1 = 3.0 * no * no * aonv * aonv;
+            xlamo = (mo + nodeo + argpo - gsto) % TAU;
+            xfact = mdot + argpdot + nodedot - EARTH_ROTATION + dmdt + domdt + dnodt - no;
+            Resonance::Synchronous {
+                del1: del1 * f311 * g310 * 2.1460748e-6 * aonv,
+                del2: 2.0 * del1 * f220 * g200 * 1.7891679e-6,
+                del3: 3.0 * del1 * f330 * g300 * 2.2123015e-7 * aonv,
+            }
+        } else if (8.26e-3..=9.24e-3).contains(&no) && em >= 0.5 {
+            let eoc = em * emsq;
+            let g201 = -0.306 - (em - 0.64) * 0.440;
+            let (g211, g310, g322, g410, g422, g520);
+            if em <= 0.65 {
+                g211 = 3.616 - 13.2470 * em + 16.2900 * emsq;
+                g310 = -19.302 + 117.3900 * em - 228.4190 * emsq + 156.5910 * eoc;
+                g322 = -18.9068 + 109.7927 * em - 214.6334 * emsq + 146.5816 * eoc;
+                g410 = -41.122 + 242.6940 * em - 471.0940 * emsq + 313.9530 * eoc;
+                g422 = -146.407 + 841.8800 * em - 1629.014 * emsq + 1083.4350 * eoc;
+                g520 = -532.114 + 3017.977 * em - 5740.032 * emsq + 3708.2760 * eoc;
+            } else {
+                g211 = -72.099 + 331.819 * em - 508.738 * emsq + 266.724 * eoc;
+                g310 = -346.844 + 1582.851 * em - 2415.925 * emsq + 1246.113 * eoc;
+                g322 = -342.585 + 1554.908 * em - 2366.899 * emsq + 1215.972 * eoc;
+                g410 = -1052.797 + 4758.686 * em - 7193.992 * emsq + 3651.957 * eoc;
+                g422 = -3581.690 + 16178.110 * em - 24462.770 * emsq + 12422.520 * eoc;
+                g520 = if em > 0.715 {
+                    -5149.66 + 29936.92 * em - 54087.36 * emsq + 31324.56 * eoc
+                } else {
+                    1464.74 - 4664.75 * em + 3763.64 * emsq
+                };
+            }
+            let (g533, g521, g532) = if em < 0.7 {
+                (
+                    -919.22770 + 4988.6100 * em - 9064.7700 * emsq + 5542.21 * eoc,
+                    -822.71072 + 4568.6173 * em - 8491.4146 * emsq + 5337.524 * eoc,
+                    -853.66600 + 4690.2500 * em - 8624.7700 * emsq + 5341.4 * eoc,
+                )
+            } else {
+                (
+                    -37995.780 + 161616.52 * em - 229838.20 * emsq + 109377.94 * eoc,
+                    -51752.104 + 218913.95 * em - 309468.16 * emsq + 146349.42 * eoc,
+                    -40023.880 + 170470.89 * em - 242699.48 * emsq + 115605.82 * eoc,
+                )
+            };
+
+            let cosisq = cosim * cosim;
+            let sini2 = sinim * sinim;
+            let f220 = 0.75 * (1.0 + 2.0 * cosim + cosisq);
+            let f221 = 1.5 * sini2;
+            let f321 = 1.875 * sinim * (1.0 - 2.0 * cosim - 3.0 * cosisq);
+            let f322 = -1.875 * sinim * (1.0 + 2.0 * cosim - 3.0 * cosisq);
+            let f441 = 35.0 * sini2 * f220;
+            let f442 = 39.3750 * sini2 * sini2;
+            let f522 = 9.84375
+                * sinim
+                * (sini2 * (1.0 - 2.0 * cosim - 5.0 * cosisq)
+                    + 0.33333333 * (-2.0 + 4.0 * cosim + 6.0 * cosisq));
+            let f523 = sinim
+                * (4.92187512 * sini2 * (-2.0 - 4.0 * cosim + 10.0 * cosisq)
+                    + 6.56250012 * (1.0 + 2.0 * cosim - 3.0 * cosisq));
+            let f542 = 29.53125
+                * sinim
+                * (2.0 - 8.0 * cosim + cosisq * (-12.0 + 8.0 * cosim + 10.0 * cosisq));
+            let f543 = 29.53125
+                * sinim
+                * (-2.0 - 8.0 * cosim + cosisq * (12.0 + 8.0 * cosim - 10.0 * cosisq));
+
+            let temp1 = 3.0 * no * no * aonv * aonv;
+            let temp = temp1 * 1.7891679e-6;
+            let (d2201, d2211) = (temp * f220 * g201, temp * f221 * g211);
+            let temp1 = temp1 * aonv;
+            let temp = temp1 * 3.7393792e-7;
+            let (d3210, d3222) = (temp * f321 * g310, temp * f322 * g322);
+            let temp1 = temp1 * aonv;
+            let temp = 2.0 * temp1 * 7.3636953e-9;
+            let (d4410, d4422) = (temp * f441 * g410, temp * f442 * g422);
+            let temp1 = temp1 * aonv;
+            let temp = temp1 * 1.1428639e-7;
+            let (d5220, d5232) = (temp * f522 * g520, temp * f523 * g532);
+            let temp = 2.0 * temp1 * 2.1765803e-9;
+            let (d5421, d5433) = (temp * f542 * g521, temp * f543 * g533);
+            xlamo = (mo + nodeo + nodeo - gsto - gsto) % TAU;
+            xfact = mdot + dmdt + 2.0 * (nodedot + dnodt - EARTH_ROTATION) - no;
+            Resonance::HalfDay {
+                d: [
+                    d2201, d2211, d3210, d3222, d4410, d4422, d5220, d5232, d5421, d5433,
+                ],
+            }
+        } else {
+            Resonance::None
+        };
+
+        Self {
+            solar: solar.periodics(zmos, SOLAR_MEAN_MOTION, SOLAR_ECCENTRICITY, emsq),
+            lunar: lunar.periodics(zmol, LUNAR_MEAN_MOTION, LUNAR_ECCENTRICITY, emsq),
+            dedt,
+            didt,
+            dmdt,
+            domdt,
+            dnodt,
+            resonance,
+            gsto,
+            argument_of_perigee: argpo,
+            argpdot,
+            mean_motion: no,
+            xlamo,
+            xfact,
+        }
+    }
+
+    /// Adds the secular effects of the sun, the moon and resonance over `t`
+    /// minutes to `elements`, which have the near Earth secular effects.
+    /// Resonant orbits are integrated from the epoch on every call.
+    pub fn secular(&self, t: f64, elements: &mut MeanElements) {
+        elements.eccentricity += self.dedt * t;
+        elements.inclination += self.didt * t;
+        elements.argument_of_perigee += self.domdt * t;
+        elements.right_ascension += self.dnodt * t;
+        elements.mean_anomaly += self.dmdt * t;
+
+        let theta = (self.gsto + t * EARTH_ROTATION) % TAU;
+        let (xl, mean_motion) = match self.resonance {
+            Resonance::None => return,
+            _ => self.integrate(t),
+        };
+        elements.mean_anomaly = match self.resonance {
+            Resonance::Synchronous { .. } => {
+                xl - elements.right_ascension - elements.argument_of_perigee + theta
+            }
+            _ => xl - 2.0 * elements.right_ascension + 2.0 * theta,
+        };
+        elements.mean_motion = mean_motion;
+    }
+
+    /// The resonant angle and the mean motion `t` minutes after the epoch,
+    /// stepping there with Euler-Maclaurin integration.
+    fn integrate(&self, t: f64) -> (f64, f64) {
+        let step = if t > 0.0 { STEP } else { -STEP };
+        let (mut atime, mut xli, mut xni) = (0.0, self.xlamo, self.mean_motion);
+        loop {
+            let (xndt, xnddt) = self.resonance_rates(atime, xli, xni);
+            let xldot = xni + self.xfact;
+            if (t - atime).abs() < STEP {
+                let ft = t - atime;
+                return (
+                    xli + xldot * ft + xndt * ft * ft * 0.5,
+                    xni + xndt * ft + xnddt * ft * ft * 0.5,
+                );
+            }
+            xli += xldot * step + xndt * STEP * STEP / 2.0;
+            xni += xndt * step + xnddt * STEP * STEP / 2.0;
+            atime += step;
+        }
+    }
+
+    /// The first and second derivatives of the mean motion `atime` minutes
+    /// after the epoch, at resonant angle `xli` and mean motion `xni`.
+    fn resonance_rates(&self, atime: f64, xli: f64, xni: f64) -> (f64, f64) {
+        let xldot = xni + self.xfact;
+        match self.resonance {
+            Resonance::None => (0.0, 0.0),
+            Resonance::Synchronous { del1, del2, del3 } => {
+                const FASX2: f64 = 0.13130908;
+                const FASX4: f64 = 2.8843198;
+                const FASX6: f64 = 0.37448087;
+                let xndt = del1 * (xli - FASX2).sin()
+                    + del2 * (2.0 * (xli - FASX4)).sin()
+                    + del3 * (3.0 * (xli - FASX6)).sin();
+                let xnddt = del1 * (xli - FASX2).cos()
+                    + 2.0 * del2 * (2.0 * (xli - FASX4)).cos()
+                    + 3.0 * del3 * (3.0 * (xli - FASX6)).cos();
+                (xndt, xnddt * xldot)
+            }
+            Resonance::HalfDay { d } => {
+                const G22: f64 = 5.7686396;
+                const G32: f64 = 0.95240898;
+                const G44: f64 = 1.8014998;
+                const G52: f64 = 1.0508330;
+                const G54: f64 = 4.4108898;
+                let [d2201, d2211, d3210, d3222, d4410, d4422, d5220, d5232, d5421, d5433] = d;
+                let xomi = self.argument_of_perigee + self.argpdot * atime;
+                let x2omi = xomi + xomi;
+                let x2li = xli + xli;
+                let xndt = d2201 * (x2omi + xli - G22).sin()
+                    + d2211 * (xli - G22).sin()
+                    + d3210 * (xomi + xli - G32).sin()
+                    + d3222 * (-xomi + xli - G32).sin()
+                    + d4410 * (x2omi + x2li - G44).sin()
+                    + d4422 * (x2li - G44).sin()
+                    + d5220 * (xomi + xli - G52).sin()
+                    + d5232 * (-xomi + xli - G52).sin()
+                    + d5421 * (xomi + x2li - G54).sin()
+                    + d5433 * (-xomi + x2li - G54).sin();
+                let xnddt = d2201 * (x2omi + xli - G22).cos()
+                    + d2211 * (xli - G22).cos()
+                    + d3210 * (xomi + xli - G32).cos()
+                    + d3222 * (-xomi + xli - G32).cos()
+                    + d5220 * (xomi + xli - G52).cos()
+                    + d5232 * (-xomi + xli - G52).cos()
+                    + 2.0
+                        * (d4410 * (x2omi + x2li - G44).cos()
+                            + d4422 * (x2li - G44).cos()
+                            + d5421 * (xomi + x2li - G54).cos()
+                            + d5433 * (-xomi + x2li - G54).cos());
+                (xndt, xnddt * xldot)
+            }
+        }
+    }
+
+    /// Adds the long period periodics of the sun and the moon `t` minutes
+    /// after the epoch to `elements`.
+    pub fn periodics(&self, t: f64, elements: &mut MeanElements) {
+        let solar = self.solar.at(t);
+        let lunar = self.lunar.at(t);
+        let [pe, pinc, pl, pgh, ph] = [0, 1, 2, 3, 4].map(|i| solar[i] + lunar[i]);
+
+        elements.inclination += pinc;
+        elements.eccentricity += pe;
+        let (sinip, cosip) = elements.inclination.sin_cos();
+        if elements.inclination >= LYDDANE_INCLINATION {
+            let ph = ph / sinip;
+            elements.argument_of_perigee += pgh - cosip * ph;
+            elements.right_ascension += ph;
+            elements.mean_anomaly += pl;
+        } else {
+            let (sinop, cosop) = elements.right_ascension.sin_cos();
+            let alfdp = sinip * sinop + ph * cosop + pinc * cosip * sinop;
+            let betdp = sinip * cosop - ph * sinop + pinc * cosip * cosop;
+            let xnoh = elements.right_ascension % TAU;
+            let xls =
+                elements.mean_anomaly + elements.argument_of_perigee + cosip * xnoh + pl + pgh
+                    - pinc * xnoh * sinip;
+            let mut nodep = alfdp.atan2(betdp);
+            if (xnoh - nodep).abs() > PI {
+                nodep += if nodep < xnoh { TAU } else { -TAU };
+            }
+            elements.mean_anomaly += pl;
+            elements.right_ascension = nodep;
+            elements.argument_of_perigee = xls - elements.mean_anomaly - cosip * nodep;
+        }
+
+        if elements.inclination < 0.0 {
+            elements.inclination = -elements.inclination;
+            elements.right_ascension += PI;
+            elements.argument_of_perigee -= PI;
+        }
+    }
+}
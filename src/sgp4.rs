@@ -0,0 +1,694 @@
+use cgmath::Vector3;
+use std::f64::consts::TAU;
+
+use crate::clock::UtcTime;
+use crate::sdp4::{DeepSpace, MeanElements};
+
+// WGS72, which the element sets are fitted against
+const EARTH_RADIUS_KM: f64 = 6378.135;
+const MU_KM3_S2: f64 = 398_600.8;
+const J2: f64 = 0.001_082_616;
+const J3: f64 = -0.000_002_538_81;
+const J4: f64 = -0.000_001_655_97;
+const J3_OVER_J2: f64 = J3 / J2;
+const TWO_THIRDS: f64 = 2.0 / 3.0;
+/// Orbits this long or longer are propagated with the deep space terms of
+/// SDP4.
+const DEEP_SPACE_PERIOD_MINUTES: f64 = 225.0;
+
+/// Square root of the gravitational parameter, in Earth radii and minutes.
+fn xke() -> f64 {
+    60.0 / (EARTH_RADIUS_KM.powi(3) / MU_KM3_S2).sqrt()
+}
+
+/// A two-line element set, angles in radians.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tle {
+    /// From the title line of three-line sets.
+    pub name: Option<String>,
+    pub catalog_number: u32,
+    pub epoch: UtcTime,
+    /// Drag term, in inverse Earth radii.
+    pub bstar: f64,
+    pub inclination: f64,
+    pub right_ascension: f64,
+    pub eccentricity: f64,
+    pub argument_of_perigee: f64,
+    pub mean_anomaly: f64,
+    /// Radians per minute.
+    pub mean_motion: f64,
+}
+
+impl Tle {
+    /// Parses the two lines of an element set, checking their checksums.
+    pub fn parse(name: Option<&str>, line1: &str, line2: &str) -> anyhow::Result<Self> {
+        let line1 = line1.trim_end();
+        let line2 = line2.trim_end();
+        check_line(line1, '1')?;
+        check_line(line2, '2')?;
+
+        let field = |line: &'static str, text: &str, columns: std::ops::Range<usize>| {
+            let value = text[columns.start - 1..columns.end].trim();
+            anyhow::ensure!(
+                !value.is_empty(),
+                "Line {} of a TLE has an empty field",
+                line
+            );
+            Ok(value.to_string())
+        };
+        let number = |line, text, columns| -> anyhow::Result<f64> {
+            Ok(field(line, text, columns)?.parse()?)
+        };
+        let angle =
+            |columns| -> anyhow::Result<f64> { Ok(number("2", line2, columns)?.to_radians()) };
+
+        let year = number("1", line1, 19..20)? as i64;
+        let year = if year < 57 { 2000 + year } else { 1900 + year };
+        let day_of_year = number("1", line1, 21..32)?;
+        let epoch = UtcTime::from_unix_seconds(
+            UtcTime::from_calendar(year, 1, 1, 0, 0, 0.0).unix_seconds
+                + (day_of_year - 1.0) * 86_400.0,
+        );
+
+        Ok(Self {
+            name: name.map(|name| name.trim().to_string()),
+            catalog_number: field("1", line1, 3..7)?.parse()?,
+            epoch,
+            bstar: parse_exponent(&line1[53..61])?,
+            inclination: angle(9..16)?,
+            right_ascension: angle(18..25)?,
+            eccentricity: format!("0.{}", field("2", line2, 27..33)?).parse()?,
+            argument_of_perigee: angle(35..42)?,
+            mean_anomaly: angle(44..51)?,
+            mean_motion: number("2", line2, 53..63)? * TAU / 1440.0,
+        })
+    }
+
+    /// The name, or the catalog number for sets without one.
+    pub fn label(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => format!("{:05}", self.catalog_number),
+        }
+    }
+
+    /// Whether the orbit takes 225 minutes or more, which [`Sgp4`]
+    /// propagates with the deep space terms of SDP4.
+    pub fn is_deep_space(&self) -> bool {
+        TAU / brouwer_mean_motion(self) >= DEEP_SPACE_PERIOD_MINUTES
+    }
+}
+
+/// Reads a file of element sets, with or without title lines.
+pub fn parse_tles(text: &str) -> anyhow::Result<Vec<Tle>> {
+    let lines: Vec<_> = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let mut tles = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let titled = !lines[i].starts_with("1 ");
+        let name = if titled {
+            // Some files mark title lines with a leading 0
+            Some(lines[i].strip_prefix("0 ").unwrap_or(lines[i]))
+        } else {
+            None
+        };
+        let first = i + titled as usize;
+        anyhow::ensure!(
+            first + 1 < lines.len(),
+            "A TLE is cut short at line {}",
+            first + 1
+        );
+        tles.push(Tle::parse(name, lines[first], lines[first + 1])?);
+        i = first + 2;
+    }
+    Ok(tles)
+}
+
+fn check_line(line: &str, number: char) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        line.len() == 69 && line.is_ascii() && line.starts_with(number),
+        "Not line {} of a TLE: {:?}",
+        number,
+        line
+    );
+    let sum: u32 = line[..68]
+        .chars()
+        .map(|c| match c {
+            '-' => 1,
+            c => c.to_digit(10).unwrap_or(0),
+        })
+        .sum();
+    anyhow::ensure!(
+        line[68..].parse::<u32>().ok() == Some(sum % 10),
+        "Bad checksum on line {} of a TLE: {:?}",
+        number,
+        line
+    );
+    Ok(())
+}
+
+/// Reads fields like ` 28098-4`, meaning 0.28098e-4.
+fn parse_exponent(text: &str) -> anyhow::Result<f64> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(0.0);
+    }
+    let split = text
+        .rfind(['-', '+'])
+        .filter(|&i| i > 0)
+        .ok_or_else(|| anyhow::anyhow!("Bad exponent field in a TLE: {:?}", text))?;
+    let (mantissa, exponent) = text.split_at(split);
+    let (sign, digits) = match mantissa.strip_prefix('-') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, mantissa.trim_start_matches('+')),
+    };
+    let mantissa: f64 = format!("0.{}", digits).parse()?;
+    Ok(sign * mantissa * 10f64.powi(exponent.parse()?))
+}
+
+/// Recovers the Brouwer mean motion from the Kozai mean motion in the
+/// element set.
+fn brouwer_mean_motion(tle: &Tle) -> f64 {
+    let omeosq = 1.0 - tle.eccentricity * tle.eccentricity;
+    let cosio = tle.inclination.cos();
+    let ak = (xke() / tle.mean_motion).powf(TWO_THIRDS);
+    let d1 = 0.75 * J2 * (3.0 * cosio * cosio - 1.0) / (omeosq.sqrt() * omeosq);
+    let del = d1 / (ak * ak);
+    let adel = ak * (1.0 - del * del - del * (1.0 / 3.0 + 134.0 * del * del / 81.0));
+    let del = d1 / (adel * adel);
+    tle.mean_motion / (1.0 + del)
+}
+
+/// The SGP4 propagator, after Vallado's revision of Spacetrack Report #3.
+/// Orbits of 225 minutes or more, like GNSS, Molniya and geostationary
+/// ones, get SDP4's deep space terms for the sun, the moon and resonance.
+#[derive(Debug, Clone)]
+pub struct Sgp4 {
+    pub epoch: UtcTime,
+    bstar: f64,
+    eccentricity: f64,
+    inclination: f64,
+    right_ascension: f64,
+    argument_of_perigee: f64,
+    mean_anomaly: f64,
+    /// Brouwer mean motion, radians per minute.
+    mean_motion: f64,
+    /// Drops the higher order drag terms for perigees under 220 km.
+    simple: bool,
+    aycof: f64,
+    con41: f64,
+    cc1: f64,
+    cc4: f64,
+    cc5: f64,
+    d2: f64,
+    d3: f64,
+    d4: f64,
+    delmo: f64,
+    eta: f64,
+    argpdot: f64,
+    omgcof: f64,
+    sinmao: f64,
+    t2cof: f64,
+    t3cof: f64,
+    t4cof: f64,
+    t5cof: f64,
+    x1mth2: f64,
+    x7thm1: f64,
+    mdot: f64,
+    nodedot: f64,
+    xlcof: f64,
+    xmcof: f64,
+    nodecf: f64,
+    deep_space: Option<Box<DeepSpace>>,
+}
+
+impl Sgp4 {
+    pub fn new(tle: &Tle) -> anyhow::Result<Self> {
+        let xke = xke();
+        let ecco = tle.eccentricity;
+        let inclo = tle.inclination;
+        let argpo = tle.argument_of_perigee;
+        let mo = tle.mean_anomaly;
+        let bstar = tle.bstar;
+
+        let eccsq = ecco * ecco;
+        let omeosq = 1.0 - eccsq;
+        let rteosq = omeosq.sqrt();
+        let cosio = inclo.cos();
+        let cosio2 = cosio * cosio;
+        let no = brouwer_mean_motion(tle);
+        anyhow::ensure!(
+            no > 0.0 && (0.0..1.0).contains(&ecco),
+            "TLE {} has no valid orbit",
+            tle.label()
+        );
+        let ao = (xke / no).powf(TWO_THIRDS);
+        let sinio = inclo.sin();
+        let po = ao * omeosq;
+        let con42 = 1.0 - 5.0 * cosio2;
+        let con41 = -con42 - 2.0 * cosio2;
+        let posq = po * po;
+        let rp = ao * (1.0 - ecco);
+
+        // The atmosphere's density falls off from s to q0, adjusted for low
+        // perigees
+        let ss = 78.0 / EARTH_RADIUS_KM + 1.0;
+        let qzms2t = ((120.0 - 78.0) / EARTH_RADIUS_KM).powi(4);
+        let deep = tle.is_deep_space();
+        let simple = deep || rp < 220.0 / EARTH_RADIUS_KM + 1.0;
+        let mut sfour = ss;
+        let mut qzms24 = qzms2t;
+        let perigee = (rp - 1.0) * EARTH_RADIUS_KM;
+        if perigee < 156.0 {
+            sfour = if perigee < 98.0 { 20.0 } else { perigee - 78.0 };
+            qzms24 = ((120.0 - sfour) / EARTH_RADIUS_KM).powi(4);
+            sfour = sfour / EARTH_RADIUS_KM + 1.0;
+        }
+
+        let pinvsq = 1.0 / posq;
+        let tsi = 1.0 / (ao - sfour);
+        let eta = ao * ecco * tsi;
+        let etasq = eta * eta;
+        let eeta = ecco * eta;
+        let psisq = (1.0 - etasq).abs();
+        let coef = qzms24 * tsi.powi(4);
+        let coef1 = coef / psisq.powf(3.5);
+        let cc2 = coef1
+            * no
+            * (ao * (1.0 + 1.5 * etasq + eeta * (4.0 + etasq))
+                + 0.375 * J2 * tsi / psisq * con41 * (8.0 + 3.0 * etasq * (8.0 + etasq)));
+        let cc1 = bstar * cc2;
+        let cc3 = if ecco > 1.0e-4 {
+            -2.0 * coef * tsi * J3_OVER_J2 * no * sinio / ecco
+        } else {
+            0.0
+        };
+        let x1mth2 = 1.0 - cosio2;
+        let cc4 = 2.0
+            * no
+            * coef1
+            * ao
+            * omeosq
+            * (eta * (2.0 + 0.5 * etasq) + ecco * (0.5 + 2.0 * etasq)
+                - J2 * tsi / (ao * psisq)
+                    * (-3.0 * con41 * (1.0 - 2.0 * eeta + etasq * (1.5 - 0.5 * eeta))
+                        + 0.75
+                            * x1mth2
+                            * (2.0 * etasq - eeta * (1.0 + etasq))
+                            * (2.0 * argpo).cos()));
+        let cc5 = 2.0 * coef1 * ao * omeosq * (1.0 + 2.75 * (etasq + eeta) + eeta * etasq);
+
+        // Secular rates from the zonal harmonics
+        let cosio4 = cosio2 * cosio2;
+        let temp1 = 1.5 * J2 * pinvsq * no;
+        let temp2 = 0.5 * temp1 * J2 * pinvsq;
+        let temp3 = -0.46875 * J4 * pinvsq * pinvsq * no;
+        let mdot = no
+            + 0.5 * temp1 * rteosq * con41
+            + 0.0625 * temp2 * rteosq * (13.0 - 78.0 * cosio2 + 137.0 * cosio4);
+        let argpdot = -0.5 * temp1 * con42
+            + 0.0625 * temp2 * (7.0 - 114.0 * cosio2 + 395.0 * cosio4)
+            + temp3 * (3.0 - 36.0 * cosio2 + 49.0 * cosio4);
+        let xhdot1 = -temp1 * cosio;
+        let nodedot = xhdot1
+            + (0.5 * temp2 * (4.0 - 19.0 * cosio2) + 2.0 * temp3 * (3.0 - 7.0 * cosio2)) * cosio;
+
+        let deep_space = deep.then(|| {
+            let elements = MeanElements {
+                eccentricity: ecco,
+                inclination: inclo,
+                right_ascension: tle.right_ascension,
+                argument_of_perigee: argpo,
+                mean_anomaly: mo,
+                mean_motion: no,
+            };
+            Box::new(DeepSpace::new(
+                tle.epoch, &elements, mdot, argpdot, nodedot, xke,
+            ))
+        });
+
+        let omgcof = bstar * cc3 * argpo.cos();
+        let xmcof = if ecco > 1.0e-4 {
+            -TWO_THIRDS * coef * bstar / eeta
+        } else {
+            0.0
+        };
+        let nodecf = 3.5 * omeosq * xhdot1 * cc1;
+        let t2cof = 1.5 * cc1;
+        // Avoid dividing by zero for retrograde equatorial orbits
+        let xlcof =
+            -0.25 * J3_OVER_J2 * sinio * (3.0 + 5.0 * cosio) / (1.0 + cosio).abs().max(1.5e-12);
+        let aycof = -0.5 * J3_OVER_J2 * sinio;
+        let delmo = (1.0 + eta * mo.cos()).powi(3);
+
+        let (mut d2, mut d3, mut d4) = (0.0, 0.0, 0.0);
+        let (mut t3cof, mut t4cof, mut t5cof) = (0.0, 0.0, 0.0);
+        if !simple {
+            let cc1sq = cc1 * cc1;
+            d2 = 4.0 * ao * tsi * cc1sq;
+            let temp = d2 * tsi * cc1 / 3.0;
+            d3 = (17.0 * ao + sfour) * temp;
+            d4 = 0.5 * temp * ao * tsi * (221.0 * ao + 31.0 * sfour) * cc1;
+            t3cof = d2 + 2.0 * cc1sq;
+            t4cof = 0.25 * (3.0 * d3 + cc1 * (12.0 * d2 + 10.0 * cc1sq));
+            t5cof = 0.2
+                * (3.0 * d4 + 12.0 * cc1 * d3 + 6.0 * d2 * d2 + 15.0 * cc1sq * (2.0 * d2 + cc1sq));
+        }
+
+        Ok(Self {
+            epoch: tle.epoch,
+            bstar,
+            eccentricity: ecco,
+            inclination: inclo,
+            right_ascension: tle.right_ascension,
+            argument_of_perigee: argpo,
+            mean_anomaly: mo,
+            mean_motion: no,
+            simple,
+            aycof,
+            con41,
+            cc1,
+            cc4,
+            cc5,
+            d2,
+            d3,
+            d4,
+            delmo,
+            eta,
+            argpdot,
+            omgcof,
+            sinmao: mo.sin(),
+            t2cof,
+            t3cof,
+            t4cof,
+            t5cof,
+            x1mth2,
+            x7thm1: 7.0 * cosio2 - 1.0,
+            mdot,
+            nodedot,
+            xlcof,
+            xmcof,
+            nodecf,
+            deep_space,
+        })
+    }
+
+    /// Minutes per revolution.
+    pub fn period(&self) -> f64 {
+        TAU / self.mean_motion
+    }
+
+    /// Position in km and velocity in km/s in the TEME frame, `minutes`
+    /// after the epoch. Fails once the orbit has decayed.
+    pub fn propagate(&self, minutes: f64) -> anyhow::Result<(Vector3<f64>, Vector3<f64>)> {
+        let xke = xke();
+        let t = minutes;
+
+        // Secular gravity and drag
+        let xmdf = self.mean_anomaly + self.mdot * t;
+        let argpdf = self.argument_of_perigee + self.argpdot * t;
+        let nodedf = self.right_ascension + self.nodedot * t;
+        let mut argpm = argpdf;
+        let mut mm = xmdf;
+        let t2 = t * t;
+        let mut nodem = nodedf + self.nodecf * t2;
+        let mut tempa = 1.0 - self.cc1 * t;
+        let mut tempe = self.bstar * self.cc4 * t;
+        let mut templ = self.t2cof * t2;
+        if !self.simple {
+            let delomg = self.omgcof * t;
+            let delm = self.xmcof * ((1.0 + self.eta * xmdf.cos()).powi(3) - self.delmo);
+            let temp = delomg + delm;
+            mm = xmdf + temp;
+            argpm = argpdf - temp;
+            let t3 = t2 * t;
+            let t4 = t3 * t;
+            tempa -= self.d2 * t2 + self.d3 * t3 + self.d4 * t4;
+            tempe += self.bstar * self.cc5 * (mm.sin() - self.sinmao);
+            templ += self.t3cof * t3 + t4 * (self.t4cof + t * self.t5cof);
+        }
+
+        // The sun, the moon and resonance
+        let mut mean = MeanElements {
+            eccentricity: self.eccentricity,
+            inclination: self.inclination,
+            right_ascension: nodem,
+            argument_of_perigee: argpm,
+            mean_anomaly: mm,
+            mean_motion: self.mean_motion,
+        };
+        if let Some(deep_space) = &self.deep_space {
+            deep_space.secular(t, &mut mean);
+            anyhow::ensure!(
+                mean.mean_motion > 0.0,
+                "The orbit's mean motion went negative"
+            );
+            nodem = mean.right_ascension;
+            argpm = mean.argument_of_perigee;
+            mm = mean.mean_anomaly;
+        }
+
+        let am = (xke / mean.mean_motion).powf(TWO_THIRDS) * tempa * tempa;
+        let nm = xke / am.powf(1.5);
+        let em = mean.eccentricity - tempe;
+        anyhow::ensure!(
+            (-0.001..1.0).contains(&em),
+            "The orbit's eccentricity left its bounds"
+        );
+        let em = em.max(1.0e-6);
+        mm += self.mean_motion * templ;
+        let xlm = mm + argpm + nodem;
+        let nodem = nodem.rem_euclid(TAU);
+        let argpm = argpm.rem_euclid(TAU);
+        let xlm = xlm.rem_euclid(TAU);
+        mm = (xlm - argpm - nodem).rem_euclid(TAU);
+
+        // Long period periodics
+        let mut periodic = MeanElements {
+            eccentricity: em,
+            inclination: mean.inclination,
+            right_ascension: nodem,
+            argument_of_perigee: argpm,
+            mean_anomaly: mm,
+            mean_motion: nm,
+        };
+        let (mut aycof, mut xlcof) = (self.aycof, self.xlcof);
+        let (mut con41, mut x1mth2, mut x7thm1) = (self.con41, self.x1mth2, self.x7thm1);
+        if let Some(deep_space) = &self.deep_space {
+            deep_space.periodics(t, &mut periodic);
+            anyhow::ensure!(
+                (0.0..=1.0).contains(&periodic.eccentricity),
+                "The orbit's eccentricity left its bounds"
+            );
+            let (sinip, cosip) = periodic.inclination.sin_cos();
+            aycof = -0.5 * J3_OVER_J2 * sinip;
+            xlcof =
+                -0.25 * J3_OVER_J2 * sinip * (3.0 + 5.0 * cosip) / (1.0 + cosip).abs().max(1.5e-12);
+            let cosisq = cosip * cosip;
+            con41 = 3.0 * cosisq - 1.0;
+            x1mth2 = 1.0 - cosisq;
+            x7thm1 = 7.0 * cosisq - 1.0;
+        }
+        let MeanElements {
+            eccentricity: em,
+            inclination: inclp,
+            right_ascension: nodem,
+            argument_of_perigee: argpm,
+            mean_anomaly: mm,
+            ..
+        } = periodic;
+        let (sinip, cosip) = inclp.sin_cos();
+        let axnl = em * argpm.cos();
+        let temp = 1.0 / (am * (1.0 - em * em));
+        let aynl = em * argpm.sin() + temp * aycof;
+        let xl = mm + argpm + nodem + temp * xlcof * axnl;
+
+        // Kepler's equation
+        let u = (xl - nodem).rem_euclid(TAU);
+        let mut eo1 = u;
+        let (mut sineo1, mut coseo1) = (0.0, 0.0);
+        for _ in 0..10 {
+            sineo1 = eo1.sin();
+            coseo1 = eo1.cos();
+            let step =
+                (u - aynl * coseo1 + axnl * sineo1 - eo1) / (1.0 - coseo1 * axnl - sineo1 * aynl);
+            eo1 += step.clamp(-0.95, 0.95);
+            if step.abs() < 1.0e-12 {
+                break;
+            }
+        }
+
+        // Short period periodics
+        let ecose = axnl * coseo1 + aynl * sineo1;
+        let esine = axnl * sineo1 - aynl * coseo1;
+        let el2 = axnl * axnl + aynl * aynl;
+        let pl = am * (1.0 - el2);
+        anyhow::ensure!(pl >= 0.0, "The orbit's semi-latus rectum went negative");
+        let rl = am * (1.0 - ecose);
+        let rdotl = am.sqrt() * esine / rl;
+        let rvdotl = pl.sqrt() / rl;
+        let betal = (1.0 - el2).sqrt();
+        let temp = esine / (1.0 + betal);
+        let sinu = am / rl * (sineo1 - aynl - axnl * temp);
+        let cosu = am / rl * (coseo1 - axnl + aynl * temp);
+        let su = sinu.atan2(cosu);
+        let sin2u = (cosu + cosu) * sinu;
+        let cos2u = 1.0 - 2.0 * sinu * sinu;
+        let temp = 1.0 / pl;
+        let temp1 = 0.5 * J2 * temp;
+        let temp2 = temp1 * temp;
+
+        let mrt = rl * (1.0 - 1.5 * temp2 * betal * con41) + 0.5 * temp1 * x1mth2 * cos2u;
+        let su = su - 0.25 * temp2 * x7thm1 * sin2u;
+        let xnode = nodem + 1.5 * temp2 * cosip * sin2u;
+        let xinc = inclp + 1.5 * temp2 * cosip * sinip * cos2u;
+        let mvt = rdotl - nm * temp1 * x1mth2 * sin2u / xke;
+        let rvdot = rvdotl + nm * temp1 * (x1mth2 * cos2u + 1.5 * con41) / xke;
+        anyhow::ensure!(mrt >= 1.0, "The satellite has decayed");
+
+        // Orient the orbit
+        let (sinsu, cossu) = su.sin_cos();
+        let (snod, cnod) = xnode.sin_cos();
+        let (sini, cosi) = xinc.sin_cos();
+        let xmx = -snod * cosi;
+        let xmy = cnod * cosi;
+        let u = Vector3::new(
+            xmx * sinsu + cnod * cossu,
+            xmy * sinsu + snod * cossu,
+            sini * sinsu,
+        );
+        let v = Vector3::new(
+            xmx * cossu - cnod * sinsu,
+            xmy * cossu - snod * sinsu,
+            sini * cossu,
+        );
+        let km_per_second = EARTH_RADIUS_KM * xke / 60.0;
+        Ok((
+            u * (mrt * EARTH_RADIUS_KM),
+            (u * mvt + v * rvdot) * km_per_second,
+        ))
+    }
+
+    /// Position in km and velocity in km/s in the TEME frame at `time`.
+    pub fn propagate_to(&self, time: UtcTime) -> anyhow::Result<(Vector3<f64>, Vector3<f64>)> {
+        self.propagate((time.unix_seconds - self.epoch.unix_seconds) / 60.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::prelude::*;
+
+    // Vanguard 1, the first case of the SGP4 verification set
+    const VANGUARD: &str = "\
+1 00005U 58002B   00179.78495062  .00000023  00000-0  28098-4 0  4753
+2 00005  34.2682 348.7242 1859667 331.7664  19.3264 10.82419157413667";
+
+    #[test]
+    fn element_sets_parse_with_and_without_titles() {
+        let tle = &parse_tles(VANGUARD).unwrap()[0];
+        assert_eq!(tle.catalog_number, 5);
+        assert_eq!(tle.label(), "00005");
+        assert_eq!(tle.epoch.to_string(), "2000-06-27T18:50:19Z");
+        assert!((tle.bstar - 2.8098e-5).abs() < 1e-12);
+        assert!((tle.eccentricity - 0.1859667).abs() < 1e-12);
+        assert!((tle.mean_motion * 1440.0 / TAU - 10.82419157).abs() < 1e-8);
+
+        let titled = format!("VANGUARD 1\n{}\n0 VANGUARD 1\n{}\n", VANGUARD, VANGUARD);
+        let tles = parse_tles(&titled).unwrap();
+        assert_eq!(tles.len(), 2);
+        assert_eq!(tles[1].name.as_deref(), Some("VANGUARD 1"));
+
+        // A digit changed without fixing the checksum
+        assert!(parse_tles(&VANGUARD.replace("34.2682", "34.2683")).is_err());
+        assert!(parse_tles(&VANGUARD[..70]).is_err());
+    }
+
+    #[test]
+    fn vanguard_propagates_as_in_the_verification_set() {
+        let sgp4 = Sgp4::new(&parse_tles(VANGUARD).unwrap()[0]).unwrap();
+        let cases = [
+            (
+                0.0,
+                [7022.46529266, -1400.08296755, 0.03995155],
+                [1.893841015, 6.405893759, 4.534807250],
+            ),
+            (
+                360.0,
+                [-7154.03120202, -3783.17682504, -3536.19412294],
+                [4.741887409, -4.151817765, -2.093935425],
+            ),
+        ];
+        for (minutes, position, velocity) in cases {
+            let (r, v) = sgp4.propagate(minutes).unwrap();
+            assert!((r - Vector3::from(position)).magnitude() < 1e-3, "{:?}", r);
+            assert!((v - Vector3::from(velocity)).magnitude() < 1e-6, "{:?}", v);
+        }
+    }
+
+    #[test]
+    fn deep_space_orbits_propagate_with_sdp4() {
+        // A Molniya orbit, resonant over half a day, from the verification set
+        let molniya = Tle::parse(
+            None,
+            "1 08195U 75081A   06176.33215444  .00000099  00000-0  11873-3 0   813",
+            "2 08195  64.1586 279.0717 6877146 264.7651  20.2257  2.00491383225656",
+        )
+        .unwrap();
+        assert!(molniya.is_deep_space());
+        let sgp4 = Sgp4::new(&molniya).unwrap();
+        let cases = [
+            (
+                0.0,
+                [2349.89483350, -14785.93811562, 0.02119378],
+                [2.721488096, -3.256811655, 4.498416672],
+            ),
+            (
+                120.0,
+                [15223.91713658, -17852.95881713, 25280.39558224],
+                [1.079041732, 0.875187372, 2.485682813],
+            ),
+        ];
+        for (minutes, position, velocity) in cases {
+            let (r, v) = sgp4.propagate(minutes).unwrap();
+            assert!((r - Vector3::from(position)).magnitude() < 1e-3, "{:?}", r);
+            assert!((v - Vector3::from(velocity)).magnitude() < 1e-6, "{:?}", v);
+        }
+
+        // A geostationary satellite, resonant over a day, stays near the
+        // geostationary radius and drifts slowly over the Earth
+        let geostationary = Tle::parse(
+            None,
+            "1 24208U 96044A   06177.04061740 -.00000094  00000-0  10000-3 0  1600",
+            "2 24208   3.8536  80.0121 0026640 311.0977  48.3000  1.00778054 36119",
+        )
+        .unwrap();
+        let sgp4 = Sgp4::new(&geostationary).unwrap();
+        let fixed = |minutes: f64| {
+            let (r, _) = sgp4.propagate(minutes).unwrap();
+            let time = UtcTime::from_unix_seconds(sgp4.epoch.unix_seconds + minutes * 60.0);
+            crate::sun::inertial_to_ecef(time) * r
+        };
+        for minutes in [0.0, 720.0, 1440.0, 14400.0] {
+            assert!((fixed(minutes).magnitude() - 42_000.0).abs() < 300.0);
+        }
+        assert!((fixed(1440.0) - fixed(0.0)).magnitude() < 2000.0);
+
+        // GPS, which isn't resonant
+        let gps = Tle::parse(
+            None,
+            "1 28129U 03058A   06175.57071136 -.00000104  00000-0  10000-3 0   459",
+            "2 28129  54.7298 324.8098 0048506 266.2640  93.1663  2.00562768 18443",
+        )
+        .unwrap();
+        let sgp4 = Sgp4::new(&gps).unwrap();
+        for minutes in [0.0, 360.0, 1440.0] {
+            let (r, _) = sgp4.propagate(minutes).unwrap();
+            assert!((r.magnitude() - 26_560.0).abs() < 200.0, "{:?}", r);
+        }
+    }
+}
@@ -37,6 +37,8 @@ struct VertexOutput {
     [[location(1)]] tangent_position: vec3<f32>;
     [[location(2)]] tangent_light_position: vec3<f32>;
     [[location(3)]] tangent_view_position: vec3<f32>;
+    // Relative to the camera, for the haze in front of it
+    [[location(4)]] world_position: vec3<f32>;
 };
 
 [[stage(vertex)]]
@@ -74,6 +76,7 @@ fn vs_main(
     out.tangent_position = tangent_matrix * world_position.xyz;
     out.tangent_view_position = tangent_matrix * camera.view_pos.xyz;
     out.tangent_light_position = tangent_matrix * light.position;
+    out.world_position = world_position.xyz;
     return out;
 }
 
@@ -88,8 +91,8 @@ var t_normal: texture_2d<f32>;
 [[group(0), binding(3)]]
 var s_normal: sampler;
 
-[[stage(fragment)]]
-fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+// Lights a fragment, adding `emission` that glows whether lit or not
+fn shade(in: VertexOutput, emission: vec3<f32>) -> vec4<f32> {
     let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
     let object_normal: vec4<f32> = textureSample(t_normal, s_normal, in.tex_coords);
     
@@ -103,13 +106,53 @@ fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
     let view_dir = normalize(in.tangent_view_position - in.tangent_position);
     let half_dir = normalize(view_dir + light_dir);
 
+    // Sunlight reddens and fades as it comes through more air
+    let camera_position = atmosphere.camera_position.xyz;
+    let position = camera_position + squash(in.world_position);
+    let r = length(position);
+    let sunlight = light.color
+        * sun_transmittance(r, dot(position, atmosphere.sun_direction.xyz) / r);
+
     let diffuse_strength = max(dot(tangent_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
+    let diffuse_color = sunlight * diffuse_strength;
 
     let specular_strength = pow(max(dot(tangent_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+    let specular_color = specular_strength * sunlight;
+
+    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz + emission;
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+    let haze = aerial_perspective(camera_position, position);
+    return vec4<f32>(result * haze.transmittance + haze.in_scattering, object_color.a);
+}
 
-    return vec4<f32>(result, object_color.a);
-}
\ No newline at end of file
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return shade(in, vec3<f32>(0.0));
+}
+
+// The globe, with city lights on its night side
+
+[[group(2), binding(1)]]
+var t_night: texture_2d<f32>;
+[[group(2), binding(2)]]
+var s_night: sampler;
+
+let WGS84_E2: f32 = 0.00669438;
+
+[[stage(fragment)]]
+fn fs_globe(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let squashed = atmosphere.camera_position.xyz + squash(in.world_position);
+    let position = vec3<f32>(squashed.xy, squashed.z / ELLIPSOID_SQUASH);
+    // The lights are an equirectangular image
+    let longitude = atan2(position.y, position.x);
+    let latitude = atan2(position.z, (1.0 - WGS84_E2) * length(position.xy));
+    let uv = vec2<f32>(
+        longitude / (2.0 * ATMOSPHERE_PI) + 0.5,
+        0.5 - latitude / ATMOSPHERE_PI,
+    );
+    let night_lights = textureSample(t_night, s_night, uv).rgb;
+    // They come on through dusk, as the sun sinks below the horizon
+    let mu_s = dot(normalize(squashed), atmosphere.sun_direction.xyz);
+    let night = 1.0 - smoothStep(-0.1, 0.0, mu_s);
+    return shade(in, night_lights * night);
+}
@@ -0,0 +1,800 @@
+use anyhow::{anyhow, bail, Context};
+use serde_json::Value;
+
+use crate::geodesy::{self, Geodetic};
+use crate::vector::{Feature, Geometry, Path, Properties};
+
+/// Reads the features of an ESRI Shapefile: the geometry from `shp`, the
+/// attributes of each record from `dbf` and the coordinate system from the
+/// WKT in `prj`. Without a `.prj` coordinates are taken as longitudes and
+/// latitudes in degrees.
+///
+/// Null shapes and records marked as deleted are skipped. Features are
+/// numbered from 1 in their `id`, as the records are.
+pub fn parse(shp: &[u8], dbf: Option<&[u8]>, prj: Option<&str>) -> anyhow::Result<Vec<Feature>> {
+    let projection = match prj {
+        Some(wkt) => Projection::from_wkt(wkt).context("reading the .prj")?,
+        None => Projection::Geographic {
+            radians_per_unit: 1f64.to_radians(),
+        },
+    };
+    let records = match dbf {
+        Some(dbf) => Some(parse_dbf(dbf).context("reading the .dbf")?),
+        None => None,
+    };
+
+    let mut reader = Reader::new(shp);
+    if reader.i32_be()? != 9994 {
+        bail!("not a shapefile");
+    }
+    reader.take(96)?;
+
+    let mut features = Vec::new();
+    let mut index = 0;
+    while !reader.is_empty() {
+        let number = reader.i32_be()?;
+        let length = reader.i32_be()? as usize * 2;
+        let content = reader.take(length)?;
+        let record = records.as_ref().and_then(|records| records.get(index));
+        index += 1;
+
+        let geometry = parse_shape(content, &projection)
+            .with_context(|| format!("reading shape {}", number))?;
+        let properties = match record {
+            Some(Some(properties)) => properties.clone(),
+            // Deleted
+            Some(None) => continue,
+            None => Properties::new(),
+        };
+        if let Some(geometry) = geometry {
+            features.push(Feature {
+                id: Some(Value::from(number)),
+                geometry,
+                properties,
+            });
+        }
+    }
+    Ok(features)
+}
+
+/// Reads `path` with the `.dbf` and `.prj` files next to it.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read(path: &std::path::Path) -> anyhow::Result<Vec<Feature>> {
+    let shp = std::fs::read(path)?;
+    let dbf = std::fs::read(path.with_extension("dbf")).ok();
+    let prj = std::fs::read_to_string(path.with_extension("prj")).ok();
+    parse(&shp, dbf.as_deref(), prj.as_deref())
+}
+
+fn parse_shape(content: &[u8], projection: &Projection) -> anyhow::Result<Option<Geometry>> {
+    let mut reader = Reader::new(content);
+    let shape_type = reader.i32_le()?;
+    // PointZ and friends carry their heights after the x and y values
+    let has_z = matches!(shape_type, 11 | 13 | 15 | 18);
+    Ok(match shape_type {
+        0 => None,
+        // Point, PointZ, PointM
+        1 | 11 | 21 => {
+            let (x, y) = (reader.f64_le()?, reader.f64_le()?);
+            let z = if has_z { reader.f64_le()? } else { 0.0 };
+            Some(Geometry::Point(projection.to_geodetic(x, y, z)))
+        }
+        // MultiPoint, MultiPointZ, MultiPointM
+        8 | 18 | 28 => {
+            reader.take(32)?;
+            let count = reader.count()?;
+            let points = read_points(&mut reader, count, has_z, projection)?;
+            Some(Geometry::MultiPoint(points))
+        }
+        // PolyLine, Polygon and their Z and M variants
+        3 | 5 | 13 | 15 | 23 | 25 => {
+            reader.take(32)?;
+            let part_count = reader.count()?;
+            let point_count = reader.count()?;
+            let mut starts = (0..part_count)
+                .map(|_| Ok(reader.i32_le()? as usize))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let planar = (0..point_count)
+                .map(|_| Ok((reader.f64_le()?, reader.f64_le()?)))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            let heights = if has_z {
+                reader.take(16)?;
+                (0..point_count)
+                    .map(|_| reader.f64_le())
+                    .collect::<anyhow::Result<Vec<_>>>()?
+            } else {
+                vec![0.0; point_count]
+            };
+
+            starts.push(point_count);
+            if starts.windows(2).any(|pair| pair[0] > pair[1]) || starts[0] > point_count {
+                bail!("parts out of order");
+            }
+            let parts = starts
+                .windows(2)
+                .map(|pair| pair[0]..pair[1])
+                .filter(|range| !range.is_empty())
+                .collect::<Vec<_>>();
+            let to_path = |range: &std::ops::Range<usize>| -> Path {
+                range
+                    .clone()
+                    .map(|i| projection.to_geodetic(planar[i].0, planar[i].1, heights[i]))
+                    .collect()
+            };
+
+            if matches!(shape_type, 3 | 13 | 23) {
+                let mut lines = parts.iter().map(to_path).collect::<Vec<_>>();
+                match lines.len() {
+                    0 => None,
+                    1 => lines.pop().map(Geometry::LineString),
+                    _ => Some(Geometry::MultiLineString(lines)),
+                }
+            } else {
+                let mut polygons = group_rings(&planar, &parts)
+                    .into_iter()
+                    .map(|rings| {
+                        rings
+                            .iter()
+                            .map(|ring| {
+                                let mut path = to_path(ring);
+                                if path.len() > 1 && path.first() == path.last() {
+                                    path.pop();
+                                }
+                                path
+                            })
+                            .collect::<Vec<_>>()
+                    })
+                    .collect::<Vec<_>>();
+                match polygons.len() {
+                    0 => None,
+                    1 => polygons.pop().map(Geometry::Polygon),
+                    _ => Some(Geometry::MultiPolygon(polygons)),
+                }
+            }
+        }
+        other => {
+            log::warn!("Skipping unsupported shape type {}", other);
+            None
+        }
+    })
+}
+
+fn read_points(
+    reader: &mut Reader,
+    count: usize,
+    has_z: bool,
+    projection: &Projection,
+) -> anyhow::Result<Vec<Geodetic>> {
+    let planar = (0..count)
+        .map(|_| Ok((reader.f64_le()?, reader.f64_le()?)))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut heights = vec![0.0; count];
+    if has_z {
+        reader.take(16)?;
+        for height in &mut heights {
+            *height = reader.f64_le()?;
+        }
+    }
+    Ok(planar
+        .iter()
+        .zip(heights)
+        .map(|(&(x, y), z)| projection.to_geodetic(x, y, z))
+        .collect())
+}
+
+/// Twice the signed area of a ring, positive when it runs counterclockwise.
+fn signed_area(points: &[(f64, f64)]) -> f64 {
+    (0..points.len())
+        .map(|i| {
+            let (a, b) = (points[i], points[(i + 1) % points.len()]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum()
+}
+
+fn contains(ring: &[(f64, f64)], (x, y): (f64, f64)) -> bool {
+    let mut inside = false;
+    for i in 0..ring.len() {
+        let (a, b) = (ring[i], ring[(i + 1) % ring.len()]);
+        if (a.1 > y) != (b.1 > y) && x < a.0 + (y - a.1) / (b.1 - a.1) * (b.0 - a.0) {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+/// Sorts the rings of a polygon shape into polygons, each an outer ring
+/// followed by its holes. Outer rings run clockwise and holes
+/// counterclockwise; a hole belongs to the outer ring containing it.
+fn group_rings(
+    points: &[(f64, f64)],
+    parts: &[std::ops::Range<usize>],
+) -> Vec<Vec<std::ops::Range<usize>>> {
+    let (outer, holes): (Vec<_>, Vec<_>) = parts
+        .iter()
+        .cloned()
+        .partition(|ring| signed_area(&points[ring.clone()]) <= 0.0);
+    if outer.is_empty() {
+        // Written with the wrong orientation, so there's no telling holes
+        // apart
+        return holes.into_iter().map(|ring| vec![ring]).collect();
+    }
+
+    let mut polygons = outer.into_iter().map(|ring| vec![ring]).collect::<Vec<_>>();
+    for hole in holes {
+        let point = points[hole.start];
+        let owner = polygons
+            .iter()
+            .position(|polygon| contains(&points[polygon[0].clone()], point))
+            .unwrap_or(polygons.len() - 1);
+        polygons[owner].push(hole);
+    }
+    polygons
+}
+
+/// Reads the records of a dBASE table, `None` for those marked as deleted.
+fn parse_dbf(data: &[u8]) -> anyhow::Result<Vec<Option<Properties>>> {
+    struct Field {
+        name: String,
+        kind: u8,
+        length: usize,
+    }
+
+    let mut reader = Reader::new(data);
+    reader.take(4)?;
+    let record_count = reader.u32_le()? as usize;
+    let header_length = reader.u16_le()? as usize;
+    let record_length = reader.u16_le()? as usize;
+    reader.take(20)?;
+
+    let mut fields = Vec::new();
+    while reader.offset + 32 <= header_length {
+        let descriptor = reader.take(32)?;
+        if descriptor[0] == 0x0d {
+            break;
+        }
+        let name = &descriptor[..11];
+        let name = &name[..name.iter().position(|&b| b == 0).unwrap_or(11)];
+        fields.push(Field {
+            name: decode_text(name),
+            kind: descriptor[11],
+            length: descriptor[16] as usize,
+        });
+    }
+
+    // Records start with their deletion flag, followed by the fields
+    let fields_length = 1 + fields.iter().map(|field| field.length).sum::<usize>();
+    if record_length < fields_length {
+        bail!(
+            "dBASE records of {} bytes can't hold {} bytes of fields",
+            record_length,
+            fields_length
+        );
+    }
+
+    let mut reader = Reader::new(data);
+    reader.take(header_length)?;
+    let mut records = Vec::with_capacity(record_count.min(data.len() / record_length));
+    for _ in 0..record_count {
+        let record = reader.take(record_length)?;
+        if record[0] == b'*' {
+            records.push(None);
+            continue;
+        }
+        let mut properties = Properties::new();
+        let mut offset = 1;
+        for field in &fields {
+            let raw = &record[offset..offset + field.length];
+            offset += field.length;
+            let text = decode_text(raw);
+            let text = text.trim();
+            let value = match field.kind {
+                b'N' | b'F' => match text.parse::<f64>() {
+                    // Integral values stay integers
+                    Ok(number) if !text.contains('.') && number.abs() < 2f64.powi(53) => {
+                        Value::from(number as i64)
+                    }
+                    Ok(number) => Value::from(number),
+                    Err(_) => Value::Null,
+                },
+                b'L' => match text {
+                    "T" | "t" | "Y" | "y" => Value::from(true),
+                    "F" | "f" | "N" | "n" => Value::from(false),
+                    _ => Value::Null,
+                },
+                _ => Value::from(text),
+            };
+            properties.insert(field.name.clone(), value);
+        }
+        records.push(Some(properties));
+    }
+    Ok(records)
+}
+
+/// dBASE files carry no reliable encoding, so text that isn't UTF-8 is read
+/// as Latin-1. Either way NUL padding is left out.
+fn decode_text(bytes: &[u8]) -> String {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => bytes.iter().map(|&b| b as char).collect(),
+    };
+    text.trim_end_matches('\0').to_string()
+}
+
+/// The coordinate systems a `.prj` can name that can be read. Datums are
+/// taken to be WGS84.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    Geographic {
+        radians_per_unit: f64,
+    },
+    /// Spherical Web Mercator, EPSG:3857.
+    WebMercator {
+        meters_per_unit: f64,
+    },
+    TransverseMercator {
+        latitude_of_origin: f64,
+        central_meridian: f64,
+        scale_factor: f64,
+        false_easting: f64,
+        false_northing: f64,
+        meters_per_unit: f64,
+    },
+}
+
+impl Projection {
+    pub fn from_wkt(text: &str) -> anyhow::Result<Self> {
+        let root = Wkt::parse(text)?;
+        let unit = |node: &Wkt| {
+            node.child("UNIT")
+                .and_then(|unit| unit.number(1))
+                .ok_or_else(|| anyhow!("{} has no unit", node.keyword))
+        };
+        match root.keyword.as_str() {
+            "GEOGCS" => Ok(Projection::Geographic {
+                radians_per_unit: unit(&root)?,
+            }),
+            "PROJCS" => {
+                let name = root
+                    .child("PROJECTION")
+                    .and_then(|projection| projection.string(0))
+                    .ok_or_else(|| anyhow!("PROJCS without a PROJECTION"))?;
+                let parameter = |name: &str| {
+                    root.children("PARAMETER")
+                        .find(|parameter| {
+                            parameter
+                                .string(0)
+                                .is_some_and(|found| found.eq_ignore_ascii_case(name))
+                        })
+                        .and_then(|parameter| parameter.number(1))
+                };
+                let degrees = |name: &str| parameter(name).unwrap_or(0.0).to_radians();
+                let meters_per_unit = unit(&root)?;
+                match name {
+                    "Mercator_Auxiliary_Sphere" | "Popular_Visualisation_Pseudo_Mercator" => {
+                        Ok(Projection::WebMercator { meters_per_unit })
+                    }
+                    "Transverse_Mercator" => Ok(Projection::TransverseMercator {
+                        latitude_of_origin: degrees("latitude_of_origin"),
+                        central_meridian: degrees("central_meridian"),
+                        scale_factor: parameter("scale_factor").unwrap_or(1.0),
+                        false_easting: parameter("false_easting").unwrap_or(0.0),
+                        false_northing: parameter("false_northing").unwrap_or(0.0),
+                        meters_per_unit,
+                    }),
+                    other => bail!("unsupported projection {}", other),
+                }
+            }
+            other => bail!("unsupported coordinate system {}", other),
+        }
+    }
+
+    pub fn to_geodetic(self, x: f64, y: f64, height: f64) -> Geodetic {
+        match self {
+            Projection::Geographic { radians_per_unit } => {
+                Geodetic::new(y * radians_per_unit, x * radians_per_unit, height)
+            }
+            Projection::WebMercator { meters_per_unit } => {
+                let (x, y) = (x * meters_per_unit, y * meters_per_unit);
+                Geodetic::new(
+                    (y / geodesy::WGS84_A).sinh().atan(),
+                    x / geodesy::WGS84_A,
+                    height,
+                )
+            }
+            Projection::TransverseMercator {
+                latitude_of_origin,
+                central_meridian,
+                scale_factor,
+                false_easting,
+                false_northing,
+                meters_per_unit,
+            } => {
+                let (latitude, longitude) = inverse_transverse_mercator(
+                    (x - false_easting) * meters_per_unit,
+                    (y - false_northing) * meters_per_unit,
+                    latitude_of_origin,
+                    scale_factor,
+                );
+                Geodetic::new(latitude, central_meridian + longitude, height)
+            }
+        }
+    }
+}
+
+/// The distance along a WGS84 meridian from the equator to `latitude`.
+fn meridian_arc(latitude: f64) -> f64 {
+    let e2 = geodesy::WGS84_E2;
+    let (e4, e6) = (e2 * e2, e2 * e2 * e2);
+    geodesy::WGS84_A
+        * ((1.0 - e2 / 4.0 - 3.0 * e4 / 64.0 - 5.0 * e6 / 256.0) * latitude
+            - (3.0 * e2 / 8.0 + 3.0 * e4 / 32.0 + 45.0 * e6 / 1024.0) * (2.0 * latitude).sin()
+            + (15.0 * e4 / 256.0 + 45.0 * e6 / 1024.0) * (4.0 * latitude).sin()
+            - (35.0 * e6 / 3072.0) * (6.0 * latitude).sin())
+}
+
+/// Latitude and longitude from the central meridian for easting and
+/// northing in meters, by Snyder's series.
+fn inverse_transverse_mercator(
+    easting: f64,
+    northing: f64,
+    latitude_of_origin: f64,
+    scale_factor: f64,
+) -> (f64, f64) {
+    let e2 = geodesy::WGS84_E2;
+    let ep2 = e2 / (1.0 - e2);
+    let m = meridian_arc(latitude_of_origin) + northing / scale_factor;
+    let mu = m
+        / (geodesy::WGS84_A * (1.0 - e2 / 4.0 - 3.0 * e2 * e2 / 64.0 - 5.0 * e2 * e2 * e2 / 256.0));
+    let e1 = (1.0 - (1.0 - e2).sqrt()) / (1.0 + (1.0 - e2).sqrt());
+    let phi1 = mu
+        + (3.0 * e1 / 2.0 - 27.0 * e1.powi(3) / 32.0) * (2.0 * mu).sin()
+        + (21.0 * e1 * e1 / 16.0 - 55.0 * e1.powi(4) / 32.0) * (4.0 * mu).sin()
+        + (151.0 * e1.powi(3) / 96.0) * (6.0 * mu).sin()
+        + (1097.0 * e1.powi(4) / 512.0) * (8.0 * mu).sin();
+
+    let (sin, cos, tan) = (phi1.sin(), phi1.cos(), phi1.tan());
+    let c1 = ep2 * cos * cos;
+    let t1 = tan * tan;
+    let n1 = geodesy::WGS84_A / (1.0 - e2 * sin * sin).sqrt();
+    let r1 = geodesy::WGS84_A * (1.0 - e2) / (1.0 - e2 * sin * sin).powf(1.5);
+    let d = easting / (n1 * scale_factor);
+
+    let latitude = phi1
+        - n1 * tan / r1
+            * (d * d / 2.0
+                - (5.0 + 3.0 * t1 + 10.0 * c1 - 4.0 * c1 * c1 - 9.0 * ep2) * d.powi(4) / 24.0
+                + (61.0 + 90.0 * t1 + 298.0 * c1 + 45.0 * t1 * t1 - 252.0 * ep2 - 3.0 * c1 * c1)
+                    * d.powi(6)
+                    / 720.0);
+    let longitude = (d - (1.0 + 2.0 * t1 + c1) * d.powi(3) / 6.0
+        + (5.0 - 2.0 * c1 + 28.0 * t1 - 3.0 * c1 * c1 + 8.0 * ep2 + 24.0 * t1 * t1) * d.powi(5)
+            / 120.0)
+        / cos;
+    (latitude, longitude)
+}
+
+/// A node of OGC well-known text, such as `UNIT["metre",1]`.
+#[derive(Debug)]
+struct Wkt {
+    keyword: String,
+    values: Vec<WktValue>,
+}
+
+#[derive(Debug)]
+enum WktValue {
+    Text(String),
+    Number(f64),
+    Node(Wkt),
+}
+
+impl Wkt {
+    fn parse(text: &str) -> anyhow::Result<Wkt> {
+        let mut chars = text.trim().chars().peekable();
+        Self::parse_node(&mut chars)
+    }
+
+    fn parse_node(chars: &mut std::iter::Peekable<std::str::Chars>) -> anyhow::Result<Wkt> {
+        let mut keyword = String::new();
+        while let Some(&c) = chars.peek() {
+            if !c.is_ascii_alphanumeric() && c != '_' {
+                break;
+            }
+            keyword.push(c);
+            chars.next();
+        }
+        let close = match chars.next() {
+            Some('[') => ']',
+            Some('(') => ')',
+            _ => bail!("expected [ after {}", keyword),
+        };
+
+        let mut values = Vec::new();
+        loop {
+            while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                chars.next();
+            }
+            match chars.peek() {
+                Some('"') => {
+                    chars.next();
+                    let mut text = String::new();
+                    for c in chars.by_ref() {
+                        if c == '"' {
+                            break;
+                        }
+                        text.push(c);
+                    }
+                    values.push(WktValue::Text(text));
+                }
+                Some(c) if c.is_ascii_digit() || *c == '-' || *c == '+' || *c == '.' => {
+                    let mut number = String::new();
+                    while let Some(&c) = chars.peek() {
+                        if !(c.is_ascii_alphanumeric() || c == '-' || c == '+' || c == '.') {
+                            break;
+                        }
+                        number.push(c);
+                        chars.next();
+                    }
+                    values.push(WktValue::Number(number.parse()?));
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    values.push(WktValue::Node(Self::parse_node(chars)?))
+                }
+                _ => bail!("unexpected end of {}", keyword),
+            }
+
+            while chars.peek().is_some_and(|c| c.is_whitespace()) {
+                chars.next();
+            }
+            match chars.next() {
+                Some(',') => continue,
+                Some(c) if c == close => break,
+                _ => bail!("expected , or {} in {}", close, keyword),
+            }
+        }
+        Ok(Wkt { keyword, values })
+    }
+
+    fn children<'a>(&'a self, keyword: &'a str) -> impl Iterator<Item = &'a Wkt> {
+        self.values.iter().filter_map(move |value| match value {
+            WktValue::Node(node) if node.keyword.eq_ignore_ascii_case(keyword) => Some(node),
+            _ => None,
+        })
+    }
+
+    fn child<'a>(&'a self, keyword: &'a str) -> Option<&'a Wkt> {
+        self.children(keyword).next()
+    }
+
+    fn string(&self, index: usize) -> Option<&str> {
+        match self.values.get(index)? {
+            WktValue::Text(text) => Some(text),
+            _ => None,
+        }
+    }
+
+    fn number(&self, index: usize) -> Option<f64> {
+        match self.values.get(index)? {
+            WktValue::Number(number) => Some(*number),
+            _ => None,
+        }
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.offset >= self.data.len()
+    }
+
+    fn take(&mut self, length: usize) -> anyhow::Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.offset..self.offset.saturating_add(length))
+            .ok_or_else(|| anyhow!("unexpected end of file"))?;
+        self.offset += length;
+        Ok(bytes)
+    }
+
+    fn i32_be(&mut self) -> anyhow::Result<i32> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn i32_le(&mut self) -> anyhow::Result<i32> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u32_le(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn u16_le(&mut self) -> anyhow::Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn f64_le(&mut self) -> anyhow::Result<f64> {
+        Ok(f64::from_le_bytes(self.take(8)?.try_into()?))
+    }
+
+    /// A non-negative count, checked against what is left of the data.
+    fn count(&mut self) -> anyhow::Result<usize> {
+        let count = self.i32_le()?;
+        if count < 0 || count as usize > self.data.len() - self.offset {
+            bail!("implausible count {}", count);
+        }
+        Ok(count as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shp(records: &[Vec<u8>]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&9994i32.to_be_bytes());
+        data.extend_from_slice(&[0; 20]);
+        let length = 100 + records.iter().map(|r| r.len() + 8).sum::<usize>();
+        data.extend_from_slice(&(length as i32 / 2).to_be_bytes());
+        data.extend_from_slice(&1000i32.to_le_bytes());
+        data.extend_from_slice(&5i32.to_le_bytes());
+        data.extend_from_slice(&[0; 64]);
+        for (i, record) in records.iter().enumerate() {
+            data.extend_from_slice(&(i as i32 + 1).to_be_bytes());
+            data.extend_from_slice(&(record.len() as i32 / 2).to_be_bytes());
+            data.extend_from_slice(record);
+        }
+        data
+    }
+
+    fn polygon(rings: &[&[(f64, f64)]]) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(&5i32.to_le_bytes());
+        data.extend_from_slice(&[0; 32]);
+        data.extend_from_slice(&(rings.len() as i32).to_le_bytes());
+        let count = rings.iter().map(|ring| ring.len()).sum::<usize>();
+        data.extend_from_slice(&(count as i32).to_le_bytes());
+        let mut start = 0;
+        for ring in rings {
+            data.extend_from_slice(&(start as i32).to_le_bytes());
+            start += ring.len();
+        }
+        for &(x, y) in rings.iter().copied().flatten() {
+            data.extend_from_slice(&x.to_le_bytes());
+            data.extend_from_slice(&y.to_le_bytes());
+        }
+        data
+    }
+
+    fn dbf(fields: &[(&str, u8, u8)], records: &[&str]) -> Vec<u8> {
+        let record_length = 1 + fields.iter().map(|f| f.2 as usize).sum::<usize>();
+        let header_length = 32 + 32 * fields.len() + 1;
+        let mut data = vec![3, 124, 1, 1];
+        data.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        data.extend_from_slice(&(header_length as u16).to_le_bytes());
+        data.extend_from_slice(&(record_length as u16).to_le_bytes());
+        data.extend_from_slice(&[0; 20]);
+        for &(name, kind, length) in fields {
+            let mut descriptor = [0u8; 32];
+            descriptor[..name.len()].copy_from_slice(name.as_bytes());
+            descriptor[11] = kind;
+            descriptor[16] = length;
+            data.extend_from_slice(&descriptor);
+        }
+        data.push(0x0d);
+        for record in records {
+            assert_eq!(record.len(), record_length);
+            data.extend_from_slice(record.as_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn reads_polygons_with_attributes() {
+        // Clockwise outer rings and a counterclockwise hole in the second
+        let square: &[(f64, f64)] = &[(0.0, 0.0), (0.0, 1.0), (1.0, 1.0), (1.0, 0.0), (0.0, 0.0)];
+        let big: &[(f64, f64)] = &[(2.0, 0.0), (2.0, 4.0), (6.0, 4.0), (6.0, 0.0), (2.0, 0.0)];
+        let hole: &[(f64, f64)] = &[(3.0, 1.0), (5.0, 1.0), (5.0, 3.0), (3.0, 3.0), (3.0, 1.0)];
+        let shp = shp(&[
+            polygon(&[square]),
+            polygon(&[big, square, hole]),
+            polygon(&[square]),
+        ]);
+        let dbf = dbf(
+            &[
+                ("NAME", b'C', 6),
+                ("POP", b'N', 5),
+                ("AREA", b'F', 6),
+                ("WET", b'L', 1),
+            ],
+            &[
+                &format!(" {:<6}{:>5}{:>6}T", "Bern", 1337, "1.25"),
+                &format!(" {:<6}{:>5}{:>6}F", "Thun", "", "12.00"),
+                &format!("*{:<6}{:>5}{:>6}?", "Gone", 1, "0.00"),
+            ],
+        );
+
+        let features = parse(&shp, Some(&dbf), None).unwrap();
+        assert_eq!(features.len(), 2, "the deleted record is skipped");
+
+        assert_eq!(features[0].id, Some(Value::from(1)));
+        assert_eq!(features[0].properties["NAME"], "Bern");
+        assert_eq!(features[0].properties["POP"], 1337);
+        assert_eq!(features[0].properties["AREA"], 1.25);
+        assert_eq!(features[0].properties["WET"], true);
+        assert_eq!(features[1].properties["POP"], Value::Null);
+        assert_eq!(features[1].properties["WET"], false);
+
+        match &features[1].geometry {
+            Geometry::MultiPolygon(polygons) => {
+                assert_eq!(polygons.len(), 2);
+                assert_eq!(
+                    polygons[0].len(),
+                    2,
+                    "the hole goes with the ring around it"
+                );
+                assert_eq!(polygons[1].len(), 1);
+                assert_eq!(polygons[0][1].len(), 4, "rings aren't closed");
+                assert_eq!(polygons[0][1][0], Geodetic::from_degrees(1.0, 3.0, 0.0));
+            }
+            geometry => panic!("expected a multipolygon, found {:?}", geometry),
+        }
+
+        assert!(parse(&shp[..shp.len() - 4], None, None).is_err());
+
+        // Records too short for their fields, down to none at all
+        for record_length in [0u16, 5] {
+            let mut short = dbf.clone();
+            short[10..12].copy_from_slice(&record_length.to_le_bytes());
+            assert!(parse(&shp, Some(&short), None).is_err());
+        }
+        assert_eq!(decode_text(b"Z\xfcrich\0\0"), "Zürich");
+    }
+
+    #[test]
+    fn reads_prj_coordinate_systems() {
+        let utm = r#"PROJCS["WGS_1984_UTM_Zone_32N",GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984",
+            SPHEROID["WGS_1984",6378137.0,298.257223563]],PRIMEM["Greenwich",0.0],
+            UNIT["Degree",0.0174532925199433]],PROJECTION["Transverse_Mercator"],
+            PARAMETER["False_Easting",500000.0],PARAMETER["False_Northing",0.0],
+            PARAMETER["Central_Meridian",9.0],PARAMETER["Scale_Factor",0.9996],
+            PARAMETER["Latitude_Of_Origin",0.0],UNIT["Meter",1.0]]"#;
+        let projection = Projection::from_wkt(utm).unwrap();
+        // 4,984,944.378 m is the WGS84 meridian arc from the equator to 45°
+        let point = projection.to_geodetic(500_000.0, 0.9996 * 4_984_944.378, 10.0);
+        assert!((point.latitude.to_degrees() - 45.0).abs() < 1e-7);
+        assert!((point.longitude.to_degrees() - 9.0).abs() < 1e-12);
+        assert_eq!(point.height, 10.0);
+        // A kilometer east of the meridian at 45° is about 0.0127° of longitude
+        let east = projection.to_geodetic(500_000.0 + 0.9996 * 1000.0, 0.9996 * 4_984_944.378, 0.0);
+        let expected = 1000.0 / (6_388_838.0 * 45f64.to_radians().cos());
+        assert!((east.longitude - 9f64.to_radians() - expected).abs() < 1e-7);
+
+        let mercator = Projection::from_wkt(
+            r#"PROJCS["WGS 84 / Pseudo-Mercator",GEOGCS["WGS 84",DATUM["WGS_1984",
+            SPHEROID["WGS 84",6378137,298.257223563]],UNIT["degree",0.0174532925199433]],
+            PROJECTION["Popular_Visualisation_Pseudo_Mercator"],UNIT["metre",1]]"#,
+        )
+        .unwrap();
+        let point = mercator.to_geodetic(std::f64::consts::PI * geodesy::WGS84_A, 0.0, 0.0);
+        assert!((point.longitude.to_degrees() - 180.0).abs() < 1e-9);
+
+        let geographic = Projection::from_wkt(
+            r#"GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984",SPHEROID["WGS_1984",6378137.0,298.257223563]],PRIMEM["Greenwich",0.0],UNIT["Degree",0.0174532925199433]]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            geographic.to_geodetic(7.5, 46.0, 0.0).latitude,
+            46.0 * 0.0174532925199433
+        );
+        assert!(Projection::from_wkt(r#"PROJCS["x",PROJECTION["Lambert"],UNIT["m",1]]"#).is_err());
+    }
+}
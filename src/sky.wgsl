@@ -0,0 +1,28 @@
+// Vertex shader
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] ndc: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] index: u32) -> VertexOutput {
+    // A triangle reaching past the corners of the screen
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    let ndc = uv * 2.0 - 1.0;
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.ndc = ndc;
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let view = atmosphere.view_forward.xyz
+        + atmosphere.view_right.xyz * in.ndc.x
+        + atmosphere.view_up.xyz * in.ndc.y;
+    let view = normalize(squash(view));
+    return vec4<f32>(sky_color(atmosphere.camera_position.xyz, view), 1.0);
+}
@@ -0,0 +1,376 @@
+use cgmath::{Matrix4, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::clock::UtcTime;
+use crate::{sun, texture};
+
+/// Stars fainter than this are left out of catalogs as they load.
+pub const MAGNITUDE_LIMIT: f32 = 6.5;
+/// The brightest stars, enough for the constellations to be made out when
+/// there is no catalog.
+pub const BRIGHT_STARS: &str = include_str!("bright_stars.csv");
+
+/// A star at magnitude 0 is drawn with this radius in pixels, at full
+/// brightness.
+const REFERENCE_RADIUS: f32 = 2.5;
+const MIN_RADIUS: f32 = 1.0;
+const MAX_RADIUS: f32 = 4.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Star {
+    /// Radians, J2000 equatorial.
+    pub right_ascension: f64,
+    /// Radians, J2000 equatorial.
+    pub declination: f64,
+    /// Apparent visual magnitude.
+    pub magnitude: f32,
+    /// B-V color index.
+    pub color_index: f32,
+}
+
+impl Star {
+    /// The unit vector towards the star in the inertial frame.
+    pub fn direction(&self) -> Vector3<f64> {
+        let (sin_ra, cos_ra) = self.right_ascension.sin_cos();
+        let (sin_dec, cos_dec) = self.declination.sin_cos();
+        Vector3::new(cos_dec * cos_ra, cos_dec * sin_ra, sin_dec)
+    }
+
+    /// The sprite's radius in pixels and its brightness. Each magnitude is
+    /// 2.512 times fainter; half of that comes off the sprite's area and half
+    /// off its brightness, until the radius reaches its bounds.
+    pub fn sprite(&self) -> (f32, f32) {
+        let flux = 10f32.powf(-0.4 * self.magnitude);
+        let radius = (REFERENCE_RADIUS * flux.powf(0.25)).clamp(MIN_RADIUS, MAX_RADIUS);
+        let brightness = flux * (REFERENCE_RADIUS / radius).powi(2);
+        (radius, brightness.min(1.0))
+    }
+
+    /// A rough linear RGB for the color index, from blue-white through white
+    /// to orange.
+    pub fn color(&self) -> [f32; 3] {
+        const STOPS: [(f32, [f32; 3]); 3] = [
+            (-0.3, [0.62, 0.72, 1.0]),
+            (0.6, [1.0, 0.96, 0.9]),
+            (1.8, [1.0, 0.62, 0.32]),
+        ];
+        let bv = self.color_index.clamp(STOPS[0].0, STOPS[2].0);
+        let (low, high) = if bv < STOPS[1].0 {
+            (STOPS[0], STOPS[1])
+        } else {
+            (STOPS[1], STOPS[2])
+        };
+        let t = (bv - low.0) / (high.0 - low.0);
+        [0, 1, 2].map(|i| low.1[i] + (high.1[i] - low.1[i]) * t)
+    }
+}
+
+/// Reads a star catalog in the CSV layout of the HYG database: a header row
+/// naming the columns, right ascension in hours under `ra`, declination in
+/// degrees under `dec`, visual magnitude under `mag` and, optionally, the B-V
+/// color index under `ci`. Other columns are ignored, as are stars fainter
+/// than `magnitude_limit` and the sun, which HYG lists too.
+pub fn parse_catalog(text: &str, magnitude_limit: f32) -> anyhow::Result<Vec<Star>> {
+    let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+    let header: Vec<_> = lines
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("The star catalog is empty"))?
+        .split(',')
+        .map(unquote)
+        .collect();
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| column.eq_ignore_ascii_case(name))
+    };
+    let missing = |name: &str| anyhow::anyhow!("The star catalog has no {} column", name);
+    let ra = column("ra").ok_or_else(|| missing("ra"))?;
+    let dec = column("dec").ok_or_else(|| missing("dec"))?;
+    let mag = column("mag").ok_or_else(|| missing("mag"))?;
+    let ci = column("ci");
+
+    let mut stars = Vec::new();
+    for (row, line) in lines.enumerate() {
+        let fields: Vec<_> = line.split(',').map(unquote).collect();
+        let field = |i: usize| {
+            fields
+                .get(i)
+                .copied()
+                .ok_or_else(|| anyhow::anyhow!("Row {} of the star catalog is too short", row + 1))
+        };
+        let magnitude = field(mag)?.parse::<f32>()?;
+        // Nothing but the sun is brighter than Sirius at -1.46
+        if magnitude > magnitude_limit || magnitude < -5.0 {
+            continue;
+        }
+        let color_index = match ci.map(field).transpose()? {
+            Some(ci) if !ci.is_empty() => ci.parse()?,
+            _ => 0.6,
+        };
+        stars.push(Star {
+            right_ascension: (field(ra)?.parse::<f64>()? * 15.0).to_radians(),
+            declination: field(dec)?.parse::<f64>()?.to_radians(),
+            magnitude,
+            color_index,
+        });
+    }
+    Ok(stars)
+}
+
+fn unquote(field: &str) -> &str {
+    field.trim().trim_matches('"')
+}
+
+/// A star's direction, with its sprite radius in w, and its color.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct StarInstance {
+    direction: [f32; 4],
+    color: [f32; 4],
+}
+
+impl StarInstance {
+    fn new(star: &Star) -> Self {
+        let (radius, brightness) = star.sprite();
+        let [r, g, b] = star.color();
+        Self {
+            direction: star
+                .direction()
+                .cast::<f32>()
+                .unwrap()
+                .extend(radius)
+                .into(),
+            color: [r * brightness, g * brightness, b * brightness, 1.0],
+        }
+    }
+
+    fn desc<'a>() -> wgpu::VertexBufferLayout<'a> {
+        use std::mem;
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<StarInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttribute {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+                wgpu::VertexAttribute {
+                    offset: mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float32x4,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct StarFieldUniform {
+    inertial_to_ecef: [[f32; 4]; 4],
+    /// Width and height in pixels.
+    viewport: [f32; 4],
+}
+
+/// Stars as point sprites at infinity, turning with the sky. They're drawn
+/// behind everything, brightening what is already there the less bright it
+/// is, so they fade out against the daytime sky.
+pub struct StarField {
+    instance_buffer: wgpu::Buffer,
+    count: u32,
+    buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl StarField {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        stars: &[Star],
+    ) -> Self {
+        let instances: Vec<_> = stars.iter().map(StarInstance::new).collect();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Star Instance Buffer"),
+            contents: bytemuck::cast_slice(&instances),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Star Field Buffer"),
+            size: std::mem::size_of::<StarFieldUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("star_field_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: buffer.as_entire_binding(),
+            }],
+            label: Some("star_field_bind_group"),
+        });
+
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Star Field Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(&wgpu::ShaderModuleDescriptor {
+            label: Some("Star Field Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("stars.wgsl").into()),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Star Field Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[StarInstance::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::OneMinusDst,
+                            dst_factor: wgpu::BlendFactor::One,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                strip_index_format: None,
+                front_face: wgpu::FrontFace::Ccw,
+                cull_mode: None,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                unclipped_depth: false,
+                conservative: false,
+            },
+            // Drawn straight after the sky, before anything that could hide
+            // them
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: 1,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+        });
+
+        Self {
+            instance_buffer,
+            count: instances.len() as u32,
+            buffer,
+            bind_group,
+            pipeline,
+        }
+    }
+
+    /// Turns the stars with the Earth to where they stand at `time`.
+    pub fn update(&self, queue: &wgpu::Queue, time: UtcTime, width: u32, height: u32) {
+        let inertial_to_ecef = Matrix4::from(sun::inertial_to_ecef(time));
+        let uniform = StarFieldUniform {
+            inertial_to_ecef: inertial_to_ecef.cast::<f32>().unwrap().into(),
+            viewport: [width as f32, height as f32, 0.0, 0.0],
+        };
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
+
+pub trait DrawStars<'a> {
+    fn draw_stars(&mut self, stars: &'a StarField, camera_bind_group: &'a wgpu::BindGroup);
+}
+
+impl<'a, 'b> DrawStars<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_stars(&mut self, stars: &'b StarField, camera_bind_group: &'b wgpu::BindGroup) {
+        if stars.count == 0 {
+            return;
+        }
+        self.set_pipeline(&stars.pipeline);
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(1, &stars.bind_group, &[]);
+        self.set_vertex_buffer(0, stars.instance_buffer.slice(..));
+        // Two triangles for each star
+        self.draw(0..6, 0..stars.count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::prelude::*;
+
+    #[test]
+    fn the_bright_star_list_reads_as_a_catalog() {
+        let stars = parse_catalog(BRIGHT_STARS, MAGNITUDE_LIMIT).unwrap();
+        assert_eq!(stars.len(), 40);
+        let sirius = stars[0];
+        assert!((sirius.right_ascension.to_degrees() - 101.287).abs() < 0.01);
+        assert!((sirius.declination.to_degrees() + 16.716).abs() < 0.01);
+
+        // Quoted, reordered, with the sun and a faint star to drop
+        let hyg = "\"id\",\"dec\",\"ra\",\"mag\"\n\
+                   \"0\",\"0\",\"0\",\"-26.7\"\n\
+                   \"1\",\"89.26\",\"2.53\",\"1.98\"\n\
+                   \"2\",\"10\",\"3\",\"9.1\"\n";
+        let stars = parse_catalog(hyg, MAGNITUDE_LIMIT).unwrap();
+        assert_eq!(stars.len(), 1);
+        assert_eq!(stars[0].magnitude, 1.98);
+        assert_eq!(stars[0].color_index, 0.6);
+        assert!(parse_catalog("ra,dec\n1,2\n", MAGNITUDE_LIMIT).is_err());
+    }
+
+    #[test]
+    fn stars_turn_with_sidereal_time() {
+        // A star on the equator at the sidereal time stands over Greenwich
+        let time = UtcTime::parse("2024-06-20T20:51Z").unwrap();
+        let star = Star {
+            right_ascension: sun::greenwich_sidereal_time(time).0,
+            declination: 0.0,
+            magnitude: 0.0,
+            color_index: 0.6,
+        };
+        let ecef = sun::inertial_to_ecef(time) * star.direction();
+        assert!((ecef - Vector3::unit_x()).magnitude() < 1e-9);
+        // Polaris stays near the pole
+        let polaris = parse_catalog(BRIGHT_STARS, MAGNITUDE_LIMIT).unwrap()[33];
+        assert!((sun::inertial_to_ecef(time) * polaris.direction()).z > 0.9998);
+
+        // Brighter stars are bigger and brighter, within bounds
+        let sprite = |magnitude| Star { magnitude, ..star }.sprite();
+        assert_eq!(sprite(0.0), (REFERENCE_RADIUS, 1.0));
+        assert!(sprite(-1.5).0 > REFERENCE_RADIUS && sprite(-1.5).1 == 1.0);
+        assert_eq!(sprite(6.0).0, MIN_RADIUS);
+        assert!(sprite(6.0).1 < sprite(3.0).1);
+    }
+}
@@ -0,0 +1,71 @@
+// Vertex shader
+
+struct Camera {
+    view_pos: vec4<f32>;
+    view_proj: mat4x4<f32>;
+};
+[[group(0), binding(0)]]
+var<uniform> camera: Camera;
+
+struct StarField {
+    inertial_to_ecef: mat4x4<f32>;
+    // Width and height in pixels
+    viewport: vec4<f32>;
+};
+[[group(1), binding(0)]]
+var<uniform> stars: StarField;
+
+struct InstanceInput {
+    // Inertial, with the sprite's radius in pixels in w
+    [[location(0)]] direction: vec4<f32>;
+    [[location(1)]] color: vec4<f32>;
+};
+
+struct VertexOutput {
+    [[builtin(position)]] clip_position: vec4<f32>;
+    [[location(0)]] color: vec3<f32>;
+    // In units of the radius
+    [[location(1)]] offset: vec2<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    [[builtin(vertex_index)]] index: u32,
+    instance: InstanceInput,
+) -> VertexOutput {
+    // Two triangles covering twice the radius, for the falloff
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    let offset = corners[index] * 2.0;
+
+    // Directions have no position, so only the camera's rotation applies.
+    // Stars behind the camera get a negative w and are clipped.
+    let direction = stars.inertial_to_ecef * vec4<f32>(instance.direction.xyz, 0.0);
+    var clip = camera.view_proj * vec4<f32>(direction.xyz, 0.0);
+    let pixels = offset * instance.direction.w;
+    clip = vec4<f32>(
+        clip.xy + pixels * 2.0 / stars.viewport.xy * clip.w,
+        0.0,
+        clip.w,
+    );
+
+    var out: VertexOutput;
+    out.clip_position = clip;
+    out.color = instance.color.rgb;
+    out.offset = offset;
+    return out;
+}
+
+// Fragment shader
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let falloff = exp(-2.0 * dot(in.offset, in.offset));
+    return vec4<f32>(in.color * falloff, 0.0);
+}
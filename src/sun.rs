@@ -0,0 +1,100 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Rad, Vector3};
+
+use crate::clock::UtcTime;
+use crate::geodesy::Geodetic;
+
+/// Meters in an astronomical unit.
+pub const ASTRONOMICAL_UNIT: f64 = 149_597_870_700.0;
+
+/// Greenwich mean sidereal time, the angle the Earth has turned through
+/// since its x axis last pointed at the vernal equinox. UT1 is taken as
+/// UTC, which is good to a second.
+pub fn greenwich_sidereal_time(time: UtcTime) -> Rad<f64> {
+    let days = time.days_since_j2000();
+    let centuries = days / 36_525.0;
+    let degrees = 280.460_618_37 + 360.985_647_366_29 * days + 0.000_387_933 * centuries.powi(2)
+        - centuries.powi(3) / 38_710_000.0;
+    Rad(degrees.rem_euclid(360.0).to_radians())
+}
+
+/// Where the sun is in the Earth-centered inertial frame of the date, in
+/// meters, by the Astronomical Almanac's low precision formulae. These are
+/// good to about 0.01 degrees between 1950 and 2050.
+pub fn inertial_position(time: UtcTime) -> Vector3<f64> {
+    let days = time.days_since_j2000();
+    let mean_longitude = (280.460 + 0.985_647_4 * days).to_radians();
+    let mean_anomaly = (357.528 + 0.985_600_3 * days).to_radians();
+    let ecliptic_longitude = mean_longitude
+        + 1.915f64.to_radians() * mean_anomaly.sin()
+        + 0.020f64.to_radians() * (2.0 * mean_anomaly).sin();
+    let obliquity = (23.439 - 0.000_000_4 * days).to_radians();
+    let distance = 1.000_14 - 0.016_71 * mean_anomaly.cos() - 0.000_14 * (2.0 * mean_anomaly).cos();
+
+    Vector3::new(
+        ecliptic_longitude.cos(),
+        obliquity.cos() * ecliptic_longitude.sin(),
+        obliquity.sin() * ecliptic_longitude.sin(),
+    ) * (distance * ASTRONOMICAL_UNIT)
+}
+
+/// Turns the inertial frame of the date into ECEF, about the pole. Polar
+/// motion is left out.
+pub fn inertial_to_ecef(time: UtcTime) -> Matrix3<f64> {
+    Matrix3::from_angle_z(-greenwich_sidereal_time(time))
+}
+
+/// Where the sun is in ECEF meters.
+pub fn position(time: UtcTime) -> Vector3<f64> {
+    inertial_to_ecef(time) * inertial_position(time)
+}
+
+/// The ECEF unit vector towards the sun.
+pub fn direction(time: UtcTime) -> Vector3<f64> {
+    position(time).normalize()
+}
+
+/// Where on the ground the sun is straight overhead. The ellipsoid's normal
+/// points at the sun there, so the latitude is the sun's declination.
+pub fn subsolar_point(time: UtcTime) -> Geodetic {
+    let direction = direction(time);
+    Geodetic {
+        latitude: direction.z.asin(),
+        longitude: direction.y.atan2(direction.x),
+        height: 0.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_sun_crosses_the_equator_at_the_equinox() {
+        // The March equinox and the June solstice of 2024
+        let equinox = subsolar_point(UtcTime::parse("2024-03-20T03:06Z").unwrap());
+        assert!(equinox.latitude.to_degrees().abs() < 0.02);
+        let solstice = subsolar_point(UtcTime::parse("2024-06-20T20:51Z").unwrap());
+        assert!((solstice.latitude.to_degrees() - 23.44).abs() < 0.02);
+
+        // Earth is nearest the sun in early January
+        let perihelion = position(UtcTime::parse("2024-01-03").unwrap()).magnitude();
+        let aphelion = position(UtcTime::parse("2024-07-05").unwrap()).magnitude();
+        assert!((perihelion / ASTRONOMICAL_UNIT - 0.9833).abs() < 0.0005);
+        assert!((aphelion / ASTRONOMICAL_UNIT - 1.0167).abs() < 0.0005);
+    }
+
+    #[test]
+    fn the_sun_runs_ahead_of_noon_by_the_equation_of_time() {
+        // Apparent noon comes 16.4 minutes early in early November, so at
+        // noon UTC the sun is already over 4.1 degrees west
+        let noon = subsolar_point(UtcTime::parse("2024-11-03T12:00Z").unwrap());
+        assert!((noon.longitude.to_degrees() + 4.1).abs() < 0.1);
+        // It comes round again a day later
+        let later = subsolar_point(UtcTime::parse("2024-11-04T12:00Z").unwrap());
+        assert!((later.longitude - noon.longitude).to_degrees().abs() < 0.1);
+
+        let sidereal = greenwich_sidereal_time(UtcTime::parse("2000-01-01T12:00").unwrap());
+        assert!((sidereal.0.to_degrees() - 280.460_618_37).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,523 @@
+use anyhow::{anyhow, bail, Context};
+use std::io::Cursor;
+use std::sync::Arc;
+
+use crate::geodesy;
+use crate::globe::{Extent, TextureProjection, TileGeometry};
+use crate::quantized_mesh::{self, QuantizedMesh};
+use crate::tiles::{self, TileId, TileSource};
+
+/// A regular grid of heights in meters above the ellipsoid.
+///
+/// Samples are stored row by row from the north-west corner. `extent` runs
+/// through the centers of the outermost samples, and rows are evenly spaced
+/// in `projection`. Voids are `NaN`.
+#[derive(Debug, Clone)]
+pub struct Heightmap {
+    pub width: usize,
+    pub height: usize,
+    pub extent: Extent,
+    pub projection: TextureProjection,
+    pub heights: Vec<f32>,
+}
+
+impl Heightmap {
+    pub fn new(
+        width: usize,
+        height: usize,
+        extent: Extent,
+        projection: TextureProjection,
+        heights: Vec<f32>,
+    ) -> anyhow::Result<Self> {
+        if width < 2 || height < 2 || heights.len() != width * height {
+            bail!(
+                "{} heights don't make a {}x{} heightmap",
+                heights.len(),
+                width,
+                height
+            );
+        }
+        Ok(Self {
+            width,
+            height,
+            extent,
+            projection,
+            heights,
+        })
+    }
+
+    /// Reads an SRTM `.hgt` cell: a square of big-endian `i16` samples, one
+    /// or three arc seconds apart, whose south-west sample sits on the whole
+    /// degrees `south` and `west`.
+    pub fn from_hgt(data: &[u8], south: i32, west: i32) -> anyhow::Result<Self> {
+        let side = ((data.len() / 2) as f64).sqrt() as usize;
+        if side * side * 2 != data.len() {
+            bail!("an .hgt file of {} bytes isn't square", data.len());
+        }
+        const VOID: i16 = -32768;
+        let heights = data
+            .chunks_exact(2)
+            .map(|sample| match i16::from_be_bytes([sample[0], sample[1]]) {
+                VOID => f32::NAN,
+                height => height as f32,
+            })
+            .collect();
+        let extent = Extent {
+            west: (west as f64).to_radians(),
+            south: (south as f64).to_radians(),
+            east: (west as f64 + 1.0).to_radians(),
+            north: (south as f64 + 1.0).to_radians(),
+        };
+        Self::new(side, side, extent, TextureProjection::Geographic, heights)
+    }
+
+    /// Like [`Heightmap::from_hgt`], taking the cell from a file name such as
+    /// `N45E006.hgt`.
+    pub fn from_hgt_file(file_name: &str, data: &[u8]) -> anyhow::Result<Self> {
+        let (south, west) = hgt_origin(file_name)
+            .ok_or_else(|| anyhow!("{} isn't named after an SRTM cell", file_name))?;
+        Self::from_hgt(data, south, west)
+    }
+
+    /// Reads a single band GeoTIFF of `Int16` or `Float32` samples in
+    /// geographic coordinates, located by its tie point and pixel scale.
+    pub fn from_geotiff(data: &[u8]) -> anyhow::Result<Self> {
+        use tiff::decoder::{Decoder, DecodingResult};
+        use tiff::tags::Tag;
+
+        let mut decoder = Decoder::new(Cursor::new(data))?;
+        let (width, height) = decoder.dimensions()?;
+        let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag)?;
+        let tie_point = decoder.get_tag_f64_vec(Tag::ModelTiepointTag)?;
+        if scale.len() < 2 || tie_point.len() < 6 {
+            bail!("malformed GeoTIFF georeferencing");
+        }
+
+        // Keys are (id, location, count, value) after a four value header
+        let mut raster_is_point = false;
+        if let Some(keys) = decoder
+            .find_tag(Tag::GeoKeyDirectoryTag)?
+            .map(|value| value.into_u16_vec())
+            .transpose()?
+        {
+            for key in keys.chunks_exact(4).skip(1) {
+                match (key[0], key[1], key[3]) {
+                    // GTModelTypeGeoKey: anything but ModelTypeGeographic
+                    (1024, 0, model_type) if model_type != 2 => {
+                        bail!("only geographic GeoTIFFs are supported")
+                    }
+                    // GTRasterTypeGeoKey: RasterPixelIsPoint
+                    (1025, 0, 2) => raster_is_point = true,
+                    _ => {}
+                }
+            }
+        }
+        let no_data = decoder
+            .find_tag(Tag::GdalNodata)?
+            .map(|value| value.into_string())
+            .transpose()?
+            .and_then(|text| text.trim_end_matches('\0').trim().parse::<f32>().ok());
+
+        let heights: Vec<f32> = match decoder.read_image()? {
+            DecodingResult::I16(samples) => samples.into_iter().map(f32::from).collect(),
+            DecodingResult::F32(samples) => samples,
+            _ => bail!("only Int16 and Float32 GeoTIFFs are supported"),
+        };
+        let heights = heights
+            .into_iter()
+            .map(|height| match no_data {
+                Some(no_data) if height == no_data => f32::NAN,
+                _ => height,
+            })
+            .collect();
+
+        // The tie point pins raster position (i, j) to (longitude, latitude)
+        let center = if raster_is_point { 0.0 } else { 0.5 };
+        let longitude = |column: f64| tie_point[3] + (column + center - tie_point[0]) * scale[0];
+        let latitude = |row: f64| tie_point[4] - (row + center - tie_point[1]) * scale[1];
+        let extent = Extent {
+            west: longitude(0.0).to_radians(),
+            south: latitude(height as f64 - 1.0).to_radians(),
+            east: longitude(width as f64 - 1.0).to_radians(),
+            north: latitude(0.0).to_radians(),
+        };
+        Self::new(
+            width as usize,
+            height as usize,
+            extent,
+            TextureProjection::Geographic,
+            heights,
+        )
+    }
+
+    /// Decodes a Mapbox Terrain-RGB tile, where each pixel holds
+    /// `(R * 65536 + G * 256 + B) / 10 - 10000` meters.
+    pub fn from_terrain_rgb(data: &[u8], tile: TileId) -> anyhow::Result<Self> {
+        let image = image::load_from_memory(data)?.to_rgb8();
+        let (width, height) = image.dimensions();
+        let heights = image
+            .pixels()
+            .map(|pixel| {
+                let [r, g, b] = pixel.0.map(f32::from);
+                (r * 65536.0 + g * 256.0 + b) * 0.1 - 10000.0
+            })
+            .collect();
+
+        // Each pixel covers an area, so the samples sit half a pixel inside
+        let tiles = (1u64 << tile.z) as f64;
+        let column = |pixel: f64| tile.x as f64 + pixel / width as f64;
+        let row = |pixel: f64| tile.y as f64 + pixel / height as f64;
+        let extent = Extent {
+            west: tiles::web_mercator_longitude(column(0.5), tiles),
+            south: tiles::web_mercator_latitude(row(height as f64 - 0.5), tiles),
+            east: tiles::web_mercator_longitude(column(width as f64 - 0.5), tiles),
+            north: tiles::web_mercator_latitude(row(0.5), tiles),
+        };
+        Self::new(
+            width as usize,
+            height as usize,
+            extent,
+            TextureProjection::WebMercator,
+            heights,
+        )
+    }
+
+    /// The height at a point, interpolated bilinearly between the
+    /// surrounding samples and ignoring voids among them. `None` outside the
+    /// heightmap, which still covers half a sample beyond its outermost
+    /// samples, or where all four samples are voids.
+    pub fn sample(&self, latitude: f64, longitude: f64) -> Option<f64> {
+        let column = (longitude - self.extent.west) / self.extent.width() * (self.width - 1) as f64;
+        let row = self.projection.v(latitude, &self.extent) * (self.height - 1) as f64;
+        // Half a sample, with some slack for rounding at tile corners
+        const MARGIN: f64 = 0.5 + 1e-9;
+        if !(-MARGIN..=self.width as f64 - 1.0 + MARGIN).contains(&column)
+            || !(-MARGIN..=self.height as f64 - 1.0 + MARGIN).contains(&row)
+        {
+            return None;
+        }
+        let column = column.clamp(0.0, (self.width - 1) as f64);
+        let row = row.clamp(0.0, (self.height - 1) as f64);
+
+        let left = (column as usize).min(self.width - 2);
+        let top = (row as usize).min(self.height - 2);
+        let (s, t) = (column - left as f64, row - top as f64);
+        let mut sum = 0.0;
+        let mut weights = 0.0;
+        for (dy, dx, weight) in [
+            (0, 0, (1.0 - s) * (1.0 - t)),
+            (0, 1, s * (1.0 - t)),
+            (1, 0, (1.0 - s) * t),
+            (1, 1, s * t),
+        ] {
+            let height = self.heights[(top + dy) * self.width + left + dx];
+            if !height.is_nan() && weight > 0.0 {
+                sum += height as f64 * weight;
+                weights += weight;
+            }
+        }
+        (weights > 0.0).then(|| sum / weights)
+    }
+
+    fn intersects(&self, extent: &Extent) -> bool {
+        self.extent.west <= extent.east
+            && extent.west <= self.extent.east
+            && self.extent.south <= extent.north
+            && extent.south <= self.extent.north
+    }
+}
+
+/// The south-west corner in whole degrees of an SRTM cell named like
+/// `S12W077.hgt`.
+fn hgt_origin(file_name: &str) -> Option<(i32, i32)> {
+    let name = std::path::Path::new(file_name).file_stem()?.to_str()?;
+    let name = name.get(..7)?.to_ascii_uppercase();
+    let latitude: i32 = name.get(1..3)?.parse().ok()?;
+    let longitude: i32 = name.get(4..7)?.parse().ok()?;
+    let latitude = match &name[..1] {
+        "N" => latitude,
+        "S" => -latitude,
+        _ => return None,
+    };
+    let longitude = match &name[3..4] {
+        "E" => longitude,
+        "W" => -longitude,
+        _ => return None,
+    };
+    Some((latitude, longitude))
+}
+
+/// The heightmaps and terrain meshes that cover one tile.
+#[derive(Debug, Clone, Default)]
+pub struct TileElevation {
+    pub heightmaps: Vec<Arc<Heightmap>>,
+    pub meshes: Vec<Arc<QuantizedMesh>>,
+}
+
+impl TileElevation {
+    /// The height from the first heightmap or mesh that has data at the
+    /// point.
+    pub fn height_at(&self, latitude: f64, longitude: f64) -> Option<f64> {
+        self.heightmaps
+            .iter()
+            .find_map(|heightmap| heightmap.sample(latitude, longitude))
+            .or_else(|| {
+                self.meshes
+                    .iter()
+                    .find_map(|mesh| mesh.height_at(latitude, longitude))
+            })
+    }
+
+    /// The surface of the terrain meshes inside `extent`, textured for an
+    /// image covering it in `projection`, or `None` without meshes.
+    pub fn mesh_geometry(
+        &self,
+        extent: &Extent,
+        projection: TextureProjection,
+        skirt_height: f64,
+    ) -> Option<TileGeometry> {
+        if self.meshes.is_empty() {
+            return None;
+        }
+        let (latitude, longitude) = extent.center();
+        let center = geodesy::geodetic_to_ecef(latitude, longitude, 0.0);
+        let mut merged = TileGeometry {
+            center,
+            vertices: Vec::new(),
+            indices: Vec::new(),
+        };
+        for mesh in &self.meshes {
+            let geometry = mesh.geometry(extent, projection, center, skirt_height);
+            let offset = merged.vertices.len() as u32;
+            merged.vertices.extend(geometry.vertices);
+            merged
+                .indices
+                .extend(geometry.indices.into_iter().map(|i| i + offset));
+        }
+        Some(merged)
+    }
+}
+
+/// Where the heights that displace the globe surface come from.
+#[derive(Debug, Clone)]
+pub enum ElevationSource {
+    /// Terrain-RGB tiles in the imagery's slippy-map scheme. Tiles deeper
+    /// than `max_level` use the heights of their ancestor at `max_level`.
+    TerrainRgb { source: TileSource, max_level: u32 },
+    /// Rasters held in memory, such as SRTM cells or GeoTIFFs. Where they
+    /// overlap the first one wins.
+    Rasters(Vec<Arc<Heightmap>>),
+    /// Quantized-mesh tiles in the geographic scheme, addressed by
+    /// [`TileId`]s whose `y` counts from the south. Each imagery tile takes
+    /// its heights from the terrain tiles of about the same size, or from
+    /// coarser ones where those are missing or beyond `max_level`.
+    QuantizedMesh { source: TileSource, max_level: u32 },
+}
+
+impl ElevationSource {
+    /// Loads every `.hgt`, `.tif` and `.tiff` file in `paths`.
+    pub fn from_files<P: AsRef<std::path::Path>>(paths: &[P]) -> anyhow::Result<Self> {
+        let rasters = paths
+            .iter()
+            .map(|path| {
+                let path = path.as_ref();
+                let data =
+                    std::fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+                let name = path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .unwrap_or("");
+                let extension = path
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .unwrap_or("")
+                    .to_ascii_lowercase();
+                let heightmap = match extension.as_str() {
+                    "hgt" => Heightmap::from_hgt_file(name, &data),
+                    "tif" | "tiff" => Heightmap::from_geotiff(&data),
+                    _ => Err(anyhow!("unknown elevation format")),
+                };
+                heightmap
+                    .map(Arc::new)
+                    .with_context(|| format!("loading {}", path.display()))
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(ElevationSource::Rasters(rasters))
+    }
+
+    pub async fn load(&self, tile: TileId) -> anyhow::Result<TileElevation> {
+        let mut elevation = TileElevation::default();
+        match self {
+            ElevationSource::TerrainRgb { source, max_level } => {
+                let tile = tile.ancestor(tile.z.min(*max_level));
+                let data = source.load(tile).await?;
+                elevation.heightmaps = vec![Arc::new(Heightmap::from_terrain_rgb(&data, tile)?)];
+            }
+            ElevationSource::Rasters(rasters) => {
+                let extent = tile.extent();
+                elevation.heightmaps = rasters
+                    .iter()
+                    .filter(|raster| raster.intersects(&extent))
+                    .cloned()
+                    .collect();
+            }
+            ElevationSource::QuantizedMesh { source, max_level } => {
+                // A geographic tile is as wide as a Web Mercator tile one
+                // level deeper
+                let extent = tile.extent();
+                let mut z = tile.z.saturating_sub(1).min(*max_level);
+                elevation.meshes = loop {
+                    match load_quantized_meshes(source, &extent, z).await {
+                        Ok(meshes) => break meshes,
+                        Err(_) if z > 0 => z -= 1,
+                        Err(e) => return Err(e),
+                    }
+                };
+            }
+        }
+        Ok(elevation)
+    }
+}
+
+async fn load_quantized_meshes(
+    source: &TileSource,
+    extent: &Extent,
+    z: u32,
+) -> anyhow::Result<Vec<Arc<QuantizedMesh>>> {
+    let mut meshes = Vec::new();
+    for tile in quantized_mesh::covering_tiles(extent, z) {
+        let data = source.load(tile).await?;
+        let mesh = QuantizedMesh::decode(&data, quantized_mesh::geographic_extent(tile))
+            .with_context(|| format!("decoding terrain tile {:?}", tile))?;
+        meshes.push(Arc::new(mesh));
+    }
+    Ok(meshes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hgt(side: usize, height: impl Fn(usize, usize) -> i16) -> Vec<u8> {
+        (0..side * side)
+            .flat_map(|i| height(i / side, i % side).to_be_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn hgt_cells_are_located_by_name() {
+        assert_eq!(hgt_origin("N45E006.hgt"), Some((45, 6)));
+        assert_eq!(hgt_origin("dem/s12w077.HGT"), Some((-12, -77)));
+        assert_eq!(hgt_origin("45E006.hgt"), None);
+
+        // Heights rise by 10 m per sample towards the east
+        let data = hgt(3, |_, column| column as i16 * 10);
+        let heightmap = Heightmap::from_hgt_file("N45E006.hgt", &data).unwrap();
+        let at = |latitude: f64, longitude: f64| {
+            heightmap.sample(latitude.to_radians(), longitude.to_radians())
+        };
+        assert!((at(45.5, 6.0).unwrap() - 0.0).abs() < 1e-9);
+        assert!((at(45.5, 6.25).unwrap() - 5.0).abs() < 1e-9);
+        assert!((at(45.0, 7.0).unwrap() - 20.0).abs() < 1e-9);
+        assert_eq!(at(47.0, 6.5), None);
+    }
+
+    #[test]
+    fn hgt_voids_are_skipped() {
+        let data = hgt(2, |row, column| match (row, column) {
+            (0, 0) => -32768,
+            _ => 100,
+        });
+        let heightmap = Heightmap::from_hgt(&data, 0, 0).unwrap();
+        assert!(heightmap.heights[0].is_nan());
+        let extent = heightmap.extent;
+        assert_eq!(heightmap.sample(extent.north, extent.west), None);
+        assert_eq!(heightmap.sample(0.0, 0.0), Some(100.0));
+        assert!(
+            (heightmap
+                .sample(extent.north * 0.5, extent.east * 0.5)
+                .unwrap()
+                - 100.0)
+                .abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn terrain_rgb_decodes_heights() {
+        // 1000 m is (1000 + 10000) * 10 = 110000 = 0x01adb0
+        let image = image::RgbImage::from_pixel(4, 4, image::Rgb([0x01, 0xad, 0xb0]));
+        let mut png = Vec::new();
+        image::DynamicImage::ImageRgb8(image)
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+
+        let tile = TileId::new(2, 1, 1);
+        let heightmap = Heightmap::from_terrain_rgb(&png, tile).unwrap();
+        let (latitude, longitude) = tile.extent().center();
+        assert!((heightmap.sample(latitude, longitude).unwrap() - 1000.0).abs() < 1e-3);
+        // The tile corners are half a pixel outside the samples
+        let extent = tile.extent();
+        assert!(heightmap.sample(extent.north, extent.west).is_some());
+        assert!(heightmap.sample(extent.north + 0.01, extent.west).is_none());
+    }
+
+    #[test]
+    fn geotiff_int16_and_float32() {
+        use tiff::encoder::{colortype, TiffEncoder};
+        use tiff::tags::Tag;
+
+        fn write<C: colortype::ColorType>(samples: &[C::Inner], point: bool) -> Vec<u8>
+        where
+            [C::Inner]: tiff::encoder::TiffValue,
+        {
+            let mut data = Cursor::new(Vec::new());
+            let mut encoder = TiffEncoder::new(&mut data).unwrap();
+            let mut image = encoder.new_image::<C>(3, 2).unwrap();
+            // 0.5 degree pixels with the north-west corner at 47 N, 8 E
+            image
+                .encoder()
+                .write_tag(Tag::ModelPixelScaleTag, &[0.5, 0.5, 0.0][..])
+                .unwrap();
+            image
+                .encoder()
+                .write_tag(Tag::ModelTiepointTag, &[0.0, 0.0, 0.0, 8.0, 47.0, 0.0][..])
+                .unwrap();
+            let raster_type: u16 = if point { 2 } else { 1 };
+            image
+                .encoder()
+                .write_tag(
+                    Tag::GeoKeyDirectoryTag,
+                    &[1u16, 1, 0, 2, 1024, 0, 1, 2, 1025, 0, 1, raster_type][..],
+                )
+                .unwrap();
+            image.write_data(samples).unwrap();
+            data.into_inner()
+        }
+
+        let area = Heightmap::from_geotiff(&write::<colortype::GrayI16>(
+            &[100, 200, 300, -100, -200, -300],
+            false,
+        ))
+        .unwrap();
+        assert!((area.extent.north - 46.75f64.to_radians()).abs() < 1e-12);
+        assert!((area.extent.west - 8.25f64.to_radians()).abs() < 1e-12);
+        let height = area.sample(46.75f64.to_radians(), 8.75f64.to_radians());
+        assert!((height.unwrap() - 200.0).abs() < 1e-9);
+
+        let point = Heightmap::from_geotiff(&write::<colortype::Gray32Float>(
+            &[1.5, 2.5, 3.5, 4.5, 5.5, 6.5],
+            true,
+        ))
+        .unwrap();
+        assert!((point.extent.east - 9.0f64.to_radians()).abs() < 1e-12);
+        assert!(
+            (point
+                .sample(46.5f64.to_radians(), 9.0f64.to_radians())
+                .unwrap()
+                - 6.5)
+                .abs()
+                < 1e-9
+        );
+    }
+}
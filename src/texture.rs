@@ -14,6 +14,7 @@ impl Texture {
     pub fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
+        compare: wgpu::CompareFunction,
         label: &str,
     ) -> Self {
         let size = wgpu::Extent3d {
@@ -39,7 +40,7 @@ impl Texture {
             mag_filter: wgpu::FilterMode::Linear,
             min_filter: wgpu::FilterMode::Linear,
             mipmap_filter: wgpu::FilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual),
+            compare: Some(compare),
             lod_min_clamp: -100.0,
             lod_max_clamp: 100.0,
             ..Default::default()
@@ -0,0 +1,306 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::mpsc;
+
+use crate::tiles::{TileBounds, TileId, View};
+
+/// Tiles that failed to load are requested again after this many frames.
+const FAILED_RETRY_FRAMES: u64 = 600;
+
+/// Tile loads run on a thread pool natively, so what they return must be
+/// `Send`. On the web they run on the event loop and needn't be.
+#[cfg(not(target_arch = "wasm32"))]
+pub trait MaybeSend: Send {}
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send> MaybeSend for T {}
+#[cfg(target_arch = "wasm32")]
+pub trait MaybeSend {}
+#[cfg(target_arch = "wasm32")]
+impl<T> MaybeSend for T {}
+
+struct CachedTile<T> {
+    tile: T,
+    /// What the tile counts against the budget of [`TileCache::evict`].
+    size: usize,
+    last_used: u64,
+}
+
+enum TileState<T> {
+    Loading,
+    Ready(Box<CachedTile<T>>),
+    /// Missing or unusable in the given frame. The parent is shown instead
+    /// until the tile is evicted and requested again.
+    Failed(u64),
+}
+
+/// The tiles of a quadtree layer: which are loaded, which are drawn and
+/// which are still coming. Data `D` is loaded in the background and built
+/// into tiles `T` on the render thread.
+///
+/// Each frame a layer calls [`TileCache::receive`], [`TileCache::select`],
+/// [`TileCache::request`] and [`TileCache::evict`] in turn.
+pub struct TileCache<T, D> {
+    tiles: HashMap<TileId, TileState<T>>,
+    loading: usize,
+    used: usize,
+    frame: u64,
+    selected: Vec<TileId>,
+    sender: mpsc::Sender<(TileId, anyhow::Result<D>)>,
+    receiver: mpsc::Receiver<(TileId, anyhow::Result<D>)>,
+}
+
+impl<T, D: MaybeSend + 'static> TileCache<T, D> {
+    pub fn new() -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            tiles: HashMap::new(),
+            loading: 0,
+            used: 0,
+            frame: 0,
+            sender,
+            receiver,
+            selected: Vec::new(),
+        }
+    }
+
+    /// The tile if it has loaded.
+    pub fn get(&self, tile: TileId) -> Option<&T> {
+        match self.tiles.get(&tile) {
+            Some(TileState::Ready(cached)) => Some(&cached.tile),
+            _ => None,
+        }
+    }
+
+    /// The tiles drawn this frame.
+    pub fn selected(&self) -> impl Iterator<Item = &T> {
+        self.selected.iter().filter_map(|&tile| self.get(tile))
+    }
+
+    /// Starts a new frame, building at most `max` tiles from the loads that
+    /// finished. `build` returns a tile and its size, and tiles it fails on
+    /// are retried later.
+    pub fn receive(
+        &mut self,
+        max: usize,
+        mut build: impl FnMut(TileId, anyhow::Result<D>) -> anyhow::Result<(T, usize)>,
+    ) {
+        self.frame += 1;
+        for (tile, data) in self.receiver.try_iter().take(max) {
+            self.loading -= 1;
+            let state = match build(tile, data) {
+                Ok((built, size)) => {
+                    self.used += size;
+                    TileState::Ready(Box::new(CachedTile {
+                        tile: built,
+                        size,
+                        last_used: self.frame,
+                    }))
+                }
+                Err(e) => {
+                    log::warn!("Couldn't load tile {:?}: {}", tile, e);
+                    TileState::Failed(self.frame)
+                }
+            };
+            self.tiles.insert(tile, state);
+        }
+    }
+
+    /// Chooses the coarsest tiles whose screen space error is at most
+    /// `max_error` pixels, down to `max_level`. A tile is only replaced by
+    /// its children once all of its visible children are loaded, so the
+    /// surface never has holes while zooming. Returns the tiles missing for
+    /// that.
+    pub fn select(&mut self, view: &View, max_level: u32, max_error: f64) -> Vec<TileId> {
+        let mut wanted = Vec::new();
+        self.selected.clear();
+        let root = TileId::new(0, 0, 0);
+        match self.tiles.get(&root) {
+            Some(TileState::Ready(_)) => {
+                self.select_below(root, view, max_level, max_error, &mut wanted)
+            }
+            Some(_) => {}
+            None => wanted.push(root),
+        }
+        wanted
+    }
+
+    fn select_below(
+        &mut self,
+        tile: TileId,
+        view: &View,
+        max_level: u32,
+        max_error: f64,
+        wanted: &mut Vec<TileId>,
+    ) {
+        if let Some(TileState::Ready(ready)) = self.tiles.get_mut(&tile) {
+            ready.last_used = self.frame;
+        }
+
+        let bounds = TileBounds::new(&tile.extent());
+        if tile.z < max_level && view.screen_space_error(tile, &bounds) > max_error {
+            let visible = tile
+                .children()
+                .iter()
+                .copied()
+                .filter(|&child| view.is_visible(child, &TileBounds::new(&child.extent())))
+                .collect::<Vec<_>>();
+
+            let mut all_ready = true;
+            for &child in &visible {
+                match self.tiles.get(&child) {
+                    Some(TileState::Ready(_)) => {}
+                    Some(_) => all_ready = false,
+                    None => {
+                        wanted.push(child);
+                        all_ready = false;
+                    }
+                }
+            }
+
+            if all_ready {
+                for child in visible {
+                    self.select_below(child, view, max_level, max_error, wanted);
+                }
+                return;
+            }
+        }
+
+        self.selected.push(tile);
+    }
+
+    /// Starts loading the `wanted` tiles, coarse ones first as they unblock
+    /// the most refinement, while fewer than `max_requests` are loading.
+    pub fn request<F>(
+        &mut self,
+        mut wanted: Vec<TileId>,
+        max_requests: usize,
+        mut load: impl FnMut(TileId) -> F,
+    ) where
+        F: Future<Output = anyhow::Result<D>> + MaybeSend + 'static,
+    {
+        wanted.sort_by_key(|tile| tile.z);
+        for tile in wanted {
+            if self.loading >= max_requests {
+                break;
+            }
+            self.tiles.insert(tile, TileState::Loading);
+            self.loading += 1;
+
+            let data = load(tile);
+            let sender = self.sender.clone();
+            let task = async move {
+                let data = data.await;
+                // The layer may have been dropped in the meantime
+                let _ = sender.send((tile, data));
+            };
+            cfg_if::cfg_if! {
+                if #[cfg(target_arch = "wasm32")] {
+                    async_std::task::spawn_local(task);
+                } else {
+                    async_std::task::spawn(task);
+                }
+            }
+        }
+    }
+
+    /// Drops failed tiles that are due a retry, and the least recently used
+    /// tiles until their sizes add up to no more than `budget`.
+    pub fn evict(&mut self, budget: usize) {
+        let frame = self.frame;
+        self.tiles.retain(|_, state| match state {
+            TileState::Failed(failed) => frame - *failed < FAILED_RETRY_FRAMES,
+            _ => true,
+        });
+
+        let ready = self.tiles.iter().filter_map(|(&id, state)| match state {
+            TileState::Ready(cached) => Some((id, cached.last_used, cached.size)),
+            _ => None,
+        });
+        for id in eviction_order(ready, frame, self.used, budget) {
+            if let Some(TileState::Ready(cached)) = self.tiles.remove(&id) {
+                self.used -= cached.size;
+            }
+        }
+    }
+}
+
+/// Which of the `(tile, last used frame, size)` to drop for `used` to fit
+/// `budget`: the least recently used first, and the finest of those used as
+/// recently. Tiles used in `frame` and the root are kept regardless.
+fn eviction_order(
+    tiles: impl Iterator<Item = (TileId, u64, usize)>,
+    frame: u64,
+    mut used: usize,
+    budget: usize,
+) -> Vec<TileId> {
+    if used <= budget {
+        return Vec::new();
+    }
+    let mut candidates = tiles
+        .filter(|&(id, last_used, _)| last_used < frame && id.z > 0)
+        .collect::<Vec<_>>();
+    candidates.sort_unstable_by_key(|&(id, last_used, _)| (last_used, std::cmp::Reverse(id.z)));
+
+    let mut evicted = Vec::new();
+    for (id, _, size) in candidates {
+        if used <= budget {
+            break;
+        }
+        used -= size;
+        evicted.push(id);
+    }
+    evicted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn eviction_drops_the_least_recently_used_first() {
+        let tiles = [
+            (TileId::new(0, 0, 0), 1, 100),
+            (TileId::new(1, 0, 0), 3, 100),
+            (TileId::new(2, 0, 0), 3, 100),
+            (TileId::new(2, 1, 0), 5, 100),
+            (TileId::new(3, 0, 0), 10, 100),
+            (TileId::new(3, 1, 0), 8, 100),
+        ];
+        let order = |used, budget| eviction_order(tiles.iter().copied(), 10, used, budget);
+        assert_eq!(order(600, 600), Vec::new());
+        // Finer tiles go first among those used as recently
+        assert_eq!(
+            order(650, 400),
+            vec![
+                TileId::new(2, 0, 0),
+                TileId::new(1, 0, 0),
+                TileId::new(2, 1, 0)
+            ]
+        );
+        // The root and the tiles drawn this frame stay, even over the budget
+        assert_eq!(order(600, 0).len(), 4);
+        assert!(!order(600, 0).contains(&TileId::new(3, 0, 0)));
+    }
+
+    #[test]
+    fn failed_tiles_are_retried() {
+        let mut cache = TileCache::<(), ()>::new();
+        let tile = TileId::new(1, 0, 0);
+        cache
+            .sender
+            .send((tile, Err(anyhow::anyhow!("missing"))))
+            .unwrap();
+        cache.loading = 1;
+        cache.receive(4, |_, data| data.map(|()| ((), 0)));
+        assert!(cache.get(tile).is_none());
+
+        // Kept as failed, so it isn't asked for again right away
+        cache.evict(0);
+        assert!(matches!(cache.tiles.get(&tile), Some(TileState::Failed(_))));
+        for _ in 0..FAILED_RETRY_FRAMES {
+            cache.receive(4, |_, data| data.map(|()| ((), 0)));
+        }
+        cache.evict(0);
+        assert!(!cache.tiles.contains_key(&tile));
+    }
+}
@@ -0,0 +1,641 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Vector3, Vector4};
+use std::f64::consts::{FRAC_PI_4, PI, TAU};
+use std::io::Cursor;
+use std::sync::Arc;
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::http_tiles;
+use crate::model::DrawModel;
+use crate::tile_cache::TileCache;
+use crate::{camera, geodesy, globe, model, resources, terrain, texture};
+
+/// Width and height in pixels of the tiles in a slippy-map pyramid.
+const TILE_SIZE: f64 = 256.0;
+const TILE_SEGMENTS: u32 = 16;
+/// Decoding and uploading is done on the render thread, so only a few
+/// finished downloads are taken per frame to keep the frame time steady.
+const MAX_UPLOADS_PER_FRAME: usize = 4;
+
+/// A tile in the Web Mercator slippy-map scheme, with `y` counted from the
+/// north.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileId {
+    pub z: u32,
+    pub x: u32,
+    pub y: u32,
+}
+
+impl TileId {
+    pub fn new(z: u32, x: u32, y: u32) -> Self {
+        Self { z, x, y }
+    }
+
+    pub fn extent(&self) -> globe::Extent {
+        let tiles = (1u64 << self.z) as f64;
+        globe::Extent {
+            west: web_mercator_longitude(self.x as f64, tiles),
+            south: web_mercator_latitude((self.y + 1) as f64, tiles),
+            east: web_mercator_longitude((self.x + 1) as f64, tiles),
+            north: web_mercator_latitude(self.y as f64, tiles),
+        }
+    }
+
+    /// The tile of level `z` containing a point. Points beyond the
+    /// latitudes Web Mercator covers fall in the northern- or southernmost
+    /// row.
+    pub fn containing(latitude: f64, longitude: f64, z: u32) -> TileId {
+        let tiles = (1u64 << z) as f64;
+        let column = (longitude + PI) / TAU * tiles;
+        let latitude = latitude.clamp(
+            -globe::WEB_MERCATOR_MAX_LATITUDE,
+            globe::WEB_MERCATOR_MAX_LATITUDE,
+        );
+        let row = (1.0 - (FRAC_PI_4 + latitude / 2.0).tan().ln() / PI) / 2.0 * tiles;
+        let clamp = |value: f64| value.floor().clamp(0.0, tiles - 1.0) as u32;
+        TileId::new(z, clamp(column), clamp(row))
+    }
+
+    /// The tile at level `z` containing this one.
+    pub fn ancestor(&self, z: u32) -> TileId {
+        let shift = self.z - z.min(self.z);
+        TileId::new(self.z - shift, self.x >> shift, self.y >> shift)
+    }
+
+    pub fn children(&self) -> [TileId; 4] {
+        let (z, x, y) = (self.z + 1, self.x * 2, self.y * 2);
+        [
+            TileId::new(z, x, y),
+            TileId::new(z, x + 1, y),
+            TileId::new(z, x, y + 1),
+            TileId::new(z, x + 1, y + 1),
+        ]
+    }
+}
+
+/// The longitude of a possibly fractional tile column at a level that is
+/// `tiles` tiles wide.
+pub fn web_mercator_longitude(column: f64, tiles: f64) -> f64 {
+    column / tiles * TAU - PI
+}
+
+/// The latitude of a possibly fractional tile row at a level that is
+/// `tiles` tiles high.
+pub fn web_mercator_latitude(row: f64, tiles: f64) -> f64 {
+    (PI * (1.0 - 2.0 * row / tiles)).sinh().atan()
+}
+
+/// A `z/x/y` tile pyramid inside the resource directory.
+#[derive(Debug, Clone)]
+pub struct TileDirectory {
+    pub path: String,
+    pub extension: String,
+}
+
+impl TileDirectory {
+    pub fn new(path: &str, extension: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            extension: extension.to_string(),
+        }
+    }
+
+    fn file_name(&self, tile: TileId) -> String {
+        format!(
+            "{}/{}/{}/{}.{}",
+            self.path, tile.z, tile.x, tile.y, self.extension
+        )
+    }
+
+    async fn load(&self, tile: TileId) -> anyhow::Result<Vec<u8>> {
+        resources::load_binary(&self.file_name(tile)).await
+    }
+}
+
+/// Where a tile pyramid comes from.
+#[derive(Debug, Clone)]
+pub enum TileSource {
+    Directory(TileDirectory),
+    #[cfg(not(target_arch = "wasm32"))]
+    Http(Arc<http_tiles::HttpTileProvider>),
+}
+
+impl TileSource {
+    pub async fn load(&self, tile: TileId) -> anyhow::Result<Vec<u8>> {
+        match self {
+            TileSource::Directory(directory) => directory.load(tile).await,
+            #[cfg(not(target_arch = "wasm32"))]
+            TileSource::Http(provider) => {
+                // Downloads block, so they get a thread of their own rather
+                // than stalling the executor
+                let provider = provider.clone();
+                let (sender, receiver) = async_std::channel::bounded(1);
+                std::thread::spawn(move || {
+                    let _ = sender.try_send(provider.fetch(tile));
+                });
+                receiver.recv().await?
+            }
+        }
+    }
+}
+
+impl From<TileDirectory> for TileSource {
+    fn from(directory: TileDirectory) -> Self {
+        TileSource::Directory(directory)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl From<http_tiles::HttpTileProvider> for TileSource {
+    fn from(provider: http_tiles::HttpTileProvider) -> Self {
+        TileSource::Http(Arc::new(provider))
+    }
+}
+
+/// A bounding sphere around a tile plus the points used to decide whether
+/// the tile has disappeared behind the horizon.
+pub struct TileBounds {
+    center: Vector3<f64>,
+    radius: f64,
+    samples: [Vector3<f64>; 9],
+}
+
+impl TileBounds {
+    pub fn new(extent: &globe::Extent) -> Self {
+        let (latitude, longitude) = extent.center();
+        let center = geodesy::geodetic_to_ecef(latitude, longitude, 0.0);
+        let mut samples = [center; 9];
+        let mut i = 0;
+        for &latitude in &[extent.north, latitude, extent.south] {
+            for &longitude in &[extent.west, longitude, extent.east] {
+                samples[i] = geodesy::geodetic_to_ecef(latitude, longitude, 0.0);
+                i += 1;
+            }
+        }
+        let radius = samples
+            .iter()
+            .map(|sample| (sample - center).magnitude())
+            .fold(0.0, f64::max);
+
+        Self {
+            center,
+            // Leave room for the skirts
+            radius: radius * 1.01,
+            samples,
+        }
+    }
+}
+
+/// Everything tile selection needs to know about the camera.
+pub struct View {
+    position: Vector3<f64>,
+    /// The side planes of the view frustum in camera-relative coordinates.
+    planes: [Vector4<f64>; 4],
+    /// The camera position scaled so the occluding sphere has radius 1.
+    horizon_position: Vector3<f64>,
+    horizon_distance_squared: f64,
+    /// Converts a world space error at unit distance to pixels.
+    error_to_pixels: f64,
+}
+
+impl View {
+    /// Occluding sphere radius. Using the polar radius keeps the sphere
+    /// inside the ellipsoid, so occlusion is never overestimated.
+    const OCCLUDER_RADIUS: f64 = geodesy::WGS84_A * (1.0 - geodesy::WGS84_F);
+
+    pub fn new(
+        camera: &camera::GlobeCamera,
+        projection: &camera::Projection,
+        viewport_height: u32,
+    ) -> Self {
+        let view_proj: Matrix4<f64> = (projection.calc_matrix() * camera.calc_matrix())
+            .cast()
+            .unwrap();
+        let row = |i: usize| {
+            Vector4::new(
+                view_proj.x[i],
+                view_proj.y[i],
+                view_proj.z[i],
+                view_proj.w[i],
+            )
+        };
+        let position = camera.position().to_vec();
+        let horizon_position = position / Self::OCCLUDER_RADIUS;
+
+        Self {
+            position,
+            planes: [
+                row(3) + row(0),
+                row(3) - row(0),
+                row(3) + row(1),
+                row(3) - row(1),
+            ],
+            horizon_position,
+            horizon_distance_squared: horizon_position.magnitude2() - 1.0,
+            error_to_pixels: viewport_height as f64
+                / (2.0 * (projection.fovy().0 as f64 / 2.0).tan()),
+        }
+    }
+
+    fn in_frustum(&self, bounds: &TileBounds) -> bool {
+        let center = bounds.center - self.position;
+        self.planes.iter().all(|plane| {
+            plane.truncate().dot(center) + plane.w >= -bounds.radius * plane.truncate().magnitude()
+        })
+    }
+
+    fn is_occluded(&self, point: Vector3<f64>) -> bool {
+        if self.horizon_distance_squared <= 0.0 {
+            return false;
+        }
+        let to_point = point / Self::OCCLUDER_RADIUS - self.horizon_position;
+        let along = -to_point.dot(self.horizon_position);
+        along > self.horizon_distance_squared
+            && along * along / to_point.magnitude2() > self.horizon_distance_squared
+    }
+
+    pub fn is_visible(&self, tile: TileId, bounds: &TileBounds) -> bool {
+        // The first levels are too large for the sample points to be reliable
+        if tile.z < 2 {
+            return true;
+        }
+        self.in_frustum(bounds)
+            && !bounds
+                .samples
+                .iter()
+                .all(|&sample| self.is_occluded(sample))
+    }
+
+    pub fn screen_space_error(&self, tile: TileId, bounds: &TileBounds) -> f64 {
+        let (latitude, _) = tile.extent().center();
+        let meters_per_pixel =
+            TAU * geodesy::WGS84_A * latitude.cos() / ((1u64 << tile.z) as f64 * TILE_SIZE);
+        let distance = ((bounds.center - self.position).magnitude() - bounds.radius).max(1.0);
+        meters_per_pixel * self.error_to_pixels / distance
+    }
+}
+
+/// What a background task fetches for a tile.
+struct TileData {
+    imagery: Vec<u8>,
+    elevation: terrain::TileElevation,
+}
+
+pub struct Tile {
+    pub mesh: model::Mesh,
+    pub material: model::Material,
+    /// The ECEF origin of the mesh vertices.
+    pub center: Vector3<f64>,
+    /// The heights the mesh was displaced by.
+    pub elevation: terrain::TileElevation,
+}
+
+/// Which loaded tiles a height query may use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeightLevel {
+    /// The most detailed loaded tile covering the point.
+    BestAvailable,
+    /// Only the tile of this level, so the answer doesn't change as finer
+    /// tiles come and go.
+    Exact(u32),
+}
+
+/// The ground height at a point from the tiles `lookup` finds, searching
+/// down from `max_level` for [`HeightLevel::BestAvailable`]. Tiles without
+/// elevation data there lie on the ellipsoid, as they are drawn.
+fn find_height<'a>(
+    latitude: f64,
+    longitude: f64,
+    level: HeightLevel,
+    max_level: u32,
+    lookup: impl Fn(TileId) -> Option<&'a terrain::TileElevation>,
+) -> Option<f64> {
+    let levels = match level {
+        HeightLevel::BestAvailable => 0..=max_level,
+        HeightLevel::Exact(z) => z..=z,
+    };
+    levels.rev().find_map(|z| {
+        lookup(TileId::containing(latitude, longitude, z))
+            .map(|elevation| elevation.height_at(latitude, longitude).unwrap_or(0.0))
+    })
+}
+
+/// Streams imagery tiles for the globe, choosing for every frame the
+/// coarsest tiles whose texels are no larger than
+/// `maximum_screen_space_error` pixels on screen. Tiles that were not
+/// needed recently are dropped once `memory_budget` is exceeded.
+///
+/// With a `terrain` source the tile meshes are displaced by its heights,
+/// otherwise they follow the ellipsoid.
+pub struct ImageryLayer {
+    source: TileSource,
+    pub terrain: Option<terrain::ElevationSource>,
+    pub max_level: u32,
+    pub maximum_screen_space_error: f64,
+    /// Bytes of textures and meshes the cached tiles may occupy.
+    pub memory_budget: usize,
+    pub max_requests: usize,
+    cache: TileCache<Tile, TileData>,
+    instance_buffer: wgpu::Buffer,
+    instance_capacity: usize,
+    /// Imagery has no normal map, so every tile shares this one.
+    flat_normal_texture: texture::Texture,
+}
+
+impl ImageryLayer {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        source: impl Into<TileSource>,
+    ) -> anyhow::Result<Self> {
+        let instance_capacity = 64;
+        Ok(Self {
+            source: source.into(),
+            terrain: None,
+            max_level: 19,
+            maximum_screen_space_error: 1.5,
+            memory_budget: 512 * 1024 * 1024,
+            max_requests: 8,
+            cache: TileCache::new(),
+            instance_buffer: Self::create_instance_buffer(device, instance_capacity),
+            instance_capacity,
+            flat_normal_texture: globe::flat_normal_texture(device, queue)?,
+        })
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Imagery Instance Buffer"),
+            size: (capacity * std::mem::size_of::<model::InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Whether the root tile has loaded. Until then nothing can be drawn.
+    pub fn is_ready(&self) -> bool {
+        self.cache.get(TileId::new(0, 0, 0)).is_some()
+    }
+
+    /// The tiles drawn this frame. The `i`th is drawn as instance `i` of
+    /// [`ImageryLayer::instance_buffer`].
+    pub fn selected_tiles(&self) -> impl Iterator<Item = &Tile> {
+        self.cache.selected()
+    }
+
+    pub fn instance_buffer(&self) -> &wgpu::Buffer {
+        &self.instance_buffer
+    }
+
+    /// The ground height in meters above the ellipsoid at a geodetic
+    /// latitude and longitude in radians, or `None` if no tile covering it is
+    /// loaded at the requested level.
+    pub fn height_at(&self, latitude: f64, longitude: f64, level: HeightLevel) -> Option<f64> {
+        find_height(latitude, longitude, level, self.max_level, |tile| {
+            self.cache.get(tile).map(|ready| &ready.elevation)
+        })
+    }
+
+    /// Moves each position to `height_above_ground` meters above the ground,
+    /// leaving those without a loaded tile underneath as they are. Returns
+    /// how many positions were moved.
+    pub fn clamp_to_ground(
+        &self,
+        positions: &mut [geodesy::Geodetic],
+        height_above_ground: f64,
+        level: HeightLevel,
+    ) -> usize {
+        let mut clamped = 0;
+        for position in positions {
+            if let Some(height) = self.height_at(position.latitude, position.longitude, level) {
+                position.height = height + height_above_ground;
+                clamped += 1;
+            }
+        }
+        clamped
+    }
+
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        camera: &camera::GlobeCamera,
+        projection: &camera::Projection,
+        viewport_height: u32,
+    ) {
+        let flat_normal_texture = &self.flat_normal_texture;
+        self.cache.receive(MAX_UPLOADS_PER_FRAME, |tile, data| {
+            Self::upload(device, queue, layout, flat_normal_texture, tile, data?)
+        });
+
+        let view = View::new(camera, projection, viewport_height);
+        let wanted = self
+            .cache
+            .select(&view, self.max_level, self.maximum_screen_space_error);
+        let (source, terrain) = (&self.source, &self.terrain);
+        self.cache.request(wanted, self.max_requests, |tile| {
+            let source = source.clone();
+            let terrain = terrain.clone();
+            async move {
+                let imagery = source.load(tile).await?;
+                let elevation = match terrain {
+                    Some(terrain) => terrain.load(tile).await.unwrap_or_else(|e| {
+                        // Imagery on the bare ellipsoid beats no tile
+                        log::warn!("Couldn't load terrain for {:?}: {}", tile, e);
+                        terrain::TileElevation::default()
+                    }),
+                    None => terrain::TileElevation::default(),
+                };
+                Ok(TileData { imagery, elevation })
+            }
+        });
+        self.cache.evict(self.memory_budget);
+        self.write_instances(device, queue, camera.position());
+    }
+
+    /// Builds a tile, returning it with the bytes of its texture and mesh.
+    fn upload(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        layout: &wgpu::BindGroupLayout,
+        flat_normal_texture: &texture::Texture,
+        tile: TileId,
+        data: TileData,
+    ) -> anyhow::Result<(Tile, usize)> {
+        let TileData { imagery, elevation } = data;
+        let (width, height) = image::io::Reader::new(Cursor::new(&imagery))
+            .with_guessed_format()?
+            .into_dimensions()?;
+        let label = format!("tile {}/{}/{}", tile.z, tile.x, tile.y);
+        let diffuse_texture = texture::Texture::from_bytes(device, queue, &imagery, &label, false)?;
+        let material = model::Material::with_shared_normal(
+            device,
+            &label,
+            diffuse_texture,
+            flat_normal_texture,
+            layout,
+        );
+
+        let extent = tile.extent();
+        let skirt_height = extent.width() * geodesy::WGS84_A * 0.01;
+        // Quantized-mesh terrain brings its own triangles, other elevation
+        // displaces a regular grid
+        let geometry = elevation
+            .mesh_geometry(&extent, globe::TextureProjection::WebMercator, skirt_height)
+            .unwrap_or_else(|| {
+                globe::tessellate_terrain(
+                    &extent,
+                    TILE_SEGMENTS,
+                    &extent,
+                    globe::TextureProjection::WebMercator,
+                    skirt_height,
+                    |latitude, longitude| elevation.height_at(latitude, longitude).unwrap_or(0.0),
+                )
+            });
+        let mesh = model::Mesh::new(device, &label, &geometry.vertices, &geometry.indices, 0);
+
+        let bytes = width as usize * height as usize * 4
+            + geometry.vertices.len() * std::mem::size_of::<model::ModelVertex>()
+            + geometry.indices.len() * std::mem::size_of::<u32>();
+        let tile = Tile {
+            mesh,
+            material,
+            center: geometry.center,
+            elevation,
+        };
+        Ok((tile, bytes))
+    }
+
+    fn write_instances(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera_position: Point3<f64>,
+    ) {
+        let instance_data = self
+            .cache
+            .selected()
+            .map(|tile| {
+                model::Instance::new(tile.center, cgmath::Quaternion::one()).to_raw(camera_position)
+            })
+            .collect::<Vec<_>>();
+
+        if instance_data.len() > self.instance_capacity {
+            self.instance_capacity = instance_data.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.instance_capacity);
+        }
+        queue.write_buffer(
+            &self.instance_buffer,
+            0,
+            bytemuck::cast_slice(&instance_data),
+        );
+    }
+}
+
+pub trait DrawTiles<'a> {
+    fn draw_tiles(
+        &mut self,
+        layer: &'a ImageryLayer,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawTiles<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_tiles(
+        &mut self,
+        layer: &'b ImageryLayer,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(1, layer.instance_buffer.slice(..));
+        for (i, tile) in layer.selected_tiles().enumerate() {
+            self.draw_mesh_instanced(
+                &tile.mesh,
+                &tile.material,
+                i as u32..i as u32 + 1,
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn tile_extents_follow_the_slippy_map_scheme() {
+        let root = TileId::new(0, 0, 0).extent();
+        assert!((root.north - globe::WEB_MERCATOR_MAX_LATITUDE).abs() < 1e-12);
+        assert!((root.south + globe::WEB_MERCATOR_MAX_LATITUDE).abs() < 1e-12);
+
+        let [north_west, north_east, south_west, _] = TileId::new(0, 0, 0).children();
+        assert_eq!(north_east, TileId::new(1, 1, 0));
+        let north_west = north_west.extent();
+        assert!((north_west.west + PI).abs() < 1e-12 && north_west.east.abs() < 1e-12);
+        assert!(north_west.south.abs() < 1e-12);
+        assert!(south_west.extent().north.abs() < 1e-12);
+    }
+
+    #[test]
+    fn tiles_contain_their_points() {
+        for tile in [
+            TileId::new(0, 0, 0),
+            TileId::new(5, 17, 9),
+            TileId::new(12, 4000, 1234),
+        ] {
+            let (latitude, longitude) = tile.extent().center();
+            assert_eq!(TileId::containing(latitude, longitude, tile.z), tile);
+        }
+        assert_eq!(
+            TileId::containing(FRAC_PI_4 * 2.0, PI, 3),
+            TileId::new(3, 7, 0)
+        );
+        assert_eq!(TileId::new(5, 17, 9).ancestor(3), TileId::new(3, 4, 2));
+    }
+
+    #[test]
+    fn height_queries_pick_the_level() {
+        let flat = |height: f32, tile: TileId| {
+            let heightmap = terrain::Heightmap::new(
+                2,
+                2,
+                tile.extent(),
+                globe::TextureProjection::WebMercator,
+                vec![height; 4],
+            )
+            .unwrap();
+            terrain::TileElevation {
+                heightmaps: vec![Arc::new(heightmap)],
+                meshes: Vec::new(),
+            }
+        };
+        let coarse = TileId::new(2, 1, 1);
+        let fine = TileId::new(4, 5, 6);
+        let bare = TileId::new(5, 10, 12);
+        let loaded = HashMap::from([
+            (coarse, flat(100.0, coarse)),
+            (fine, flat(200.0, fine)),
+            (bare, terrain::TileElevation::default()),
+        ]);
+        let lookup = |tile| loaded.get(&tile);
+
+        let (latitude, longitude) = TileId::new(6, 21, 25).extent().center();
+        let height = |level| find_height(latitude, longitude, level, 19, lookup).map(f64::round);
+        assert_eq!(height(HeightLevel::BestAvailable), Some(0.0));
+        assert_eq!(height(HeightLevel::Exact(4)), Some(200.0));
+        assert_eq!(height(HeightLevel::Exact(2)), Some(100.0));
+        assert_eq!(height(HeightLevel::Exact(3)), None);
+
+        let (latitude, longitude) = TileId::new(4, 4, 6).extent().center();
+        let height = |level| find_height(latitude, longitude, level, 19, lookup).map(f64::round);
+        assert_eq!(height(HeightLevel::BestAvailable), Some(100.0));
+    }
+}
@@ -0,0 +1,380 @@
+use anyhow::{anyhow, bail};
+use serde_json::{Map, Value};
+
+use crate::geodesy::Geodetic;
+use crate::polyline::{LineCap, LineJoin};
+
+/// Attributes of a feature, kept as JSON values whatever format they came
+/// from.
+pub type Properties = Map<String, Value>;
+
+/// A linear ring or line, as geodetic positions.
+pub type Path = Vec<Geodetic>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Geometry {
+    Point(Geodetic),
+    MultiPoint(Vec<Geodetic>),
+    LineString(Path),
+    MultiLineString(Vec<Path>),
+    /// The outer ring followed by any holes. Rings are not closed: the last
+    /// position doesn't repeat the first.
+    Polygon(Vec<Path>),
+    MultiPolygon(Vec<Vec<Path>>),
+    Collection(Vec<Geometry>),
+}
+
+impl Geometry {
+    /// Calls `point`, `line` and `polygon` for each simple geometry inside
+    /// this one.
+    pub fn visit(
+        &self,
+        point: &mut impl FnMut(&Geodetic),
+        line: &mut impl FnMut(&[Geodetic]),
+        polygon: &mut impl FnMut(&[Path]),
+    ) {
+        match self {
+            Geometry::Point(position) => point(position),
+            Geometry::MultiPoint(positions) => positions.iter().for_each(point),
+            Geometry::LineString(path) => line(path),
+            Geometry::MultiLineString(paths) => paths.iter().for_each(|path| line(path)),
+            Geometry::Polygon(rings) => polygon(rings),
+            Geometry::MultiPolygon(polygons) => polygons.iter().for_each(|rings| polygon(rings)),
+            Geometry::Collection(geometries) => {
+                for geometry in geometries {
+                    geometry.visit(point, line, polygon);
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Feature {
+    pub id: Option<Value>,
+    pub geometry: Geometry,
+    pub properties: Properties,
+}
+
+/// Reads the features of a GeoJSON document, which may be a
+/// `FeatureCollection`, a single `Feature` or a bare geometry. Features
+/// without a geometry are skipped.
+pub fn parse_geojson(text: &str) -> anyhow::Result<Vec<Feature>> {
+    let document: Value = serde_json::from_str(text)?;
+    match document["type"].as_str() {
+        Some("FeatureCollection") => document["features"]
+            .as_array()
+            .ok_or_else(|| anyhow!("a FeatureCollection without features"))?
+            .iter()
+            .filter_map(|feature| parse_feature(feature).transpose())
+            .collect(),
+        Some("Feature") => Ok(parse_feature(&document)?.into_iter().collect()),
+        _ => Ok(vec![Feature {
+            id: None,
+            geometry: parse_geometry(&document)?,
+            properties: Properties::new(),
+        }]),
+    }
+}
+
+/// Reads the features of a GeoJSON, Shapefile, KML or KMZ file, going by
+/// its extension.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn read_features(path: &std::path::Path) -> anyhow::Result<Vec<Feature>> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(str::to_ascii_lowercase);
+    match extension.as_deref() {
+        Some("shp") => crate::shapefile::read(path),
+        Some("kml") => crate::kml::parse_kml(&std::fs::read_to_string(path)?),
+        Some("kmz") => crate::kml::parse_kmz(&std::fs::read(path)?),
+        _ => parse_geojson(&std::fs::read_to_string(path)?),
+    }
+}
+
+fn parse_feature(feature: &Value) -> anyhow::Result<Option<Feature>> {
+    if feature["geometry"].is_null() {
+        return Ok(None);
+    }
+    Ok(Some(Feature {
+        id: feature.get("id").cloned(),
+        geometry: parse_geometry(&feature["geometry"])?,
+        properties: feature["properties"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default(),
+    }))
+}
+
+fn parse_geometry(geometry: &Value) -> anyhow::Result<Geometry> {
+    let coordinates = &geometry["coordinates"];
+    Ok(match geometry["type"].as_str() {
+        Some("Point") => Geometry::Point(position(coordinates)?),
+        Some("MultiPoint") => Geometry::MultiPoint(positions(coordinates)?),
+        Some("LineString") => Geometry::LineString(positions(coordinates)?),
+        Some("MultiLineString") => Geometry::MultiLineString(
+            array(coordinates)?
+                .iter()
+                .map(positions)
+                .collect::<Result<_, _>>()?,
+        ),
+        Some("Polygon") => Geometry::Polygon(rings(coordinates)?),
+        Some("MultiPolygon") => Geometry::MultiPolygon(
+            array(coordinates)?
+                .iter()
+                .map(rings)
+                .collect::<Result<_, _>>()?,
+        ),
+        Some("GeometryCollection") => Geometry::Collection(
+            array(&geometry["geometries"])?
+                .iter()
+                .map(parse_geometry)
+                .collect::<Result<_, _>>()?,
+        ),
+        Some(other) => bail!("unknown geometry type {}", other),
+        None => bail!("a geometry without a type"),
+    })
+}
+
+fn array(value: &Value) -> anyhow::Result<&Vec<Value>> {
+    value
+        .as_array()
+        .ok_or_else(|| anyhow!("expected an array, found {}", value))
+}
+
+/// A `[longitude, latitude, height]` position in degrees and meters.
+fn position(value: &Value) -> anyhow::Result<Geodetic> {
+    let numbers = array(value)?
+        .iter()
+        .map(|number| number.as_f64())
+        .collect::<Option<Vec<_>>>()
+        .ok_or_else(|| anyhow!("a position with a non-numeric coordinate"))?;
+    match numbers[..] {
+        [longitude, latitude] => Ok(Geodetic::from_degrees(latitude, longitude, 0.0)),
+        [longitude, latitude, height, ..] => {
+            Ok(Geodetic::from_degrees(latitude, longitude, height))
+        }
+        _ => bail!("a position needs at least two coordinates"),
+    }
+}
+
+fn positions(value: &Value) -> anyhow::Result<Path> {
+    array(value)?.iter().map(position).collect()
+}
+
+fn rings(value: &Value) -> anyhow::Result<Vec<Path>> {
+    array(value)?
+        .iter()
+        .map(|ring| {
+            let mut ring = positions(ring)?;
+            if ring.len() > 1 && ring.first() == ring.last() {
+                ring.pop();
+            }
+            Ok(ring)
+        })
+        .collect()
+}
+
+/// How a feature is drawn. Colors are non-premultiplied RGBA.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Style {
+    pub fill: [f32; 4],
+    pub stroke: [f32; 4],
+    /// Line width in pixels.
+    pub stroke_width: f32,
+    pub stroke_join: LineJoin,
+    pub stroke_cap: LineCap,
+    pub marker: [f32; 4],
+    /// Marker radius in pixels.
+    pub marker_size: f32,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            fill: [0.33, 0.33, 0.33, 0.6],
+            stroke: [0.33, 0.33, 0.33, 1.0],
+            stroke_width: 2.0,
+            stroke_join: LineJoin::Miter,
+            stroke_cap: LineCap::Butt,
+            marker: [0.49, 0.49, 0.49, 1.0],
+            marker_size: 8.0,
+        }
+    }
+}
+
+impl Style {
+    /// `base` overridden by the simplestyle-spec properties of a feature:
+    /// `fill`, `fill-opacity`, `stroke`, `stroke-opacity`, `stroke-width`,
+    /// `marker-color` and `marker-size`, plus SVG's `stroke-linejoin` and
+    /// `stroke-linecap`. Unreadable values are ignored.
+    pub fn from_properties(properties: &Properties, base: &Style) -> Style {
+        let mut style = *base;
+        let color = |key: &str| {
+            properties
+                .get(key)
+                .and_then(Value::as_str)
+                .and_then(parse_color)
+        };
+        let number = |key: &str| properties.get(key).and_then(Value::as_f64);
+
+        if let Some([r, g, b]) = color("fill") {
+            style.fill = [r, g, b, style.fill[3]];
+        }
+        if let Some(opacity) = number("fill-opacity") {
+            style.fill[3] = opacity as f32;
+        }
+        if let Some([r, g, b]) = color("stroke") {
+            style.stroke = [r, g, b, style.stroke[3]];
+        }
+        if let Some(opacity) = number("stroke-opacity") {
+            style.stroke[3] = opacity as f32;
+        }
+        if let Some(width) = number("stroke-width") {
+            style.stroke_width = width as f32;
+        }
+        match properties.get("stroke-linejoin").and_then(Value::as_str) {
+            Some("miter") => style.stroke_join = LineJoin::Miter,
+            Some("bevel") => style.stroke_join = LineJoin::Bevel,
+            Some("round") => style.stroke_join = LineJoin::Round,
+            _ => {}
+        }
+        match properties.get("stroke-linecap").and_then(Value::as_str) {
+            Some("butt") => style.stroke_cap = LineCap::Butt,
+            Some("square") => style.stroke_cap = LineCap::Square,
+            Some("round") => style.stroke_cap = LineCap::Round,
+            _ => {}
+        }
+        if let Some([r, g, b]) = color("marker-color") {
+            style.marker = [r, g, b, style.marker[3]];
+        }
+        match properties.get("marker-size").and_then(Value::as_str) {
+            Some("small") => style.marker_size = 5.0,
+            Some("medium") => style.marker_size = 8.0,
+            Some("large") => style.marker_size = 12.0,
+            _ => {}
+        }
+        style
+    }
+}
+
+/// Parses `#rgb` and `#rrggbb` colors.
+pub fn parse_color(text: &str) -> Option<[f32; 3]> {
+    let hex = text.strip_prefix('#')?;
+    let channel = |digits: &str| {
+        u8::from_str_radix(digits, 16)
+            .ok()
+            .map(|c| c as f32 / 255.0)
+    };
+    match hex.len() {
+        3 => {
+            let mut rgb = [0.0; 3];
+            for (i, digit) in hex.chars().enumerate() {
+                rgb[i] = channel(&digit.to_string().repeat(2))?;
+            }
+            Some(rgb)
+        }
+        6 => Some([
+            channel(hex.get(0..2)?)?,
+            channel(hex.get(2..4)?)?,
+            channel(hex.get(4..6)?)?,
+        ]),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_feature_collections() {
+        let features = parse_geojson(
+            r##"{
+                "type": "FeatureCollection",
+                "features": [
+                    {
+                        "type": "Feature",
+                        "id": 7,
+                        "geometry": {"type": "Point", "coordinates": [7.75, 46.0, 1600]},
+                        "properties": {"name": "Zermatt", "marker-color": "#f00"}
+                    },
+                    {
+                        "type": "Feature",
+                        "geometry": {
+                            "type": "Polygon",
+                            "coordinates": [
+                                [[0, 0], [4, 0], [4, 4], [0, 4], [0, 0]],
+                                [[1, 1], [1, 2], [2, 2], [1, 1]]
+                            ]
+                        },
+                        "properties": null
+                    },
+                    {"type": "Feature", "geometry": null, "properties": {}}
+                ]
+            }"##,
+        )
+        .unwrap();
+        assert_eq!(features.len(), 2);
+
+        assert_eq!(features[0].id, Some(Value::from(7)));
+        assert_eq!(
+            features[0].geometry,
+            Geometry::Point(Geodetic::from_degrees(46.0, 7.75, 1600.0))
+        );
+        let style = Style::from_properties(&features[0].properties, &Style::default());
+        assert_eq!(style.marker, [1.0, 0.0, 0.0, 1.0]);
+
+        match &features[1].geometry {
+            Geometry::Polygon(rings) => {
+                assert_eq!(rings.len(), 2);
+                assert_eq!(rings[0].len(), 4, "the closing position is dropped");
+                assert_eq!(rings[1].len(), 3);
+            }
+            geometry => panic!("expected a polygon, found {:?}", geometry),
+        }
+        assert!(features[1].properties.is_empty());
+    }
+
+    #[test]
+    fn parses_bare_geometries() {
+        let features = parse_geojson(
+            r#"{"type": "GeometryCollection", "geometries": [
+                {"type": "MultiLineString", "coordinates": [[[0, 0], [1, 1]], [[2, 2], [3, 3]]]},
+                {"type": "MultiPoint", "coordinates": [[0, 0], [1, 1]]}
+            ]}"#,
+        )
+        .unwrap();
+        let (mut points, mut lines, mut polygons) = (0, 0, 0);
+        features[0]
+            .geometry
+            .visit(&mut |_| points += 1, &mut |_| lines += 1, &mut |_| {
+                polygons += 1
+            });
+        assert_eq!((points, lines, polygons), (2, 2, 0));
+
+        assert!(parse_geojson(r#"{"type": "Point", "coordinates": [1]}"#).is_err());
+        assert!(parse_geojson(r#"{"type": "Circle", "coordinates": [1, 2]}"#).is_err());
+    }
+
+    #[test]
+    fn styles_follow_simplestyle_properties() {
+        let properties = serde_json::json!({
+            "fill": "#00ff80",
+            "fill-opacity": 0.25,
+            "stroke-width": 3,
+            "marker-size": "large",
+            "stroke": "not a color",
+            "stroke-linejoin": "round",
+            "stroke-linecap": "arrow"
+        });
+        let style = Style::from_properties(properties.as_object().unwrap(), &Style::default());
+        assert_eq!(style.fill, [0.0, 1.0, 128.0 / 255.0, 0.25]);
+        assert_eq!(style.stroke, Style::default().stroke);
+        assert_eq!(style.stroke_width, 3.0);
+        assert_eq!(style.marker_size, 12.0);
+        assert_eq!(style.stroke_join, LineJoin::Round);
+        assert_eq!(style.stroke_cap, LineCap::Butt);
+    }
+}
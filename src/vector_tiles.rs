@@ -0,0 +1,431 @@
+use anyhow::{anyhow, bail};
+use cgmath::Point3;
+use serde_json::Value;
+use std::sync::Arc;
+
+use crate::overlay::{DrawOverlay, FeatureGeometry, OverlayRenderer, VectorOverlay};
+use crate::tile_cache::TileCache;
+use crate::tiles::{TileId, TileSource, View};
+use crate::vector::{Feature, Geometry, Style};
+use crate::{camera, geodesy, mvt};
+
+/// Building overlay buffers is done on the render thread, so only a few
+/// finished tiles are taken per frame.
+const MAX_UPLOADS_PER_FRAME: usize = 4;
+
+/// A condition on a feature, following the filters of Mapbox GL styles.
+/// `$type` names the geometry type and `$id` the feature id, any other key
+/// a property.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+    All(Vec<Filter>),
+    Any(Vec<Filter>),
+    Not(Box<Filter>),
+    Has(String),
+    /// The key's value equals one of the values.
+    In(String, Vec<Value>),
+    Compare(String, Comparison, f64),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Filter {
+    /// Reads a filter such as `["all", ["==", "class", "river"], ["has",
+    /// "name"]]`.
+    pub fn parse(value: &Value) -> anyhow::Result<Filter> {
+        let parts = value
+            .as_array()
+            .ok_or_else(|| anyhow!("a filter must be an array, found {}", value))?;
+        let operator = parts
+            .first()
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!("a filter must start with its operator"))?;
+        let key = || {
+            parts
+                .get(1)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| anyhow!("{} needs a key", operator))
+        };
+        let value = || {
+            parts
+                .get(2)
+                .cloned()
+                .ok_or_else(|| anyhow!("{} needs a value", operator))
+        };
+        let filters = || {
+            parts[1..]
+                .iter()
+                .map(Filter::parse)
+                .collect::<Result<_, _>>()
+        };
+        let number = || {
+            value()?
+                .as_f64()
+                .ok_or_else(|| anyhow!("{} compares numbers", operator))
+        };
+        Ok(match operator {
+            "all" => Filter::All(filters()?),
+            "any" => Filter::Any(filters()?),
+            "none" => Filter::Not(Box::new(Filter::Any(filters()?))),
+            "has" => Filter::Has(key()?),
+            "!has" => Filter::Not(Box::new(Filter::Has(key()?))),
+            "==" => Filter::In(key()?, vec![value()?]),
+            "!=" => Filter::Not(Box::new(Filter::In(key()?, vec![value()?]))),
+            "in" => Filter::In(key()?, parts[2..].to_vec()),
+            "!in" => Filter::Not(Box::new(Filter::In(key()?, parts[2..].to_vec()))),
+            "<" => Filter::Compare(key()?, Comparison::Less, number()?),
+            "<=" => Filter::Compare(key()?, Comparison::LessOrEqual, number()?),
+            ">" => Filter::Compare(key()?, Comparison::Greater, number()?),
+            ">=" => Filter::Compare(key()?, Comparison::GreaterOrEqual, number()?),
+            other => bail!("unknown filter operator {}", other),
+        })
+    }
+
+    pub fn matches(&self, feature: &Feature) -> bool {
+        let lookup = |key: &str| match key {
+            "$type" => Some(Value::from(match feature.geometry {
+                Geometry::Point(_) | Geometry::MultiPoint(_) => "Point",
+                Geometry::LineString(_) | Geometry::MultiLineString(_) => "LineString",
+                Geometry::Polygon(_) | Geometry::MultiPolygon(_) => "Polygon",
+                Geometry::Collection(_) => "GeometryCollection",
+            })),
+            "$id" => feature.id.clone(),
+            _ => feature.properties.get(key).cloned(),
+        };
+        match self {
+            Filter::All(filters) => filters.iter().all(|filter| filter.matches(feature)),
+            Filter::Any(filters) => filters.iter().any(|filter| filter.matches(feature)),
+            Filter::Not(filter) => !filter.matches(feature),
+            Filter::Has(key) => lookup(key).is_some(),
+            Filter::In(key, values) => match lookup(key) {
+                // 1 and 1.0 are the same number
+                Some(Value::Number(number)) => values
+                    .iter()
+                    .any(|value| value.as_f64().is_some() && value.as_f64() == number.as_f64()),
+                Some(found) => values.contains(&found),
+                None => false,
+            },
+            Filter::Compare(key, comparison, value) => {
+                match lookup(key).as_ref().and_then(Value::as_f64) {
+                    Some(found) => match comparison {
+                        Comparison::Less => found < *value,
+                        Comparison::LessOrEqual => found <= *value,
+                        Comparison::Greater => found > *value,
+                        Comparison::GreaterOrEqual => found >= *value,
+                    },
+                    None => false,
+                }
+            }
+        }
+    }
+}
+
+/// Which features a style layer draws and how.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyleLayer {
+    /// The vector tile layer the features come from, or `None` for all of
+    /// them.
+    pub source_layer: Option<String>,
+    pub filter: Filter,
+    pub min_zoom: u32,
+    pub max_zoom: u32,
+    pub style: Style,
+}
+
+impl StyleLayer {
+    fn applies_to(&self, layer: &str, feature: &Feature, zoom: u32) -> bool {
+        self.source_layer
+            .as_deref()
+            .is_none_or(|name| name == layer)
+            && (self.min_zoom..=self.max_zoom).contains(&zoom)
+            && self.filter.matches(feature)
+    }
+}
+
+/// Decides how the features of vector tiles are drawn. The first style
+/// layer that applies to a feature draws it; features no layer applies to
+/// are left out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VectorStyle {
+    pub layers: Vec<StyleLayer>,
+}
+
+impl Default for VectorStyle {
+    /// Draws everything with the default [`Style`].
+    fn default() -> Self {
+        Self {
+            layers: vec![StyleLayer {
+                source_layer: None,
+                filter: Filter::All(Vec::new()),
+                min_zoom: 0,
+                max_zoom: u32::MAX,
+                style: Style::default(),
+            }],
+        }
+    }
+}
+
+impl VectorStyle {
+    /// Reads a style such as
+    ///
+    /// ```json
+    /// {"layers": [
+    ///     {"source-layer": "water", "fill": "#a0c8f0", "fill-opacity": 1},
+    ///     {"source-layer": "road", "filter": ["in", "class", "primary", "trunk"],
+    ///      "minzoom": 6, "stroke": "#f8d080", "stroke-width": 2}
+    /// ]}
+    /// ```
+    ///
+    /// where the colors and widths are the simplestyle-spec properties
+    /// [`Style::from_properties`] reads.
+    pub fn from_json(text: &str) -> anyhow::Result<Self> {
+        let document: Value = serde_json::from_str(text)?;
+        let layers = document["layers"]
+            .as_array()
+            .ok_or_else(|| anyhow!("a style needs a layers array"))?
+            .iter()
+            .map(|layer| {
+                let properties = layer
+                    .as_object()
+                    .ok_or_else(|| anyhow!("a style layer must be an object"))?;
+                let zoom = |key: &str, default: u32| {
+                    properties
+                        .get(key)
+                        .and_then(Value::as_u64)
+                        .map_or(default, |zoom| zoom as u32)
+                };
+                Ok(StyleLayer {
+                    source_layer: properties
+                        .get("source-layer")
+                        .and_then(Value::as_str)
+                        .map(str::to_string),
+                    filter: match properties.get("filter") {
+                        Some(filter) => Filter::parse(filter)?,
+                        None => Filter::All(Vec::new()),
+                    },
+                    min_zoom: zoom("minzoom", 0),
+                    max_zoom: zoom("maxzoom", u32::MAX),
+                    style: Style::from_properties(properties, &Style::default()),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self { layers })
+    }
+
+    /// How to draw a feature of `layer` in a tile of level `zoom`, if at
+    /// all.
+    pub fn style_for(&self, layer: &str, feature: &Feature, zoom: u32) -> Option<Style> {
+        self.layers
+            .iter()
+            .find(|style_layer| style_layer.applies_to(layer, feature, zoom))
+            .map(|style_layer| style_layer.style)
+    }
+}
+
+/// Decodes a vector tile and builds the geometry of the features `style`
+/// draws, relative to the middle of the tile.
+fn build_tile(
+    data: &[u8],
+    tile: TileId,
+    style: &VectorStyle,
+    height: f64,
+) -> anyhow::Result<FeatureGeometry> {
+    let mut geometries = Vec::new();
+    for layer in mvt::decode(data, tile)? {
+        for feature in &layer.features {
+            if let Some(mut style) = style.style_for(&layer.name, feature, tile.z) {
+                // Clipping gives polygons edges along the tile borders, which
+                // mustn't be outlined
+                if let Geometry::Polygon(_) | Geometry::MultiPolygon(_) = feature.geometry {
+                    style.stroke[3] = 0.0;
+                }
+                geometries.push(FeatureGeometry::new(feature, &style, height));
+            }
+        }
+    }
+    let (latitude, longitude) = tile.extent().center();
+    let center = geodesy::geodetic_to_ecef(latitude, longitude, height);
+    Ok(FeatureGeometry::merge(geometries, center))
+}
+
+/// Streams Mapbox Vector Tiles and draws them with a [`VectorStyle`],
+/// refining like [`crate::tiles::ImageryLayer`] does. Views closer than
+/// `max_level` allows keep drawing the tiles of `max_level`.
+///
+/// Features lie `height` meters above the ellipsoid.
+pub struct VectorTileLayer {
+    source: TileSource,
+    style: Arc<VectorStyle>,
+    pub max_level: u32,
+    pub maximum_screen_space_error: f64,
+    pub height: f64,
+    /// How many tiles are kept once they aren't drawn anymore.
+    pub max_tiles: usize,
+    pub max_requests: usize,
+    /// `None` for tiles that are missing or have nothing to draw.
+    cache: TileCache<Option<VectorOverlay>, FeatureGeometry>,
+}
+
+impl VectorTileLayer {
+    pub fn new(source: impl Into<TileSource>, style: VectorStyle) -> Self {
+        Self {
+            source: source.into(),
+            style: Arc::new(style),
+            max_level: 14,
+            maximum_screen_space_error: 3.0,
+            height: 0.0,
+            max_tiles: 256,
+            max_requests: 6,
+            cache: TileCache::new(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        camera: &camera::GlobeCamera,
+        projection: &camera::Projection,
+        viewport_height: u32,
+    ) {
+        // Each tile counts once against `max_tiles`
+        self.cache
+            .receive(MAX_UPLOADS_PER_FRAME, |tile, geometry| match geometry {
+                Ok(geometry) if geometry.is_empty() => Ok((None, 1)),
+                Ok(geometry) => Ok((
+                    Some(VectorOverlay::from_geometries(device, Some(geometry))),
+                    1,
+                )),
+                Err(e) => {
+                    // Sources often leave out empty tiles, so this isn't fatal:
+                    // the tile just has nothing to draw
+                    log::warn!("Couldn't load vector tile {:?}: {}", tile, e);
+                    Ok((None, 1))
+                }
+            });
+
+        let view = View::new(camera, projection, viewport_height);
+        let wanted = self
+            .cache
+            .select(&view, self.max_level, self.maximum_screen_space_error);
+        let (source, style, height) = (&self.source, &self.style, self.height);
+        self.cache.request(wanted, self.max_requests, |tile| {
+            let source = source.clone();
+            let style = style.clone();
+            // Decoding and triangulating happen off the render thread
+            async move {
+                let data = source.load(tile).await?;
+                build_tile(&data, tile, &style, height)
+            }
+        });
+        self.cache.evict(self.max_tiles);
+
+        let camera_position: Point3<f64> = camera.position();
+        for overlay in self.selected_overlays() {
+            overlay.update(queue, camera_position);
+        }
+    }
+
+    fn selected_overlays(&self) -> impl Iterator<Item = &VectorOverlay> {
+        self.cache.selected().flatten()
+    }
+}
+
+pub trait DrawVectorTiles<'a> {
+    fn draw_vector_tiles(
+        &mut self,
+        renderer: &'a OverlayRenderer,
+        layer: &'a VectorTileLayer,
+        camera_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawVectorTiles<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_vector_tiles(
+        &mut self,
+        renderer: &'b OverlayRenderer,
+        layer: &'b VectorTileLayer,
+        camera_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for overlay in layer.selected_overlays() {
+            self.draw_overlay(renderer, overlay, camera_bind_group);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geodesy::Geodetic;
+
+    fn feature(geometry: Geometry, properties: Value) -> Feature {
+        Feature {
+            id: Some(Value::from(4)),
+            geometry,
+            properties: properties.as_object().cloned().unwrap_or_default(),
+        }
+    }
+
+    #[test]
+    fn filters_follow_mapbox_gl() {
+        let river = feature(
+            Geometry::LineString(vec![Geodetic::new(0.0, 0.0, 0.0); 2]),
+            serde_json::json!({"class": "river", "width": 12, "name": "Aare"}),
+        );
+        let parse = |filter| Filter::parse(&serde_json::from_str(filter).unwrap()).unwrap();
+
+        assert!(parse(r#"["==", "class", "river"]"#).matches(&river));
+        assert!(parse(r#"["==", "width", 12.0]"#).matches(&river));
+        assert!(parse(r#"["==", "$type", "LineString"]"#).matches(&river));
+        assert!(parse(r#"["==", "$id", 4]"#).matches(&river));
+        assert!(parse(r#"["in", "class", "canal", "river"]"#).matches(&river));
+        assert!(!parse(r#"["!in", "class", "canal", "river"]"#).matches(&river));
+        assert!(
+            parse(r#"["all", ["has", "name"], [">", "width", 10], ["<=", "width", 12]]"#)
+                .matches(&river)
+        );
+        assert!(!parse(r#"["any", ["!has", "name"], ["<", "width", 10]]"#).matches(&river));
+        assert!(parse(r#"["none", ["!=", "class", "river"]]"#).matches(&river));
+        assert!(!parse(r#"[">", "class", 1]"#).matches(&river));
+
+        assert!(Filter::parse(&serde_json::json!(["~", "class"])).is_err());
+        assert!(Filter::parse(&serde_json::json!(["==", "class"])).is_err());
+    }
+
+    #[test]
+    fn the_first_matching_style_layer_wins() {
+        let style = VectorStyle::from_json(
+            r##"{"layers": [
+                {"source-layer": "water", "filter": ["==", "$type", "Polygon"], "fill": "#0000ff"},
+                {"source-layer": "road", "minzoom": 10, "stroke": "#ff0000", "stroke-width": 5},
+                {"source-layer": "road", "stroke": "#00ff00"}
+            ]}"##,
+        )
+        .unwrap();
+        let road = feature(
+            Geometry::LineString(vec![Geodetic::new(0.0, 0.0, 0.0); 2]),
+            Value::Null,
+        );
+
+        let near = style.style_for("road", &road, 12).unwrap();
+        assert_eq!(near.stroke, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(near.stroke_width, 5.0);
+        let far = style.style_for("road", &road, 8).unwrap();
+        assert_eq!(far.stroke, [0.0, 1.0, 0.0, 1.0]);
+        assert!(style.style_for("water", &road, 12).is_none());
+        assert!(style.style_for("buildings", &road, 12).is_none());
+        assert!(VectorStyle::default()
+            .style_for("buildings", &road, 12)
+            .is_some());
+    }
+}